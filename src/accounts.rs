@@ -0,0 +1,261 @@
+use arrayref::array_ref;
+use mango::state::MAX_PAIRS;
+use solana_program::account_info::AccountInfo;
+use solana_program::sysvar;
+
+use crate::error::{check_assert, QuasarError, QuasarErrorCode, QuasarResult, SourceFileId};
+
+declare_check_assert_macros!(SourceFileId::Accounts);
+
+/// Accounts of `MintLeverageToken`, checked for everything that doesn't need loading
+/// the quasar group
+pub struct MintAccounts<'a, 'b> {
+    pub quasar_group_ai: &'b AccountInfo<'a>,
+    pub token_mint_ai: &'b AccountInfo<'a>,
+    pub owner_leverage_token_account_ai: &'b AccountInfo<'a>,
+    pub mango_program_ai: &'b AccountInfo<'a>,
+    pub mango_group_ai: &'b AccountInfo<'a>,
+    pub mango_account_ai: &'b AccountInfo<'a>,
+    pub owner_ai: &'b AccountInfo<'a>,
+    pub mango_cache_ai: &'b AccountInfo<'a>,
+    pub root_bank_ai: &'b AccountInfo<'a>,
+    pub node_bank_ai: &'b AccountInfo<'a>,
+    pub vault_ai: &'b AccountInfo<'a>,
+    pub token_program_ai: &'b AccountInfo<'a>,
+    pub owner_quote_token_account_ai: &'b AccountInfo<'a>,
+    pub pda_ai: &'b AccountInfo<'a>,
+    pub fee_vault_ai: &'b AccountInfo<'a>,
+    /// Token ledger of tokens that have one, the quote oracle of tokens whose quote it
+    /// prices, then the spot accounts of `trade_mango_spot` followed by the open orders of
+    /// the margin basket, only passed by tokens swapping part of the deposit into base
+    pub spot_ais: &'b [AccountInfo<'a>],
+    /// Associated token program, optionally passed last. Mints don't create the owner's
+    /// token account, but a program passed for it must be the right one
+    pub associated_token_program_ai: Option<&'b AccountInfo<'a>>,
+}
+
+impl<'a, 'b> MintAccounts<'a, 'b> {
+    pub const NUM_FIXED: usize = 15;
+
+    pub fn load(accounts: &'b [AccountInfo<'a>]) -> QuasarResult<Self> {
+        check!(
+            accounts.len() >= Self::NUM_FIXED,
+            QuasarErrorCode::InvalidAccount
+        )?;
+        let (fixed_ais, spot_ais) = accounts.split_at(Self::NUM_FIXED);
+        // None of the other trailing accounts is a program, so an executable one is the
+        // associated token program
+        let (spot_ais, associated_token_program_ai) = match spot_ais.split_last() {
+            Some((last_ai, spot_ais)) if last_ai.executable => (spot_ais, Some(last_ai)),
+            _ => (spot_ais, None),
+        };
+        let ctx = Self {
+            quasar_group_ai: &fixed_ais[0],
+            token_mint_ai: &fixed_ais[1],
+            owner_leverage_token_account_ai: &fixed_ais[2],
+            mango_program_ai: &fixed_ais[3],
+            mango_group_ai: &fixed_ais[4],
+            mango_account_ai: &fixed_ais[5],
+            owner_ai: &fixed_ais[6],
+            mango_cache_ai: &fixed_ais[7],
+            root_bank_ai: &fixed_ais[8],
+            node_bank_ai: &fixed_ais[9],
+            vault_ai: &fixed_ais[10],
+            token_program_ai: &fixed_ais[11],
+            owner_quote_token_account_ai: &fixed_ais[12],
+            pda_ai: &fixed_ais[13],
+            fee_vault_ai: &fixed_ais[14],
+            spot_ais,
+            associated_token_program_ai,
+        };
+
+        check!(ctx.owner_ai.is_signer, QuasarErrorCode::SignerNecessary)?;
+        // Fail here rather than deep inside a Mango or token program CPI
+        check_writable(&[
+            ctx.token_mint_ai,
+            ctx.owner_leverage_token_account_ai,
+            ctx.mango_account_ai,
+            ctx.node_bank_ai,
+            ctx.vault_ai,
+            ctx.owner_quote_token_account_ai,
+            ctx.fee_vault_ai,
+        ])?;
+        if let Some(associated_token_program_ai) = ctx.associated_token_program_ai {
+            check_eq!(
+                *associated_token_program_ai.key,
+                spl_associated_token_account::id(),
+                QuasarErrorCode::InvalidProgramId
+            )?;
+        }
+
+        Ok(ctx)
+    }
+}
+
+/// Accounts of `BurnLeverageToken`, checked for everything that doesn't need loading
+/// the quasar group
+pub struct BurnAccounts<'a, 'b> {
+    pub quasar_group_ai: &'b AccountInfo<'a>,
+    pub token_mint_ai: &'b AccountInfo<'a>,
+    pub owner_leverage_token_account_ai: &'b AccountInfo<'a>,
+    pub mango_program_ai: &'b AccountInfo<'a>,
+    pub mango_group_ai: &'b AccountInfo<'a>,
+    pub mango_account_ai: &'b AccountInfo<'a>,
+    pub owner_ai: &'b AccountInfo<'a>,
+    pub mango_cache_ai: &'b AccountInfo<'a>,
+    pub root_bank_ai: &'b AccountInfo<'a>,
+    pub node_bank_ai: &'b AccountInfo<'a>,
+    pub vault_ai: &'b AccountInfo<'a>,
+    pub token_program_ai: &'b AccountInfo<'a>,
+    pub owner_quote_token_account_ai: &'b AccountInfo<'a>,
+    pub pda_ai: &'b AccountInfo<'a>,
+    pub mango_signer_ai: &'b AccountInfo<'a>,
+    pub fee_vault_ai: &'b AccountInfo<'a>,
+    /// Token ledger of tokens that have one, then the open orders of the markets in the
+    /// Mango account's margin basket, in market index order. The rest is padded before
+    /// calling Mango
+    pub open_orders_ais: &'b [AccountInfo<'a>],
+    /// Instructions sysvar, passed last after the open orders. Burns of a token with
+    /// `same_slot_burn_guard` need it
+    pub instructions_sysvar_ai: Option<&'b AccountInfo<'a>>,
+}
+
+impl<'a, 'b> BurnAccounts<'a, 'b> {
+    pub const NUM_FIXED: usize = 16;
+    /// With a full margin basket a burn takes more accounts than fit a transaction next to
+    /// other instructions, so it is best sent on its own
+    pub const MAX_ACCOUNTS: usize = Self::NUM_FIXED + 1 + MAX_PAIRS + 1;
+
+    pub fn load(accounts: &'b [AccountInfo<'a>]) -> QuasarResult<Self> {
+        check!(
+            accounts.len() >= Self::NUM_FIXED,
+            QuasarErrorCode::InvalidAccount
+        )?;
+        check!(
+            accounts.len() <= Self::MAX_ACCOUNTS,
+            QuasarErrorCode::InvalidParam
+        )?;
+        let (fixed_ais, open_orders_ais) = accounts.split_at(Self::NUM_FIXED);
+        // No open orders account can be the sysvar, so its key tells whether it was passed
+        let (open_orders_ais, instructions_sysvar_ai) = match open_orders_ais.split_last() {
+            Some((last_ai, open_orders_ais)) if *last_ai.key == sysvar::instructions::id() => {
+                (open_orders_ais, Some(last_ai))
+            }
+            _ => (open_orders_ais, None),
+        };
+        let ctx = Self {
+            quasar_group_ai: &fixed_ais[0],
+            token_mint_ai: &fixed_ais[1],
+            owner_leverage_token_account_ai: &fixed_ais[2],
+            mango_program_ai: &fixed_ais[3],
+            mango_group_ai: &fixed_ais[4],
+            mango_account_ai: &fixed_ais[5],
+            owner_ai: &fixed_ais[6],
+            mango_cache_ai: &fixed_ais[7],
+            root_bank_ai: &fixed_ais[8],
+            node_bank_ai: &fixed_ais[9],
+            vault_ai: &fixed_ais[10],
+            token_program_ai: &fixed_ais[11],
+            owner_quote_token_account_ai: &fixed_ais[12],
+            pda_ai: &fixed_ais[13],
+            mango_signer_ai: &fixed_ais[14],
+            fee_vault_ai: &fixed_ais[15],
+            open_orders_ais,
+            instructions_sysvar_ai,
+        };
+
+        check!(ctx.owner_ai.is_signer, QuasarErrorCode::SignerNecessary)?;
+        check_writable(&[
+            ctx.token_mint_ai,
+            ctx.owner_leverage_token_account_ai,
+            ctx.mango_account_ai,
+            ctx.node_bank_ai,
+            ctx.vault_ai,
+            ctx.owner_quote_token_account_ai,
+            ctx.fee_vault_ai,
+        ])?;
+        // The burn CPI runs before Mango would see the token program
+        check_eq!(
+            *ctx.token_program_ai.key,
+            spl_token::id(),
+            QuasarErrorCode::InvalidProgramId
+        )?;
+
+        Ok(ctx)
+    }
+}
+
+/// Accounts of `Rebalance`. Which trailing accounts are expected depends on the leveraged
+/// token, so they are split by the handler
+pub struct RebalanceAccounts<'a, 'b> {
+    pub quasar_group_ai: &'b AccountInfo<'a>,
+    pub token_mint_ai: &'b AccountInfo<'a>,
+    pub pda_ai: &'b AccountInfo<'a>,
+    pub mango_program_ai: &'b AccountInfo<'a>,
+    pub mango_group_ai: &'b AccountInfo<'a>,
+    pub mango_account_ai: &'b AccountInfo<'a>,
+    pub caller_ai: &'b AccountInfo<'a>,
+    pub mango_cache_ai: &'b AccountInfo<'a>,
+    pub mango_perp_market_ai: &'b AccountInfo<'a>,
+    pub mango_bids_ai: &'b AccountInfo<'a>,
+    pub mango_asks_ai: &'b AccountInfo<'a>,
+    pub mango_event_queue_ai: &'b AccountInfo<'a>,
+    pub mango_open_orders_ais: &'b [AccountInfo<'a>; MAX_PAIRS],
+    pub trailing_ais: &'b [AccountInfo<'a>],
+}
+
+impl<'a, 'b> RebalanceAccounts<'a, 'b> {
+    pub const NUM_FIXED: usize = 12;
+    /// Quote bank accounts, token program and spot accounts of `trade_mango_spot` passed to
+    /// sell spot collateral
+    pub const NUM_SPOT_TRADE: usize = 20;
+    /// A rebalance selling spot collateral exceeds what fits a transaction next to other
+    /// instructions, so keepers should send rebalances on their own
+    pub const MAX_ACCOUNTS: usize = Self::NUM_FIXED + MAX_PAIRS + 2 + Self::NUM_SPOT_TRADE;
+
+    pub fn load(accounts: &'b [AccountInfo<'a>]) -> QuasarResult<Self> {
+        check!(
+            accounts.len() >= Self::NUM_FIXED + MAX_PAIRS,
+            QuasarErrorCode::InvalidAccount
+        )?;
+        check!(
+            accounts.len() <= Self::MAX_ACCOUNTS,
+            QuasarErrorCode::InvalidParam
+        )?;
+        let (fixed_ais, accounts) = accounts.split_at(Self::NUM_FIXED);
+        let (mango_open_orders_ais, trailing_ais) = accounts.split_at(MAX_PAIRS);
+        let ctx = Self {
+            quasar_group_ai: &fixed_ais[0],
+            token_mint_ai: &fixed_ais[1],
+            pda_ai: &fixed_ais[2],
+            mango_program_ai: &fixed_ais[3],
+            mango_group_ai: &fixed_ais[4],
+            mango_account_ai: &fixed_ais[5],
+            caller_ai: &fixed_ais[6],
+            mango_cache_ai: &fixed_ais[7],
+            mango_perp_market_ai: &fixed_ais[8],
+            mango_bids_ai: &fixed_ais[9],
+            mango_asks_ai: &fixed_ais[10],
+            mango_event_queue_ai: &fixed_ais[11],
+            mango_open_orders_ais: array_ref![mango_open_orders_ais, 0, MAX_PAIRS],
+            trailing_ais,
+        };
+
+        check_writable(&[
+            ctx.mango_account_ai,
+            ctx.mango_perp_market_ai,
+            ctx.mango_bids_ai,
+            ctx.mango_asks_ai,
+            ctx.mango_event_queue_ai,
+        ])?;
+
+        Ok(ctx)
+    }
+}
+
+fn check_writable(accounts: &[&AccountInfo]) -> QuasarResult {
+    for ai in accounts.iter() {
+        check!(ai.is_writable, QuasarErrorCode::AccountNotWritable)?;
+    }
+    Ok(())
+}
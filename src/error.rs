@@ -14,6 +14,8 @@ pub enum SourceFileId {
     Processor = 0,
     State = 1,
     Oracle = 2,
+    Utils = 3,
+    Accounts = 4,
 }
 
 impl std::fmt::Display for SourceFileId {
@@ -22,14 +24,42 @@ impl std::fmt::Display for SourceFileId {
             SourceFileId::Processor => write!(f, "src/processor.rs"),
             SourceFileId::State => write!(f, "src/state.rs"),
             SourceFileId::Oracle => write!(f, "src/oracle.rs"),
+            SourceFileId::Utils => write!(f, "src/utils.rs"),
+            SourceFileId::Accounts => write!(f, "src/accounts.rs"),
         }
     }
 }
 
+/// Where a Mango error came from, so logs tell Mango side rejections from Quasar's own checks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MangoCpi {
+    /// Loading a Mango account in Quasar, before any CPI
+    Load,
+    InitMangoAccount,
+    Deposit,
+    Withdraw,
+    PlacePerpOrder,
+    CancelAllPerpOrders,
+    PlaceSpotOrder,
+    SettleFunds,
+    InitSpotOpenOrders,
+}
+
+impl std::fmt::Display for MangoCpi {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(self, f)
+    }
+}
+
 #[derive(Error, Debug, PartialEq, Eq)]
 pub enum QuasarError {
     #[error(transparent)]
     ProgramError(#[from] ProgramError),
+    #[error("{mango_error}; mango {mango_cpi}")]
+    MangoError {
+        mango_error: mango::error::MangoError,
+        mango_cpi: MangoCpi,
+    },
     #[error("{quasar_error_code}; {source_file_id}:{line}")]
     QuasarErrorCode {
         quasar_error_code: QuasarErrorCode,
@@ -71,6 +101,58 @@ pub enum QuasarErrorCode {
     InvalidAccount,
     #[error("QuasarErrorCode::SignerNecessary")]
     SignerNecessary,
+    #[error("QuasarErrorCode::MathError")]
+    MathError,
+    #[error("QuasarErrorCode::StaleOracle")]
+    StaleOracle,
+    #[error("QuasarErrorCode::NavBelowMinimum")]
+    NavBelowMinimum,
+    #[error("QuasarErrorCode::NavMoveTooLarge")]
+    NavMoveTooLarge,
+    #[error("QuasarErrorCode::UnreliableOracle")]
+    UnreliableOracle,
+    #[error("QuasarErrorCode::AccountNotWritable")]
+    AccountNotWritable,
+    #[error("QuasarErrorCode::TokenFrozen")]
+    TokenFrozen,
+    #[error("QuasarErrorCode::TokenNotActive")]
+    TokenNotActive,
+    #[error("QuasarErrorCode::SupplyMismatch")]
+    SupplyMismatch,
+    #[error("QuasarErrorCode::RebalanceCooldown")]
+    RebalanceCooldown,
+    #[error("QuasarErrorCode::DepositNotCredited")]
+    DepositNotCredited,
+    #[error("QuasarErrorCode::PositionNotFlat")]
+    PositionNotFlat,
+    #[error("QuasarErrorCode::GroupCapExceeded")]
+    GroupCapExceeded,
+    #[error("QuasarErrorCode::PriceOutOfBand")]
+    PriceOutOfBand,
+    #[error("QuasarErrorCode::PriceRoundsToZero")]
+    PriceRoundsToZero,
+    #[error("QuasarErrorCode::AlreadyInitialized")]
+    AlreadyInitialized,
+    #[error("QuasarErrorCode::DuplicateToken")]
+    DuplicateToken,
+    #[error("QuasarErrorCode::SlotOccupied")]
+    SlotOccupied,
+    #[error("QuasarErrorCode::SameSlotBurn")]
+    SameSlotBurn,
+    #[error("QuasarErrorCode::BaseTokenInUse")]
+    BaseTokenInUse,
+    #[error("QuasarErrorCode::NonPositiveNav")]
+    NonPositiveNav,
+    #[error("QuasarErrorCode::RebalanceLimitReached")]
+    RebalanceLimitReached,
+    #[error("QuasarErrorCode::MangoProgramInUse")]
+    MangoProgramInUse,
+    #[error("QuasarErrorCode::MangoAccountNotEmpty")]
+    MangoAccountNotEmpty,
+    #[error("QuasarErrorCode::UnsupportedVersion")]
+    UnsupportedVersion,
+    #[error("QuasarErrorCode::FeesNotWithdrawn")]
+    FeesNotWithdrawn,
 
     #[error("QuasarErrorCode::Default Check the source code for more info")]
     Default = u32::MAX_VALUE,
@@ -85,14 +167,24 @@ impl From<QuasarError> for ProgramError {
                 line: _,
                 source_file_id: _,
             } => ProgramError::Custom(quasar_error_code.into()),
+            // Keep Mango's own error code so clients can decode it with Mango's error table
+            QuasarError::MangoError { mango_error, .. } => mango_error.into(),
+        }
+    }
+}
+
+impl QuasarError {
+    pub fn mango_cpi(mango_cpi: MangoCpi, e: impl Into<mango::error::MangoError>) -> Self {
+        QuasarError::MangoError {
+            mango_error: e.into(),
+            mango_cpi,
         }
     }
 }
 
 impl From<mango::error::MangoError> for QuasarError {
     fn from(me: mango::error::MangoError) -> Self {
-        let pe: ProgramError = me.into();
-        pe.into()
+        QuasarError::mango_cpi(MangoCpi::Load, me)
     }
 }
 
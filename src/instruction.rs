@@ -1,79 +1,618 @@
 use arrayref::{array_ref, array_refs};
 use fixed::types::I80F48;
-use solana_program::program_error::ProgramError;
-use std::convert::TryInto;
+use mango::state::MAX_PAIRS;
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    system_program, sysvar,
+};
+use spl_associated_token_account::get_associated_token_address;
+use std::convert::{TryFrom, TryInto};
 
+use crate::{
+    oracle::OracleType,
+    state::{RebalancePriceSource, TokenState},
+};
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum QuasarInstruction {
     /// Initialize a quasar group account
     ///
-    /// Accounts expected by this instruction (12):
+    /// The admin has to sign unless it is an account owned by the SPL governance program
+    /// (`utils::governance_program`), passed as the optional last account
+    ///
+    /// `signer_nonce` must derive `signer_ai` as a PDA of the group, so clients try nonces from
+    /// zero up until one lands off the curve (see `utils::simulate_init`). Any other nonce
+    /// fails with `InvalidSignerKey`
+    ///
+    /// Accounts expected by this instruction (4 + 1 optional):
     ///
     /// 0. `[writable]` quasar_group_ai
-    /// 1. `[signer]` signer_ai
-    /// 2. `[]` admin_ai
-    /// 3. `[]` mango_program_ai    
+    /// 1. `[]` signer_ai
+    /// 2. `[signer]` admin_ai
+    /// 3. `[]` mango_program_ai
+    /// 4. `[]` governance_program_ai (optional)
     InitQuasarGroup { signer_nonce: u64 },
 
     /// Add a base token which leveraged tokens are going to use as the underlying
+    /// The Mango group must list a perp market for it, and the mint must belong to the classic
+    /// token program: Mango v3 vaults can't hold Token-2022 mints
     ///
-    /// Accounts expected by this instruction (8):
+    /// Accounts expected by this instruction (5):
     ///
     /// 0. `[writable]` quasar_group_ai
     /// 1. `[]` mint_ai
     /// 2. `[]` oracle_ai
     /// 3. `[signer]` admin_ai
+    /// 4. `[]` mango_group_ai
     AddBaseToken,
 
     /// Add a leveraged token
     ///
-    /// Accounts expected by this instruction (8):
+    /// A nonzero `seed_quantity` mints that many tokens to the admin's associated token
+    /// account at the initial price, paid from its quote account, so the token never has an
+    /// empty supply whose price a donation could inflate
+    ///
+    /// A token whose Mango group is quoted in another mint than the quasar group's also
+    /// takes the fee vault of that quote, created here unless an earlier token created it
+    ///
+    /// Accounts expected by this instruction (12 + 7 when seeding + 3 for another quote):
     ///
     /// 0. `[writable]` quasar_group_ai
-    /// 1. `[]` mint_ai
+    /// 1. `[writable, signer]` mint_ai, created by this instruction
     /// 2. `[]` base_token_mint_ai
-    /// 3. `[]` mango_account_ai
-    /// 4. `[]` mango_perp_market_ai
-    /// 5. `[signer]` admin_ai
-    AddLeverageToken { target_leverage: I80F48 },
+    /// 3. `[]` mango_program_ai
+    /// 4. `[]` mango_group_ai
+    /// 5. `[writable]` mango_account_ai
+    /// 6. `[]` mango_perp_market_ai
+    /// 7. `[]` system_program_ai
+    /// 8. `[]` token_program_ai
+    /// 9. `[]` rent_ai
+    /// 10. `[writable, signer]` admin_ai
+    /// 11. `[]` pda_ai
+    /// 12. `[]` mango_cache_ai
+    /// 13. `[]` quote_root_bank_ai
+    /// 14. `[writable]` quote_node_bank_ai
+    /// 15. `[writable]` quote_vault_ai
+    /// 16. `[writable]` admin_quote_token_account_ai
+    /// 17. `[writable]` admin_leverage_token_account_ai, created by this instruction
+    /// 18. `[]` associated_token_program_ai
+    ///
+    /// 0. `[writable]` quote_fee_vault_ai, the group signer's associated token account
+    /// 1. `[]` quote_mint_ai
+    /// 2. `[]` associated_token_program_ai
+    AddLeverageToken {
+        /// Target leverage in bps, see `utils::leverage_to_bps`
+        target_leverage_bps: i32,
+        seed_quantity: u64,
+    },
 
-    /// mint a leveraged token
+    /// mint a leveraged token, publishing the mint price and its UI price as return data
     ///
-    /// Accounts expected by this instruction (8):
+    /// Accounts expected by this instruction (15 + token ledger + quote oracle + spot accounts
+    /// + 1 optional):
     ///
-    /// 0. `[writable]` quasar_group_ai
-    /// 2. `[]` leverage_token_ai
-    /// 3. `[]` mango_account_ai
-    /// 4. `[]` mint_ai
-    /// 4. `[]` base_token_mint_ai
-    /// 4. `[]` oracle_ai
-    /// 8. `[signer]` admin_ai
+    /// 0. `[]` quasar_group_ai, writable while the group caps its net deposits
+    /// 1. `[writable]` token_mint_ai
+    /// 2. `[writable]` owner_leverage_token_account_ai
+    /// 3. `[]` mango_program_ai
+    /// 4. `[]` mango_group_ai
+    /// 5. `[writable]` mango_account_ai
+    /// 6. `[signer]` owner_ai
+    /// 7. `[]` mango_cache_ai
+    /// 8. `[]` root_bank_ai
+    /// 9. `[writable]` node_bank_ai
+    /// 10. `[writable]` vault_ai
+    /// 11. `[]` token_program_ai
+    /// 12. `[writable]` owner_quote_token_account_ai, owned by owner_ai
+    /// 13. `[]` pda_ai
+    /// 14. `[writable]` fee_vault_ai
+    /// 15. `[writable]` token_ledger_ai, only for tokens with a ledger, which every token
+    ///     needs while the group caps its net deposits
+    /// 16. `[]` quote_oracle_ai, only for tokens quoted in the mint the group's quote oracle
+    ///     prices
+    /// 17.. spot accounts of the swap into base, only for tokens with a `spot_swap_bps`
+    /// last. `[]` associated_token_program_ai, optional
     MintLeverageToken { quantity: u64 },
 
-    /// redeem a leveraged token
+    /// redeem a leveraged token, publishing the burn price and its UI price as return data
     ///
-    /// Accounts expected by this instruction (8):
+    /// Accounts expected by this instruction (16 + token ledger + open orders + 1 optional):
     ///
-    /// 0. `[writable]` quasar_group_ai
-    /// 2. `[]` leverage_token_ai
+    /// 0. `[]` quasar_group_ai, writable while the group caps its net deposits
+    /// 1. `[writable]` token_mint_ai
+    /// 2. `[writable]` owner_leverage_token_account_ai
     /// 3. `[]` mango_program_ai
-    /// 4. `[]` mint_ai
-    /// 4. `[]` base_token_mint_ai
-    /// 4. `[]` oracle_ai
-    /// 8. `[signer]` admin_ai
+    /// 4. `[]` mango_group_ai
+    /// 5. `[writable]` mango_account_ai
+    /// 6. `[signer]` owner_ai
+    /// 7. `[]` mango_cache_ai
+    /// 8. `[]` root_bank_ai
+    /// 9. `[writable]` node_bank_ai
+    /// 10. `[writable]` vault_ai
+    /// 11. `[]` token_program_ai
+    /// 12. `[writable]` owner_quote_token_account_ai
+    /// 13. `[]` pda_ai
+    /// 14. `[]` mango_signer_ai
+    /// 15. `[writable]` fee_vault_ai
+    /// 16. `[writable]` token_ledger_ai, only for tokens with a ledger
+    /// 17.. `[]` open orders of the markets in the Mango account's margin basket
+    /// then `[]` instructions_sysvar_ai, required when the token has `same_slot_burn_guard`
     BurnLeverageToken { quantity: u64 },
 
-    /// rebalance a leveraged token
+    /// rebalance a leveraged token, publishing a packed `utils::RebalanceResult` as return data
     ///
-    /// Accounts expected by this instruction (8):
+    /// Accounts expected by this instruction (12 + MAX_PAIRS + optional accounts):
     ///
     /// 0. `[writable]` quasar_group_ai
-    /// 2. `[]` leverage_token_ai
+    /// 1. `[]` token_mint_ai
+    /// 2. `[]` pda_ai
     /// 3. `[]` mango_program_ai
-    /// 4. `[]` mint_ai
-    /// 4. `[]` base_token_mint_ai
-    /// 4. `[]` oracle_ai
-    /// 8. `[signer]` admin_ai
+    /// 4. `[]` mango_group_ai
+    /// 5. `[writable]` mango_account_ai
+    /// 6. `[signer]` caller_ai, only signing when a rebalance authority is set
+    /// 7. `[]` mango_cache_ai
+    /// 8. `[writable]` mango_perp_market_ai
+    /// 9. `[writable]` mango_bids_ai
+    /// 10. `[writable]` mango_asks_ai
+    /// 11. `[writable]` mango_event_queue_ai
+    /// 12..12 + MAX_PAIRS. `[]` spot open orders of the Mango account
+    /// then `[]` rebalance_config_ai, only when the token follows a rebalance config
+    /// then `[]` base_oracle_ai, only when the token prices rebalances off its base oracle
+    /// then, required when the rebalance sells spot collateral, `[]` quote_root_bank_ai,
+    /// `[writable]` quote_node_bank_ai, `[writable]` quote_vault_ai, `[]` token_program_ai
+    /// and the spot accounts
     Rebalance,
+
+    /// Restrict rebalance to a single caller, or make it permissionless again
+    /// by passing the default pubkey
+    ///
+    /// Accounts expected by this instruction (3):
+    ///
+    /// 0. `[writable]` quasar_group_ai
+    /// 1. `[]` rebalance_authority_ai
+    /// 2. `[signer]` admin_ai
+    SetRebalanceAuthority,
+
+    /// Quote the amount of native quote token a burn of `quantity` would return,
+    /// published as a little endian u64 return data. Fails wherever the burn would
+    ///
+    /// Accounts expected by this instruction (6):
+    ///
+    /// 0. `[]` quasar_group_ai
+    /// 1. `[]` token_mint_ai
+    /// 2. `[]` mango_program_ai
+    /// 3. `[]` mango_group_ai
+    /// 4. `[]` mango_account_ai
+    /// 5. `[]` mango_cache_ai
+    QuoteRedeem { quantity: u64 },
+
+    /// Set the fee vault collecting protocol fees and the treasury they are withdrawn to
+    ///
+    /// Accounts expected by this instruction (4):
+    ///
+    /// 0. `[writable]` quasar_group_ai
+    /// 1. `[]` fee_vault_ai
+    /// 2. `[]` treasury_ai
+    /// 3. `[signer]` admin_ai
+    SetTreasury,
+
+    /// Withdraw accrued fees from the fee vault to the treasury. Fees of tokens quoted in
+    /// another mint than the fee vault's are collected in the group signer's associated token
+    /// account for that mint and withdrawn to the treasury owner's
+    ///
+    /// Accounts expected by this instruction (6):
+    ///
+    /// 0. `[writable]` quasar_group_ai
+    /// 1. `[writable]` fee_vault_ai
+    /// 2. `[writable]` treasury_ai
+    /// 3. `[]` pda_ai
+    /// 4. `[]` token_program_ai
+    /// 5. `[signer]` admin_ai
+    WithdrawFees { quantity: u64 },
+
+    /// Change the parameters of a leveraged token. Parameters left as `None` are kept
+    ///
+    /// A non zero `spot_swap_bps` needs the spot open orders from `InitSpotOpenOrders`.
+    /// Fees set to `USE_GROUP_FEE` fall back to the group's fees. A zero
+    /// `max_effective_leverage` disables the leverage cap. `allow_borrow` lets burns borrow
+    /// quote on Mango, up to `max_borrow_bps` of the NAV. Mints must be multiples of a non
+    /// zero `min_mint_increment`. `same_slot_burn_guard` rejects burns sharing a transaction
+    /// with a mint of the token. A non zero `max_redemption_per_tx` caps the quantity of a burn.
+    /// A non zero `volatility_target` scales the target leverage of rebalances down while
+    /// the token's volatility EMA exceeds it. `price_offset_bps` moves the limit price of
+    /// rebalance orders away from the market, up to `utils::MAX_REBALANCE_PRICE_OFFSET_BPS`,
+    /// except deleverages past `max_effective_leverage`. A non zero
+    /// `max_rebalances_per_epoch` caps the rebalances of an epoch, except those deleverages
+    ///
+    /// Accounts expected by this instruction (3):
+    ///
+    /// 0. `[writable]` quasar_group_ai
+    /// 1. `[]` token_mint_ai
+    /// 2. `[signer]` admin_ai
+    SetLeverageTokenParams {
+        min_native_price: Option<I80F48>,
+        spot_swap_bps: Option<u16>,
+        mint_fee_bps: Option<u16>,
+        burn_fee_bps: Option<u16>,
+        max_effective_leverage: Option<I80F48>,
+        rebalance_cooldown_slots: Option<u64>,
+        allow_borrow: Option<bool>,
+        max_borrow_bps: Option<u16>,
+        min_mint_increment: Option<u32>,
+        same_slot_burn_guard: Option<bool>,
+        max_redemption_per_tx: Option<u64>,
+        volatility_target: Option<I80F48>,
+        price_offset_bps: Option<u16>,
+        max_rebalances_per_epoch: Option<u16>,
+    },
+
+    /// Remove a leveraged token once it is fully redeemed and its Mango account is empty, so
+    /// a token relisted on the Mango account starts from nothing
+    /// Mango v3 can't close Mango accounts, so the Mango account's rent stays locked
+    ///
+    /// The last token quoted in a mint other than the group's needs the fees of that quote
+    /// withdrawn first
+    ///
+    /// Accounts expected by this instruction (6 + 1 for the last token of another quote):
+    ///
+    /// 0. `[writable]` quasar_group_ai
+    /// 1. `[]` token_mint_ai
+    /// 2. `[]` mango_program_ai
+    /// 3. `[]` mango_group_ai
+    /// 4. `[]` mango_account_ai
+    /// 5. `[signer]` admin_ai
+    /// 6. `[]` quote_fee_vault_ai, the group signer's associated token account of the quote
+    RemoveLeverageToken,
+
+    /// Close a quasar group without leveraged tokens and with its fees withdrawn, returning
+    /// its rent to the recipient
+    /// Only the group's own rent comes back, SPL mints of removed tokens can't be closed
+    ///
+    /// Accounts expected by this instruction (4):
+    ///
+    /// 0. `[writable]` quasar_group_ai
+    /// 1. `[writable]` recipient_ai
+    /// 2. `[signer]` admin_ai
+    /// 3. `[]` fee_vault_ai, any account while the group has no fee vault
+    CloseQuasarGroup,
+
+    /// Initialize the Mango spot open orders of a leveraged token on the spot market of
+    /// its base token, so that mints can swap part of their deposit into base
+    ///
+    /// The open orders account has to be created beforehand, owned by the dex program
+    ///
+    /// Accounts expected by this instruction (12):
+    ///
+    /// 0. `[]` quasar_group_ai
+    /// 1. `[]` token_mint_ai
+    /// 2. `[]` mango_program_ai
+    /// 3. `[]` mango_group_ai
+    /// 4. `[writable]` mango_account_ai
+    /// 5. `[]` pda_ai
+    /// 6. `[]` dex_program_ai
+    /// 7. `[writable]` spot_open_orders_ai
+    /// 8. `[]` spot_market_ai
+    /// 9. `[]` mango_signer_ai
+    /// 10. `[]` rent_ai
+    /// 11. `[signer]` admin_ai
+    InitSpotOpenOrders,
+
+    /// Set the mint and burn fees of leveraged tokens without their own override, in bps
+    ///
+    /// Accounts expected by this instruction (2):
+    ///
+    /// 0. `[writable]` quasar_group_ai
+    /// 1. `[signer]` admin_ai
+    SetFees {
+        mint_fee_bps: u16,
+        burn_fee_bps: u16,
+    },
+
+    /// Set the largest move of the native price a single mint or burn may cause, in bps.
+    /// Zero disables the check
+    ///
+    /// Accounts expected by this instruction (2):
+    ///
+    /// 0. `[writable]` quasar_group_ai
+    /// 1. `[signer]` admin_ai
+    SetMaxNavMove { max_nav_move_bps: u16 },
+
+    /// Cancel the perp orders a rebalance left resting on the token's perp market
+    ///
+    /// The caller has to be the rebalance authority, or the admin while rebalance is
+    /// permissionless
+    ///
+    /// Accounts expected by this instruction (10):
+    ///
+    /// 0. `[writable]` quasar_group_ai
+    /// 1. `[]` token_mint_ai
+    /// 2. `[]` mango_program_ai
+    /// 3. `[]` mango_group_ai
+    /// 4. `[writable]` mango_account_ai
+    /// 5. `[]` pda_ai
+    /// 6. `[writable]` mango_perp_market_ai
+    /// 7. `[writable]` mango_bids_ai
+    /// 8. `[writable]` mango_asks_ai
+    /// 9. `[signer]` caller_ai
+    CancelRebalanceOrders,
+
+    /// Estimate the Mango taker fee of the perp order a rebalance would place now, published
+    /// in native quote unit as a little endian u64 return data
+    ///
+    /// Accounts expected by this instruction (6 to 8):
+    ///
+    /// 0. `[]` quasar_group_ai
+    /// 1. `[]` token_mint_ai
+    /// 2. `[]` mango_program_ai
+    /// 3. `[]` mango_group_ai
+    /// 4. `[]` mango_account_ai
+    /// 5. `[]` mango_cache_ai
+    /// 6. `[]` rebalance_config_ai, only when the token follows a rebalance config
+    /// then `[]` base_oracle_ai, only when the token prices rebalances off its base oracle
+    EstimateRebalanceCost,
+
+    /// Make a leveraged token take its rebalance target from a new rebalance config,
+    /// starting from its current target leverage
+    ///
+    /// The config account has to be created beforehand, owned by this program
+    ///
+    /// Accounts expected by this instruction (5):
+    ///
+    /// 0. `[writable]` quasar_group_ai
+    /// 1. `[]` token_mint_ai
+    /// 2. `[writable]` rebalance_config_ai
+    /// 3. `[]` update_authority_ai
+    /// 4. `[signer]` admin_ai
+    InitRebalanceConfig { max_target_leverage: I80F48 },
+
+    /// Update the target leverage of a rebalance config
+    ///
+    /// Accounts expected by this instruction (2):
+    ///
+    /// 0. `[writable]` rebalance_config_ai
+    /// 1. `[signer]` update_authority_ai
+    UpdateRebalanceConfig { target_leverage: I80F48 },
+
+    /// Set the size premium of mints and discount of burns, in bps of the native price for
+    /// one the size of the whole supply. Zero disables it
+    ///
+    /// Accounts expected by this instruction (2):
+    ///
+    /// 0. `[writable]` quasar_group_ai
+    /// 1. `[signer]` admin_ai
+    SetSizePremium { size_premium_bps: u16 },
+
+    /// Freeze or reactivate a leveraged token. Freezing also cancels the perp orders a
+    /// rebalance left resting, so the token stops trading at once
+    ///
+    /// Accounts expected by this instruction (10):
+    ///
+    /// 0. `[writable]` quasar_group_ai
+    /// 1. `[]` token_mint_ai
+    /// 2. `[]` mango_program_ai
+    /// 3. `[]` mango_group_ai
+    /// 4. `[writable]` mango_account_ai
+    /// 5. `[]` pda_ai
+    /// 6. `[writable]` mango_perp_market_ai
+    /// 7. `[writable]` mango_bids_ai
+    /// 8. `[writable]` mango_asks_ai
+    /// 9. `[signer]` admin_ai
+    SetTokenState { state: TokenState },
+
+    /// Set the oracle pricing the quote token in the reference unit. Passing the system
+    /// program (`Pubkey::default()`) pegs the quote at exactly one reference unit again
+    /// Mints and burns still settle in quote at the NAV, the reference unit only prices the
+    /// first mint, `QuoteReferencePrice` and the net deposit cap
+    ///
+    /// Accounts expected by this instruction (3):
+    ///
+    /// 0. `[writable]` quasar_group_ai
+    /// 1. `[]` quote_oracle_ai
+    /// 2. `[signer]` admin_ai
+    SetQuoteOracle,
+
+    /// Quote the native price of a leveraged token in the reference unit, published as a
+    /// little endian I80F48 return data followed by the same price per whole token
+    ///
+    /// Accounts expected by this instruction (7):
+    ///
+    /// 0. `[]` quasar_group_ai
+    /// 1. `[]` token_mint_ai
+    /// 2. `[]` mango_program_ai
+    /// 3. `[]` mango_group_ai
+    /// 4. `[]` mango_account_ai
+    /// 5. `[]` mango_cache_ai
+    /// 6. `[]` quote_oracle_ai, any account while the quote is pegged
+    QuoteReferencePrice,
+
+    /// Set the number of slots a newly added leveraged token waits before its first mint.
+    /// Tokens already listed keep their activation slot
+    ///
+    /// Accounts expected by this instruction (2):
+    ///
+    /// 0. `[writable]` quasar_group_ai
+    /// 1. `[signer]` admin_ai
+    SetListingGracePeriod { listing_grace_slots: u64 },
+
+    /// Compare the supply of a leveraged token's mint against the one expected from the
+    /// mints and burns its token ledger counted, failing with `SupplyMismatch` when they
+    /// differ
+    ///
+    /// Accounts expected by this instruction (3):
+    ///
+    /// 0. `[]` quasar_group_ai
+    /// 1. `[]` token_mint_ai
+    /// 2. `[]` token_ledger_ai
+    Reconcile,
+
+    /// Point a leveraged token at another perp market of its base token, e.g. after Mango
+    /// migrated the market to a new account. The token's Mango account has to be flat
+    ///
+    /// Accounts expected by this instruction (7):
+    ///
+    /// 0. `[writable]` quasar_group_ai
+    /// 1. `[]` token_mint_ai
+    /// 2. `[]` mango_program_ai
+    /// 3. `[]` mango_group_ai
+    /// 4. `[]` mango_account_ai
+    /// 5. `[]` mango_perp_market_ai
+    /// 6. `[signer]` admin_ai
+    SetMangoPerpMarket,
+
+    /// Break the NAV of a leveraged token down into its exposure and the exposure its
+    /// resting perp orders would add, published as the little endian I80F48 return data
+    /// `net_asset_value`, `exposure` and `pending_exposure`, all in native quote unit
+    ///
+    /// Accounts expected by this instruction (6):
+    ///
+    /// 0. `[]` quasar_group_ai
+    /// 1. `[]` token_mint_ai
+    /// 2. `[]` mango_program_ai
+    /// 3. `[]` mango_group_ai
+    /// 4. `[]` mango_account_ai
+    /// 5. `[]` mango_cache_ai
+    QuoteNavBreakdown,
+
+    /// Choose whether a leveraged token's rebalances price off the Mango cache or the
+    /// oracle of its base token
+    ///
+    /// Accounts expected by this instruction (3):
+    ///
+    /// 0. `[writable]` quasar_group_ai
+    /// 1. `[]` token_mint_ai
+    /// 2. `[signer]` admin_ai
+    SetRebalancePriceSource { source: RebalancePriceSource },
+
+    /// Cap the quote deposited for the outstanding supply of the group's leveraged tokens,
+    /// for a guarded launch. Deposits count at their reference value when minted, in native
+    /// unit of `NET_DEPOSITS_DECIMALS`, so the cap bounds what was paid in rather than the
+    /// NAV it grew or shrank to. Zero lifts the cap
+    ///
+    /// Net deposits are only summed up while a cap is set, so setting one on an uncapped
+    /// group counts them from the token ledgers, which every mint then needs
+    ///
+    /// Accounts expected by this instruction (2 + token ledgers):
+    ///
+    /// 0. `[writable]` quasar_group_ai
+    /// 1. `[signer]` admin_ai
+    /// 2.. `[]` token ledgers of every leveraged token with one, in token order, when
+    ///     setting a cap on an uncapped group
+    SetMaxNetDeposits { max_net_deposits: u64 },
+
+    /// Add up to `utils::MAX_BATCH_SIZE` base tokens at once, each checked as by `AddBaseToken`
+    ///
+    /// Accounts expected by this instruction (3 + 2 * tokens):
+    ///
+    /// 0. `[writable]` quasar_group_ai
+    /// 1. `[signer]` admin_ai
+    /// 2. `[]` mango_group_ai
+    /// 3.. `[]` mint_ai and `[writable]` oracle_ai of every token
+    AddBaseTokens,
+
+    /// Quote the oracle price of every base token, published as return data of the mint,
+    /// the oracle and the little endian I80F48 price of each
+    ///
+    /// Accounts expected by this instruction (1 + base tokens):
+    ///
+    /// 0. `[]` quasar_group_ai
+    /// 1.. `[]` oracle_ai of every base token, in base token order
+    ListBaseTokenPrices,
+
+    /// Point the group at a new Mango program id, e.g. after Mango redeploys. Only allowed
+    /// while no leveraged token is listed, as their Mango accounts stay with the old program
+    ///
+    /// Accounts expected by this instruction (3):
+    ///
+    /// 0. `[writable]` quasar_group_ai
+    /// 1. `[]` mango_program_ai, the new program
+    /// 2. `[signer]` admin_ai
+    SetMangoProgram,
+
+    /// Set the keeper allowed to rebalance a token towards a target exposure it computed off
+    /// chain, or disable it by passing the default pubkey
+    ///
+    /// Accounts expected by this instruction (3):
+    ///
+    /// 0. `[writable]` rebalance_config_ai
+    /// 1. `[signer]` update_authority_ai
+    /// 2. `[]` keeper_ai
+    SetRebalanceKeeper,
+
+    /// rebalance a leveraged token following a rebalance config towards `target_exposure`, in
+    /// native quote, instead of recomputing it from the target leverage. Takes the accounts
+    /// of `Rebalance`, with the config's keeper as the signing caller_ai
+    RebalanceToExposure { target_exposure: I80F48 },
+
+    /// Quote how far the base price can move against a leveraged token before its Mango
+    /// account reaches maintenance margin, published as the little endian I80F48 fraction of
+    /// the current price as return data. `I80F48::MAX` means no price move liquidates it
+    ///
+    /// Accounts expected by this instruction (6):
+    ///
+    /// 0. `[]` quasar_group_ai
+    /// 1. `[]` token_mint_ai
+    /// 2. `[]` mango_program_ai
+    /// 3. `[]` mango_group_ai
+    /// 4. `[]` mango_account_ai
+    /// 5. `[]` mango_cache_ai
+    LiquidationDistance,
+
+    /// Override the oracle type recorded for a base token, e.g. to record it for tokens added
+    /// before it was stored. The oracle must read as that type
+    ///
+    /// Accounts expected by this instruction (4):
+    ///
+    /// 0. `[writable]` quasar_group_ai
+    /// 1. `[]` mint_ai of the base token
+    /// 2. `[signer]` admin_ai
+    /// 3. `[]` oracle_ai
+    SetBaseTokenOracleType { oracle_type: OracleType },
+
+    /// Remove a base token no leveraged token references anymore. The last base token moves
+    /// into its slot
+    ///
+    /// Accounts expected by this instruction (3):
+    ///
+    /// 0. `[writable]` quasar_group_ai
+    /// 1. `[]` mint_ai of the base token
+    /// 2. `[signer]` admin_ai
+    RemoveBaseToken,
+
+    /// Move a leveraged token off a rebalance config created before configs had a keeper,
+    /// copying it into a new config account without a keeper. The old account is closed
+    ///
+    /// The new config account has to be created beforehand, owned by this program
+    ///
+    /// Accounts expected by this instruction (5):
+    ///
+    /// 0. `[writable]` quasar_group_ai
+    /// 1. `[]` token_mint_ai
+    /// 2. `[writable]` legacy_config_ai
+    /// 3. `[writable]` rebalance_config_ai
+    /// 4. `[writable, signer]` admin_ai, receiving the lamports of the old account
+    MigrateRebalanceConfig,
+
+    /// Give a leveraged token a ledger counting its mints, burns and net deposits, which its
+    /// mints and burns then require. Supply already outstanding counts as minted, for net
+    /// deposits worth its NAV
+    ///
+    /// The ledger account has to be created beforehand, owned by this program
+    ///
+    /// Accounts expected by this instruction (8 + 1 optional):
+    ///
+    /// 0. `[writable]` quasar_group_ai
+    /// 1. `[]` token_mint_ai
+    /// 2. `[writable]` token_ledger_ai
+    /// 3. `[signer]` admin_ai
+    /// 4. `[]` mango_program_ai
+    /// 5. `[]` mango_group_ai
+    /// 6. `[]` mango_account_ai
+    /// 7. `[]` mango_cache_ai
+    /// 8. `[]` quote_oracle_ai, only for tokens with a supply quoted in the mint the group's
+    ///    quote oracle prices
+    InitTokenLedger,
 }
 
 impl QuasarInstruction {
@@ -91,9 +630,11 @@ impl QuasarInstruction {
             }
             1 => Self::AddBaseToken,
             2 => {
-                let target_leverage = array_ref![data, 0, 16];
+                let data = array_ref![data, 0, 12];
+                let (target_leverage_bps, seed_quantity) = array_refs![data, 4, 8];
                 QuasarInstruction::AddLeverageToken {
-                    target_leverage: I80F48::from_le_bytes(*target_leverage),
+                    target_leverage_bps: i32::from_le_bytes(*target_leverage_bps),
+                    seed_quantity: u64::from_le_bytes(*seed_quantity),
                 }
             }
             3 => {
@@ -111,10 +652,276 @@ impl QuasarInstruction {
                 }
             }
             5 => Self::Rebalance,
+            6 => Self::SetRebalanceAuthority,
+            7 => {
+                let quantity = array_ref![data, 0, 8];
+
+                QuasarInstruction::QuoteRedeem {
+                    quantity: u64::from_le_bytes(*quantity),
+                }
+            }
+            8 => Self::SetTreasury,
+            9 => {
+                let quantity = array_ref![data, 0, 8];
+
+                QuasarInstruction::WithdrawFees {
+                    quantity: u64::from_le_bytes(*quantity),
+                }
+            }
+            10 => {
+                let data = array_ref![data, 0, 96];
+                let (
+                    min_native_price,
+                    spot_swap_bps,
+                    mint_fee_bps,
+                    burn_fee_bps,
+                    max_effective_leverage,
+                    rebalance_cooldown_slots,
+                    allow_borrow,
+                    max_borrow_bps,
+                    min_mint_increment,
+                    same_slot_burn_guard,
+                    max_redemption_per_tx,
+                    volatility_target,
+                    price_offset_bps,
+                    max_rebalances_per_epoch,
+                ) = array_refs![data, 17, 3, 3, 3, 17, 9, 2, 3, 5, 2, 9, 17, 3, 3];
+
+                QuasarInstruction::SetLeverageTokenParams {
+                    min_native_price: Self::unpack_i80f48_opt(min_native_price),
+                    spot_swap_bps: Self::unpack_u16_opt(spot_swap_bps),
+                    mint_fee_bps: Self::unpack_u16_opt(mint_fee_bps),
+                    burn_fee_bps: Self::unpack_u16_opt(burn_fee_bps),
+                    max_effective_leverage: Self::unpack_i80f48_opt(max_effective_leverage),
+                    rebalance_cooldown_slots: Self::unpack_u64_opt(rebalance_cooldown_slots),
+                    allow_borrow: Self::unpack_bool_opt(allow_borrow),
+                    max_borrow_bps: Self::unpack_u16_opt(max_borrow_bps),
+                    min_mint_increment: Self::unpack_u32_opt(min_mint_increment),
+                    same_slot_burn_guard: Self::unpack_bool_opt(same_slot_burn_guard),
+                    max_redemption_per_tx: Self::unpack_u64_opt(max_redemption_per_tx),
+                    volatility_target: Self::unpack_i80f48_opt(volatility_target),
+                    price_offset_bps: Self::unpack_u16_opt(price_offset_bps),
+                    max_rebalances_per_epoch: Self::unpack_u16_opt(max_rebalances_per_epoch),
+                }
+            }
+            11 => Self::RemoveLeverageToken,
+            12 => Self::CloseQuasarGroup,
+            13 => Self::InitSpotOpenOrders,
+            14 => {
+                let data = array_ref![data, 0, 4];
+                let (mint_fee_bps, burn_fee_bps) = array_refs![data, 2, 2];
+
+                QuasarInstruction::SetFees {
+                    mint_fee_bps: u16::from_le_bytes(*mint_fee_bps),
+                    burn_fee_bps: u16::from_le_bytes(*burn_fee_bps),
+                }
+            }
+            15 => {
+                let max_nav_move_bps = array_ref![data, 0, 2];
+
+                QuasarInstruction::SetMaxNavMove {
+                    max_nav_move_bps: u16::from_le_bytes(*max_nav_move_bps),
+                }
+            }
+            16 => Self::CancelRebalanceOrders,
+            17 => Self::EstimateRebalanceCost,
+            18 => {
+                let max_target_leverage = array_ref![data, 0, 16];
+
+                QuasarInstruction::InitRebalanceConfig {
+                    max_target_leverage: I80F48::from_le_bytes(*max_target_leverage),
+                }
+            }
+            19 => {
+                let target_leverage = array_ref![data, 0, 16];
+
+                QuasarInstruction::UpdateRebalanceConfig {
+                    target_leverage: I80F48::from_le_bytes(*target_leverage),
+                }
+            }
+            20 => {
+                let size_premium_bps = array_ref![data, 0, 2];
+
+                QuasarInstruction::SetSizePremium {
+                    size_premium_bps: u16::from_le_bytes(*size_premium_bps),
+                }
+            }
+            21 => {
+                let state = array_ref![data, 0, 1];
+
+                QuasarInstruction::SetTokenState {
+                    state: TokenState::try_from(state[0]).ok()?,
+                }
+            }
+            22 => QuasarInstruction::SetQuoteOracle,
+            23 => QuasarInstruction::QuoteReferencePrice,
+            24 => {
+                let listing_grace_slots = array_ref![data, 0, 8];
+
+                QuasarInstruction::SetListingGracePeriod {
+                    listing_grace_slots: u64::from_le_bytes(*listing_grace_slots),
+                }
+            }
+            25 => Self::Reconcile,
+            26 => Self::SetMangoPerpMarket,
+            27 => Self::QuoteNavBreakdown,
+            28 => {
+                let source = array_ref![data, 0, 1];
+
+                Self::SetRebalancePriceSource {
+                    source: RebalancePriceSource::try_from(source[0]).ok()?,
+                }
+            }
+            29 => {
+                let max_net_deposits = array_ref![data, 0, 8];
+
+                Self::SetMaxNetDeposits {
+                    max_net_deposits: u64::from_le_bytes(*max_net_deposits),
+                }
+            }
+            30 => Self::AddBaseTokens,
+            31 => Self::ListBaseTokenPrices,
+            32 => Self::SetMangoProgram,
+            33 => Self::SetRebalanceKeeper,
+            34 => {
+                let target_exposure = array_ref![data, 0, 16];
+
+                Self::RebalanceToExposure {
+                    target_exposure: I80F48::from_le_bytes(*target_exposure),
+                }
+            }
+            35 => Self::LiquidationDistance,
+            36 => {
+                let oracle_type = array_ref![data, 0, 1];
+
+                Self::SetBaseTokenOracleType {
+                    oracle_type: OracleType::try_from(oracle_type[0]).ok()?,
+                }
+            }
+            37 => Self::RemoveBaseToken,
+            38 => Self::MigrateRebalanceConfig,
+            39 => Self::InitTokenLedger,
             _ => return None,
         })
     }
 
+    pub fn pack(&self) -> Vec<u8> {
+        let (discrim, data): (u32, Vec<u8>) = match self {
+            Self::InitQuasarGroup { signer_nonce } => (0, signer_nonce.to_le_bytes().to_vec()),
+            Self::AddBaseToken => (1, vec![]),
+            Self::AddLeverageToken {
+                target_leverage_bps,
+                seed_quantity,
+            } => {
+                let mut data = target_leverage_bps.to_le_bytes().to_vec();
+                data.extend_from_slice(&seed_quantity.to_le_bytes());
+                (2, data)
+            }
+            Self::MintLeverageToken { quantity } => (3, quantity.to_le_bytes().to_vec()),
+            Self::BurnLeverageToken { quantity } => (4, quantity.to_le_bytes().to_vec()),
+            Self::Rebalance => (5, vec![]),
+            Self::SetRebalanceAuthority => (6, vec![]),
+            Self::QuoteRedeem { quantity } => (7, quantity.to_le_bytes().to_vec()),
+            Self::SetTreasury => (8, vec![]),
+            Self::WithdrawFees { quantity } => (9, quantity.to_le_bytes().to_vec()),
+            Self::SetLeverageTokenParams {
+                min_native_price,
+                spot_swap_bps,
+                mint_fee_bps,
+                burn_fee_bps,
+                max_effective_leverage,
+                rebalance_cooldown_slots,
+                allow_borrow,
+                max_borrow_bps,
+                min_mint_increment,
+                same_slot_burn_guard,
+                max_redemption_per_tx,
+                volatility_target,
+                price_offset_bps,
+                max_rebalances_per_epoch,
+            } => {
+                let mut data = Self::pack_i80f48_opt(min_native_price).to_vec();
+                data.extend_from_slice(&Self::pack_u16_opt(spot_swap_bps));
+                data.extend_from_slice(&Self::pack_u16_opt(mint_fee_bps));
+                data.extend_from_slice(&Self::pack_u16_opt(burn_fee_bps));
+                data.extend_from_slice(&Self::pack_i80f48_opt(max_effective_leverage));
+                data.extend_from_slice(&Self::pack_u64_opt(rebalance_cooldown_slots));
+                data.extend_from_slice(&Self::pack_bool_opt(allow_borrow));
+                data.extend_from_slice(&Self::pack_u16_opt(max_borrow_bps));
+                data.extend_from_slice(&Self::pack_u32_opt(min_mint_increment));
+                data.extend_from_slice(&Self::pack_bool_opt(same_slot_burn_guard));
+                data.extend_from_slice(&Self::pack_u64_opt(max_redemption_per_tx));
+                data.extend_from_slice(&Self::pack_i80f48_opt(volatility_target));
+                data.extend_from_slice(&Self::pack_u16_opt(price_offset_bps));
+                data.extend_from_slice(&Self::pack_u16_opt(max_rebalances_per_epoch));
+                (10, data)
+            }
+            Self::RemoveLeverageToken => (11, vec![]),
+            Self::CloseQuasarGroup => (12, vec![]),
+            Self::InitSpotOpenOrders => (13, vec![]),
+            Self::SetFees {
+                mint_fee_bps,
+                burn_fee_bps,
+            } => {
+                let mut data = mint_fee_bps.to_le_bytes().to_vec();
+                data.extend_from_slice(&burn_fee_bps.to_le_bytes());
+                (14, data)
+            }
+            Self::SetMaxNavMove { max_nav_move_bps } => {
+                (15, max_nav_move_bps.to_le_bytes().to_vec())
+            }
+            Self::CancelRebalanceOrders => (16, vec![]),
+            Self::EstimateRebalanceCost => (17, vec![]),
+            Self::InitRebalanceConfig {
+                max_target_leverage,
+            } => (18, max_target_leverage.to_le_bytes().to_vec()),
+            Self::UpdateRebalanceConfig { target_leverage } => {
+                (19, target_leverage.to_le_bytes().to_vec())
+            }
+            Self::SetSizePremium { size_premium_bps } => {
+                (20, size_premium_bps.to_le_bytes().to_vec())
+            }
+            Self::SetTokenState { state } => (21, vec![*state as u8]),
+            Self::SetQuoteOracle => (22, vec![]),
+            Self::QuoteReferencePrice => (23, vec![]),
+            Self::SetListingGracePeriod {
+                listing_grace_slots,
+            } => (24, listing_grace_slots.to_le_bytes().to_vec()),
+            Self::Reconcile => (25, vec![]),
+            Self::SetMangoPerpMarket => (26, vec![]),
+            Self::QuoteNavBreakdown => (27, vec![]),
+            Self::SetRebalancePriceSource { source } => (28, vec![*source as u8]),
+            Self::SetMaxNetDeposits { max_net_deposits } => {
+                (29, max_net_deposits.to_le_bytes().to_vec())
+            }
+            Self::AddBaseTokens => (30, vec![]),
+            Self::ListBaseTokenPrices => (31, vec![]),
+            Self::SetMangoProgram => (32, vec![]),
+            Self::SetRebalanceKeeper => (33, vec![]),
+            Self::RebalanceToExposure { target_exposure } => {
+                (34, target_exposure.to_le_bytes().to_vec())
+            }
+            Self::LiquidationDistance => (35, vec![]),
+            Self::SetBaseTokenOracleType { oracle_type } => (36, vec![*oracle_type as u8]),
+            Self::RemoveBaseToken => (37, vec![]),
+            Self::MigrateRebalanceConfig => (38, vec![]),
+            Self::InitTokenLedger => (39, vec![]),
+        };
+
+        let mut buf = discrim.to_le_bytes().to_vec();
+        buf.extend(data);
+        buf
+    }
+
+    fn pack_i80f48_opt(value: &Option<I80F48>) -> [u8; 17] {
+        let mut data = [0u8; 17];
+        if let Some(value) = value {
+            data[0] = 1;
+            data[1..].copy_from_slice(&value.to_le_bytes());
+        }
+        data
+    }
+
     fn unpack_i80f48_opt(data: &[u8; 17]) -> Option<I80F48> {
         let (opt, val) = array_refs![data, 1, 16];
         if opt[0] == 0 {
@@ -123,6 +930,66 @@ impl QuasarInstruction {
             Some(I80F48::from_le_bytes(*val))
         }
     }
+    fn pack_u16_opt(value: &Option<u16>) -> [u8; 3] {
+        let mut data = [0u8; 3];
+        if let Some(value) = value {
+            data[0] = 1;
+            data[1..].copy_from_slice(&value.to_le_bytes());
+        }
+        data
+    }
+
+    fn unpack_u16_opt(data: &[u8; 3]) -> Option<u16> {
+        let (opt, val) = array_refs![data, 1, 2];
+        if opt[0] == 0 {
+            None
+        } else {
+            Some(u16::from_le_bytes(*val))
+        }
+    }
+
+    fn pack_bool_opt(value: &Option<bool>) -> [u8; 2] {
+        match value {
+            Some(value) => [1, *value as u8],
+            None => [0, 0],
+        }
+    }
+
+    fn unpack_bool_opt(data: &[u8; 2]) -> Option<bool> {
+        if data[0] == 0 {
+            None
+        } else {
+            Some(data[1] != 0)
+        }
+    }
+
+    fn pack_u32_opt(value: &Option<u32>) -> [u8; 5] {
+        let mut data = [0u8; 5];
+        if let Some(value) = value {
+            data[0] = 1;
+            data[1..].copy_from_slice(&value.to_le_bytes());
+        }
+        data
+    }
+
+    fn unpack_u32_opt(data: &[u8; 5]) -> Option<u32> {
+        let (opt, val) = array_refs![data, 1, 4];
+        if opt[0] == 0 {
+            None
+        } else {
+            Some(u32::from_le_bytes(*val))
+        }
+    }
+
+    fn pack_u64_opt(value: &Option<u64>) -> [u8; 9] {
+        let mut data = [0u8; 9];
+        if let Some(value) = value {
+            data[0] = 1;
+            data[1..].copy_from_slice(&value.to_le_bytes());
+        }
+        data
+    }
+
     fn unpack_u64_opt(data: &[u8; 9]) -> Option<u64> {
         let (opt, val) = array_refs![data, 1, 8];
         if opt[0] == 0 {
@@ -132,3 +999,1105 @@ impl QuasarInstruction {
         }
     }
 }
+
+/// Accounts of a trade on the Mango spot market of a leveraged token's base token,
+/// in the order `trade_mango_spot` expects them
+pub struct SpotTradeKeys {
+    pub mango_signer: Pubkey,
+    pub dex_program: Pubkey,
+    pub spot_market: Pubkey,
+    pub spot_bids: Pubkey,
+    pub spot_asks: Pubkey,
+    pub dex_request_queue: Pubkey,
+    pub dex_event_queue: Pubkey,
+    pub dex_base: Pubkey,
+    pub dex_quote: Pubkey,
+    pub base_root_bank: Pubkey,
+    pub base_node_bank: Pubkey,
+    pub base_vault: Pubkey,
+    pub dex_signer: Pubkey,
+    pub msrm_or_srm_vault: Pubkey,
+    pub spot_open_orders: Pubkey,
+}
+
+impl SpotTradeKeys {
+    fn to_account_metas(&self) -> Vec<AccountMeta> {
+        vec![
+            AccountMeta::new_readonly(self.mango_signer, false),
+            AccountMeta::new_readonly(self.dex_program, false),
+            AccountMeta::new(self.spot_market, false),
+            AccountMeta::new(self.spot_bids, false),
+            AccountMeta::new(self.spot_asks, false),
+            AccountMeta::new(self.dex_request_queue, false),
+            AccountMeta::new(self.dex_event_queue, false),
+            AccountMeta::new(self.dex_base, false),
+            AccountMeta::new(self.dex_quote, false),
+            AccountMeta::new_readonly(self.base_root_bank, false),
+            AccountMeta::new(self.base_node_bank, false),
+            AccountMeta::new(self.base_vault, false),
+            AccountMeta::new_readonly(self.dex_signer, false),
+            AccountMeta::new_readonly(self.msrm_or_srm_vault, false),
+            AccountMeta::new(self.spot_open_orders, false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+        ]
+    }
+}
+
+/// Quote bank accounts a rebalance needs on top of the spot accounts to sell spot collateral
+pub struct RebalanceSpotKeys {
+    pub quote_root_bank: Pubkey,
+    pub quote_node_bank: Pubkey,
+    pub quote_vault: Pubkey,
+    pub spot: SpotTradeKeys,
+}
+
+/// Accounts and quantity of the admin's first mint seeding a new leveraged token
+pub struct LeverageTokenSeedKeys {
+    pub quantity: u64,
+    pub mango_cache: Pubkey,
+    pub quote_root_bank: Pubkey,
+    pub quote_node_bank: Pubkey,
+    pub quote_vault: Pubkey,
+    pub admin_quote_token_account: Pubkey,
+}
+
+/// Accounts locating a leveraged token and its Mango account, shared by the builders of the
+/// instructions acting on one token
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TokenKeys {
+    pub quasar_group: Pubkey,
+    pub token_mint: Pubkey,
+    pub mango_program: Pubkey,
+    pub mango_group: Pubkey,
+    pub mango_account: Pubkey,
+}
+
+impl TokenKeys {
+    /// The accounts most token instructions start with, in this order
+    fn to_account_metas(
+        &self,
+        group_writable: bool,
+        mango_account_writable: bool,
+    ) -> Vec<AccountMeta> {
+        vec![
+            writable_meta(self.quasar_group, group_writable),
+            AccountMeta::new_readonly(self.token_mint, false),
+            AccountMeta::new_readonly(self.mango_program, false),
+            AccountMeta::new_readonly(self.mango_group, false),
+            writable_meta(self.mango_account, mango_account_writable),
+        ]
+    }
+}
+
+/// Perp market and order book of a leveraged token's orders
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PerpBookKeys {
+    pub perp_market: Pubkey,
+    pub bids: Pubkey,
+    pub asks: Pubkey,
+}
+
+impl PerpBookKeys {
+    fn to_account_metas(&self) -> Vec<AccountMeta> {
+        vec![
+            AccountMeta::new(self.perp_market, false),
+            AccountMeta::new(self.bids, false),
+            AccountMeta::new(self.asks, false),
+        ]
+    }
+}
+
+/// Accounts of `AddLeverageToken`. `quote_mint` is the quote of the Mango group when it
+/// isn't the quasar group's, whose fee vault the instruction then creates
+pub struct AddLeverageTokenKeys {
+    pub quasar_group: Pubkey,
+    pub mint: Pubkey,
+    pub base_token_mint: Pubkey,
+    pub mango_program: Pubkey,
+    pub mango_group: Pubkey,
+    pub mango_account: Pubkey,
+    pub mango_perp_market: Pubkey,
+    pub admin: Pubkey,
+    pub signer: Pubkey,
+    pub quote_mint: Option<Pubkey>,
+}
+
+/// Accounts of `MintLeverageToken` and `BurnLeverageToken`. `token_ledger` must be passed
+/// for tokens with a token ledger, and `group_capped` makes the quasar group writable, which
+/// it has to be while the group caps its net deposits
+pub struct MintBurnKeys {
+    pub token: TokenKeys,
+    pub owner: Pubkey,
+    pub mango_cache: Pubkey,
+    pub root_bank: Pubkey,
+    pub node_bank: Pubkey,
+    pub vault: Pubkey,
+    pub owner_quote_token_account: Pubkey,
+    pub signer: Pubkey,
+    pub fee_vault: Pubkey,
+    pub token_ledger: Option<Pubkey>,
+    pub group_capped: bool,
+}
+
+impl MintBurnKeys {
+    /// Accounts up to `pda_ai`, which mints and burns share
+    fn to_account_metas(&self) -> Vec<AccountMeta> {
+        let token = &self.token;
+        vec![
+            writable_meta(token.quasar_group, self.group_capped),
+            AccountMeta::new(token.token_mint, false),
+            AccountMeta::new(
+                get_associated_token_address(&self.owner, &token.token_mint),
+                false,
+            ),
+            AccountMeta::new_readonly(token.mango_program, false),
+            AccountMeta::new_readonly(token.mango_group, false),
+            AccountMeta::new(token.mango_account, false),
+            AccountMeta::new_readonly(self.owner, true),
+            AccountMeta::new_readonly(self.mango_cache, false),
+            AccountMeta::new_readonly(self.root_bank, false),
+            AccountMeta::new(self.node_bank, false),
+            AccountMeta::new(self.vault, false),
+            AccountMeta::new_readonly(spl_token::id(), false),
+            AccountMeta::new(self.owner_quote_token_account, false),
+            AccountMeta::new_readonly(self.signer, false),
+        ]
+    }
+}
+
+/// Accounts of `Rebalance` and `RebalanceToExposure`. The caller only signs when the group
+/// has a rebalance authority, or for `RebalanceToExposure`
+pub struct RebalanceKeys {
+    pub token: TokenKeys,
+    pub signer: Pubkey,
+    pub caller: Pubkey,
+    pub caller_is_signer: bool,
+    pub mango_cache: Pubkey,
+    pub book: PerpBookKeys,
+    pub event_queue: Pubkey,
+}
+
+/// Accounts of `InitSpotOpenOrders`
+pub struct InitSpotOpenOrdersKeys {
+    pub token: TokenKeys,
+    pub signer: Pubkey,
+    pub dex_program: Pubkey,
+    pub spot_open_orders: Pubkey,
+    pub spot_market: Pubkey,
+    pub mango_signer: Pubkey,
+    pub admin: Pubkey,
+}
+
+/// Parameters of `SetLeverageTokenParams`, `None` keeping the current value
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LeverageTokenParams {
+    pub min_native_price: Option<I80F48>,
+    pub spot_swap_bps: Option<u16>,
+    pub mint_fee_bps: Option<u16>,
+    pub burn_fee_bps: Option<u16>,
+    pub max_effective_leverage: Option<I80F48>,
+    pub rebalance_cooldown_slots: Option<u64>,
+    pub allow_borrow: Option<bool>,
+    pub max_borrow_bps: Option<u16>,
+    pub min_mint_increment: Option<u32>,
+    pub same_slot_burn_guard: Option<bool>,
+    pub max_redemption_per_tx: Option<u64>,
+    pub volatility_target: Option<I80F48>,
+    pub price_offset_bps: Option<u16>,
+    pub max_rebalances_per_epoch: Option<u16>,
+}
+
+pub fn init_quasar_group(
+    program_id: &Pubkey,
+    quasar_group_pk: &Pubkey,
+    signer_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    mango_program_pk: &Pubkey,
+    signer_nonce: u64,
+) -> Instruction {
+    let accounts = vec![
+        AccountMeta::new(*quasar_group_pk, false),
+        AccountMeta::new_readonly(*signer_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+        AccountMeta::new_readonly(*mango_program_pk, false),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::InitQuasarGroup { signer_nonce }.pack(),
+    }
+}
+
+pub fn add_base_token(
+    program_id: &Pubkey,
+    quasar_group_pk: &Pubkey,
+    mint_pk: &Pubkey,
+    oracle_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    mango_group_pk: &Pubkey,
+) -> Instruction {
+    let accounts = vec![
+        AccountMeta::new(*quasar_group_pk, false),
+        AccountMeta::new_readonly(*mint_pk, false),
+        AccountMeta::new(*oracle_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+        AccountMeta::new_readonly(*mango_group_pk, false),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::AddBaseToken.pack(),
+    }
+}
+
+pub fn add_leverage_token(
+    program_id: &Pubkey,
+    keys: &AddLeverageTokenKeys,
+    target_leverage_bps: i32,
+    seed: Option<&LeverageTokenSeedKeys>,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(keys.quasar_group, false),
+        AccountMeta::new(keys.mint, true),
+        AccountMeta::new_readonly(keys.base_token_mint, false),
+        AccountMeta::new_readonly(keys.mango_program, false),
+        AccountMeta::new_readonly(keys.mango_group, false),
+        AccountMeta::new(keys.mango_account, false),
+        AccountMeta::new_readonly(keys.mango_perp_market, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new(keys.admin, true),
+        AccountMeta::new_readonly(keys.signer, false),
+    ];
+    if let Some(seed) = seed {
+        accounts.extend_from_slice(&[
+            AccountMeta::new_readonly(seed.mango_cache, false),
+            AccountMeta::new_readonly(seed.quote_root_bank, false),
+            AccountMeta::new(seed.quote_node_bank, false),
+            AccountMeta::new(seed.quote_vault, false),
+            AccountMeta::new(seed.admin_quote_token_account, false),
+            AccountMeta::new(get_associated_token_address(&keys.admin, &keys.mint), false),
+            AccountMeta::new_readonly(spl_associated_token_account::id(), false),
+        ]);
+    }
+    if let Some(quote_mint) = keys.quote_mint {
+        accounts.extend_from_slice(&[
+            AccountMeta::new(
+                get_associated_token_address(&keys.signer, &quote_mint),
+                false,
+            ),
+            AccountMeta::new_readonly(quote_mint, false),
+            AccountMeta::new_readonly(spl_associated_token_account::id(), false),
+        ]);
+    }
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::AddLeverageToken {
+            target_leverage_bps,
+            seed_quantity: seed.map_or(0, |seed| seed.quantity),
+        }
+        .pack(),
+    }
+}
+
+/// `quote_oracle_pk` must be passed for tokens quoted in the mint the group's quote oracle
+/// prices. `spot` is only needed by tokens swapping part of the deposit into base, along
+/// with the open orders of the other markets in the Mango account's margin basket
+pub fn mint_leverage_token(
+    program_id: &Pubkey,
+    keys: &MintBurnKeys,
+    quote_oracle_pk: Option<&Pubkey>,
+    spot: Option<(&SpotTradeKeys, &[Pubkey])>,
+    quantity: u64,
+) -> Instruction {
+    let mut accounts = keys.to_account_metas();
+    accounts.push(AccountMeta::new(keys.fee_vault, false));
+    accounts.extend(keys.token_ledger.map(|pk| AccountMeta::new(pk, false)));
+    accounts.extend(quote_oracle_pk.map(|pk| AccountMeta::new_readonly(*pk, false)));
+    if let Some((spot, basket_open_orders_pks)) = spot {
+        accounts.extend(spot.to_account_metas());
+        accounts.extend(
+            basket_open_orders_pks
+                .iter()
+                .map(|pk| AccountMeta::new_readonly(*pk, false)),
+        );
+    }
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::MintLeverageToken { quantity }.pack(),
+    }
+}
+
+/// `open_orders_pks` are those of the markets in the Mango account's margin basket,
+/// in market index order
+pub fn burn_leverage_token(
+    program_id: &Pubkey,
+    keys: &MintBurnKeys,
+    mango_signer_pk: &Pubkey,
+    open_orders_pks: &[Pubkey],
+    quantity: u64,
+) -> Instruction {
+    let mut accounts = keys.to_account_metas();
+    accounts.push(AccountMeta::new_readonly(*mango_signer_pk, false));
+    accounts.push(AccountMeta::new(keys.fee_vault, false));
+    accounts.extend(keys.token_ledger.map(|pk| AccountMeta::new(pk, false)));
+    accounts.extend(
+        open_orders_pks
+            .iter()
+            .map(|pk| AccountMeta::new_readonly(*pk, false)),
+    );
+    accounts.push(AccountMeta::new_readonly(sysvar::instructions::id(), false));
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::BurnLeverageToken { quantity }.pack(),
+    }
+}
+
+/// `rebalance_config_pk` must be passed for tokens following a rebalance config, and
+/// `base_oracle_pk` for tokens pricing rebalances off their base oracle. A
+/// `target_exposure` makes it a `RebalanceToExposure` signed by the config's keeper
+pub fn rebalance(
+    program_id: &Pubkey,
+    keys: &RebalanceKeys,
+    open_orders_pks: &[Pubkey; MAX_PAIRS],
+    rebalance_config_pk: Option<&Pubkey>,
+    base_oracle_pk: Option<&Pubkey>,
+    spot: Option<&RebalanceSpotKeys>,
+    target_exposure: Option<I80F48>,
+) -> Instruction {
+    let token = &keys.token;
+    let mut accounts = vec![
+        AccountMeta::new(token.quasar_group, false),
+        AccountMeta::new_readonly(token.token_mint, false),
+        AccountMeta::new_readonly(keys.signer, false),
+        AccountMeta::new_readonly(token.mango_program, false),
+        AccountMeta::new_readonly(token.mango_group, false),
+        AccountMeta::new(token.mango_account, false),
+        AccountMeta::new_readonly(keys.caller, keys.caller_is_signer),
+        AccountMeta::new_readonly(keys.mango_cache, false),
+    ];
+    accounts.extend(keys.book.to_account_metas());
+    accounts.push(AccountMeta::new(keys.event_queue, false));
+    accounts.extend(
+        open_orders_pks
+            .iter()
+            .map(|pk| AccountMeta::new_readonly(*pk, false)),
+    );
+    if let Some(rebalance_config_pk) = rebalance_config_pk {
+        accounts.push(AccountMeta::new_readonly(*rebalance_config_pk, false));
+    }
+    if let Some(base_oracle_pk) = base_oracle_pk {
+        accounts.push(AccountMeta::new_readonly(*base_oracle_pk, false));
+    }
+    if let Some(spot) = spot {
+        accounts.push(AccountMeta::new_readonly(spot.quote_root_bank, false));
+        accounts.push(AccountMeta::new(spot.quote_node_bank, false));
+        accounts.push(AccountMeta::new(spot.quote_vault, false));
+        accounts.push(AccountMeta::new_readonly(spl_token::id(), false));
+        accounts.extend(spot.spot.to_account_metas());
+    }
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: match target_exposure {
+            Some(target_exposure) => {
+                QuasarInstruction::RebalanceToExposure { target_exposure }.pack()
+            }
+            None => QuasarInstruction::Rebalance.pack(),
+        },
+    }
+}
+
+/// Passing `Pubkey::default()` as the authority makes rebalance permissionless again
+pub fn set_rebalance_authority(
+    program_id: &Pubkey,
+    quasar_group_pk: &Pubkey,
+    rebalance_authority_pk: &Pubkey,
+    admin_pk: &Pubkey,
+) -> Instruction {
+    let accounts = vec![
+        AccountMeta::new(*quasar_group_pk, false),
+        AccountMeta::new_readonly(*rebalance_authority_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::SetRebalanceAuthority.pack(),
+    }
+}
+
+pub fn quote_redeem(
+    program_id: &Pubkey,
+    token: &TokenKeys,
+    mango_cache_pk: &Pubkey,
+    quantity: u64,
+) -> Instruction {
+    let mut accounts = token.to_account_metas(false, false);
+    accounts.push(AccountMeta::new_readonly(*mango_cache_pk, false));
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::QuoteRedeem { quantity }.pack(),
+    }
+}
+
+pub fn set_treasury(
+    program_id: &Pubkey,
+    quasar_group_pk: &Pubkey,
+    fee_vault_pk: &Pubkey,
+    treasury_pk: &Pubkey,
+    admin_pk: &Pubkey,
+) -> Instruction {
+    let accounts = vec![
+        AccountMeta::new(*quasar_group_pk, false),
+        AccountMeta::new_readonly(*fee_vault_pk, false),
+        AccountMeta::new_readonly(*treasury_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::SetTreasury.pack(),
+    }
+}
+
+/// Fees of another quote than the group's are withdrawn from `QuasarGroup::get_fee_vault` to
+/// `QuasarGroup::get_treasury` of that quote
+pub fn withdraw_fees(
+    program_id: &Pubkey,
+    quasar_group_pk: &Pubkey,
+    fee_vault_pk: &Pubkey,
+    treasury_pk: &Pubkey,
+    signer_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    quantity: u64,
+) -> Instruction {
+    let accounts = vec![
+        AccountMeta::new(*quasar_group_pk, false),
+        AccountMeta::new(*fee_vault_pk, false),
+        AccountMeta::new(*treasury_pk, false),
+        AccountMeta::new_readonly(*signer_pk, false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(*admin_pk, true),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::WithdrawFees { quantity }.pack(),
+    }
+}
+
+pub fn set_leverage_token_params(
+    program_id: &Pubkey,
+    quasar_group_pk: &Pubkey,
+    token_mint_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    params: &LeverageTokenParams,
+) -> Instruction {
+    let accounts = vec![
+        AccountMeta::new(*quasar_group_pk, false),
+        AccountMeta::new_readonly(*token_mint_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+    ];
+    let params = params.clone();
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::SetLeverageTokenParams {
+            min_native_price: params.min_native_price,
+            spot_swap_bps: params.spot_swap_bps,
+            mint_fee_bps: params.mint_fee_bps,
+            burn_fee_bps: params.burn_fee_bps,
+            max_effective_leverage: params.max_effective_leverage,
+            rebalance_cooldown_slots: params.rebalance_cooldown_slots,
+            allow_borrow: params.allow_borrow,
+            max_borrow_bps: params.max_borrow_bps,
+            min_mint_increment: params.min_mint_increment,
+            same_slot_burn_guard: params.same_slot_burn_guard,
+            max_redemption_per_tx: params.max_redemption_per_tx,
+            volatility_target: params.volatility_target,
+            price_offset_bps: params.price_offset_bps,
+            max_rebalances_per_epoch: params.max_rebalances_per_epoch,
+        }
+        .pack(),
+    }
+}
+
+/// `quote_fee_vault_pk` must be passed for the last token quoted in a mint other than the
+/// group's
+pub fn remove_leverage_token(
+    program_id: &Pubkey,
+    token: &TokenKeys,
+    admin_pk: &Pubkey,
+    quote_fee_vault_pk: Option<&Pubkey>,
+) -> Instruction {
+    let mut accounts = token.to_account_metas(true, false);
+    accounts.push(AccountMeta::new_readonly(*admin_pk, true));
+    accounts.extend(quote_fee_vault_pk.map(|pk| AccountMeta::new_readonly(*pk, false)));
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::RemoveLeverageToken.pack(),
+    }
+}
+
+pub fn close_quasar_group(
+    program_id: &Pubkey,
+    quasar_group_pk: &Pubkey,
+    recipient_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    fee_vault_pk: &Pubkey,
+) -> Instruction {
+    let accounts = vec![
+        AccountMeta::new(*quasar_group_pk, false),
+        AccountMeta::new(*recipient_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+        AccountMeta::new_readonly(*fee_vault_pk, false),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::CloseQuasarGroup.pack(),
+    }
+}
+
+pub fn init_spot_open_orders(program_id: &Pubkey, keys: &InitSpotOpenOrdersKeys) -> Instruction {
+    let mut accounts = keys.token.to_account_metas(false, true);
+    accounts.extend_from_slice(&[
+        AccountMeta::new_readonly(keys.signer, false),
+        AccountMeta::new_readonly(keys.dex_program, false),
+        AccountMeta::new(keys.spot_open_orders, false),
+        AccountMeta::new_readonly(keys.spot_market, false),
+        AccountMeta::new_readonly(keys.mango_signer, false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(keys.admin, true),
+    ]);
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::InitSpotOpenOrders.pack(),
+    }
+}
+
+pub fn set_fees(
+    program_id: &Pubkey,
+    quasar_group_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    mint_fee_bps: u16,
+    burn_fee_bps: u16,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: group_admin_metas(quasar_group_pk, admin_pk),
+        data: QuasarInstruction::SetFees {
+            mint_fee_bps,
+            burn_fee_bps,
+        }
+        .pack(),
+    }
+}
+
+pub fn set_max_nav_move(
+    program_id: &Pubkey,
+    quasar_group_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    max_nav_move_bps: u16,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: group_admin_metas(quasar_group_pk, admin_pk),
+        data: QuasarInstruction::SetMaxNavMove { max_nav_move_bps }.pack(),
+    }
+}
+
+pub fn cancel_rebalance_orders(
+    program_id: &Pubkey,
+    token: &TokenKeys,
+    signer_pk: &Pubkey,
+    book: &PerpBookKeys,
+    caller_pk: &Pubkey,
+) -> Instruction {
+    let mut accounts = token.to_account_metas(true, true);
+    accounts.push(AccountMeta::new_readonly(*signer_pk, false));
+    accounts.extend(book.to_account_metas());
+    accounts.push(AccountMeta::new_readonly(*caller_pk, true));
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::CancelRebalanceOrders.pack(),
+    }
+}
+
+/// `rebalance_config_pk` and `base_oracle_pk` are as for `rebalance`
+pub fn estimate_rebalance_cost(
+    program_id: &Pubkey,
+    token: &TokenKeys,
+    mango_cache_pk: &Pubkey,
+    rebalance_config_pk: Option<&Pubkey>,
+    base_oracle_pk: Option<&Pubkey>,
+) -> Instruction {
+    let mut accounts = token.to_account_metas(false, false);
+    accounts.push(AccountMeta::new_readonly(*mango_cache_pk, false));
+    accounts.extend(rebalance_config_pk.map(|pk| AccountMeta::new_readonly(*pk, false)));
+    accounts.extend(base_oracle_pk.map(|pk| AccountMeta::new_readonly(*pk, false)));
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::EstimateRebalanceCost.pack(),
+    }
+}
+
+pub fn init_rebalance_config(
+    program_id: &Pubkey,
+    quasar_group_pk: &Pubkey,
+    token_mint_pk: &Pubkey,
+    rebalance_config_pk: &Pubkey,
+    update_authority_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    max_target_leverage: I80F48,
+) -> Instruction {
+    let accounts = vec![
+        AccountMeta::new(*quasar_group_pk, false),
+        AccountMeta::new_readonly(*token_mint_pk, false),
+        AccountMeta::new(*rebalance_config_pk, false),
+        AccountMeta::new_readonly(*update_authority_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::InitRebalanceConfig {
+            max_target_leverage,
+        }
+        .pack(),
+    }
+}
+
+pub fn update_rebalance_config(
+    program_id: &Pubkey,
+    rebalance_config_pk: &Pubkey,
+    update_authority_pk: &Pubkey,
+    target_leverage: I80F48,
+) -> Instruction {
+    let accounts = vec![
+        AccountMeta::new(*rebalance_config_pk, false),
+        AccountMeta::new_readonly(*update_authority_pk, true),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::UpdateRebalanceConfig { target_leverage }.pack(),
+    }
+}
+
+pub fn set_size_premium(
+    program_id: &Pubkey,
+    quasar_group_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    size_premium_bps: u16,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: group_admin_metas(quasar_group_pk, admin_pk),
+        data: QuasarInstruction::SetSizePremium { size_premium_bps }.pack(),
+    }
+}
+
+pub fn set_token_state(
+    program_id: &Pubkey,
+    token: &TokenKeys,
+    signer_pk: &Pubkey,
+    book: &PerpBookKeys,
+    admin_pk: &Pubkey,
+    state: TokenState,
+) -> Instruction {
+    let mut accounts = token.to_account_metas(true, true);
+    accounts.push(AccountMeta::new_readonly(*signer_pk, false));
+    accounts.extend(book.to_account_metas());
+    accounts.push(AccountMeta::new_readonly(*admin_pk, true));
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::SetTokenState { state }.pack(),
+    }
+}
+
+/// Passing `Pubkey::default()` as the oracle pegs the quote at one reference unit again
+pub fn set_quote_oracle(
+    program_id: &Pubkey,
+    quasar_group_pk: &Pubkey,
+    quote_oracle_pk: &Pubkey,
+    admin_pk: &Pubkey,
+) -> Instruction {
+    let accounts = vec![
+        AccountMeta::new(*quasar_group_pk, false),
+        AccountMeta::new_readonly(*quote_oracle_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::SetQuoteOracle.pack(),
+    }
+}
+
+/// `quote_oracle_pk` may be any key while the quote is pegged
+pub fn quote_reference_price(
+    program_id: &Pubkey,
+    token: &TokenKeys,
+    mango_cache_pk: &Pubkey,
+    quote_oracle_pk: &Pubkey,
+) -> Instruction {
+    let mut accounts = token.to_account_metas(false, false);
+    accounts.push(AccountMeta::new_readonly(*mango_cache_pk, false));
+    accounts.push(AccountMeta::new_readonly(*quote_oracle_pk, false));
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::QuoteReferencePrice.pack(),
+    }
+}
+
+pub fn set_listing_grace_period(
+    program_id: &Pubkey,
+    quasar_group_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    listing_grace_slots: u64,
+) -> Instruction {
+    Instruction {
+        program_id: *program_id,
+        accounts: group_admin_metas(quasar_group_pk, admin_pk),
+        data: QuasarInstruction::SetListingGracePeriod {
+            listing_grace_slots,
+        }
+        .pack(),
+    }
+}
+
+pub fn reconcile(
+    program_id: &Pubkey,
+    quasar_group_pk: &Pubkey,
+    token_mint_pk: &Pubkey,
+    token_ledger_pk: &Pubkey,
+) -> Instruction {
+    let accounts = vec![
+        AccountMeta::new_readonly(*quasar_group_pk, false),
+        AccountMeta::new_readonly(*token_mint_pk, false),
+        AccountMeta::new_readonly(*token_ledger_pk, false),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::Reconcile.pack(),
+    }
+}
+
+pub fn set_mango_perp_market(
+    program_id: &Pubkey,
+    token: &TokenKeys,
+    mango_perp_market_pk: &Pubkey,
+    admin_pk: &Pubkey,
+) -> Instruction {
+    let mut accounts = token.to_account_metas(true, false);
+    accounts.push(AccountMeta::new_readonly(*mango_perp_market_pk, false));
+    accounts.push(AccountMeta::new_readonly(*admin_pk, true));
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::SetMangoPerpMarket.pack(),
+    }
+}
+
+pub fn quote_nav_breakdown(
+    program_id: &Pubkey,
+    token: &TokenKeys,
+    mango_cache_pk: &Pubkey,
+) -> Instruction {
+    let mut accounts = token.to_account_metas(false, false);
+    accounts.push(AccountMeta::new_readonly(*mango_cache_pk, false));
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::QuoteNavBreakdown.pack(),
+    }
+}
+
+pub fn set_rebalance_price_source(
+    program_id: &Pubkey,
+    quasar_group_pk: &Pubkey,
+    token_mint_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    source: RebalancePriceSource,
+) -> Instruction {
+    let accounts = vec![
+        AccountMeta::new(*quasar_group_pk, false),
+        AccountMeta::new_readonly(*token_mint_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::SetRebalancePriceSource { source }.pack(),
+    }
+}
+
+/// `token_ledger_pks` are only needed when setting a cap on an uncapped group, those of every
+/// leveraged token with a ledger in token order
+pub fn set_max_net_deposits(
+    program_id: &Pubkey,
+    quasar_group_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    token_ledger_pks: &[Pubkey],
+    max_net_deposits: u64,
+) -> Instruction {
+    let mut accounts = group_admin_metas(quasar_group_pk, admin_pk);
+    accounts.extend(
+        token_ledger_pks
+            .iter()
+            .map(|pk| AccountMeta::new_readonly(*pk, false)),
+    );
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::SetMaxNetDeposits { max_net_deposits }.pack(),
+    }
+}
+
+pub fn add_base_tokens(
+    program_id: &Pubkey,
+    quasar_group_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    mango_group_pk: &Pubkey,
+    tokens: &[(Pubkey, Pubkey)],
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(*quasar_group_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+        AccountMeta::new_readonly(*mango_group_pk, false),
+    ];
+    for (mint_pk, oracle_pk) in tokens {
+        accounts.push(AccountMeta::new_readonly(*mint_pk, false));
+        accounts.push(AccountMeta::new(*oracle_pk, false));
+    }
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::AddBaseTokens.pack(),
+    }
+}
+
+/// `oracle_pks` are those of every base token, in base token order
+pub fn list_base_token_prices(
+    program_id: &Pubkey,
+    quasar_group_pk: &Pubkey,
+    oracle_pks: &[Pubkey],
+) -> Instruction {
+    let mut accounts = vec![AccountMeta::new_readonly(*quasar_group_pk, false)];
+    accounts.extend(
+        oracle_pks
+            .iter()
+            .map(|pk| AccountMeta::new_readonly(*pk, false)),
+    );
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::ListBaseTokenPrices.pack(),
+    }
+}
+
+pub fn set_mango_program(
+    program_id: &Pubkey,
+    quasar_group_pk: &Pubkey,
+    mango_program_pk: &Pubkey,
+    admin_pk: &Pubkey,
+) -> Instruction {
+    let accounts = vec![
+        AccountMeta::new(*quasar_group_pk, false),
+        AccountMeta::new_readonly(*mango_program_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::SetMangoProgram.pack(),
+    }
+}
+
+/// Passing `Pubkey::default()` as the keeper disables it
+pub fn set_rebalance_keeper(
+    program_id: &Pubkey,
+    rebalance_config_pk: &Pubkey,
+    update_authority_pk: &Pubkey,
+    keeper_pk: &Pubkey,
+) -> Instruction {
+    let accounts = vec![
+        AccountMeta::new(*rebalance_config_pk, false),
+        AccountMeta::new_readonly(*update_authority_pk, true),
+        AccountMeta::new_readonly(*keeper_pk, false),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::SetRebalanceKeeper.pack(),
+    }
+}
+
+pub fn liquidation_distance(
+    program_id: &Pubkey,
+    token: &TokenKeys,
+    mango_cache_pk: &Pubkey,
+) -> Instruction {
+    let mut accounts = token.to_account_metas(false, false);
+    accounts.push(AccountMeta::new_readonly(*mango_cache_pk, false));
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::LiquidationDistance.pack(),
+    }
+}
+
+pub fn set_base_token_oracle_type(
+    program_id: &Pubkey,
+    quasar_group_pk: &Pubkey,
+    mint_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    oracle_pk: &Pubkey,
+    oracle_type: OracleType,
+) -> Instruction {
+    let accounts = vec![
+        AccountMeta::new(*quasar_group_pk, false),
+        AccountMeta::new_readonly(*mint_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+        AccountMeta::new_readonly(*oracle_pk, false),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::SetBaseTokenOracleType { oracle_type }.pack(),
+    }
+}
+
+pub fn remove_base_token(
+    program_id: &Pubkey,
+    quasar_group_pk: &Pubkey,
+    mint_pk: &Pubkey,
+    admin_pk: &Pubkey,
+) -> Instruction {
+    let accounts = vec![
+        AccountMeta::new(*quasar_group_pk, false),
+        AccountMeta::new_readonly(*mint_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::RemoveBaseToken.pack(),
+    }
+}
+
+/// The admin receives the lamports of the closed legacy config
+pub fn migrate_rebalance_config(
+    program_id: &Pubkey,
+    quasar_group_pk: &Pubkey,
+    token_mint_pk: &Pubkey,
+    legacy_config_pk: &Pubkey,
+    rebalance_config_pk: &Pubkey,
+    admin_pk: &Pubkey,
+) -> Instruction {
+    let accounts = vec![
+        AccountMeta::new(*quasar_group_pk, false),
+        AccountMeta::new_readonly(*token_mint_pk, false),
+        AccountMeta::new(*legacy_config_pk, false),
+        AccountMeta::new(*rebalance_config_pk, false),
+        AccountMeta::new(*admin_pk, true),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::MigrateRebalanceConfig.pack(),
+    }
+}
+
+/// `quote_oracle_pk` must be passed for tokens with a supply quoted in the mint the group's
+/// quote oracle prices
+pub fn init_token_ledger(
+    program_id: &Pubkey,
+    token: &TokenKeys,
+    mango_cache_pk: &Pubkey,
+    token_ledger_pk: &Pubkey,
+    admin_pk: &Pubkey,
+    quote_oracle_pk: Option<&Pubkey>,
+) -> Instruction {
+    let mut accounts = vec![
+        AccountMeta::new(token.quasar_group, false),
+        AccountMeta::new_readonly(token.token_mint, false),
+        AccountMeta::new(*token_ledger_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+        AccountMeta::new_readonly(token.mango_program, false),
+        AccountMeta::new_readonly(token.mango_group, false),
+        AccountMeta::new_readonly(token.mango_account, false),
+        AccountMeta::new_readonly(*mango_cache_pk, false),
+    ];
+    accounts.extend(quote_oracle_pk.map(|pk| AccountMeta::new_readonly(*pk, false)));
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: QuasarInstruction::InitTokenLedger.pack(),
+    }
+}
+
+fn writable_meta(pk: Pubkey, is_writable: bool) -> AccountMeta {
+    if is_writable {
+        AccountMeta::new(pk, false)
+    } else {
+        AccountMeta::new_readonly(pk, false)
+    }
+}
+
+/// Accounts of the group settings only the admin changes
+fn group_admin_metas(quasar_group_pk: &Pubkey, admin_pk: &Pubkey) -> Vec<AccountMeta> {
+    vec![
+        AccountMeta::new(*quasar_group_pk, false),
+        AccountMeta::new_readonly(*admin_pk, true),
+    ]
+}
@@ -3,6 +3,7 @@ pub mod entrypoint;
 #[macro_use]
 pub mod error;
 
+pub mod accounts;
 pub mod instruction;
 pub mod oracle;
 pub mod processor;
@@ -1,10 +1,14 @@
 use fixed::types::I80F48;
 use mango_common::Loadable;
 use mango_macro::{Loadable, Pod};
+use num_enum::{IntoPrimitive, TryFromPrimitive};
 use solana_program::{account_info::AccountInfo, pubkey::Pubkey, rent::Rent};
 use std::{cell::RefMut, mem::size_of};
 
-use crate::error::{check_assert, QuasarErrorCode, QuasarResult, SourceFileId};
+use crate::{
+    error::{check_assert, QuasarError, QuasarErrorCode, QuasarResult, SourceFileId},
+    utils::safe_div,
+};
 
 declare_check_assert_macros!(SourceFileId::Oracle);
 
@@ -12,6 +16,7 @@ declare_check_assert_macros!(SourceFileId::Oracle);
 #[repr(C)]
 pub struct StubOracle {
     pub magic: u32,    // Magic byte
+    pub expo: i32,     // price exponent, applied the same way as pyth's
     pub price: I80F48, // unit is interpreted as how many quote native tokens for 1 base native token
     pub last_update: u64,
 }
@@ -25,7 +30,7 @@ impl StubOracle {
         check_eq!(
             account.data_len(),
             size_of::<Self>(),
-            QuasarErrorCode::Default
+            QuasarErrorCode::InvalidAccount
         )?;
         check_eq!(account.owner, program_id, QuasarErrorCode::InvalidOwner)?;
 
@@ -60,13 +65,17 @@ pub const PROD_ACCT_SIZE: usize = 512;
 pub const PROD_HDR_SIZE: usize = 48;
 pub const PROD_ATTR_SIZE: usize = PROD_ACCT_SIZE - PROD_HDR_SIZE;
 
-// oracle can be of different types
-#[derive(PartialEq)]
-#[repr(C)]
+/// Magic of a stub oracle, "Mngo" in little endian
+pub const STUB_MAGIC: u32 = 0x6F676E4D;
+
+/// Type of an oracle, recorded on each base token when it is added. Zero is `Unknown` so
+/// base tokens added before the type was recorded fall back to detecting it
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, IntoPrimitive, TryFromPrimitive)]
 pub enum OracleType {
-    Stub,
-    Pyth,
-    Unknown,
+    Unknown = 0,
+    Stub = 1,
+    Pyth = 2,
 }
 
 // each account has its own type
@@ -237,13 +246,69 @@ impl AccKey {
     }
 }
 
+/// Scales an oracle price by `10^expo`, shared by every oracle type so they stay on one scale
+pub fn scale_by_expo(value: I80F48, expo: i32) -> QuasarResult<I80F48> {
+    let decimal_adj = 10u64.checked_pow(expo.unsigned_abs()).ok_or(math_err!())?;
+    let decimal_adj = I80F48::from_num(decimal_adj);
+
+    if expo < 0 {
+        safe_div(value, decimal_adj)
+    } else {
+        value.checked_mul(decimal_adj).ok_or(math_err!())
+    }
+}
+
+/// Rejects prices that are not strictly positive, which would flip the sign of NAV and
+/// rebalance math
+pub fn check_oracle_price(price: I80F48) -> QuasarResult {
+    check!(price > I80F48::ZERO, QuasarErrorCode::UnreliableOracle)
+}
+
+/// Price of a Pyth or stub oracle, scaled by its exponent and checked to be positive
+pub fn read_oracle_price(oracle_ai: &AccountInfo) -> QuasarResult<I80F48> {
+    read_typed_oracle_price(oracle_ai, determine_oracle_type(oracle_ai))
+}
+
+/// Price of an oracle already known to be of `oracle_type`, skipping detection. The magic
+/// is still checked so an account that no longer matches the type is rejected
+pub fn read_typed_oracle_price(
+    oracle_ai: &AccountInfo,
+    oracle_type: OracleType,
+) -> QuasarResult<I80F48> {
+    let price = match oracle_type {
+        OracleType::Pyth => {
+            check!(has_magic(oracle_ai, MAGIC), QuasarErrorCode::InvalidAccount)?;
+            let price_account = Price::get_price(oracle_ai)?;
+            scale_by_expo(
+                I80F48::from_num(price_account.agg.price),
+                price_account.expo,
+            )?
+        }
+        OracleType::Stub => {
+            check!(
+                has_magic(oracle_ai, STUB_MAGIC),
+                QuasarErrorCode::InvalidAccount
+            )?;
+            let oracle = StubOracle::load(oracle_ai)?;
+            scale_by_expo(oracle.price, oracle.expo)?
+        }
+        OracleType::Unknown => return Err(throw_err!(QuasarErrorCode::InvalidAccount)),
+    };
+    check_oracle_price(price)?;
+    Ok(price)
+}
+
 pub fn determine_oracle_type<'a>(account: &'a AccountInfo) -> OracleType {
-    let borrowed = &account.data.borrow();
-    if borrowed[0] == 212 && borrowed[1] == 195 && borrowed[2] == 178 && borrowed[3] == 161 {
-        return OracleType::Pyth;
-    } else if borrowed[0] == 77 && borrowed[1] == 110 && borrowed[2] == 103 && borrowed[3] == 111 {
-        return OracleType::Stub;
+    if has_magic(account, MAGIC) {
+        OracleType::Pyth
+    } else if has_magic(account, STUB_MAGIC) {
+        OracleType::Stub
     } else {
-        return OracleType::Unknown;
+        OracleType::Unknown
     }
 }
+
+fn has_magic(account: &AccountInfo, magic: u32) -> bool {
+    let borrowed = account.data.borrow();
+    borrowed.len() >= 4 && borrowed[..4] == magic.to_le_bytes()
+}
@@ -1,4 +1,4 @@
-use std::{cell::Ref, mem::size_of};
+use std::{cell::Ref, mem::size_of, num::NonZeroU64};
 
 use mango::{
     matching::{OrderType, Side},
@@ -6,14 +6,21 @@ use mango::{
         MangoAccount, MangoCache, MangoGroup, RootBankCache, MAX_PAIRS, QUOTE_INDEX, ZERO_I80F48,
     },
 };
+use serum_dex::{
+    instruction::{NewOrderInstructionV3, SelfTradeBehavior},
+    matching::{OrderType as SerumOrderType, Side as SerumSide},
+    state::MarketState,
+};
 use solana_program::{
     account_info::{next_account_info, Account, AccountInfo},
+    clock::Clock,
     entrypoint::ProgramResult,
     instruction::{AccountMeta, Instruction},
     msg,
     native_token::LAMPORTS_PER_SOL,
     program::{invoke, invoke_signed},
     program_error::ProgramError,
+    program_option::COption,
     program_pack::{IsInitialized, Pack},
     pubkey::Pubkey,
     system_instruction, system_program,
@@ -26,15 +33,38 @@ use mango_common::Loadable;
 use mango_macro::{Loadable, Pod};
 
 use arrayref::{array_ref, array_refs};
+use bytemuck::Zeroable;
 use fixed::types::I80F48;
 use std::cell::RefMut;
 
 use crate::{
-    error::{check_assert, QuasarError, QuasarErrorCode, QuasarResult, SourceFileId},
+    accounts::{BurnAccounts, MintAccounts, RebalanceAccounts},
+    error::{check_assert, MangoCpi, QuasarError, QuasarErrorCode, QuasarResult, SourceFileId},
     instruction::QuasarInstruction,
-    oracle::{determine_oracle_type, OracleType, Price, StubOracle},
-    state::{BaseToken, DataType, LeverageToken, MetaData, QuasarGroup, LEVERGAE_TOKEN_DECIMALS},
-    utils::{gen_signer_key, gen_signer_seeds, get_mango_spot_value},
+    oracle::{
+        determine_oracle_type, read_oracle_price, read_typed_oracle_price, OracleType, StubOracle,
+        STUB_MAGIC,
+    },
+    state::{
+        check_mint_price_band, initial_quote_amount, net_deposit_amount, BaseToken, DataType,
+        LeverageToken, MetaData, QuasarGroup, RebalanceConfig, RebalancePriceSource,
+        RebalanceRecord, TokenLedger, TokenState, LEVERAGE_TOKEN_RESERVED, LEVERGAE_TOKEN_DECIMALS,
+        MAX_BASE_TOKENS, QUASAR_GROUP_VERSION, REBALANCE_HISTORY_LEN, USE_GROUP_FEE,
+    },
+    utils::{
+        apply_price_offset, bps_to_leverage, check_attested_exposure, check_batch_size,
+        check_deposit_credited, check_exposure_sign, check_mango_account_empty,
+        check_mango_cache_fresh, check_mint_funds, check_nav_move, check_nav_positive,
+        check_no_mint_in_transaction, check_perp_flat, estimate_taker_fee, format_leverage,
+        gen_signer_key, gen_signer_seeds, get_fee_amount, get_maint_weighted_exposure,
+        get_mango_quote_value, get_mango_spot_value, get_perp_base_position, get_quote_amount,
+        get_size_adjusted_price, get_spot_ask_limits, get_spot_bid_limits, governance_program,
+        liquidation_distance, load_mango_context, mango_net_asset_value, native_to_ui_price,
+        needs_mango_account_init, quote_to_reference, rebalance_client_order_id,
+        reference_to_quote, safe_div, set_return_data, split_mint_deposit,
+        split_rebalance_exposure, BaseLots, NavBreakdown, PerpMarketUnits, QuoteNative,
+        RebalanceResult, BPS_UNIT, MAX_REBALANCE_PRICE_OFFSET_BPS, MAX_SPOT_SWAP_SLIPPAGE_BPS,
+    },
 };
 
 declare_check_assert_macros!(SourceFileId::Processor);
@@ -58,9 +88,17 @@ impl Processor {
                 msg!("Instruction: AddBaseToken");
                 Self::add_base_token(program_id, accounts)
             }
-            QuasarInstruction::AddLeverageToken { target_leverage } => {
+            QuasarInstruction::AddLeverageToken {
+                target_leverage_bps,
+                seed_quantity,
+            } => {
                 msg!("Instruction: AddLeverageToken");
-                Self::add_leverage_token(program_id, accounts, target_leverage)
+                Self::add_leverage_token(
+                    program_id,
+                    accounts,
+                    bps_to_leverage(target_leverage_bps),
+                    seed_quantity,
+                )
             }
             QuasarInstruction::MintLeverageToken { quantity } => {
                 msg!("Instruction: MintLeverageToken");
@@ -72,7 +110,182 @@ impl Processor {
             }
             QuasarInstruction::Rebalance => {
                 msg!("Instruction: Rebalance");
-                Self::rebalance(program_id, accounts)
+                Self::rebalance(program_id, accounts, None)
+            }
+            QuasarInstruction::SetRebalanceAuthority => {
+                msg!("Instruction: SetRebalanceAuthority");
+                Self::set_rebalance_authority(program_id, accounts)
+            }
+            QuasarInstruction::QuoteRedeem { quantity } => {
+                msg!("Instruction: QuoteRedeem");
+                Self::quote_redeem(program_id, accounts, quantity)
+            }
+            QuasarInstruction::SetTreasury => {
+                msg!("Instruction: SetTreasury");
+                Self::set_treasury(program_id, accounts)
+            }
+            QuasarInstruction::WithdrawFees { quantity } => {
+                msg!("Instruction: WithdrawFees");
+                Self::withdraw_fees(program_id, accounts, quantity)
+            }
+            QuasarInstruction::SetLeverageTokenParams {
+                min_native_price,
+                spot_swap_bps,
+                mint_fee_bps,
+                burn_fee_bps,
+                max_effective_leverage,
+                rebalance_cooldown_slots,
+                allow_borrow,
+                max_borrow_bps,
+                min_mint_increment,
+                same_slot_burn_guard,
+                max_redemption_per_tx,
+                volatility_target,
+                price_offset_bps,
+                max_rebalances_per_epoch,
+            } => {
+                msg!("Instruction: SetLeverageTokenParams");
+                Self::set_leverage_token_params(
+                    program_id,
+                    accounts,
+                    min_native_price,
+                    spot_swap_bps,
+                    mint_fee_bps,
+                    burn_fee_bps,
+                    max_effective_leverage,
+                    rebalance_cooldown_slots,
+                    allow_borrow,
+                    max_borrow_bps,
+                    min_mint_increment,
+                    same_slot_burn_guard,
+                    max_redemption_per_tx,
+                    volatility_target,
+                    price_offset_bps,
+                    max_rebalances_per_epoch,
+                )
+            }
+            QuasarInstruction::RemoveLeverageToken => {
+                msg!("Instruction: RemoveLeverageToken");
+                Self::remove_leverage_token(program_id, accounts)
+            }
+            QuasarInstruction::CloseQuasarGroup => {
+                msg!("Instruction: CloseQuasarGroup");
+                Self::close_quasar_group(program_id, accounts)
+            }
+            QuasarInstruction::InitSpotOpenOrders => {
+                msg!("Instruction: InitSpotOpenOrders");
+                Self::init_spot_open_orders(program_id, accounts)
+            }
+            QuasarInstruction::SetFees {
+                mint_fee_bps,
+                burn_fee_bps,
+            } => {
+                msg!("Instruction: SetFees");
+                Self::set_fees(program_id, accounts, mint_fee_bps, burn_fee_bps)
+            }
+            QuasarInstruction::SetMaxNavMove { max_nav_move_bps } => {
+                msg!("Instruction: SetMaxNavMove");
+                Self::set_max_nav_move(program_id, accounts, max_nav_move_bps)
+            }
+            QuasarInstruction::CancelRebalanceOrders => {
+                msg!("Instruction: CancelRebalanceOrders");
+                Self::cancel_rebalance_orders(program_id, accounts)
+            }
+            QuasarInstruction::EstimateRebalanceCost => {
+                msg!("Instruction: EstimateRebalanceCost");
+                Self::estimate_rebalance_cost(program_id, accounts)
+            }
+            QuasarInstruction::InitRebalanceConfig {
+                max_target_leverage,
+            } => {
+                msg!("Instruction: InitRebalanceConfig");
+                Self::init_rebalance_config(program_id, accounts, max_target_leverage)
+            }
+            QuasarInstruction::UpdateRebalanceConfig { target_leverage } => {
+                msg!("Instruction: UpdateRebalanceConfig");
+                Self::update_rebalance_config(program_id, accounts, target_leverage)
+            }
+            QuasarInstruction::SetSizePremium { size_premium_bps } => {
+                msg!("Instruction: SetSizePremium");
+                Self::set_size_premium(program_id, accounts, size_premium_bps)
+            }
+            QuasarInstruction::SetTokenState { state } => {
+                msg!("Instruction: SetTokenState");
+                Self::set_token_state(program_id, accounts, state)
+            }
+            QuasarInstruction::SetQuoteOracle => {
+                msg!("Instruction: SetQuoteOracle");
+                Self::set_quote_oracle(program_id, accounts)
+            }
+            QuasarInstruction::QuoteReferencePrice => {
+                msg!("Instruction: QuoteReferencePrice");
+                Self::quote_reference_price(program_id, accounts)
+            }
+            QuasarInstruction::SetListingGracePeriod {
+                listing_grace_slots,
+            } => {
+                msg!("Instruction: SetListingGracePeriod");
+                Self::set_listing_grace_period(program_id, accounts, listing_grace_slots)
+            }
+            QuasarInstruction::Reconcile => {
+                msg!("Instruction: Reconcile");
+                Self::reconcile(program_id, accounts)
+            }
+            QuasarInstruction::SetMangoPerpMarket => {
+                msg!("Instruction: SetMangoPerpMarket");
+                Self::set_mango_perp_market(program_id, accounts)
+            }
+            QuasarInstruction::QuoteNavBreakdown => {
+                msg!("Instruction: QuoteNavBreakdown");
+                Self::quote_nav_breakdown(program_id, accounts)
+            }
+            QuasarInstruction::SetRebalancePriceSource { source } => {
+                msg!("Instruction: SetRebalancePriceSource");
+                Self::set_rebalance_price_source(program_id, accounts, source)
+            }
+            QuasarInstruction::SetMaxNetDeposits { max_net_deposits } => {
+                msg!("Instruction: SetMaxNetDeposits");
+                Self::set_max_net_deposits(program_id, accounts, max_net_deposits)
+            }
+            QuasarInstruction::AddBaseTokens => {
+                msg!("Instruction: AddBaseTokens");
+                Self::add_base_tokens(program_id, accounts)
+            }
+            QuasarInstruction::ListBaseTokenPrices => {
+                msg!("Instruction: ListBaseTokenPrices");
+                Self::list_base_token_prices(program_id, accounts)
+            }
+            QuasarInstruction::SetMangoProgram => {
+                msg!("Instruction: SetMangoProgram");
+                Self::set_mango_program(program_id, accounts)
+            }
+            QuasarInstruction::SetRebalanceKeeper => {
+                msg!("Instruction: SetRebalanceKeeper");
+                Self::set_rebalance_keeper(program_id, accounts)
+            }
+            QuasarInstruction::RebalanceToExposure { target_exposure } => {
+                msg!("Instruction: RebalanceToExposure");
+                Self::rebalance(program_id, accounts, Some(target_exposure))
+            }
+            QuasarInstruction::LiquidationDistance => {
+                msg!("Instruction: LiquidationDistance");
+                Self::liquidation_distance(program_id, accounts)
+            }
+            QuasarInstruction::SetBaseTokenOracleType { oracle_type } => {
+                msg!("Instruction: SetBaseTokenOracleType");
+                Self::set_base_token_oracle_type(program_id, accounts, oracle_type)
+            }
+            QuasarInstruction::RemoveBaseToken => {
+                msg!("Instruction: RemoveBaseToken");
+                Self::remove_base_token(program_id, accounts)
+            }
+            QuasarInstruction::MigrateRebalanceConfig => {
+                msg!("Instruction: MigrateRebalanceConfig");
+                Self::migrate_rebalance_config(program_id, accounts)
+            }
+            QuasarInstruction::InitTokenLedger => {
+                msg!("Instruction: InitTokenLedger");
+                Self::init_token_ledger(program_id, accounts)
             }
         }
     }
@@ -84,9 +297,14 @@ impl Processor {
         signer_nonce: u64,
     ) -> QuasarResult {
         const NUM_FIXED: usize = 4;
-        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let (fixed_ais, governance_ais) = array_refs![accounts, NUM_FIXED; ..;];
 
-        let [quasar_group_ai, signer_ai, admin_ai, mango_program_ai] = accounts;
+        let [quasar_group_ai, signer_ai, admin_ai, mango_program_ai] = fixed_ais;
+        // Every later CPI goes to the stored id, which can't be changed afterwards
+        check!(
+            mango_program_ai.executable,
+            QuasarErrorCode::InvalidProgramId
+        )?;
         check_eq!(
             quasar_group_ai.owner,
             program_id,
@@ -100,31 +318,53 @@ impl Processor {
         let mut quasar_group: RefMut<QuasarGroup> = QuasarGroup::load_mut(quasar_group_ai)?;
         check!(
             !quasar_group.meta_data.is_initialized,
-            QuasarErrorCode::Default
+            QuasarErrorCode::AlreadyInitialized
         )?;
 
-        check!(
-            gen_signer_key(signer_nonce, quasar_group_ai.key, program_id)? == *signer_ai.key,
+        // About half of all nonces put the address on the curve, which isn't a valid PDA
+        let signer_key = gen_signer_key(signer_nonce, quasar_group_ai.key, program_id)
+            .map_err(|_| throw_err!(QuasarErrorCode::InvalidSignerKey))?;
+        check_eq!(
+            signer_key,
+            *signer_ai.key,
             QuasarErrorCode::InvalidSignerKey
         )?;
         quasar_group.signer_nonce = signer_nonce;
         quasar_group.signer_key = *signer_ai.key;
         quasar_group.mango_program_id = *mango_program_ai.key;
 
-        check!(admin_ai.is_signer, QuasarErrorCode::Default)?;
+        // A governance controlled admin (e.g. a DAO or multisig account) can't co-sign the init,
+        // so it is accepted unsigned when it is owned by the SPL governance program.
+        // Nothing then proves the admin key is the intended one, so it must be double checked
+        // off chain: a wrong admin can't be replaced afterwards
+        if !admin_ai.is_signer {
+            let governance_program_ai = governance_ais
+                .first()
+                .ok_or(throw_err!(QuasarErrorCode::SignerNecessary))?;
+            check_eq!(
+                *governance_program_ai.key,
+                governance_program::id(),
+                QuasarErrorCode::InvalidProgramId
+            )?;
+            check_eq!(
+                admin_ai.owner,
+                governance_program_ai.key,
+                QuasarErrorCode::InvalidAdminKey
+            )?;
+        }
         quasar_group.admin_key = *admin_ai.key;
 
-        quasar_group.meta_data = MetaData::new(DataType::QuasarGroup, 0, true);
+        quasar_group.meta_data = MetaData::new(DataType::QuasarGroup, QUASAR_GROUP_VERSION, true);
 
         Ok(())
     }
 
     #[inline(never)]
     fn add_base_token<'a>(program_id: &Pubkey, accounts: &[AccountInfo<'a>]) -> QuasarResult {
-        const NUM_FIXED: usize = 4;
+        const NUM_FIXED: usize = 5;
         let accounts = array_ref![accounts, 0, NUM_FIXED];
 
-        let [quasar_group_ai, mint_ai, oracle_ai, admin_ai] = accounts;
+        let [quasar_group_ai, mint_ai, oracle_ai, admin_ai, mango_group_ai] = accounts;
 
         let mut quasar_group = QuasarGroup::load_mut_checked(quasar_group_ai, program_id)?;
         check!(admin_ai.is_signer, QuasarErrorCode::InvalidSignerKey)?;
@@ -134,42 +374,15 @@ impl Processor {
             QuasarErrorCode::InvalidSignerKey
         )?;
 
-        // Make sure there is no duplicated base token which has the same mint key
-        check!(
-            quasar_group.find_base_token_index(mint_ai.key).is_none(),
-            QuasarErrorCode::Default
-        )?;
-
-        let oracle_type = determine_oracle_type(oracle_ai);
-        match oracle_type {
-            OracleType::Pyth => {
-                msg!("OracleType:Pyth"); // Do nothing really cause all that's needed is storing the pkey
-            }
-            OracleType::Stub | OracleType::Unknown => {
-                msg!("OracleType: got unknown or stub");
-                let rent = Rent::get()?;
-                let mut oracle = StubOracle::load_and_init(oracle_ai, program_id, &rent)?;
-                oracle.magic = 0x6F676E4D;
-            }
-        }
-
-        let base_token_index = quasar_group.num_base_tokens;
-        // Make sure base token at this index is not already initialized
-        check!(
-            quasar_group.base_tokens[base_token_index].is_empty(),
-            QuasarErrorCode::Default
-        )?;
-
-        let mint = Mint::unpack(&mint_ai.try_borrow_data()?)?;
-        quasar_group.base_tokens[base_token_index] = BaseToken {
-            mint: *mint_ai.key,
-            decimals: mint.decimals,
-            oracle: *oracle_ai.key,
-            padding: [0u8; 7],
-        };
-        quasar_group.num_base_tokens += 1;
-
-        Ok(())
+        let mango_program_id = quasar_group.mango_program_id;
+        let mango_group = MangoGroup::load_checked(mango_group_ai, &mango_program_id)?;
+        register_base_token(
+            program_id,
+            &mut quasar_group,
+            &mango_group,
+            mint_ai,
+            oracle_ai,
+        )
     }
 
     #[inline(never)]
@@ -179,11 +392,14 @@ impl Processor {
         program_id: &Pubkey,
         accounts: &[AccountInfo],
         target_leverage: I80F48,
+        seed_quantity: u64,
     ) -> QuasarResult {
         const NUM_FIXED: usize = 12;
-        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        const NUM_SEED: usize = 7;
+        const NUM_QUOTE_FEE_VAULT: usize = 3;
+        let (fixed_ais, trailing_ais) = array_refs![accounts, NUM_FIXED; ..;];
         let [quasar_group_ai, mint_ai, base_token_mint_ai, mango_program_ai, mango_group_ai, mango_account_ai, mango_perp_market_ai, system_program_ai, token_program_ai, rent_program_ai, admin_ai, pda_ai] =
-            accounts;
+            fixed_ais;
 
         let mut quasar_group = QuasarGroup::load_mut_checked(quasar_group_ai, program_id)?;
         check!(admin_ai.is_signer, QuasarErrorCode::SignerNecessary)?;
@@ -192,6 +408,11 @@ impl Processor {
             &quasar_group.admin_key,
             QuasarErrorCode::InvalidAdminKey
         )?;
+        check_eq!(
+            *mango_program_ai.key,
+            quasar_group.mango_program_id,
+            QuasarErrorCode::InvalidProgramId
+        )?;
 
         // Make sure leverage token is referencing a proper base token
         check!(
@@ -206,7 +427,7 @@ impl Processor {
             quasar_group
                 .find_leverage_token_index(base_token_mint_ai.key, target_leverage)
                 .is_none(),
-            QuasarErrorCode::Default
+            QuasarErrorCode::DuplicateToken
         )?;
 
         let token_index = quasar_group.num_leverage_tokens;
@@ -214,7 +435,7 @@ impl Processor {
         // Make sure leverage token at this index is not already initialized
         check!(
             quasar_group.leverage_tokens[token_index].is_empty(),
-            QuasarErrorCode::Default
+            QuasarErrorCode::SlotOccupied
         )?;
 
         check_eq!(
@@ -224,14 +445,51 @@ impl Processor {
         )?;
         let signer_seeds = gen_signer_seeds(&quasar_group.signer_nonce, quasar_group_ai.key);
 
-        init_mango_account(
-            mango_program_ai,
-            mango_group_ai,
-            mango_account_ai,
-            pda_ai,
-            &[&signer_seeds],
+        // A Mango account left initialized by a removed token can be listed again, but never
+        // shared by two tokens
+        check!(
+            quasar_group.leverage_tokens[..quasar_group.num_leverage_tokens]
+                .iter()
+                .all(|leverage_token| leverage_token.mango_account != *mango_account_ai.key),
+            QuasarErrorCode::InvalidAccount
         )?;
-        msg!("Init Mango Account succeeded");
+        if needs_mango_account_init(
+            mango_account_ai,
+            mango_program_ai.key,
+            mango_group_ai.key,
+            &quasar_group.signer_key,
+        )? {
+            init_mango_account(
+                mango_program_ai,
+                mango_group_ai,
+                mango_account_ai,
+                pda_ai,
+                &[&signer_seeds],
+            )?;
+            msg!("Init Mango Account succeeded");
+        } else {
+            msg!("Mango account already initialized");
+        }
+
+        // Make sure the stored Mango account is owned by this group and not someone else
+        let quote_mint;
+        let quote_decimals;
+        {
+            let mango_group = MangoGroup::load_checked(mango_group_ai, mango_program_ai.key)?;
+            quote_mint = mango_group.tokens[QUOTE_INDEX].mint;
+            quote_decimals = mango_group.tokens[QUOTE_INDEX].decimals;
+            LeverageToken::check_quote_decimals(quote_decimals)?;
+            let mango_account = MangoAccount::load_checked(
+                mango_account_ai,
+                mango_program_ai.key,
+                mango_group_ai.key,
+            )?;
+            check_eq!(
+                mango_account.owner,
+                quasar_group.signer_key,
+                QuasarErrorCode::InvalidOwner
+            )?;
+        }
 
         create_and_initialize_mint_account(
             admin_ai,
@@ -243,17 +501,172 @@ impl Processor {
             &[&signer_seeds],
             LEVERGAE_TOKEN_DECIMALS,
         )?;
-        msg!("target leverage: {}", target_leverage);
+        msg!("target leverage: {}", format_leverage(target_leverage));
+        msg!("quote mint: {}", quote_mint);
 
+        let num_seed = if seed_quantity > 0 { NUM_SEED } else { 0 };
+        check!(
+            trailing_ais.len() >= num_seed,
+            QuasarErrorCode::InvalidAccount
+        )?;
+        let (seed_ais, quote_fee_vault_ais) = trailing_ais.split_at(num_seed);
+
+        // Fees in a quote other than the group's go to the associated token account of the
+        // group signer, which has to exist before a mint pays into it
+        if quote_mint == quasar_group.quote_mint {
+            check!(
+                quote_fee_vault_ais.is_empty(),
+                QuasarErrorCode::InvalidAccount
+            )?;
+        } else {
+            check_eq!(
+                quote_fee_vault_ais.len(),
+                NUM_QUOTE_FEE_VAULT,
+                QuasarErrorCode::InvalidAccount
+            )?;
+            let quote_fee_vault_ais = array_ref![quote_fee_vault_ais, 0, NUM_QUOTE_FEE_VAULT];
+            let [fee_vault_ai, quote_mint_ai, associated_token_program_ai] = quote_fee_vault_ais;
+            check_eq!(
+                *associated_token_program_ai.key,
+                spl_associated_token_account::id(),
+                QuasarErrorCode::InvalidProgramId
+            )?;
+            check_eq!(
+                *quote_mint_ai.key,
+                quote_mint,
+                QuasarErrorCode::InvalidToken
+            )?;
+            check_eq!(
+                *fee_vault_ai.key,
+                quasar_group.get_fee_vault(&quote_mint),
+                QuasarErrorCode::InvalidAccount
+            )?;
+            // Tokens listed before in the same quote already created it
+            if fee_vault_ai.data_is_empty() {
+                invoke(
+                    &create_associated_token_account(admin_ai.key, pda_ai.key, &quote_mint),
+                    &[
+                        admin_ai.clone(),
+                        fee_vault_ai.clone(),
+                        pda_ai.clone(),
+                        quote_mint_ai.clone(),
+                        system_program_ai.clone(),
+                        token_program_ai.clone(),
+                        rent_program_ai.clone(),
+                        associated_token_program_ai.clone(),
+                    ],
+                )?;
+                msg!("fee vault: {}", fee_vault_ai.key);
+            }
+        }
+
+        let active_from_slot = Clock::get()?
+            .slot
+            .checked_add(quasar_group.listing_grace_slots)
+            .ok_or(math_err!())?;
         quasar_group.leverage_tokens[token_index] = LeverageToken {
             mint: *mint_ai.key,
             base_token_mint: *base_token_mint_ai.key,
             target_leverage: target_leverage,
             mango_account: *mango_account_ai.key,
             mango_perp_market: *mango_perp_market_ai.key,
+            quote_mint,
+            last_nav: ZERO_I80F48,
+            last_effective_leverage: ZERO_I80F48,
+            last_rebalance_slot: 0,
+            pending_rebalance_quantity: 0,
+            min_native_price: ZERO_I80F48,
+            rebalance_config: Pubkey::default(),
+            spot_swap_bps: 0,
+            mint_fee_bps: USE_GROUP_FEE,
+            burn_fee_bps: USE_GROUP_FEE,
+            state: TokenState::Active as u8,
+            rebalance_price_source: RebalancePriceSource::MangoCache as u8,
+            active_from_slot,
+            token_ledger: Pubkey::default(),
+            max_effective_leverage: ZERO_I80F48,
+            rebalance_cooldown_slots: 0,
+            allow_borrow: 0,
+            same_slot_burn_guard: 0,
+            max_borrow_bps: 0,
+            min_mint_increment: 0,
+            rebalance_history: [RebalanceRecord::zeroed(); REBALANCE_HISTORY_LEN],
+            num_rebalances: 0,
+            rebalance_epoch: 0,
+            max_redemption_per_tx: 0,
+            price_offset_bps: 0,
+            max_rebalances_per_epoch: 0,
+            epoch_rebalances: 0,
+            padding3: [0u8; 2],
+            volatility_ema: ZERO_I80F48,
+            volatility_price: ZERO_I80F48,
+            volatility_target: ZERO_I80F48,
+            volatility_slot: 0,
+            last_update_slot: 0,
+            reserved: [0u8; LEVERAGE_TOKEN_RESERVED],
         };
         quasar_group.num_leverage_tokens += 1;
 
+        if seed_quantity > 0 {
+            let seed_ais = array_ref![seed_ais, 0, NUM_SEED];
+            let [mango_cache_ai, root_bank_ai, node_bank_ai, vault_ai, admin_quote_token_account_ai, admin_leverage_token_account_ai, associated_token_program_ai] =
+                seed_ais;
+            check_eq!(
+                *associated_token_program_ai.key,
+                spl_associated_token_account::id(),
+                QuasarErrorCode::InvalidProgramId
+            )?;
+            check_eq!(
+                *admin_leverage_token_account_ai.key,
+                get_associated_token_address(admin_ai.key, mint_ai.key),
+                QuasarErrorCode::InvalidAccount
+            )?;
+
+            let quote_amount =
+                initial_quote_amount(seed_quantity, LEVERGAE_TOKEN_DECIMALS, quote_decimals)?;
+            deposit_to_mango_account(
+                mango_program_ai,
+                mango_group_ai,
+                mango_account_ai,
+                admin_ai,
+                mango_cache_ai,
+                root_bank_ai,
+                node_bank_ai,
+                vault_ai,
+                token_program_ai,
+                admin_quote_token_account_ai,
+                &[&[]],
+                quote_amount,
+            )?;
+
+            // The mint was only created above, so the admin can't hold a token account yet
+            invoke(
+                &create_associated_token_account(admin_ai.key, admin_ai.key, mint_ai.key),
+                &[
+                    admin_ai.clone(),
+                    admin_leverage_token_account_ai.clone(),
+                    admin_ai.clone(),
+                    mint_ai.clone(),
+                    system_program_ai.clone(),
+                    token_program_ai.clone(),
+                    rent_program_ai.clone(),
+                    associated_token_program_ai.clone(),
+                ],
+            )?;
+            let signer_nonce = quasar_group.signer_nonce;
+            let signer_seeds = gen_signer_seeds(&signer_nonce, quasar_group_ai.key);
+            invoke_mint_to(
+                token_program_ai,
+                mint_ai,
+                admin_leverage_token_account_ai,
+                pda_ai,
+                &[&signer_seeds],
+                seed_quantity,
+            )?;
+
+            msg!("seeded {} tokens for {} quote", seed_quantity, quote_amount);
+        }
+
         Ok(())
     }
 
@@ -263,40 +676,102 @@ impl Processor {
         accounts: &[AccountInfo<'a>],
         quantity: u64,
     ) -> QuasarResult {
-        const NUM_FIXED: usize = 14;
-        let accounts = array_ref![accounts, 0, NUM_FIXED];
-        let [quasar_group_ai, token_mint_ai, owner_leverage_token_account_ai, mango_program_ai, mango_group_ai, mango_account_ai, owner_ai, mango_cache_ai, root_bank_ai, node_bank_ai, vault_ai, token_program_ai, owner_quote_token_account_ai, pda_ai] =
-            accounts;
+        check!(quantity > 0, QuasarErrorCode::InvalidParam)?;
 
-        let quasar_group = QuasarGroup::load_checked(quasar_group_ai, program_id)?;
+        let MintAccounts {
+            quasar_group_ai,
+            token_mint_ai,
+            owner_leverage_token_account_ai,
+            mango_program_ai,
+            mango_group_ai,
+            mango_account_ai,
+            owner_ai,
+            mango_cache_ai,
+            root_bank_ai,
+            node_bank_ai,
+            vault_ai,
+            token_program_ai,
+            owner_quote_token_account_ai,
+            pda_ai,
+            fee_vault_ai,
+            spot_ais,
+            associated_token_program_ai: _,
+        } = MintAccounts::load(accounts)?;
+
+        let mut quasar_group = QuasarGroup::load_mut_checked(quasar_group_ai, program_id)?;
+        let leverage_token_index = quasar_group
+            .find_leverage_token_index_by_mint(token_mint_ai.key)
+            .ok_or(throw_err!(QuasarErrorCode::InvalidToken))?;
+        let (token_ledger_ais, spot_ais) = split_token_ledger_ais(
+            &quasar_group.leverage_tokens[leverage_token_index],
+            spot_ais,
+        )?;
+        let mut token_ledger = load_token_ledger(
+            &quasar_group.leverage_tokens[leverage_token_index],
+            token_ledger_ais,
+            program_id,
+        )?;
+        let (quote_oracle_ais, spot_ais) = split_quote_oracle_ais(
+            &quasar_group,
+            &quasar_group.leverage_tokens[leverage_token_index].quote_mint,
+            spot_ais,
+        )?;
+        // The group is only written while a cap is set, so that mints of different tokens
+        // don't contend for it otherwise. The cap then needs every mint counted
+        let capped = quasar_group.max_net_deposits != 0;
+        if capped {
+            check!(token_ledger.is_some(), QuasarErrorCode::InvalidAccount)?;
+            check!(
+                quasar_group_ai.is_writable,
+                QuasarErrorCode::AccountNotWritable
+            )?;
+        }
+
+        // A delegate of someone else's quote account could otherwise spend it as owner_ai
+        let owner_quote_token_account =
+            TokenAccount::unpack(&owner_quote_token_account_ai.try_borrow_data()?)?;
+        check_eq!(
+            owner_quote_token_account.owner,
+            *owner_ai.key,
+            QuasarErrorCode::InvalidOwner
+        )?;
+
+        // A mint authority moved out of band would otherwise only fail inside the token
+        // program, after the deposit went through
+        let mint = Mint::unpack(&token_mint_ai.try_borrow_data()?)?;
+        check!(
+            mint.mint_authority == COption::Some(quasar_group.signer_key),
+            QuasarErrorCode::InvalidToken
+        )?;
+        check_eq!(
+            *owner_leverage_token_account_ai.key,
+            get_associated_token_address(owner_ai.key, token_mint_ai.key),
+            QuasarErrorCode::InvalidAccount
+        )?;
+        check_eq!(
+            quasar_group.leverage_tokens[leverage_token_index].mango_account,
+            *mango_account_ai.key,
+            QuasarErrorCode::InvalidAccount
+        )?;
 
         let native_price;
+        let spot_swap_bps;
+        let fee_bps;
+        let quote_mint;
+        let quote_decimals;
+        let quote_net_before;
         {
-            let mango_group = MangoGroup::load_checked(&mango_group_ai, mango_program_ai.key)?;
-            let mango_cache =
-                MangoCache::load_checked(&mango_cache_ai, mango_program_ai.key, &mango_group)?;
-            let mango_account = MangoAccount::load_checked(
-                &mango_account_ai,
+            let (mango_group, mango_cache, mango_account) = load_mango_context(
                 mango_program_ai.key,
-                mango_group_ai.key,
+                mango_group_ai,
+                mango_cache_ai,
+                mango_account_ai,
             )?;
 
-            check_eq!(
-                *owner_leverage_token_account_ai.key,
-                get_associated_token_address(owner_ai.key, token_mint_ai.key),
-                QuasarErrorCode::InvalidAccount
-            );
-
-            let leverage_token_index = quasar_group
-                .find_leverage_token_index_by_mint(token_mint_ai.key)
-                .unwrap();
             let leverage_token = quasar_group.leverage_tokens[leverage_token_index];
-
-            check_eq!(
-                leverage_token.mango_account,
-                *mango_account_ai.key,
-                QuasarErrorCode::InvalidAccount
-            );
+            leverage_token.check_active()?;
+            leverage_token.check_mint_active(Clock::get()?.slot)?;
+            leverage_token.check_mint_increment(quantity)?;
 
             native_price = leverage_token.get_native_price(
                 token_mint_ai,
@@ -304,8 +779,53 @@ impl Processor {
                 &mango_account,
                 &mango_cache,
             )?;
+
+            check_nav_positive(native_price)?;
+            // Near zero NAV rounding dominates the price, which would let minters extract value
+            check!(
+                native_price >= leverage_token.min_native_price,
+                QuasarErrorCode::NavBelowMinimum
+            )?;
+            check_mint_price_band(
+                native_price,
+                mint.decimals,
+                mango_group.tokens[QUOTE_INDEX].decimals,
+            )?;
+            spot_swap_bps = leverage_token.spot_swap_bps;
+            fee_bps = leverage_token.get_mint_fee_bps(&quasar_group);
+            quote_mint = leverage_token.quote_mint;
+            quote_decimals = mango_group.tokens[QUOTE_INDEX].decimals;
+            quote_net_before = get_mango_quote_value(&mango_account, &mango_cache)?;
         }
 
+        check_eq!(
+            owner_quote_token_account.mint,
+            quote_mint,
+            QuasarErrorCode::InvalidToken
+        )?;
+
+        let supply = mint.supply;
+        let quote_price = load_quote_price(&quasar_group, &quote_mint, quote_oracle_ais.first())?;
+        // The first mint prices the token at one reference unit, which costs more quote
+        // while the quote trades below its peg. Later prices follow the NAV in quote
+        let native_price = if supply == 0 {
+            reference_to_quote(native_price, quote_price)?
+        } else {
+            native_price
+        };
+        let mint_price = get_size_adjusted_price(
+            native_price,
+            quantity,
+            supply,
+            quasar_group.size_premium_bps,
+            true,
+        )?;
+        let quote_amount = get_quote_amount(quantity, mint_price, true)?;
+        let net_deposit = net_deposit_amount(quote_amount, quote_decimals, quote_price)?;
+        quasar_group.check_group_cap(net_deposit)?;
+        // The fee is paid on top of the deposit, so it doesn't dilute existing holders
+        let fee = get_fee_amount(quote_amount, fee_bps)?;
+        check_mint_funds(owner_quote_token_account.amount, quote_amount, fee)?;
         deposit_to_mango_account(
             mango_program_ai,
             mango_group_ai,
@@ -318,10 +838,60 @@ impl Processor {
             token_program_ai,
             owner_quote_token_account_ai,
             &[&[]],
-            quantity * native_price.to_num::<u64>(),
+            quote_amount,
         )?;
 
+        // Tokens are only minted against a deposit Mango actually credited
+        {
+            let (_, mango_cache, mango_account) = load_mango_context(
+                mango_program_ai.key,
+                mango_group_ai,
+                mango_cache_ai,
+                mango_account_ai,
+            )?;
+            let quote_net_after = get_mango_quote_value(&mango_account, &mango_cache)?;
+            check_deposit_credited(quote_net_before, quote_net_after, quote_amount)?;
+        }
+
+        if fee > 0 {
+            check_eq!(
+                *fee_vault_ai.key,
+                quasar_group.get_fee_vault(&quote_mint),
+                QuasarErrorCode::InvalidAccount
+            )?;
+            invoke_transfer(
+                token_program_ai,
+                owner_quote_token_account_ai,
+                fee_vault_ai,
+                owner_ai,
+                &[],
+                fee,
+            )?;
+            msg!("mint fee: {}", fee);
+        }
+
         let signer_seeds = gen_signer_seeds(&quasar_group.signer_nonce, quasar_group_ai.key);
+        let (_, swap_amount) = split_mint_deposit(quote_amount, spot_swap_bps)?;
+        if swap_amount > 0 {
+            Self::trade_mango_spot(
+                &quasar_group,
+                token_mint_ai,
+                mango_program_ai,
+                mango_group_ai,
+                mango_account_ai,
+                mango_cache_ai,
+                root_bank_ai,
+                node_bank_ai,
+                vault_ai,
+                token_program_ai,
+                pda_ai,
+                spot_ais,
+                &[&signer_seeds],
+                SerumSide::Bid,
+                swap_amount,
+            )?;
+        }
+
         invoke_mint_to(
             token_program_ai,
             token_mint_ai,
@@ -330,73 +900,338 @@ impl Processor {
             &[&signer_seeds],
             quantity,
         )?;
+        if let Some(token_ledger) = token_ledger.as_mut() {
+            token_ledger.record_mint(quantity, net_deposit)?;
+        }
+        if capped {
+            quasar_group.add_net_deposits(net_deposit)?;
+        }
+
+        let native_price_after = load_native_price(
+            &quasar_group,
+            token_mint_ai,
+            mango_program_ai,
+            mango_group_ai,
+            mango_account_ai,
+            mango_cache_ai,
+        )?;
+        check_nav_move(
+            native_price,
+            native_price_after,
+            quasar_group.max_nav_move_bps,
+        )?;
+        set_price_return_data(mint_price, mint.decimals, quote_decimals)?;
 
         Ok(())
     }
 
+    /// Buys (`SerumSide::Bid`) or sells base worth `quote_amount` on the Mango spot market
+    /// and settles the fill into the Mango account's deposits, so the collateral is valued
+    /// the same way as the rest of the NAV. Mints buy with part of the deposit, rebalances
+    /// sell spot collateral
+    ///
+    /// Spot accounts expected:
+    ///
+    /// 0. `[]` mango_signer_ai
+    /// 1. `[]` dex_program_ai
+    /// 2. `[writable]` spot_market_ai
+    /// 3. `[writable]` spot_bids_ai
+    /// 4. `[writable]` spot_asks_ai
+    /// 5. `[writable]` dex_request_queue_ai
+    /// 6. `[writable]` dex_event_queue_ai
+    /// 7. `[writable]` dex_base_ai
+    /// 8. `[writable]` dex_quote_ai
+    /// 9. `[]` base_root_bank_ai
+    /// 10. `[writable]` base_node_bank_ai
+    /// 11. `[writable]` base_vault_ai
+    /// 12. `[]` dex_signer_ai
+    /// 13. `[]` msrm_or_srm_vault_ai
+    /// 14. `[writable]` spot_open_orders_ai
+    /// 15. `[]` rent_ai
+    /// 16.. `[]` open orders of the other markets in the margin basket
     #[inline(never)]
-    fn burn_leverage_token<'a>(
-        program_id: &Pubkey,
-        accounts: &[AccountInfo<'a>],
-        quantity: u64,
+    fn trade_mango_spot<'a>(
+        quasar_group: &QuasarGroup,
+        token_mint_ai: &AccountInfo<'a>,
+        mango_program_ai: &AccountInfo<'a>,
+        mango_group_ai: &AccountInfo<'a>,
+        mango_account_ai: &AccountInfo<'a>,
+        mango_cache_ai: &AccountInfo<'a>,
+        quote_root_bank_ai: &AccountInfo<'a>,
+        quote_node_bank_ai: &AccountInfo<'a>,
+        quote_vault_ai: &AccountInfo<'a>,
+        token_program_ai: &AccountInfo<'a>,
+        pda_ai: &AccountInfo<'a>,
+        spot_ais: &[AccountInfo<'a>],
+        signers_seeds: &[&[&[u8]]],
+        side: SerumSide,
+        quote_amount: u64,
     ) -> QuasarResult {
-        const NUM_FIXED: usize = 15;
-        let accounts = array_ref![accounts, 0, NUM_FIXED + MAX_PAIRS];
-        let (fixed_ais, mango_open_orders_ais) = array_refs![accounts, NUM_FIXED, MAX_PAIRS];
-        let [quasar_group_ai, token_mint_ai, owner_leverage_token_account_ai, mango_program_ai, mango_group_ai, mango_account_ai, owner_ai, mango_cache_ai, root_bank_ai, node_bank_ai, vault_ai, token_program_ai, owner_quote_token_account_ai, pda_ai, mango_signer_ai] =
-            fixed_ais;
-
-        let quasar_group = QuasarGroup::load_checked(quasar_group_ai, program_id)?;
-
-        check_eq!(
-            *owner_leverage_token_account_ai.key,
-            get_associated_token_address(owner_ai.key, token_mint_ai.key),
-            QuasarErrorCode::InvalidAccount
-        );
-
-        let leverage_token_index =
-            quasar_group.find_leverage_token_index_by_mint(token_mint_ai.key);
+        const NUM_SPOT: usize = 16;
+        check!(spot_ais.len() >= NUM_SPOT, QuasarErrorCode::InvalidAccount)?;
+        let (spot_fixed_ais, open_orders_ais) = array_refs![spot_ais, NUM_SPOT; ..;];
         check!(
-            leverage_token_index.is_some(),
-            QuasarErrorCode::InvalidToken
-        );
+            open_orders_ais.len() <= MAX_PAIRS,
+            QuasarErrorCode::InvalidParam
+        )?;
+        let [mango_signer_ai, dex_program_ai, spot_market_ai, spot_bids_ai, spot_asks_ai, dex_request_queue_ai, dex_event_queue_ai, dex_base_ai, dex_quote_ai, base_root_bank_ai, base_node_bank_ai, base_vault_ai, dex_signer_ai, msrm_or_srm_vault_ai, spot_open_orders_ai, rent_ai] =
+            spot_fixed_ais;
 
-        let native_price;
+        let leverage_token_index = quasar_group
+            .find_leverage_token_index_by_mint(token_mint_ai.key)
+            .ok_or(throw_err!(QuasarErrorCode::InvalidToken))?;
+        let leverage_token = &quasar_group.leverage_tokens[leverage_token_index];
+
+        let market_index;
+        let mut mango_open_orders_ais;
+        let (limit_price, max_coin_qty);
         {
-            let mango_group = MangoGroup::load_checked(&mango_group_ai, mango_program_ai.key)?;
+            let mango_group = MangoGroup::load_checked(mango_group_ai, mango_program_ai.key)?;
             let mango_cache =
-                MangoCache::load_checked(&mango_cache_ai, mango_program_ai.key, &mango_group)?;
+                MangoCache::load_checked(mango_cache_ai, mango_program_ai.key, &mango_group)?;
             let mango_account = MangoAccount::load_checked(
-                &mango_account_ai,
+                mango_account_ai,
                 mango_program_ai.key,
                 mango_group_ai.key,
             )?;
 
+            market_index = mango_group
+                .find_token_index(&leverage_token.base_token_mint)
+                .ok_or(throw_err!(QuasarErrorCode::InvalidToken))?;
             check_eq!(
-                *owner_leverage_token_account_ai.key,
-                get_associated_token_address(owner_ai.key, token_mint_ai.key),
+                mango_group.spot_markets[market_index].spot_market,
+                *spot_market_ai.key,
                 QuasarErrorCode::InvalidAccount
-            );
-
-            let leverage_token_index = quasar_group
-                .find_leverage_token_index_by_mint(token_mint_ai.key)
-                .unwrap();
-            let leverage_token = quasar_group.leverage_tokens[leverage_token_index];
-
+            )?;
             check_eq!(
-                leverage_token.mango_account,
-                *mango_account_ai.key,
+                mango_group.dex_program_id,
+                *dex_program_ai.key,
+                QuasarErrorCode::InvalidProgramId
+            )?;
+            check!(
+                mango_account.spot_open_orders[market_index] != Pubkey::default(),
                 QuasarErrorCode::InvalidAccount
-            );
+            )?;
+            check_eq!(
+                mango_account.spot_open_orders[market_index],
+                *spot_open_orders_ai.key,
+                QuasarErrorCode::InvalidAccount
+            )?;
 
-            native_price = leverage_token.get_native_price(
+            // The market's open orders may not be in the margin basket before its first order
+            mango_open_orders_ais =
+                pad_open_orders(&mango_account, open_orders_ais, mango_program_ai)?;
+            mango_open_orders_ais[market_index] = spot_open_orders_ai.clone();
+
+            let (coin_lot_size, pc_lot_size) = load_spot_lot_sizes(spot_market_ai, dex_program_ai)?;
+            let price = mango_cache.price_cache[market_index].price;
+            let limits = match side {
+                SerumSide::Bid => get_spot_bid_limits(
+                    quote_amount,
+                    price,
+                    coin_lot_size,
+                    pc_lot_size,
+                    MAX_SPOT_SWAP_SLIPPAGE_BPS,
+                )?,
+                SerumSide::Ask => get_spot_ask_limits(
+                    quote_amount,
+                    price,
+                    coin_lot_size,
+                    pc_lot_size,
+                    MAX_SPOT_SWAP_SLIPPAGE_BPS,
+                )?,
+            };
+            limit_price = limits.0;
+            max_coin_qty = limits.1;
+        }
+
+        // Too small to trade a single lot, the amount just stays where it is
+        if limit_price == 0 || max_coin_qty == 0 {
+            msg!("spot trade of {} skipped: below one lot", quote_amount);
+            return Ok(());
+        }
+        msg!(
+            "spot trade: {} quote worth of up to {} lots at {}",
+            quote_amount,
+            max_coin_qty,
+            limit_price
+        );
+
+        place_mango_spot_order(
+            mango_program_ai,
+            mango_group_ai,
+            mango_account_ai,
+            pda_ai,
+            mango_cache_ai,
+            dex_program_ai,
+            spot_market_ai,
+            spot_bids_ai,
+            spot_asks_ai,
+            dex_request_queue_ai,
+            dex_event_queue_ai,
+            dex_base_ai,
+            dex_quote_ai,
+            base_root_bank_ai,
+            base_node_bank_ai,
+            base_vault_ai,
+            quote_root_bank_ai,
+            quote_node_bank_ai,
+            quote_vault_ai,
+            token_program_ai,
+            mango_signer_ai,
+            rent_ai,
+            dex_signer_ai,
+            msrm_or_srm_vault_ai,
+            &mango_open_orders_ais,
+            market_index,
+            signers_seeds,
+            NewOrderInstructionV3 {
+                side,
+                limit_price: NonZeroU64::new(limit_price).unwrap(),
+                max_coin_qty: NonZeroU64::new(max_coin_qty).unwrap(),
+                // Only bids spend quote, asks are bounded by `max_coin_qty` alone
+                max_native_pc_qty_including_fees: match side {
+                    SerumSide::Bid => NonZeroU64::new(quote_amount).unwrap(),
+                    SerumSide::Ask => NonZeroU64::new(u64::MAX).unwrap(),
+                },
+                self_trade_behavior: SelfTradeBehavior::AbortTransaction,
+                order_type: SerumOrderType::ImmediateOrCancel,
+                client_order_id: 0,
+                limit: u16::MAX,
+            },
+        )?;
+
+        settle_mango_spot_funds(
+            mango_program_ai,
+            mango_group_ai,
+            mango_cache_ai,
+            pda_ai,
+            mango_account_ai,
+            dex_program_ai,
+            spot_market_ai,
+            spot_open_orders_ai,
+            mango_signer_ai,
+            dex_base_ai,
+            dex_quote_ai,
+            base_root_bank_ai,
+            base_node_bank_ai,
+            quote_root_bank_ai,
+            quote_node_bank_ai,
+            base_vault_ai,
+            quote_vault_ai,
+            dex_signer_ai,
+            token_program_ai,
+            signers_seeds,
+        )?;
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn burn_leverage_token<'a>(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo<'a>],
+        quantity: u64,
+    ) -> QuasarResult {
+        check!(quantity > 0, QuasarErrorCode::InvalidParam)?;
+
+        let BurnAccounts {
+            quasar_group_ai,
+            token_mint_ai,
+            owner_leverage_token_account_ai,
+            mango_program_ai,
+            mango_group_ai,
+            mango_account_ai,
+            owner_ai,
+            mango_cache_ai,
+            root_bank_ai,
+            node_bank_ai,
+            vault_ai,
+            token_program_ai,
+            owner_quote_token_account_ai,
+            pda_ai,
+            mango_signer_ai,
+            fee_vault_ai,
+            open_orders_ais,
+            instructions_sysvar_ai,
+        } = BurnAccounts::load(accounts)?;
+
+        let mut quasar_group = QuasarGroup::load_mut_checked(quasar_group_ai, program_id)?;
+        let leverage_token_index = quasar_group
+            .find_leverage_token_index_by_mint(token_mint_ai.key)
+            .ok_or(throw_err!(QuasarErrorCode::InvalidToken))?;
+        let (token_ledger_ais, open_orders_ais) = split_token_ledger_ais(
+            &quasar_group.leverage_tokens[leverage_token_index],
+            open_orders_ais,
+        )?;
+        let mut token_ledger = load_token_ledger(
+            &quasar_group.leverage_tokens[leverage_token_index],
+            token_ledger_ais,
+            program_id,
+        )?;
+        check_eq!(
+            *owner_leverage_token_account_ai.key,
+            get_associated_token_address(owner_ai.key, token_mint_ai.key),
+            QuasarErrorCode::InvalidAccount
+        )?;
+        check_eq!(
+            quasar_group.leverage_tokens[leverage_token_index].mango_account,
+            *mango_account_ai.key,
+            QuasarErrorCode::InvalidAccount
+        )?;
+        if quasar_group.leverage_tokens[leverage_token_index].same_slot_burn_guard != 0 {
+            let instructions_sysvar_ai =
+                instructions_sysvar_ai.ok_or(throw_err!(QuasarErrorCode::InvalidAccount))?;
+            check_no_mint_in_transaction(program_id, token_mint_ai.key, instructions_sysvar_ai)?;
+        }
+
+        let burn_quote;
+        let quote_mint;
+        let quote_decimals;
+        let quote_deposit;
+        let mango_open_orders_ais;
+        {
+            let (mango_group, mango_cache, mango_account) = load_mango_context(
+                mango_program_ai.key,
+                mango_group_ai,
+                mango_cache_ai,
+                mango_account_ai,
+            )?;
+
+            mango_open_orders_ais =
+                pad_open_orders(&mango_account, open_orders_ais, mango_program_ai)?;
+
+            burn_quote = quote_burn(
+                &quasar_group,
+                leverage_token_index,
                 token_mint_ai,
                 &mango_group,
                 &mango_account,
                 &mango_cache,
+                quantity,
             )?;
+            quote_mint = quasar_group.leverage_tokens[leverage_token_index].quote_mint;
+            quote_decimals = mango_group.tokens[QUOTE_INDEX].decimals;
+            quote_deposit = get_mango_quote_value(&mango_account, &mango_cache)?;
         }
 
+        let owner_quote_token_account =
+            TokenAccount::unpack(&owner_quote_token_account_ai.try_borrow_data()?)?;
+        check_eq!(
+            owner_quote_token_account.mint,
+            quote_mint,
+            QuasarErrorCode::InvalidToken
+        )?;
+
+        let BurnQuote {
+            native_price,
+            burn_price,
+            supply,
+            quote_amount,
+            fee,
+        } = burn_quote;
+        let decimals = Mint::unpack(&token_mint_ai.try_borrow_data()?)?.decimals;
+
         invoke_burn(
             token_program_ai,
             owner_leverage_token_account_ai,
@@ -405,8 +1240,28 @@ impl Processor {
             &[],
             quantity,
         )?;
+        let net_deposit = match token_ledger.as_mut() {
+            Some(token_ledger) => token_ledger.record_burn(quantity, supply)?,
+            None => 0,
+        };
+        // As for mints, the group is only written while a cap is set
+        if quasar_group.max_net_deposits != 0 && net_deposit > 0 {
+            check!(
+                quasar_group_ai.is_writable,
+                QuasarErrorCode::AccountNotWritable
+            )?;
+            quasar_group.remove_net_deposits(net_deposit)?;
+        }
 
         let signer_seeds = gen_signer_seeds(&quasar_group.signer_nonce, quasar_group_ai.key);
+        let net_asset_value = native_price
+            .checked_mul(I80F48::from_num(supply))
+            .ok_or(math_err!())?;
+        let allow_borrow = quasar_group.leverage_tokens[leverage_token_index].check_burn_borrow(
+            quote_amount,
+            quote_deposit,
+            net_asset_value,
+        )?;
 
         withdraw_from_mango_account(
             mango_program_ai,
@@ -420,173 +1275,2418 @@ impl Processor {
             owner_quote_token_account_ai,
             mango_signer_ai,
             token_program_ai,
-            mango_open_orders_ais,
+            &mango_open_orders_ais,
             &[&signer_seeds],
-            quantity * native_price.to_num::<u64>(),
-            false,
+            quote_amount,
+            allow_borrow,
+        )?;
+
+        if fee > 0 {
+            check_eq!(
+                *fee_vault_ai.key,
+                quasar_group.get_fee_vault(&quote_mint),
+                QuasarErrorCode::InvalidAccount
+            )?;
+            invoke_transfer(
+                token_program_ai,
+                owner_quote_token_account_ai,
+                fee_vault_ai,
+                owner_ai,
+                &[],
+                fee,
+            )?;
+            msg!("burn fee: {}", fee);
+        }
+
+        // Burning the whole supply resets the price to the initial one, nothing left to protect
+        let supply_after = Mint::unpack(&token_mint_ai.try_borrow_data()?)?.supply;
+        if supply_after > 0 {
+            let native_price_after = load_native_price(
+                &quasar_group,
+                token_mint_ai,
+                mango_program_ai,
+                mango_group_ai,
+                mango_account_ai,
+                mango_cache_ai,
+            )?;
+            check_nav_move(
+                native_price,
+                native_price_after,
+                quasar_group.max_nav_move_bps,
+            )?;
+        }
+        set_price_return_data(burn_price, decimals, quote_decimals)?;
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn rebalance<'a>(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo<'a>],
+        target_exposure: Option<I80F48>,
+    ) -> QuasarResult {
+        let RebalanceAccounts {
+            quasar_group_ai,
+            token_mint_ai,
+            pda_ai,
+            mango_program_ai,
+            mango_group_ai,
+            mango_account_ai,
+            caller_ai,
+            mango_cache_ai,
+            mango_perp_market_ai,
+            mango_bids_ai,
+            mango_asks_ai,
+            mango_event_queue_ai,
+            mango_open_orders_ais,
+            trailing_ais,
+        } = RebalanceAccounts::load(accounts)?;
+
+        let mut quasar_group = QuasarGroup::load_mut_checked(quasar_group_ai, program_id)?;
+
+        if !quasar_group.is_rebalance_permissionless() {
+            check!(caller_ai.is_signer, QuasarErrorCode::SignerNecessary)?;
+            check_eq!(
+                *caller_ai.key,
+                quasar_group.rebalance_authority,
+                QuasarErrorCode::InvalidSignerKey
+            )?;
+        }
+
+        let leverage_token_index = quasar_group
+            .find_leverage_token_index_by_mint(token_mint_ai.key)
+            .ok_or(throw_err!(QuasarErrorCode::InvalidToken))?;
+        let leverage_token = quasar_group.leverage_tokens[leverage_token_index];
+        leverage_token.check_active()?;
+
+        check_eq!(
+            leverage_token.mango_account,
+            *mango_account_ai.key,
+            QuasarErrorCode::InvalidAccount
+        )?;
+        check_eq!(
+            leverage_token.mango_perp_market,
+            *mango_perp_market_ai.key,
+            QuasarErrorCode::InvalidAccount
+        )?;
+
+        // A token following a rebalance config passes it first, then its base oracle when it
+        // prices rebalances off it. Spot collateral is only sold when the quote bank
+        // accounts, the token program and the spot accounts of `trade_mango_spot` follow,
+        // otherwise the perp leg takes the whole correction
+        const NUM_SPOT_TRADE: usize = RebalanceAccounts::NUM_SPOT_TRADE;
+        let (rebalance_config_ais, trailing_ais) =
+            split_rebalance_config_ais(&leverage_token, trailing_ais)?;
+        let (oracle_ais, spot_trade_ais) =
+            split_rebalance_oracle_ais(&leverage_token, trailing_ais)?;
+        check!(
+            spot_trade_ais.is_empty() || spot_trade_ais.len() == NUM_SPOT_TRADE,
+            QuasarErrorCode::InvalidAccount
         )?;
+        let target = load_rebalance_target(
+            &leverage_token,
+            rebalance_config_ais,
+            program_id,
+            caller_ai,
+            target_exposure,
+        )?;
+
+        let order_lots;
+        let slot;
+        let epoch;
+        let market_index;
+        let effective_leverage_before;
+        let base_position_before;
+        let spot_sell_amount;
+        let basket_open_orders_ais: Vec<AccountInfo>;
+        {
+            let (mango_group, mango_cache, mango_account) = load_mango_context(
+                mango_program_ai.key,
+                mango_group_ai,
+                mango_cache_ai,
+                mango_account_ai,
+            )?;
+
+            // Mango can delist the perp market of a token
+            market_index = mango_group
+                .find_perp_market_index(&leverage_token.mango_perp_market)
+                .ok_or(throw_err!(QuasarErrorCode::InvalidAccount))?;
+
+            let clock = Clock::get()?;
+            let now_ts = clock.unix_timestamp as u64;
+            check_mango_cache_fresh(&mango_group, &mango_cache, now_ts)?;
+
+            base_position_before = get_perp_base_position(&mango_account, market_index);
+
+            let price = load_rebalance_price(
+                &quasar_group,
+                &leverage_token,
+                &mango_group,
+                &mango_cache,
+                market_index,
+                oracle_ais,
+            )?;
+            // The order is sized off the volatility this price folds in
+            let stored_token = &mut quasar_group.leverage_tokens[leverage_token_index];
+            stored_token.update_volatility(price, clock.slot)?;
+            let order = compute_rebalance_order(
+                target,
+                price,
+                &mango_group,
+                &mango_cache,
+                &mango_account,
+                market_index,
+                stored_token,
+            )?;
+            // The perp order leaves the spot collateral the split sells to the spot leg, so
+            // a rebalance selling spot can't go ahead without the spot market accounts
+            check!(
+                order.spot_sell_amount == 0 || !spot_trade_ais.is_empty(),
+                QuasarErrorCode::InvalidAccount
+            )?;
+
+            slot = clock.slot;
+            leverage_token.check_rebalance_due(order.effective_leverage, slot)?;
+            leverage_token.check_epoch_rebalance(order.effective_leverage, clock.epoch)?;
+            epoch = clock.epoch;
+            if leverage_token.is_over_max_leverage(order.effective_leverage) {
+                msg!(
+                    "effective leverage {} over the cap, deleveraging",
+                    format_leverage(order.effective_leverage)
+                );
+            }
+
+            basket_open_orders_ais = mango_open_orders_ais
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| mango_account.in_margin_basket[*i])
+                .map(|(_, ai)| ai.clone())
+                .collect();
+
+            let stored_token = &mut quasar_group.leverage_tokens[leverage_token_index];
+            stored_token.last_nav = order.net_asset_value;
+            stored_token.last_effective_leverage = order.effective_leverage;
+            stored_token.last_update_slot = slot;
+
+            order_lots = order.to_lots();
+            spot_sell_amount = order.spot_sell_amount;
+            effective_leverage_before = order.effective_leverage;
+        }
+
+        let signer_seeds = gen_signer_seeds(&quasar_group.signer_nonce, quasar_group_ai.key);
+
+        // The split reduces exposure out of the spot collateral first, so it is sold before
+        // the perp order. Mango then checks the health of the perp order on the account as
+        // the rebalance leaves it
+        if spot_sell_amount > 0 {
+            let spot_trade_ais = array_ref![spot_trade_ais, 0, NUM_SPOT_TRADE];
+            let (quote_ais, spot_ais) = array_refs![spot_trade_ais, 4, 16];
+            let [quote_root_bank_ai, quote_node_bank_ai, quote_vault_ai, token_program_ai] =
+                quote_ais;
+            let spot_ais: Vec<AccountInfo> = spot_ais
+                .iter()
+                .chain(basket_open_orders_ais.iter())
+                .cloned()
+                .collect();
+
+            Self::trade_mango_spot(
+                &quasar_group,
+                token_mint_ai,
+                mango_program_ai,
+                mango_group_ai,
+                mango_account_ai,
+                mango_cache_ai,
+                quote_root_bank_ai,
+                quote_node_bank_ai,
+                quote_vault_ai,
+                token_program_ai,
+                pda_ai,
+                &spot_ais,
+                &[&signer_seeds],
+                SerumSide::Ask,
+                spot_sell_amount,
+            )?;
+        }
+
+        let (price, quantity) = order_lots;
+        msg!("price: {}, quantity: {}", price, quantity.abs());
+
+        let mut filled_quantity = 0;
+        if quantity.abs() > 0 {
+            place_mango_perp_order(
+                mango_program_ai,
+                mango_group_ai,
+                mango_account_ai,
+                pda_ai,
+                mango_cache_ai,
+                mango_perp_market_ai,
+                mango_bids_ai,
+                mango_asks_ai,
+                mango_event_queue_ai,
+                mango_open_orders_ais,
+                &[&signer_seeds],
+                price,
+                quantity.abs(),
+                rebalance_client_order_id(slot, leverage_token_index),
+                if quantity > 0 { Side::Bid } else { Side::Ask },
+                OrderType::Limit,
+            )?;
+
+            // A limit order may only fill partially (or not at all), so compare against
+            // the position Mango actually reports after the order
+            let mango_account = MangoAccount::load_checked(
+                &mango_account_ai,
+                mango_program_ai.key,
+                mango_group_ai.key,
+            )?;
+            filled_quantity =
+                get_perp_base_position(&mango_account, market_index) - base_position_before;
+        }
+
+        let pending_quantity = quantity - filled_quantity;
+        if pending_quantity != 0 {
+            msg!(
+                "rebalance pending: {} of {} base lots not filled",
+                pending_quantity,
+                quantity
+            );
+        }
+        quasar_group.leverage_tokens[leverage_token_index].pending_rebalance_quantity =
+            pending_quantity;
+
+        let traded = filled_quantity != 0 || spot_sell_amount > 0;
+        let effective_leverage_after = if traded {
+            let (mango_group, mango_cache, mango_account) = load_mango_context(
+                mango_program_ai.key,
+                mango_group_ai,
+                mango_cache_ai,
+                mango_account_ai,
+            )?;
+            NavBreakdown::load(&mango_group, &mango_cache, &mango_account, market_index)?
+                .effective_leverage()?
+        } else {
+            effective_leverage_before
+        };
+        let result = RebalanceResult {
+            traded,
+            side: if quantity < 0 { Side::Ask } else { Side::Bid },
+            price: if quantity != 0 { price } else { 0 },
+            quantity: filled_quantity.abs(),
+            effective_leverage_before,
+            effective_leverage_after,
+        };
+        // Only trades start the cooldown, count against the epoch's rebalances and make the
+        // history
+        if traded {
+            let stored_token = &mut quasar_group.leverage_tokens[leverage_token_index];
+            stored_token.last_rebalance_slot = slot;
+            stored_token.record_epoch_rebalance(epoch);
+            stored_token.record_rebalance(RebalanceRecord {
+                slot,
+                price: result.price,
+                quantity: result.quantity,
+                side: result.side as u8,
+                padding: [0u8; 7],
+            });
+        }
+        set_return_data(&result.pack());
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn set_rebalance_authority(program_id: &Pubkey, accounts: &[AccountInfo]) -> QuasarResult {
+        const NUM_FIXED: usize = 3;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [quasar_group_ai, rebalance_authority_ai, admin_ai] = accounts;
+
+        let mut quasar_group = QuasarGroup::load_mut_checked(quasar_group_ai, program_id)?;
+        check!(admin_ai.is_signer, QuasarErrorCode::SignerNecessary)?;
+        check_eq!(
+            admin_ai.key,
+            &quasar_group.admin_key,
+            QuasarErrorCode::InvalidAdminKey
+        )?;
+
+        quasar_group.rebalance_authority = *rebalance_authority_ai.key;
+        msg!("rebalance authority: {}", rebalance_authority_ai.key);
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn quote_redeem(program_id: &Pubkey, accounts: &[AccountInfo], quantity: u64) -> QuasarResult {
+        check!(quantity > 0, QuasarErrorCode::InvalidParam)?;
+
+        const NUM_FIXED: usize = 6;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [quasar_group_ai, token_mint_ai, mango_program_ai, mango_group_ai, mango_account_ai, mango_cache_ai] =
+            accounts;
+
+        let quasar_group = QuasarGroup::load_checked(quasar_group_ai, program_id)?;
+        check_eq!(
+            *mango_program_ai.key,
+            quasar_group.mango_program_id,
+            QuasarErrorCode::InvalidProgramId
+        )?;
+
+        let leverage_token_index = quasar_group
+            .find_leverage_token_index_by_mint(token_mint_ai.key)
+            .ok_or(throw_err!(QuasarErrorCode::InvalidToken))?;
+        let leverage_token = quasar_group.leverage_tokens[leverage_token_index];
+
+        check_eq!(
+            leverage_token.mango_account,
+            *mango_account_ai.key,
+            QuasarErrorCode::InvalidAccount
+        )?;
+
+        let (mango_group, mango_cache, mango_account) = load_mango_context(
+            mango_program_ai.key,
+            mango_group_ai,
+            mango_cache_ai,
+            mango_account_ai,
+        )?;
+
+        // Quotes what a burn would redeem, so it fails the same way
+        let BurnQuote {
+            quote_amount, fee, ..
+        } = quote_burn(
+            &quasar_group,
+            leverage_token_index,
+            token_mint_ai,
+            &mango_group,
+            &mango_account,
+            &mango_cache,
+            quantity,
+        )?;
+        let quote_amount = quote_amount - fee;
+        msg!("redeemable quote amount: {}", quote_amount);
+        set_return_data(&quote_amount.to_le_bytes());
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn set_treasury(program_id: &Pubkey, accounts: &[AccountInfo]) -> QuasarResult {
+        const NUM_FIXED: usize = 4;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [quasar_group_ai, fee_vault_ai, treasury_ai, admin_ai] = accounts;
+
+        let mut quasar_group = QuasarGroup::load_mut_checked(quasar_group_ai, program_id)?;
+        check!(admin_ai.is_signer, QuasarErrorCode::SignerNecessary)?;
+        check_eq!(
+            admin_ai.key,
+            &quasar_group.admin_key,
+            QuasarErrorCode::InvalidAdminKey
+        )?;
+
+        let fee_vault = TokenAccount::unpack(&fee_vault_ai.try_borrow_data()?)?;
+        check_eq!(
+            fee_vault.owner,
+            quasar_group.signer_key,
+            QuasarErrorCode::InvalidOwner
+        )?;
+        let treasury = TokenAccount::unpack(&treasury_ai.try_borrow_data()?)?;
+        check_eq!(treasury.mint, fee_vault.mint, QuasarErrorCode::InvalidToken)?;
+
+        quasar_group.quote_mint = fee_vault.mint;
+        quasar_group.fee_vault = *fee_vault_ai.key;
+        quasar_group.treasury = *treasury_ai.key;
+        quasar_group.treasury_owner = treasury.owner;
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn withdraw_fees<'a>(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo<'a>],
+        quantity: u64,
+    ) -> QuasarResult {
+        const NUM_FIXED: usize = 6;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [quasar_group_ai, fee_vault_ai, treasury_ai, pda_ai, token_program_ai, admin_ai] =
+            accounts;
+        check!(
+            fee_vault_ai.is_writable,
+            QuasarErrorCode::AccountNotWritable
+        )?;
+        check!(treasury_ai.is_writable, QuasarErrorCode::AccountNotWritable)?;
+
+        let mut quasar_group = QuasarGroup::load_mut_checked(quasar_group_ai, program_id)?;
+        check!(admin_ai.is_signer, QuasarErrorCode::SignerNecessary)?;
+        check_eq!(
+            admin_ai.key,
+            &quasar_group.admin_key,
+            QuasarErrorCode::InvalidAdminKey
+        )?;
+        check_eq!(
+            *pda_ai.key,
+            quasar_group.signer_key,
+            QuasarErrorCode::InvalidSignerKey
+        )?;
+
+        // Each quote currency has its own vault and treasury
+        let fee_vault = TokenAccount::unpack(&fee_vault_ai.try_borrow_data()?)?;
+        check_eq!(
+            *fee_vault_ai.key,
+            quasar_group.get_fee_vault(&fee_vault.mint),
+            QuasarErrorCode::InvalidAccount
+        )?;
+        check_eq!(
+            *treasury_ai.key,
+            quasar_group.get_treasury(&fee_vault.mint),
+            QuasarErrorCode::InvalidAccount
+        )?;
+        check!(
+            quantity <= fee_vault.amount,
+            QuasarErrorCode::InsufficientFunds
+        )?;
+
+        let signer_seeds = gen_signer_seeds(&quasar_group.signer_nonce, quasar_group_ai.key);
+        invoke_transfer(
+            token_program_ai,
+            fee_vault_ai,
+            treasury_ai,
+            pda_ai,
+            &[&signer_seeds],
+            quantity,
+        )?;
+
+        quasar_group.total_fees_withdrawn = quasar_group
+            .total_fees_withdrawn
+            .checked_add(quantity)
+            .ok_or(math_err!())?;
+        msg!(
+            "total fees withdrawn: {}",
+            quasar_group.total_fees_withdrawn
+        );
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn set_leverage_token_params(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        min_native_price: Option<I80F48>,
+        spot_swap_bps: Option<u16>,
+        mint_fee_bps: Option<u16>,
+        burn_fee_bps: Option<u16>,
+        max_effective_leverage: Option<I80F48>,
+        rebalance_cooldown_slots: Option<u64>,
+        allow_borrow: Option<bool>,
+        max_borrow_bps: Option<u16>,
+        min_mint_increment: Option<u32>,
+        same_slot_burn_guard: Option<bool>,
+        max_redemption_per_tx: Option<u64>,
+        volatility_target: Option<I80F48>,
+        price_offset_bps: Option<u16>,
+        max_rebalances_per_epoch: Option<u16>,
+    ) -> QuasarResult {
+        const NUM_FIXED: usize = 3;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [quasar_group_ai, token_mint_ai, admin_ai] = accounts;
+
+        let mut quasar_group = QuasarGroup::load_mut_checked(quasar_group_ai, program_id)?;
+        check!(admin_ai.is_signer, QuasarErrorCode::SignerNecessary)?;
+        check_eq!(
+            admin_ai.key,
+            &quasar_group.admin_key,
+            QuasarErrorCode::InvalidAdminKey
+        )?;
+
+        let leverage_token_index = quasar_group
+            .find_leverage_token_index_by_mint(token_mint_ai.key)
+            .ok_or(throw_err!(QuasarErrorCode::InvalidToken))?;
+        let leverage_token = &mut quasar_group.leverage_tokens[leverage_token_index];
+
+        if let Some(min_native_price) = min_native_price {
+            check!(
+                min_native_price >= ZERO_I80F48,
+                QuasarErrorCode::InvalidParam
+            )?;
+            leverage_token.min_native_price = min_native_price;
+        }
+        if let Some(spot_swap_bps) = spot_swap_bps {
+            check!(
+                u64::from(spot_swap_bps) <= BPS_UNIT,
+                QuasarErrorCode::InvalidParam
+            )?;
+            leverage_token.spot_swap_bps = spot_swap_bps;
+        }
+        if let Some(mint_fee_bps) = mint_fee_bps {
+            check!(
+                mint_fee_bps == USE_GROUP_FEE || u64::from(mint_fee_bps) <= BPS_UNIT,
+                QuasarErrorCode::InvalidParam
+            )?;
+            leverage_token.mint_fee_bps = mint_fee_bps;
+        }
+        if let Some(burn_fee_bps) = burn_fee_bps {
+            check!(
+                burn_fee_bps == USE_GROUP_FEE || u64::from(burn_fee_bps) <= BPS_UNIT,
+                QuasarErrorCode::InvalidParam
+            )?;
+            leverage_token.burn_fee_bps = burn_fee_bps;
+        }
+        if let Some(max_effective_leverage) = max_effective_leverage {
+            // A cap below the target would skip the cooldown on every rebalance
+            check!(
+                max_effective_leverage == ZERO_I80F48
+                    || max_effective_leverage >= leverage_token.target_leverage.abs(),
+                QuasarErrorCode::InvalidParam
+            )?;
+            leverage_token.max_effective_leverage = max_effective_leverage;
+        }
+        if let Some(rebalance_cooldown_slots) = rebalance_cooldown_slots {
+            leverage_token.rebalance_cooldown_slots = rebalance_cooldown_slots;
+        }
+        if let Some(allow_borrow) = allow_borrow {
+            leverage_token.allow_borrow = allow_borrow as u8;
+        }
+        if let Some(max_borrow_bps) = max_borrow_bps {
+            check!(
+                u64::from(max_borrow_bps) <= BPS_UNIT,
+                QuasarErrorCode::InvalidParam
+            )?;
+            leverage_token.max_borrow_bps = max_borrow_bps;
+        }
+        if let Some(min_mint_increment) = min_mint_increment {
+            leverage_token.min_mint_increment = min_mint_increment;
+        }
+        if let Some(same_slot_burn_guard) = same_slot_burn_guard {
+            leverage_token.same_slot_burn_guard = same_slot_burn_guard as u8;
+        }
+        if let Some(max_redemption_per_tx) = max_redemption_per_tx {
+            leverage_token.max_redemption_per_tx = max_redemption_per_tx;
+        }
+        if let Some(volatility_target) = volatility_target {
+            check!(
+                volatility_target >= ZERO_I80F48,
+                QuasarErrorCode::InvalidParam
+            )?;
+            leverage_token.volatility_target = volatility_target;
+        }
+        if let Some(price_offset_bps) = price_offset_bps {
+            check!(
+                price_offset_bps <= MAX_REBALANCE_PRICE_OFFSET_BPS,
+                QuasarErrorCode::InvalidParam
+            )?;
+            leverage_token.price_offset_bps = price_offset_bps;
+        }
+        if let Some(max_rebalances_per_epoch) = max_rebalances_per_epoch {
+            leverage_token.max_rebalances_per_epoch = max_rebalances_per_epoch;
+        }
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn remove_leverage_token(program_id: &Pubkey, accounts: &[AccountInfo]) -> QuasarResult {
+        const NUM_FIXED: usize = 6;
+        let (fixed_ais, quote_fee_vault_ais) = array_refs![accounts, NUM_FIXED; ..;];
+        let [quasar_group_ai, token_mint_ai, mango_program_ai, mango_group_ai, mango_account_ai, admin_ai] =
+            fixed_ais;
+
+        let mut quasar_group = QuasarGroup::load_mut_checked(quasar_group_ai, program_id)?;
+        check!(admin_ai.is_signer, QuasarErrorCode::SignerNecessary)?;
+        check_eq!(
+            admin_ai.key,
+            &quasar_group.admin_key,
+            QuasarErrorCode::InvalidAdminKey
+        )?;
+        check_eq!(
+            *mango_program_ai.key,
+            quasar_group.mango_program_id,
+            QuasarErrorCode::InvalidProgramId
+        )?;
+
+        let leverage_token_index = quasar_group
+            .find_leverage_token_index_by_mint(token_mint_ai.key)
+            .ok_or(throw_err!(QuasarErrorCode::InvalidToken))?;
+        let leverage_token = quasar_group.leverage_tokens[leverage_token_index];
+        check_eq!(
+            leverage_token.mango_account,
+            *mango_account_ai.key,
+            QuasarErrorCode::InvalidAccount
+        )?;
+
+        // Removing a token with holders would orphan their funds. A token listed later may
+        // reuse the Mango account, and anything left in it would go to its first minters
+        let mint = Mint::unpack(&token_mint_ai.try_borrow_data()?)?;
+        check_eq!(mint.supply, 0, QuasarErrorCode::InvalidToken)?;
+        {
+            let mango_account = MangoAccount::load_checked(
+                mango_account_ai,
+                mango_program_ai.key,
+                mango_group_ai.key,
+            )?;
+            check_mango_account_empty(&mango_account)?;
+        }
+
+        // Nothing signs for the fee vault of a quote no token is listed in anymore once the
+        // group is closed, so its fees have to be withdrawn first
+        let quote_mint = leverage_token.quote_mint;
+        let is_last_of_quote = quote_mint != quasar_group.quote_mint
+            && !quasar_group.leverage_tokens[..quasar_group.num_leverage_tokens]
+                .iter()
+                .enumerate()
+                .any(|(i, other)| i != leverage_token_index && other.quote_mint == quote_mint);
+        if is_last_of_quote {
+            let quote_fee_vault_ai = quote_fee_vault_ais
+                .first()
+                .ok_or(throw_err!(QuasarErrorCode::InvalidAccount))?;
+            check_fee_vault_empty(quote_fee_vault_ai, &quasar_group.get_fee_vault(&quote_mint))?;
+        }
+
+        quasar_group.remove_leverage_token(leverage_token_index);
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn close_quasar_group(program_id: &Pubkey, accounts: &[AccountInfo]) -> QuasarResult {
+        const NUM_FIXED: usize = 4;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [quasar_group_ai, recipient_ai, admin_ai, fee_vault_ai] = accounts;
+
+        {
+            let mut quasar_group = QuasarGroup::load_mut_checked(quasar_group_ai, program_id)?;
+            check!(admin_ai.is_signer, QuasarErrorCode::SignerNecessary)?;
+            check_eq!(
+                admin_ai.key,
+                &quasar_group.admin_key,
+                QuasarErrorCode::InvalidAdminKey
+            )?;
+            check_eq!(
+                quasar_group.num_leverage_tokens,
+                0,
+                QuasarErrorCode::InvalidAccount
+            )?;
+            // Fees left in the vault could never be withdrawn without the group signing
+            if quasar_group.fee_vault != Pubkey::default() {
+                check_fee_vault_empty(fee_vault_ai, &quasar_group.fee_vault)?;
+            }
+
+            quasar_group.meta_data = MetaData::default();
+        }
+
+        let lamports = quasar_group_ai.lamports();
+        **recipient_ai.lamports.borrow_mut() = recipient_ai
+            .lamports()
+            .checked_add(lamports)
+            .ok_or(math_err!())?;
+        **quasar_group_ai.lamports.borrow_mut() = 0;
+        msg!("returned {} lamports", lamports);
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn init_spot_open_orders(program_id: &Pubkey, accounts: &[AccountInfo]) -> QuasarResult {
+        const NUM_FIXED: usize = 12;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [quasar_group_ai, token_mint_ai, mango_program_ai, mango_group_ai, mango_account_ai, pda_ai, dex_program_ai, spot_open_orders_ai, spot_market_ai, mango_signer_ai, rent_ai, admin_ai] =
+            accounts;
+
+        let quasar_group = QuasarGroup::load_checked(quasar_group_ai, program_id)?;
+        check!(admin_ai.is_signer, QuasarErrorCode::SignerNecessary)?;
+        check_eq!(
+            admin_ai.key,
+            &quasar_group.admin_key,
+            QuasarErrorCode::InvalidAdminKey
+        )?;
+        check_eq!(
+            *mango_program_ai.key,
+            quasar_group.mango_program_id,
+            QuasarErrorCode::InvalidProgramId
+        )?;
+
+        let leverage_token_index = quasar_group
+            .find_leverage_token_index_by_mint(token_mint_ai.key)
+            .ok_or(throw_err!(QuasarErrorCode::InvalidToken))?;
+        let leverage_token = &quasar_group.leverage_tokens[leverage_token_index];
+        check_eq!(
+            leverage_token.mango_account,
+            *mango_account_ai.key,
+            QuasarErrorCode::InvalidAccount
+        )?;
+
+        {
+            let mango_group = MangoGroup::load_checked(mango_group_ai, mango_program_ai.key)?;
+            let market_index = mango_group
+                .find_token_index(&leverage_token.base_token_mint)
+                .ok_or(throw_err!(QuasarErrorCode::InvalidToken))?;
+            check_eq!(
+                mango_group.spot_markets[market_index].spot_market,
+                *spot_market_ai.key,
+                QuasarErrorCode::InvalidAccount
+            )?;
+        }
+
+        let signer_seeds = gen_signer_seeds(&quasar_group.signer_nonce, quasar_group_ai.key);
+        let instruction = mango::instruction::init_spot_open_orders(
+            mango_program_ai.key,
+            mango_group_ai.key,
+            mango_account_ai.key,
+            pda_ai.key,
+            dex_program_ai.key,
+            spot_open_orders_ai.key,
+            spot_market_ai.key,
+            mango_signer_ai.key,
+        )?;
+        let account_infos = [
+            mango_program_ai.clone(),
+            mango_group_ai.clone(),
+            mango_account_ai.clone(),
+            pda_ai.clone(),
+            dex_program_ai.clone(),
+            spot_open_orders_ai.clone(),
+            spot_market_ai.clone(),
+            mango_signer_ai.clone(),
+            rent_ai.clone(),
+        ];
+        invoke_signed(&instruction, &account_infos, &[&signer_seeds])
+            .map_err(|e| QuasarError::mango_cpi(MangoCpi::InitSpotOpenOrders, e))?;
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn set_fees(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        mint_fee_bps: u16,
+        burn_fee_bps: u16,
+    ) -> QuasarResult {
+        const NUM_FIXED: usize = 2;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [quasar_group_ai, admin_ai] = accounts;
+
+        let mut quasar_group = QuasarGroup::load_mut_checked(quasar_group_ai, program_id)?;
+        check!(admin_ai.is_signer, QuasarErrorCode::SignerNecessary)?;
+        check_eq!(
+            admin_ai.key,
+            &quasar_group.admin_key,
+            QuasarErrorCode::InvalidAdminKey
+        )?;
+        check!(
+            u64::from(mint_fee_bps) <= BPS_UNIT && u64::from(burn_fee_bps) <= BPS_UNIT,
+            QuasarErrorCode::InvalidParam
+        )?;
+
+        quasar_group.mint_fee_bps = mint_fee_bps;
+        quasar_group.burn_fee_bps = burn_fee_bps;
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn set_max_nav_move(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        max_nav_move_bps: u16,
+    ) -> QuasarResult {
+        const NUM_FIXED: usize = 2;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [quasar_group_ai, admin_ai] = accounts;
+
+        let mut quasar_group = QuasarGroup::load_mut_checked(quasar_group_ai, program_id)?;
+        check!(admin_ai.is_signer, QuasarErrorCode::SignerNecessary)?;
+        check_eq!(
+            admin_ai.key,
+            &quasar_group.admin_key,
+            QuasarErrorCode::InvalidAdminKey
+        )?;
+        check!(
+            u64::from(max_nav_move_bps) <= BPS_UNIT,
+            QuasarErrorCode::InvalidParam
+        )?;
+
+        quasar_group.max_nav_move_bps = max_nav_move_bps;
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn cancel_rebalance_orders(program_id: &Pubkey, accounts: &[AccountInfo]) -> QuasarResult {
+        const NUM_FIXED: usize = 10;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [quasar_group_ai, token_mint_ai, mango_program_ai, mango_group_ai, mango_account_ai, pda_ai, mango_perp_market_ai, mango_bids_ai, mango_asks_ai, caller_ai] =
+            accounts;
+
+        let mut quasar_group = QuasarGroup::load_mut_checked(quasar_group_ai, program_id)?;
+
+        // Anyone could otherwise pull every rebalance order before it fills
+        check!(caller_ai.is_signer, QuasarErrorCode::SignerNecessary)?;
+        let keeper = if quasar_group.is_rebalance_permissionless() {
+            quasar_group.admin_key
+        } else {
+            quasar_group.rebalance_authority
+        };
+        check_eq!(*caller_ai.key, keeper, QuasarErrorCode::InvalidSignerKey)?;
+
+        check_eq!(
+            *mango_program_ai.key,
+            quasar_group.mango_program_id,
+            QuasarErrorCode::InvalidProgramId
+        )?;
+        for ai in [
+            mango_account_ai,
+            mango_perp_market_ai,
+            mango_bids_ai,
+            mango_asks_ai,
+        ]
+        .iter()
+        {
+            check!(ai.is_writable, QuasarErrorCode::AccountNotWritable)?;
+        }
+        let leverage_token_index = quasar_group
+            .find_leverage_token_index_by_mint(token_mint_ai.key)
+            .ok_or(throw_err!(QuasarErrorCode::InvalidToken))?;
+        let leverage_token = quasar_group.leverage_tokens[leverage_token_index];
+        check_eq!(
+            leverage_token.mango_account,
+            *mango_account_ai.key,
+            QuasarErrorCode::InvalidAccount
+        )?;
+        check_eq!(
+            leverage_token.mango_perp_market,
+            *mango_perp_market_ai.key,
+            QuasarErrorCode::InvalidAccount
+        )?;
+
+        let signer_seeds = gen_signer_seeds(&quasar_group.signer_nonce, quasar_group_ai.key);
+        cancel_all_mango_perp_orders(
+            mango_program_ai,
+            mango_group_ai,
+            mango_account_ai,
+            pda_ai,
+            mango_perp_market_ai,
+            mango_bids_ai,
+            mango_asks_ai,
+            &[&signer_seeds],
+            u8::MAX,
+        )?;
+
+        // Nothing rests on the book anymore, the next rebalance starts over from the position
+        quasar_group.leverage_tokens[leverage_token_index].pending_rebalance_quantity = 0;
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn estimate_rebalance_cost(program_id: &Pubkey, accounts: &[AccountInfo]) -> QuasarResult {
+        const NUM_FIXED: usize = 6;
+        let (fixed_ais, trailing_ais) = array_refs![accounts, NUM_FIXED; ..;];
+        let [quasar_group_ai, token_mint_ai, mango_program_ai, mango_group_ai, mango_account_ai, mango_cache_ai] =
+            fixed_ais;
+
+        let quasar_group = QuasarGroup::load_checked(quasar_group_ai, program_id)?;
+        check_eq!(
+            *mango_program_ai.key,
+            quasar_group.mango_program_id,
+            QuasarErrorCode::InvalidProgramId
+        )?;
+
+        let leverage_token_index = quasar_group
+            .find_leverage_token_index_by_mint(token_mint_ai.key)
+            .ok_or(throw_err!(QuasarErrorCode::InvalidToken))?;
+        let mut leverage_token = quasar_group.leverage_tokens[leverage_token_index];
+        check_eq!(
+            leverage_token.mango_account,
+            *mango_account_ai.key,
+            QuasarErrorCode::InvalidAccount
+        )?;
+        let (rebalance_config_ais, trailing_ais) =
+            split_rebalance_config_ais(&leverage_token, trailing_ais)?;
+        let (oracle_ais, _) = split_rebalance_oracle_ais(&leverage_token, trailing_ais)?;
+        let target_leverage =
+            load_target_leverage(&leverage_token, rebalance_config_ais, program_id)?;
+
+        let mango_group = MangoGroup::load_checked(mango_group_ai, mango_program_ai.key)?;
+        let mango_cache =
+            MangoCache::load_checked(mango_cache_ai, mango_program_ai.key, &mango_group)?;
+        let mango_account =
+            MangoAccount::load_checked(mango_account_ai, mango_program_ai.key, mango_group_ai.key)?;
+
+        let clock = Clock::get()?;
+        let now_ts = clock.unix_timestamp as u64;
+        check_mango_cache_fresh(&mango_group, &mango_cache, now_ts)?;
+
+        let market_index = mango_group
+            .find_perp_market_index(&leverage_token.mango_perp_market)
+            .ok_or(throw_err!(QuasarErrorCode::InvalidAccount))?;
+        let price = load_rebalance_price(
+            &quasar_group,
+            &leverage_token,
+            &mango_group,
+            &mango_cache,
+            market_index,
+            oracle_ais,
+        )?;
+        // Same target as a rebalance at this price, on a copy of the token
+        leverage_token.update_volatility(price, clock.slot)?;
+        let order = compute_rebalance_order(
+            RebalanceTarget::Leverage(target_leverage),
+            price,
+            &mango_group,
+            &mango_cache,
+            &mango_account,
+            market_index,
+            &leverage_token,
+        )?;
+
+        // Estimate the order rebalance would actually place, with its lot rounding
+        let (price, quantity) = order.to_lots();
+        let fee = estimate_taker_fee(
+            &order.units,
+            BaseLots(I80F48::from_num(quantity)),
+            I80F48::from_num(price),
+            mango_group.perp_markets[market_index].taker_fee,
+        )?;
+        let fee: u64 = fee
+            .0
+            .checked_ceil()
+            .and_then(|fee| fee.checked_to_num())
+            .ok_or(math_err!())?;
+
+        msg!("estimated rebalance taker fee: {}", fee);
+        set_return_data(&fee.to_le_bytes());
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn init_rebalance_config(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        max_target_leverage: I80F48,
+    ) -> QuasarResult {
+        const NUM_FIXED: usize = 5;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [quasar_group_ai, token_mint_ai, rebalance_config_ai, update_authority_ai, admin_ai] =
+            accounts;
+
+        let mut quasar_group = QuasarGroup::load_mut_checked(quasar_group_ai, program_id)?;
+        check!(admin_ai.is_signer, QuasarErrorCode::SignerNecessary)?;
+        check_eq!(
+            admin_ai.key,
+            &quasar_group.admin_key,
+            QuasarErrorCode::InvalidAdminKey
+        )?;
+
+        let leverage_token_index = quasar_group
+            .find_leverage_token_index_by_mint(token_mint_ai.key)
+            .ok_or(throw_err!(QuasarErrorCode::InvalidToken))?;
+
+        let rent = Rent::get()?;
+        check_eq!(
+            rebalance_config_ai.owner,
+            program_id,
+            QuasarErrorCode::InvalidOwner
+        )?;
+        check!(
+            rent.is_exempt(
+                rebalance_config_ai.lamports(),
+                rebalance_config_ai.data_len()
+            ),
+            QuasarErrorCode::AccountNotRentExempt
+        )?;
+
+        let mut rebalance_config = RebalanceConfig::load_mut(rebalance_config_ai)?;
+        check!(
+            !rebalance_config.meta_data.is_initialized,
+            QuasarErrorCode::InvalidAccount
+        )?;
+
+        let leverage_token = &mut quasar_group.leverage_tokens[leverage_token_index];
+        rebalance_config.meta_data = MetaData::new(DataType::RebalanceConfig, 0, true);
+        rebalance_config.last_update = Clock::get()?.unix_timestamp as u64;
+        rebalance_config.quasar_group = *quasar_group_ai.key;
+        rebalance_config.leverage_token_mint = *token_mint_ai.key;
+        rebalance_config.update_authority = *update_authority_ai.key;
+        rebalance_config.max_target_leverage = max_target_leverage;
+        rebalance_config.check_target_leverage(leverage_token.target_leverage)?;
+        rebalance_config.target_leverage = leverage_token.target_leverage;
+
+        leverage_token.rebalance_config = *rebalance_config_ai.key;
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn update_rebalance_config(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        target_leverage: I80F48,
+    ) -> QuasarResult {
+        const NUM_FIXED: usize = 2;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [rebalance_config_ai, update_authority_ai] = accounts;
+
+        let mut rebalance_config =
+            RebalanceConfig::load_mut_checked(rebalance_config_ai, program_id)?;
+        check!(
+            update_authority_ai.is_signer,
+            QuasarErrorCode::SignerNecessary
+        )?;
+        check_eq!(
+            *update_authority_ai.key,
+            rebalance_config.update_authority,
+            QuasarErrorCode::InvalidSignerKey
+        )?;
+        rebalance_config.check_target_leverage(target_leverage)?;
+
+        rebalance_config.target_leverage = target_leverage;
+        rebalance_config.last_update = Clock::get()?.unix_timestamp as u64;
+        msg!("target leverage: {}", format_leverage(target_leverage));
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn set_size_premium(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        size_premium_bps: u16,
+    ) -> QuasarResult {
+        const NUM_FIXED: usize = 2;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [quasar_group_ai, admin_ai] = accounts;
+
+        let mut quasar_group = QuasarGroup::load_mut_checked(quasar_group_ai, program_id)?;
+        check!(admin_ai.is_signer, QuasarErrorCode::SignerNecessary)?;
+        check_eq!(
+            admin_ai.key,
+            &quasar_group.admin_key,
+            QuasarErrorCode::InvalidAdminKey
+        )?;
+        check!(
+            u64::from(size_premium_bps) <= BPS_UNIT,
+            QuasarErrorCode::InvalidParam
+        )?;
+
+        quasar_group.size_premium_bps = size_premium_bps;
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn set_token_state(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        state: TokenState,
+    ) -> QuasarResult {
+        const NUM_FIXED: usize = 10;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [quasar_group_ai, token_mint_ai, mango_program_ai, mango_group_ai, mango_account_ai, pda_ai, mango_perp_market_ai, mango_bids_ai, mango_asks_ai, admin_ai] =
+            accounts;
+
+        let mut quasar_group = QuasarGroup::load_mut_checked(quasar_group_ai, program_id)?;
+        check!(admin_ai.is_signer, QuasarErrorCode::SignerNecessary)?;
+        check_eq!(
+            admin_ai.key,
+            &quasar_group.admin_key,
+            QuasarErrorCode::InvalidAdminKey
+        )?;
+
+        let leverage_token_index = quasar_group
+            .find_leverage_token_index_by_mint(token_mint_ai.key)
+            .ok_or(throw_err!(QuasarErrorCode::InvalidToken))?;
+        let leverage_token = quasar_group.leverage_tokens[leverage_token_index];
+
+        if state == TokenState::Frozen && leverage_token.check_active().is_ok() {
+            check_eq!(
+                *mango_program_ai.key,
+                quasar_group.mango_program_id,
+                QuasarErrorCode::InvalidProgramId
+            )?;
+            for ai in [
+                mango_account_ai,
+                mango_perp_market_ai,
+                mango_bids_ai,
+                mango_asks_ai,
+            ]
+            .iter()
+            {
+                check!(ai.is_writable, QuasarErrorCode::AccountNotWritable)?;
+            }
+            check_eq!(
+                leverage_token.mango_account,
+                *mango_account_ai.key,
+                QuasarErrorCode::InvalidAccount
+            )?;
+            check_eq!(
+                leverage_token.mango_perp_market,
+                *mango_perp_market_ai.key,
+                QuasarErrorCode::InvalidAccount
+            )?;
+
+            let signer_seeds = gen_signer_seeds(&quasar_group.signer_nonce, quasar_group_ai.key);
+            cancel_all_mango_perp_orders(
+                mango_program_ai,
+                mango_group_ai,
+                mango_account_ai,
+                pda_ai,
+                mango_perp_market_ai,
+                mango_bids_ai,
+                mango_asks_ai,
+                &[&signer_seeds],
+                u8::MAX,
+            )?;
+            quasar_group.leverage_tokens[leverage_token_index].pending_rebalance_quantity = 0;
+        }
+
+        quasar_group.leverage_tokens[leverage_token_index].state = state as u8;
+        msg!("token state: {:?}", state);
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn set_quote_oracle(program_id: &Pubkey, accounts: &[AccountInfo]) -> QuasarResult {
+        const NUM_FIXED: usize = 3;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [quasar_group_ai, quote_oracle_ai, admin_ai] = accounts;
+
+        let mut quasar_group = QuasarGroup::load_mut_checked(quasar_group_ai, program_id)?;
+        check!(admin_ai.is_signer, QuasarErrorCode::SignerNecessary)?;
+        check_eq!(
+            admin_ai.key,
+            &quasar_group.admin_key,
+            QuasarErrorCode::InvalidAdminKey
+        )?;
+
+        if *quote_oracle_ai.key != Pubkey::default() {
+            let quote_price = read_oracle_price(quote_oracle_ai)?;
+            msg!("quote price: {}", quote_price);
+        }
+        quasar_group.quote_oracle = *quote_oracle_ai.key;
+        msg!("quote oracle: {}", quote_oracle_ai.key);
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn quote_reference_price(program_id: &Pubkey, accounts: &[AccountInfo]) -> QuasarResult {
+        const NUM_FIXED: usize = 7;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [quasar_group_ai, token_mint_ai, mango_program_ai, mango_group_ai, mango_account_ai, mango_cache_ai, quote_oracle_ai] =
+            accounts;
+
+        let quasar_group = QuasarGroup::load_checked(quasar_group_ai, program_id)?;
+        check_eq!(
+            *mango_program_ai.key,
+            quasar_group.mango_program_id,
+            QuasarErrorCode::InvalidProgramId
+        )?;
+        let native_price = load_native_price(
+            &quasar_group,
+            token_mint_ai,
+            mango_program_ai,
+            mango_group_ai,
+            mango_account_ai,
+            mango_cache_ai,
+        )?;
+
+        let leverage_token_index = quasar_group
+            .find_leverage_token_index_by_mint(token_mint_ai.key)
+            .ok_or(throw_err!(QuasarErrorCode::InvalidToken))?;
+        let quote_mint = quasar_group.leverage_tokens[leverage_token_index].quote_mint;
+
+        let mint = Mint::unpack(&token_mint_ai.try_borrow_data()?)?;
+        let supply = mint.supply;
+        let quote_price = load_quote_price(&quasar_group, &quote_mint, Some(quote_oracle_ai))?;
+        // Before the first mint the price is one reference unit whatever the quote trades at
+        let reference_price = if supply == 0 {
+            native_price
+        } else {
+            quote_to_reference(native_price, quote_price)?
+        };
+
+        msg!("reference price: {}", reference_price);
+        let quote_decimals = MangoGroup::load_checked(mango_group_ai, mango_program_ai.key)?.tokens
+            [QUOTE_INDEX]
+            .decimals;
+        set_price_return_data(reference_price, mint.decimals, quote_decimals)?;
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn set_listing_grace_period(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        listing_grace_slots: u64,
+    ) -> QuasarResult {
+        const NUM_FIXED: usize = 2;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [quasar_group_ai, admin_ai] = accounts;
+
+        let mut quasar_group = QuasarGroup::load_mut_checked(quasar_group_ai, program_id)?;
+        check!(admin_ai.is_signer, QuasarErrorCode::SignerNecessary)?;
+        check_eq!(
+            admin_ai.key,
+            &quasar_group.admin_key,
+            QuasarErrorCode::InvalidAdminKey
+        )?;
+
+        quasar_group.listing_grace_slots = listing_grace_slots;
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn reconcile(program_id: &Pubkey, accounts: &[AccountInfo]) -> QuasarResult {
+        const NUM_FIXED: usize = 3;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [quasar_group_ai, token_mint_ai, token_ledger_ai] = accounts;
+
+        let quasar_group = QuasarGroup::load_checked(quasar_group_ai, program_id)?;
+        let leverage_token_index = quasar_group
+            .find_leverage_token_index_by_mint(token_mint_ai.key)
+            .ok_or(throw_err!(QuasarErrorCode::InvalidToken))?;
+        check_eq!(
+            *token_ledger_ai.key,
+            quasar_group.leverage_tokens[leverage_token_index].token_ledger,
+            QuasarErrorCode::InvalidAccount
+        )?;
+        let token_ledger = TokenLedger::load_checked(token_ledger_ai, program_id)?;
+
+        let supply = Mint::unpack(&token_mint_ai.try_borrow_data()?)?.supply;
+        let expected_supply = token_ledger.get_expected_supply()?;
+        msg!("supply: {}, expected supply: {}", supply, expected_supply);
+        if supply != expected_supply {
+            msg!(
+                "supply discrepancy: {}",
+                i128::from(supply) - i128::from(expected_supply)
+            );
+        }
+
+        token_ledger.check_supply(supply)
+    }
+
+    #[inline(never)]
+    fn set_mango_perp_market(program_id: &Pubkey, accounts: &[AccountInfo]) -> QuasarResult {
+        const NUM_FIXED: usize = 7;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [quasar_group_ai, token_mint_ai, mango_program_ai, mango_group_ai, mango_account_ai, mango_perp_market_ai, admin_ai] =
+            accounts;
+
+        let mut quasar_group = QuasarGroup::load_mut_checked(quasar_group_ai, program_id)?;
+        check!(admin_ai.is_signer, QuasarErrorCode::SignerNecessary)?;
+        check_eq!(
+            admin_ai.key,
+            &quasar_group.admin_key,
+            QuasarErrorCode::InvalidAdminKey
+        )?;
+        check_eq!(
+            *mango_program_ai.key,
+            quasar_group.mango_program_id,
+            QuasarErrorCode::InvalidProgramId
+        )?;
+
+        let leverage_token_index = quasar_group
+            .find_leverage_token_index_by_mint(token_mint_ai.key)
+            .ok_or(throw_err!(QuasarErrorCode::InvalidToken))?;
+        let leverage_token = &mut quasar_group.leverage_tokens[leverage_token_index];
+        check_eq!(
+            leverage_token.mango_account,
+            *mango_account_ai.key,
+            QuasarErrorCode::InvalidAccount
+        )?;
+
+        let mango_group = MangoGroup::load_checked(mango_group_ai, mango_program_ai.key)?;
+        let market_index = mango_group
+            .find_perp_market_index(mango_perp_market_ai.key)
+            .ok_or(throw_err!(QuasarErrorCode::InvalidAccount))?;
+        check_eq!(
+            mango_group.tokens[market_index].mint,
+            leverage_token.base_token_mint,
+            QuasarErrorCode::InvalidToken
+        )?;
+
+        // Exposure left on the old market would no longer be rebalanced nor priced right
+        let mango_account =
+            MangoAccount::load_checked(mango_account_ai, mango_program_ai.key, mango_group_ai.key)?;
+        check_perp_flat(&mango_account)?;
+
+        leverage_token.mango_perp_market = *mango_perp_market_ai.key;
+        msg!("mango perp market: {}", mango_perp_market_ai.key);
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn quote_nav_breakdown(program_id: &Pubkey, accounts: &[AccountInfo]) -> QuasarResult {
+        const NUM_FIXED: usize = 6;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [quasar_group_ai, token_mint_ai, mango_program_ai, mango_group_ai, mango_account_ai, mango_cache_ai] =
+            accounts;
+
+        let quasar_group = QuasarGroup::load_checked(quasar_group_ai, program_id)?;
+        check_eq!(
+            *mango_program_ai.key,
+            quasar_group.mango_program_id,
+            QuasarErrorCode::InvalidProgramId
+        )?;
+
+        let leverage_token_index = quasar_group
+            .find_leverage_token_index_by_mint(token_mint_ai.key)
+            .ok_or(throw_err!(QuasarErrorCode::InvalidToken))?;
+        let leverage_token = &quasar_group.leverage_tokens[leverage_token_index];
+        check_eq!(
+            leverage_token.mango_account,
+            *mango_account_ai.key,
+            QuasarErrorCode::InvalidAccount
+        )?;
+
+        let mango_group = MangoGroup::load_checked(mango_group_ai, mango_program_ai.key)?;
+        let mango_cache =
+            MangoCache::load_checked(mango_cache_ai, mango_program_ai.key, &mango_group)?;
+        let mango_account =
+            MangoAccount::load_checked(mango_account_ai, mango_program_ai.key, mango_group_ai.key)?;
+
+        let now_ts = Clock::get()?.unix_timestamp as u64;
+        check_mango_cache_fresh(&mango_group, &mango_cache, now_ts)?;
+
+        let market_index = mango_group
+            .find_perp_market_index(&leverage_token.mango_perp_market)
+            .ok_or(throw_err!(QuasarErrorCode::InvalidAccount))?;
+        let breakdown =
+            NavBreakdown::load(&mango_group, &mango_cache, &mango_account, market_index)?;
+
+        msg!("net asset value: {}", breakdown.net_asset_value);
+        msg!("exposure: {}", breakdown.exposure);
+        msg!("pending exposure: {}", breakdown.pending_exposure);
+        if breakdown.net_asset_value > ZERO_I80F48 {
+            msg!(
+                "effective leverage: {}, intended leverage: {}",
+                format_leverage(breakdown.effective_leverage()?),
+                format_leverage(breakdown.intended_leverage()?)
+            );
+        }
+        set_return_data(&breakdown.pack());
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn set_rebalance_price_source(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        source: RebalancePriceSource,
+    ) -> QuasarResult {
+        const NUM_FIXED: usize = 3;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [quasar_group_ai, token_mint_ai, admin_ai] = accounts;
+
+        let mut quasar_group = QuasarGroup::load_mut_checked(quasar_group_ai, program_id)?;
+        check!(admin_ai.is_signer, QuasarErrorCode::SignerNecessary)?;
+        check_eq!(
+            admin_ai.key,
+            &quasar_group.admin_key,
+            QuasarErrorCode::InvalidAdminKey
+        )?;
+
+        let leverage_token_index = quasar_group
+            .find_leverage_token_index_by_mint(token_mint_ai.key)
+            .ok_or(throw_err!(QuasarErrorCode::InvalidToken))?;
+        if source == RebalancePriceSource::Oracle {
+            let base_token_index = quasar_group
+                .find_base_token_index(
+                    &quasar_group.leverage_tokens[leverage_token_index].base_token_mint,
+                )
+                .ok_or(throw_err!(QuasarErrorCode::InvalidToken))?;
+            check!(
+                quasar_group.base_tokens[base_token_index].oracle != Pubkey::default(),
+                QuasarErrorCode::InvalidParam
+            )?;
+        }
+
+        quasar_group.leverage_tokens[leverage_token_index].rebalance_price_source = source as u8;
+        msg!("rebalance price source: {:?}", source);
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn set_max_net_deposits(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        max_net_deposits: u64,
+    ) -> QuasarResult {
+        const NUM_FIXED: usize = 2;
+        let (fixed_ais, token_ledger_ais) = array_refs![accounts, NUM_FIXED; ..;];
+        let [quasar_group_ai, admin_ai] = fixed_ais;
+
+        let mut quasar_group = QuasarGroup::load_mut_checked(quasar_group_ai, program_id)?;
+        check!(admin_ai.is_signer, QuasarErrorCode::SignerNecessary)?;
+        check_eq!(
+            admin_ai.key,
+            &quasar_group.admin_key,
+            QuasarErrorCode::InvalidAdminKey
+        )?;
+
+        // Net deposits aren't kept while uncapped, so setting a cap counts them again
+        if max_net_deposits == 0 {
+            quasar_group.total_net_deposits = 0;
+        } else if quasar_group.max_net_deposits == 0 {
+            let mut total_net_deposits = 0u64;
+            let mut token_ledger_ais = token_ledger_ais.iter();
+            for leverage_token in
+                quasar_group.leverage_tokens[..quasar_group.num_leverage_tokens].iter()
+            {
+                if leverage_token.token_ledger == Pubkey::default() {
+                    continue;
+                }
+                let token_ledger_ai = token_ledger_ais
+                    .next()
+                    .ok_or(throw_err!(QuasarErrorCode::InvalidAccount))?;
+                check_eq!(
+                    *token_ledger_ai.key,
+                    leverage_token.token_ledger,
+                    QuasarErrorCode::InvalidAccount
+                )?;
+                let token_ledger = TokenLedger::load_checked(token_ledger_ai, program_id)?;
+                total_net_deposits = total_net_deposits
+                    .checked_add(token_ledger.net_deposits)
+                    .ok_or(math_err!())?;
+            }
+            quasar_group.total_net_deposits = total_net_deposits;
+        }
+
+        quasar_group.max_net_deposits = max_net_deposits;
+        msg!(
+            "max net deposits: {}, net deposits: {}",
+            max_net_deposits,
+            quasar_group.total_net_deposits
+        );
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn add_base_tokens(program_id: &Pubkey, accounts: &[AccountInfo]) -> QuasarResult {
+        const NUM_FIXED: usize = 3;
+        let (fixed_ais, token_ais) = array_refs![accounts, NUM_FIXED; ..;];
+        let [quasar_group_ai, admin_ai, mango_group_ai] = fixed_ais;
+        check!(token_ais.len() % 2 == 0, QuasarErrorCode::InvalidAccount)?;
+        check_batch_size(token_ais.len() / 2)?;
+
+        let mut quasar_group = QuasarGroup::load_mut_checked(quasar_group_ai, program_id)?;
+        check!(admin_ai.is_signer, QuasarErrorCode::SignerNecessary)?;
+        check_eq!(
+            admin_ai.key,
+            &quasar_group.admin_key,
+            QuasarErrorCode::InvalidAdminKey
+        )?;
+
+        let mango_program_id = quasar_group.mango_program_id;
+        let mango_group = MangoGroup::load_checked(mango_group_ai, &mango_program_id)?;
+        // Entries are registered in order, so a mint repeated within the batch is a duplicate
+        // of the earlier entry and fails the whole batch
+        for token_ais in token_ais.chunks(2) {
+            register_base_token(
+                program_id,
+                &mut quasar_group,
+                &mango_group,
+                &token_ais[0],
+                &token_ais[1],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn list_base_token_prices(program_id: &Pubkey, accounts: &[AccountInfo]) -> QuasarResult {
+        const NUM_FIXED: usize = 1;
+        let (fixed_ais, oracle_ais) = array_refs![accounts, NUM_FIXED; ..;];
+        let [quasar_group_ai] = fixed_ais;
+
+        let quasar_group = QuasarGroup::load_checked(quasar_group_ai, program_id)?;
+        let data = quasar_group.pack_base_token_prices(oracle_ais)?;
+        msg!("base tokens priced: {}", quasar_group.num_base_tokens);
+        set_return_data(&data);
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn set_mango_program(program_id: &Pubkey, accounts: &[AccountInfo]) -> QuasarResult {
+        const NUM_FIXED: usize = 3;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [quasar_group_ai, mango_program_ai, admin_ai] = accounts;
+
+        let mut quasar_group = QuasarGroup::load_mut_checked(quasar_group_ai, program_id)?;
+        check!(admin_ai.is_signer, QuasarErrorCode::SignerNecessary)?;
+        check_eq!(
+            admin_ai.key,
+            &quasar_group.admin_key,
+            QuasarErrorCode::InvalidAdminKey
+        )?;
+        check!(
+            mango_program_ai.executable,
+            QuasarErrorCode::InvalidProgramId
+        )?;
+
+        // The Mango account of a listed token, and the deposits in it, stay owned by the
+        // current program, which the group would no longer CPI into
+        check!(
+            quasar_group.num_leverage_tokens == 0,
+            QuasarErrorCode::MangoProgramInUse
+        )?;
+
+        quasar_group.mango_program_id = *mango_program_ai.key;
+        msg!("mango program: {}", mango_program_ai.key);
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn set_rebalance_keeper(program_id: &Pubkey, accounts: &[AccountInfo]) -> QuasarResult {
+        const NUM_FIXED: usize = 3;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [rebalance_config_ai, update_authority_ai, keeper_ai] = accounts;
+
+        let mut rebalance_config =
+            RebalanceConfig::load_mut_checked(rebalance_config_ai, program_id)?;
+        check!(
+            update_authority_ai.is_signer,
+            QuasarErrorCode::SignerNecessary
+        )?;
+        check_eq!(
+            *update_authority_ai.key,
+            rebalance_config.update_authority,
+            QuasarErrorCode::InvalidSignerKey
+        )?;
+
+        rebalance_config.keeper = *keeper_ai.key;
+        rebalance_config.last_update = Clock::get()?.unix_timestamp as u64;
+        msg!("rebalance keeper: {}", keeper_ai.key);
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn liquidation_distance(program_id: &Pubkey, accounts: &[AccountInfo]) -> QuasarResult {
+        const NUM_FIXED: usize = 6;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [quasar_group_ai, token_mint_ai, mango_program_ai, mango_group_ai, mango_account_ai, mango_cache_ai] =
+            accounts;
+
+        let quasar_group = QuasarGroup::load_checked(quasar_group_ai, program_id)?;
+        check_eq!(
+            *mango_program_ai.key,
+            quasar_group.mango_program_id,
+            QuasarErrorCode::InvalidProgramId
+        )?;
+
+        let leverage_token_index = quasar_group
+            .find_leverage_token_index_by_mint(token_mint_ai.key)
+            .ok_or(throw_err!(QuasarErrorCode::InvalidToken))?;
+        let leverage_token = &quasar_group.leverage_tokens[leverage_token_index];
+        check_eq!(
+            leverage_token.mango_account,
+            *mango_account_ai.key,
+            QuasarErrorCode::InvalidAccount
+        )?;
+
+        let mango_group = MangoGroup::load_checked(mango_group_ai, mango_program_ai.key)?;
+        let mango_cache =
+            MangoCache::load_checked(mango_cache_ai, mango_program_ai.key, &mango_group)?;
+        let mango_account =
+            MangoAccount::load_checked(mango_account_ai, mango_program_ai.key, mango_group_ai.key)?;
+
+        let now_ts = Clock::get()?.unix_timestamp as u64;
+        check_mango_cache_fresh(&mango_group, &mango_cache, now_ts)?;
+
+        let market_index = mango_group
+            .find_perp_market_index(&leverage_token.mango_perp_market)
+            .ok_or(throw_err!(QuasarErrorCode::InvalidAccount))?;
+        let breakdown =
+            NavBreakdown::load(&mango_group, &mango_cache, &mango_account, market_index)?;
+        let weighted_exposure =
+            get_maint_weighted_exposure(&mango_group, &mango_cache, &mango_account, market_index)?;
+        let distance = liquidation_distance(
+            breakdown.net_asset_value,
+            breakdown.exposure,
+            weighted_exposure,
+        )?;
+
+        msg!("liquidation distance: {}", distance);
+        set_return_data(&distance.to_le_bytes());
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn set_base_token_oracle_type(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        oracle_type: OracleType,
+    ) -> QuasarResult {
+        const NUM_FIXED: usize = 4;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [quasar_group_ai, mint_ai, admin_ai, oracle_ai] = accounts;
+
+        let mut quasar_group = QuasarGroup::load_mut_checked(quasar_group_ai, program_id)?;
+        check!(admin_ai.is_signer, QuasarErrorCode::SignerNecessary)?;
+        check_eq!(
+            admin_ai.key,
+            &quasar_group.admin_key,
+            QuasarErrorCode::InvalidAdminKey
+        )?;
+        check!(
+            oracle_type != OracleType::Unknown,
+            QuasarErrorCode::InvalidParam
+        )?;
+
+        let base_token_index = quasar_group
+            .find_base_token_index(mint_ai.key)
+            .ok_or(throw_err!(QuasarErrorCode::InvalidToken))?;
+        let base_token = &mut quasar_group.base_tokens[base_token_index];
+        check_eq!(
+            *oracle_ai.key,
+            base_token.oracle,
+            QuasarErrorCode::InvalidAccount
+        )?;
+        // Reading the price checks the oracle really is of the type about to be trusted
+        read_typed_oracle_price(oracle_ai, oracle_type)?;
+
+        base_token.oracle_type = oracle_type as u8;
+        msg!("oracle type: {:?}", oracle_type);
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn remove_base_token(program_id: &Pubkey, accounts: &[AccountInfo]) -> QuasarResult {
+        const NUM_FIXED: usize = 3;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [quasar_group_ai, mint_ai, admin_ai] = accounts;
+
+        let mut quasar_group = QuasarGroup::load_mut_checked(quasar_group_ai, program_id)?;
+        check!(admin_ai.is_signer, QuasarErrorCode::SignerNecessary)?;
+        check_eq!(
+            admin_ai.key,
+            &quasar_group.admin_key,
+            QuasarErrorCode::InvalidAdminKey
+        )?;
+
+        let base_token_index = quasar_group
+            .find_base_token_index(mint_ai.key)
+            .ok_or(throw_err!(QuasarErrorCode::InvalidToken))?;
+        // Leveraged tokens price and rebalance off their base token, so it outlives them
+        check!(
+            !quasar_group.is_base_token_in_use(mint_ai.key),
+            QuasarErrorCode::BaseTokenInUse
+        )?;
+
+        quasar_group.remove_base_token(base_token_index);
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn migrate_rebalance_config(program_id: &Pubkey, accounts: &[AccountInfo]) -> QuasarResult {
+        const NUM_FIXED: usize = 5;
+        let accounts = array_ref![accounts, 0, NUM_FIXED];
+        let [quasar_group_ai, token_mint_ai, legacy_config_ai, rebalance_config_ai, admin_ai] =
+            accounts;
+
+        let mut quasar_group = QuasarGroup::load_mut_checked(quasar_group_ai, program_id)?;
+        check!(admin_ai.is_signer, QuasarErrorCode::SignerNecessary)?;
+        check_eq!(
+            admin_ai.key,
+            &quasar_group.admin_key,
+            QuasarErrorCode::InvalidAdminKey
+        )?;
+
+        let leverage_token_index = quasar_group
+            .find_leverage_token_index_by_mint(token_mint_ai.key)
+            .ok_or(throw_err!(QuasarErrorCode::InvalidToken))?;
+        let leverage_token = &mut quasar_group.leverage_tokens[leverage_token_index];
+        check_eq!(
+            leverage_token.rebalance_config,
+            *legacy_config_ai.key,
+            QuasarErrorCode::InvalidAccount
+        )?;
+        check_eq!(
+            legacy_config_ai.owner,
+            program_id,
+            QuasarErrorCode::InvalidOwner
+        )?;
+        let legacy_config = RebalanceConfig::from_legacy(&legacy_config_ai.try_borrow_data()?)?;
+        check_eq!(
+            legacy_config.leverage_token_mint,
+            leverage_token.mint,
+            QuasarErrorCode::InvalidAccount
+        )?;
+
+        check_eq!(
+            rebalance_config_ai.owner,
+            program_id,
+            QuasarErrorCode::InvalidOwner
+        )?;
+        check_eq!(
+            rebalance_config_ai.data_len(),
+            size_of::<RebalanceConfig>(),
+            QuasarErrorCode::InvalidAccount
+        )?;
+        let rent = Rent::get()?;
+        check!(
+            rent.is_exempt(
+                rebalance_config_ai.lamports(),
+                rebalance_config_ai.data_len()
+            ),
+            QuasarErrorCode::AccountNotRentExempt
+        )?;
+        {
+            let mut rebalance_config = RebalanceConfig::load_mut(rebalance_config_ai)?;
+            check!(
+                !rebalance_config.meta_data.is_initialized,
+                QuasarErrorCode::InvalidAccount
+            )?;
+            *rebalance_config = legacy_config;
+        }
+        leverage_token.rebalance_config = *rebalance_config_ai.key;
+
+        // Nothing references the old account anymore
+        let lamports = legacy_config_ai.lamports();
+        **legacy_config_ai.try_borrow_mut_lamports()? = 0;
+        let admin_lamports = admin_ai
+            .lamports()
+            .checked_add(lamports)
+            .ok_or(math_err!())?;
+        **admin_ai.try_borrow_mut_lamports()? = admin_lamports;
+        msg!("rebalance config: {}", rebalance_config_ai.key);
+
+        Ok(())
+    }
+
+    #[inline(never)]
+    fn init_token_ledger(program_id: &Pubkey, accounts: &[AccountInfo]) -> QuasarResult {
+        const NUM_FIXED: usize = 8;
+        let (fixed_ais, quote_oracle_ais) = array_refs![accounts, NUM_FIXED; ..;];
+        let [quasar_group_ai, token_mint_ai, token_ledger_ai, admin_ai, mango_program_ai, mango_group_ai, mango_account_ai, mango_cache_ai] =
+            fixed_ais;
+
+        let mut quasar_group = QuasarGroup::load_mut_checked(quasar_group_ai, program_id)?;
+        check!(admin_ai.is_signer, QuasarErrorCode::SignerNecessary)?;
+        check_eq!(
+            admin_ai.key,
+            &quasar_group.admin_key,
+            QuasarErrorCode::InvalidAdminKey
+        )?;
+        check_eq!(
+            *mango_program_ai.key,
+            quasar_group.mango_program_id,
+            QuasarErrorCode::InvalidProgramId
+        )?;
+
+        let leverage_token_index = quasar_group
+            .find_leverage_token_index_by_mint(token_mint_ai.key)
+            .ok_or(throw_err!(QuasarErrorCode::InvalidToken))?;
+        // A second ledger would start the counts over
+        check_eq!(
+            quasar_group.leverage_tokens[leverage_token_index].token_ledger,
+            Pubkey::default(),
+            QuasarErrorCode::InvalidAccount
+        )?;
+
+        let rent = Rent::get()?;
+        check_eq!(
+            token_ledger_ai.owner,
+            program_id,
+            QuasarErrorCode::InvalidOwner
+        )?;
+        check_eq!(
+            token_ledger_ai.data_len(),
+            size_of::<TokenLedger>(),
+            QuasarErrorCode::InvalidAccount
+        )?;
+        check!(
+            rent.is_exempt(token_ledger_ai.lamports(), token_ledger_ai.data_len()),
+            QuasarErrorCode::AccountNotRentExempt
+        )?;
+
+        let supply = Mint::unpack(&token_mint_ai.try_borrow_data()?)?.supply;
+        let net_deposits = if supply == 0 {
+            0
+        } else {
+            let native_price = load_native_price(
+                &quasar_group,
+                token_mint_ai,
+                mango_program_ai,
+                mango_group_ai,
+                mango_account_ai,
+                mango_cache_ai,
+            )?;
+            let quote_decimals = MangoGroup::load_checked(mango_group_ai, mango_program_ai.key)?
+                .tokens[QUOTE_INDEX]
+                .decimals;
+            // The supply counts in the reference unit mints and burns count net deposits in
+            let quote_mint = quasar_group.leverage_tokens[leverage_token_index].quote_mint;
+            let (quote_oracle_ais, _) =
+                split_quote_oracle_ais(&quasar_group, &quote_mint, quote_oracle_ais)?;
+            let quote_price =
+                load_quote_price(&quasar_group, &quote_mint, quote_oracle_ais.first())?;
+            net_deposit_amount(
+                get_quote_amount(supply, native_price, false)?,
+                quote_decimals,
+                quote_price,
+            )?
+        };
+
+        {
+            let mut token_ledger = TokenLedger::load_mut(token_ledger_ai)?;
+            check!(
+                !token_ledger.meta_data.is_initialized,
+                QuasarErrorCode::InvalidAccount
+            )?;
+            token_ledger.meta_data = MetaData::new(DataType::TokenLedger, 0, true);
+            token_ledger.quasar_group = *quasar_group_ai.key;
+            token_ledger.leverage_token_mint = *token_mint_ai.key;
+            token_ledger.total_minted = supply;
+            token_ledger.total_burned = 0;
+            token_ledger.net_deposits = net_deposits;
+        }
+        quasar_group.leverage_tokens[leverage_token_index].token_ledger = *token_ledger_ai.key;
+        if quasar_group.max_net_deposits != 0 {
+            quasar_group.add_net_deposits(net_deposits)?;
+        }
+        msg!(
+            "token ledger: {}, supply: {}, net deposits: {}",
+            token_ledger_ai.key,
+            supply,
+            net_deposits
+        );
+
+        Ok(())
+    }
+}
+
+/// Perp order bringing a leveraged token's exposure back to its target leverage
+pub struct RebalanceOrder {
+    pub net_asset_value: I80F48,
+    pub effective_leverage: I80F48,
+    pub units: PerpMarketUnits,
+    /// Quote lots per base lot
+    pub price: I80F48,
+    pub quantity: BaseLots,
+    /// Native quote worth of spot collateral sold alongside the perp order
+    pub spot_sell_amount: u64,
+}
+
+impl RebalanceOrder {
+    /// Price and signed quantity as placed on the Mango orderbook
+    pub fn to_lots(&self) -> (i64, i64) {
+        (self.price.to_num::<i64>(), self.quantity.0.to_num::<i64>())
+    }
+}
+
+/// What a rebalance brings the exposure of a leveraged token back to
+#[derive(Copy, Clone)]
+pub enum RebalanceTarget {
+    Leverage(I80F48),
+    /// Exposure in native quote signed by the keeper of the rebalance config, bounded by the
+    /// config's max target leverage
+    AttestedExposure {
+        exposure: I80F48,
+        /// Leverage the token was listed with, whose side of the market the exposure keeps
+        target_leverage: I80F48,
+        max_target_leverage: I80F48,
+    },
+}
+
+/// Computes the perp order bringing the exposure back to `target`, converted into lots at
+/// `price`, the native price of the base. A target leverage is scaled down by the token's
+/// volatility, an attested exposure already accounts for it
+pub fn compute_rebalance_order(
+    target: RebalanceTarget,
+    price: I80F48,
+    mango_group: &MangoGroup,
+    mango_cache: &MangoCache,
+    mango_account: &MangoAccount,
+    market_index: usize,
+    leverage_token: &LeverageToken,
+) -> QuasarResult<RebalanceOrder> {
+    let units = PerpMarketUnits::load(&mango_group, market_index)?;
+
+    let net_asset_value = mango_net_asset_value(mango_group, mango_cache, mango_account)?;
+
+    // Base held as spot collateral is exposure as much as the perp position. Both are taken
+    // on the token's market, as `NavBreakdown` takes them once the rebalance traded
+    let market_price = mango_cache.price_cache[market_index].price;
+    let spot_asset_value = get_mango_spot_value(
+        &mango_account,
+        &mango_cache.root_bank_cache[market_index],
+        market_price,
+        market_index,
+    )?;
+    let (perp_asset_value, _) = mango_account.perp_accounts[market_index].get_val(
+        &mango_group.perp_markets[market_index],
+        &mango_cache.perp_market_cache[market_index],
+        market_price,
+    )?;
+    let exposure = perp_asset_value
+        .checked_add(spot_asset_value)
+        .ok_or(math_err!())?;
+
+    msg!("net asset value: {}", net_asset_value);
+    msg!("perp asset value: {}", perp_asset_value);
+    msg!("spot asset value: {}", spot_asset_value);
+    let effective_leverage = safe_div(exposure, net_asset_value)?;
+    msg!(
+        "effective leverage: {}",
+        format_leverage(effective_leverage)
+    );
+
+    msg!("price: {}", price);
+    let (target_leverage, target_exposure) = match target {
+        RebalanceTarget::Leverage(target_leverage) => {
+            let target_leverage = leverage_token.adapt_target_leverage(target_leverage)?;
+            let target_exposure = net_asset_value
+                .checked_mul(target_leverage)
+                .ok_or(math_err!())?;
+            check_exposure_sign(target_leverage, target_exposure)?;
+            (target_leverage, target_exposure)
+        }
+        RebalanceTarget::AttestedExposure {
+            exposure,
+            target_leverage,
+            max_target_leverage,
+        } => (
+            check_attested_exposure(
+                exposure,
+                net_asset_value,
+                target_leverage,
+                max_target_leverage,
+            )?,
+            exposure,
+        ),
+    };
+    msg!("target leverage: {}", format_leverage(target_leverage));
+    msg!("target exposure: {}", target_exposure);
+    msg!("current exposure: {}", exposure);
+
+    let (spot_delta, exposure_delta) = split_rebalance_exposure(
+        QuoteNative(spot_asset_value),
+        QuoteNative(perp_asset_value),
+        QuoteNative(target_exposure),
+    )?;
+    let spot_sell_amount: u64 = (-spot_delta.0)
+        .checked_floor()
+        .and_then(|amount| amount.checked_to_num())
+        .ok_or(math_err!())?;
+    msg!("spot to sell in native quote unit: {}", spot_sell_amount);
+    msg!("exposure delta in native quote unit: {}", exposure_delta.0);
+
+    let price = units.price_to_quote_lots(price)?;
+    msg!("price in quote lot unit: {}", price);
+    units.check_order_price(price)?;
+
+    let exposure_delta = units.native_quote_to_quote_lots(exposure_delta)?;
+    msg!("exposure delta in quote lot unit: {}", exposure_delta.0);
+
+    let quantity = units.quote_lots_to_base_lots(exposure_delta, price)?;
+    msg!("perp quantity to adjust in base lot unit: {}", quantity.0);
+
+    // The quantity is sized at the market price, only the limit price is offset. An
+    // emergency deleverage crosses at the market price to get filled
+    let price_offset_bps = if leverage_token.is_over_max_leverage(effective_leverage) {
+        0
+    } else {
+        leverage_token.price_offset_bps
+    };
+    let price = apply_price_offset(price, quantity.0 > ZERO_I80F48, price_offset_bps)?;
+    units.check_order_price(price)?;
+
+    Ok(RebalanceOrder {
+        net_asset_value,
+        effective_leverage,
+        units,
+        price,
+        quantity,
+        spot_sell_amount,
+    })
+}
+
+/// Target leverage of a leveraged token, read from the optional trailing rebalance config
+/// account when the token follows one
+fn load_target_leverage(
+    leverage_token: &LeverageToken,
+    rebalance_config_ais: &[AccountInfo],
+    program_id: &Pubkey,
+) -> QuasarResult<I80F48> {
+    match rebalance_config_ais.first() {
+        Some(rebalance_config_ai) if leverage_token.rebalance_config != Pubkey::default() => {
+            check_eq!(
+                *rebalance_config_ai.key,
+                leverage_token.rebalance_config,
+                QuasarErrorCode::InvalidAccount
+            )?;
+            let rebalance_config = RebalanceConfig::load_checked(rebalance_config_ai, program_id)?;
+            leverage_token.get_target_leverage(Some(&rebalance_config))
+        }
+        _ => leverage_token.get_target_leverage(None),
+    }
+}
+
+/// Target of a rebalance: the target leverage, or `target_exposure` when the keeper of the
+/// token's rebalance config signed for it as `caller_ai`
+fn load_rebalance_target(
+    leverage_token: &LeverageToken,
+    rebalance_config_ais: &[AccountInfo],
+    program_id: &Pubkey,
+    caller_ai: &AccountInfo,
+    target_exposure: Option<I80F48>,
+) -> QuasarResult<RebalanceTarget> {
+    let exposure = match target_exposure {
+        Some(exposure) => exposure,
+        None => {
+            return Ok(RebalanceTarget::Leverage(load_target_leverage(
+                leverage_token,
+                rebalance_config_ais,
+                program_id,
+            )?))
+        }
+    };
+
+    let rebalance_config_ai = rebalance_config_ais
+        .first()
+        .ok_or(throw_err!(QuasarErrorCode::InvalidAccount))?;
+    check_eq!(
+        *rebalance_config_ai.key,
+        leverage_token.rebalance_config,
+        QuasarErrorCode::InvalidAccount
+    )?;
+    let rebalance_config = RebalanceConfig::load_checked(rebalance_config_ai, program_id)?;
+    check_eq!(
+        rebalance_config.leverage_token_mint,
+        leverage_token.mint,
+        QuasarErrorCode::InvalidAccount
+    )?;
+    check!(
+        rebalance_config.keeper != Pubkey::default(),
+        QuasarErrorCode::InvalidSignerKey
+    )?;
+    check!(caller_ai.is_signer, QuasarErrorCode::SignerNecessary)?;
+    check_eq!(
+        *caller_ai.key,
+        rebalance_config.keeper,
+        QuasarErrorCode::InvalidSignerKey
+    )?;
+    msg!("attested target exposure: {}", exposure);
+
+    Ok(RebalanceTarget::AttestedExposure {
+        exposure,
+        target_leverage: leverage_token.target_leverage,
+        max_target_leverage: rebalance_config.max_target_leverage,
+    })
+}
+
+/// Splits the rebalance config, when the token follows one, off the trailing accounts
+fn split_rebalance_config_ais<'a, 'b>(
+    leverage_token: &LeverageToken,
+    trailing_ais: &'b [AccountInfo<'a>],
+) -> QuasarResult<(&'b [AccountInfo<'a>], &'b [AccountInfo<'a>])> {
+    let num_config = if leverage_token.rebalance_config == Pubkey::default() {
+        0
+    } else {
+        1
+    };
+    check!(
+        trailing_ais.len() >= num_config,
+        QuasarErrorCode::InvalidAccount
+    )?;
+    Ok(trailing_ais.split_at(num_config))
+}
+
+/// Splits the token ledger, when the token has one, off the trailing accounts
+fn split_token_ledger_ais<'a, 'b>(
+    leverage_token: &LeverageToken,
+    trailing_ais: &'b [AccountInfo<'a>],
+) -> QuasarResult<(&'b [AccountInfo<'a>], &'b [AccountInfo<'a>])> {
+    let num_ledger = if leverage_token.token_ledger == Pubkey::default() {
+        0
+    } else {
+        1
+    };
+    check!(
+        trailing_ais.len() >= num_ledger,
+        QuasarErrorCode::InvalidAccount
+    )?;
+    Ok(trailing_ais.split_at(num_ledger))
+}
+
+/// Rejects giving up the fee vault `fee_vault` while it still holds fees
+fn check_fee_vault_empty(fee_vault_ai: &AccountInfo, fee_vault: &Pubkey) -> QuasarResult {
+    check_eq!(
+        *fee_vault_ai.key,
+        *fee_vault,
+        QuasarErrorCode::InvalidAccount
+    )?;
+    let fee_vault = TokenAccount::unpack(&fee_vault_ai.try_borrow_data()?)?;
+    check_eq!(fee_vault.amount, 0, QuasarErrorCode::FeesNotWithdrawn)
+}
+
+/// Loads the ledger split off by `split_token_ledger_ais` to be written
+fn load_token_ledger<'a>(
+    leverage_token: &LeverageToken,
+    token_ledger_ais: &'a [AccountInfo],
+    program_id: &Pubkey,
+) -> QuasarResult<Option<RefMut<'a, TokenLedger>>> {
+    let token_ledger_ai = match token_ledger_ais.first() {
+        Some(token_ledger_ai) => token_ledger_ai,
+        None => return Ok(None),
+    };
+    check_eq!(
+        *token_ledger_ai.key,
+        leverage_token.token_ledger,
+        QuasarErrorCode::InvalidAccount
+    )?;
+    check!(
+        token_ledger_ai.is_writable,
+        QuasarErrorCode::AccountNotWritable
+    )?;
+    Ok(Some(TokenLedger::load_mut_checked(
+        token_ledger_ai,
+        program_id,
+    )?))
+}
 
-        Ok(())
+/// Splits the quote oracle, when it prices `quote_mint`, off the trailing accounts
+fn split_quote_oracle_ais<'a, 'b>(
+    quasar_group: &QuasarGroup,
+    quote_mint: &Pubkey,
+    trailing_ais: &'b [AccountInfo<'a>],
+) -> QuasarResult<(&'b [AccountInfo<'a>], &'b [AccountInfo<'a>])> {
+    let num_oracle = if quasar_group.uses_quote_oracle(quote_mint) {
+        1
+    } else {
+        0
+    };
+    check!(
+        trailing_ais.len() >= num_oracle,
+        QuasarErrorCode::InvalidAccount
+    )?;
+    Ok(trailing_ais.split_at(num_oracle))
+}
+
+/// Splits the base oracle, when the token prices rebalances off it, off the trailing accounts
+fn split_rebalance_oracle_ais<'a, 'b>(
+    leverage_token: &LeverageToken,
+    trailing_ais: &'b [AccountInfo<'a>],
+) -> QuasarResult<(&'b [AccountInfo<'a>], &'b [AccountInfo<'a>])> {
+    let num_oracle = if leverage_token.uses_oracle_price() {
+        1
+    } else {
+        0
+    };
+    check!(
+        trailing_ais.len() >= num_oracle,
+        QuasarErrorCode::InvalidAccount
+    )?;
+    Ok(trailing_ais.split_at(num_oracle))
+}
+
+/// Native price of the base a rebalance trades at, from the Mango cache or the base
+/// token's oracle as the token chose
+fn load_rebalance_price(
+    quasar_group: &QuasarGroup,
+    leverage_token: &LeverageToken,
+    mango_group: &MangoGroup,
+    mango_cache: &MangoCache,
+    market_index: usize,
+    oracle_ais: &[AccountInfo],
+) -> QuasarResult<I80F48> {
+    if !leverage_token.uses_oracle_price() {
+        return Ok(mango_cache.price_cache[market_index].price);
     }
 
-    #[inline(never)]
-    fn rebalance<'a>(program_id: &Pubkey, accounts: &[AccountInfo<'a>]) -> QuasarResult {
-        const NUM_FIXED: usize = 12;
-        let accounts = array_ref![accounts, 0, NUM_FIXED + MAX_PAIRS];
-        let (fixed_ais, mango_open_orders_ais) = array_refs![accounts, NUM_FIXED, MAX_PAIRS];
-        let [quasar_group_ai, token_mint_ai, pda_ai, mango_program_ai, mango_group_ai, mango_account_ai, owner_ai, mango_cache_ai, mango_perp_market_ai, mango_bids_ai, mango_asks_ai, mango_event_queue_ai] =
-            fixed_ais;
+    let base_token_index = quasar_group
+        .find_base_token_index(&leverage_token.base_token_mint)
+        .ok_or(throw_err!(QuasarErrorCode::InvalidToken))?;
+    let base_token = &quasar_group.base_tokens[base_token_index];
+    let oracle_ai = oracle_ais
+        .first()
+        .ok_or(throw_err!(QuasarErrorCode::InvalidAccount))?;
+    check_eq!(
+        *oracle_ai.key,
+        base_token.oracle,
+        QuasarErrorCode::InvalidAccount
+    )?;
 
-        let quasar_group = QuasarGroup::load_checked(quasar_group_ai, program_id)?;
+    oracle_to_native_price(
+        base_token.read_oracle_price(oracle_ai)?,
+        base_token.decimals,
+        mango_group.tokens[QUOTE_INDEX].decimals,
+    )
+}
 
-        let leverage_token_index = quasar_group
-            .find_leverage_token_index_by_mint(token_mint_ai.key)
-            .unwrap();
-        let leverage_token = quasar_group.leverage_tokens[leverage_token_index];
+/// Registers `mint_ai` as the next base token of `quasar_group`, priced by `oracle_ai`
+fn register_base_token(
+    program_id: &Pubkey,
+    quasar_group: &mut QuasarGroup,
+    mango_group: &MangoGroup,
+    mint_ai: &AccountInfo,
+    oracle_ai: &AccountInfo,
+) -> QuasarResult {
+    // Make sure there is no duplicated base token which has the same mint key
+    check!(
+        quasar_group.find_base_token_index(mint_ai.key).is_none(),
+        QuasarErrorCode::DuplicateToken
+    )?;
 
-        check_eq!(
-            leverage_token.mango_account,
-            *mango_account_ai.key,
-            QuasarErrorCode::InvalidAccount
-        );
-        check_eq!(
-            leverage_token.mango_perp_market,
-            *mango_perp_market_ai.key,
-            QuasarErrorCode::InvalidAccount
-        );
+    // Leverage tokens can only be rebalanced if Mango lists a perp market for the base token
+    let token_index = mango_group
+        .find_token_index(mint_ai.key)
+        .ok_or(throw_err!(QuasarErrorCode::InvalidToken))?;
+    check!(
+        mango_group.perp_markets[token_index].perp_market != Pubkey::default(),
+        QuasarErrorCode::InvalidToken
+    )?;
 
-        let mut price;
-        let mut quantity;
-        {
-            let mango_group = MangoGroup::load_checked(&mango_group_ai, mango_program_ai.key)?;
-            let mango_cache =
-                MangoCache::load_checked(&mango_cache_ai, mango_program_ai.key, &mango_group)?;
+    let base_token_index = quasar_group.num_base_tokens;
+    check!(
+        base_token_index < MAX_BASE_TOKENS,
+        QuasarErrorCode::OutOfSpace
+    )?;
+    // Make sure base token at this index is not already initialized
+    check!(
+        quasar_group.base_tokens[base_token_index].is_empty(),
+        QuasarErrorCode::SlotOccupied
+    )?;
 
-            let mango_account = MangoAccount::load_checked(
-                &mango_account_ai,
-                mango_program_ai.key,
-                mango_group_ai.key,
-            )?;
+    let oracle_type = match determine_oracle_type(oracle_ai) {
+        OracleType::Pyth => {
+            msg!("OracleType:Pyth"); // Do nothing really cause all that's needed is storing the pkey
+            OracleType::Pyth
+        }
+        OracleType::Stub | OracleType::Unknown => {
+            msg!("OracleType: got unknown or stub");
+            let rent = Rent::get()?;
+            let mut oracle = StubOracle::load_and_init(oracle_ai, program_id, &rent)?;
+            oracle.magic = STUB_MAGIC;
+            OracleType::Stub
+        }
+    };
 
-            let mut net_asset_value = ZERO_I80F48;
-            let mut perp_asset_value = ZERO_I80F48;
+    // Mango vaults only hold classic SPL tokens, so Token-2022 mints can't be supported
+    check_eq!(
+        *mint_ai.owner,
+        spl_token::id(),
+        QuasarErrorCode::InvalidToken
+    )?;
+    let mint = Mint::unpack(&mint_ai.try_borrow_data()?)?;
+    BaseToken::check_decimals(mint.decimals)?;
+    quasar_group.base_tokens[base_token_index] = BaseToken {
+        mint: *mint_ai.key,
+        decimals: mint.decimals,
+        oracle: *oracle_ai.key,
+        oracle_type: oracle_type as u8,
+        padding: [0u8; 6],
+    };
+    quasar_group.num_base_tokens += 1;
 
-            let market_index = mango_group
-                .find_perp_market_index(&leverage_token.mango_perp_market)
-                .unwrap();
-
-            for i in 0..mango_group.num_oracles {
-                let spot_value = get_mango_spot_value(
-                    &mango_account,
-                    &mango_cache.root_bank_cache[i],
-                    mango_cache.price_cache[i].price,
-                    i,
-                )?;
+    Ok(())
+}
 
-                let (perp_base_value, perp_quote_value) = mango_account.perp_accounts[i].get_val(
-                    &mango_group.perp_markets[i],
-                    &mango_cache.perp_market_cache[i],
-                    mango_cache.price_cache[i].price,
-                )?;
+/// Publishes `native_price` followed by the matching price of one whole token in whole
+/// quote, both as little endian I80F48, so clients don't have to scale it themselves
+fn set_price_return_data(native_price: I80F48, decimals: u8, quote_decimals: u8) -> QuasarResult {
+    let ui_price = native_to_ui_price(native_price, decimals, quote_decimals)?;
+    msg!("ui price: {}", ui_price);
 
-                msg!(
-                    "market {}: spot {} / perp_base {} / perp_quote {}",
-                    i,
-                    spot_value,
-                    perp_base_value,
-                    perp_quote_value,
-                );
+    let mut data = native_price.to_le_bytes().to_vec();
+    data.extend_from_slice(&ui_price.to_le_bytes());
+    set_return_data(&data);
+    Ok(())
+}
 
-                net_asset_value = net_asset_value
-                    .checked_add(
-                        spot_value
-                            .checked_add(perp_base_value.checked_add(perp_quote_value).unwrap())
-                            .unwrap(),
-                    )
-                    .unwrap();
+/// Pricing of a burn, as withdrawn from the Mango account with the fee paid out of it
+struct BurnQuote {
+    native_price: I80F48,
+    burn_price: I80F48,
+    supply: u64,
+    quote_amount: u64,
+    fee: u64,
+}
 
-                perp_asset_value = perp_asset_value.checked_add(perp_base_value).unwrap();
-            }
+/// Prices a burn of `quantity` native units of a leveraged token after the checks every burn
+/// passes, shared by burns and the quotes of what they would redeem so the two can't drift
+fn quote_burn(
+    quasar_group: &QuasarGroup,
+    leverage_token_index: usize,
+    token_mint_ai: &AccountInfo,
+    mango_group: &MangoGroup,
+    mango_account: &MangoAccount,
+    mango_cache: &MangoCache,
+    quantity: u64,
+) -> QuasarResult<BurnQuote> {
+    let leverage_token = &quasar_group.leverage_tokens[leverage_token_index];
+    leverage_token.check_active()?;
+    leverage_token.check_redemption_size(quantity)?;
 
-            msg!("net asset value: {}", net_asset_value);
-            msg!("perp asset value: {}", perp_asset_value);
-            msg!("effective leverage: {}", perp_asset_value / net_asset_value);
+    let native_price =
+        leverage_token.get_native_price(token_mint_ai, mango_group, mango_account, mango_cache)?;
+    check_nav_positive(native_price)?;
 
-            price = mango_cache.price_cache[market_index].price;
-            msg!("price: {}", price);
-            let target_exposure = net_asset_value
-                .checked_mul(leverage_token.target_leverage)
-                .unwrap();
-            msg!("target leverage: {}", leverage_token.target_leverage);
-            msg!("target exposure: {}", target_exposure);
-            msg!("current exposure: {}", perp_asset_value);
-
-            let base_decimals = mango_group.tokens[market_index].decimals;
-            let base_unit = 10u64.pow(base_decimals.into());
-            let base_lot_size =
-                I80F48::from_num(mango_group.perp_markets[market_index].base_lot_size);
-
-            let quote_decimals = mango_group.tokens[QUOTE_INDEX].decimals;
-            let quote_unit = 10u64.pow(quote_decimals.into());
-            let quote_lot_size =
-                I80F48::from_num(mango_group.perp_markets[market_index].quote_lot_size);
-
-            let exposure_delta = target_exposure.checked_sub(perp_asset_value).unwrap();
-            msg!("exposure delta in native quote unit: {}", exposure_delta);
-
-            price = price
-                .checked_mul(I80F48::from_num(quote_unit))
-                .unwrap()
-                .checked_mul(base_lot_size)
-                .unwrap()
-                .checked_div(quote_lot_size)
-                .unwrap()
-                .checked_div(I80F48::from_num(base_unit))
-                .unwrap();
-            msg!("price in quote lot unit: {}", price);
-
-            let exposure_delta = exposure_delta
-                .checked_div(I80F48::from_num(quote_lot_size))
-                .unwrap();
-            msg!("exposure delta in quote lot unit: {}", exposure_delta);
-
-            quantity = exposure_delta.checked_div(price).unwrap();
-            msg!("perp quantity to adjust in base lot unit: {}", quantity);
-        }
+    let supply = Mint::unpack(&token_mint_ai.try_borrow_data()?)?.supply;
+    check!(quantity <= supply, QuasarErrorCode::InvalidParam)?;
+    let burn_price = get_size_adjusted_price(
+        native_price,
+        quantity,
+        supply,
+        quasar_group.size_premium_bps,
+        false,
+    )?;
+    let quote_amount = get_quote_amount(quantity, burn_price, false)?;
+    let fee = get_fee_amount(quote_amount, leverage_token.get_burn_fee_bps(quasar_group))?;
 
-        let signer_seeds = gen_signer_seeds(&quasar_group.signer_nonce, quasar_group_ai.key);
+    Ok(BurnQuote {
+        native_price,
+        burn_price,
+        supply,
+        quote_amount,
+        fee,
+    })
+}
 
-        let price = price.to_num::<i64>();
-        let quantity = quantity.to_num::<i64>();
-        msg!("price: {}, quantity: {}", price, quantity.abs());
+/// Native price of a leveraged token as its Mango account is valued right now, e.g. to
+/// compare against the price an operation started from
+fn load_native_price(
+    quasar_group: &QuasarGroup,
+    token_mint_ai: &AccountInfo,
+    mango_program_ai: &AccountInfo,
+    mango_group_ai: &AccountInfo,
+    mango_account_ai: &AccountInfo,
+    mango_cache_ai: &AccountInfo,
+) -> QuasarResult<I80F48> {
+    let leverage_token_index = quasar_group
+        .find_leverage_token_index_by_mint(token_mint_ai.key)
+        .ok_or(throw_err!(QuasarErrorCode::InvalidToken))?;
+    let leverage_token = &quasar_group.leverage_tokens[leverage_token_index];
+    check_eq!(
+        leverage_token.mango_account,
+        *mango_account_ai.key,
+        QuasarErrorCode::InvalidAccount
+    )?;
 
-        if quantity.abs() > 0 {
-            place_mango_perp_order(
-                mango_program_ai,
-                mango_group_ai,
-                mango_account_ai,
-                pda_ai,
-                mango_cache_ai,
-                mango_perp_market_ai,
-                mango_bids_ai,
-                mango_asks_ai,
-                mango_event_queue_ai,
-                mango_open_orders_ais,
-                &[&signer_seeds],
-                price,
-                quantity.abs(),
-                0,
-                if quantity > ZERO_I80F48 {
-                    Side::Bid
-                } else {
-                    Side::Ask
-                },
-                OrderType::Limit,
-            )?;
-        }
+    let (mango_group, mango_cache, mango_account) = load_mango_context(
+        mango_program_ai.key,
+        mango_group_ai,
+        mango_cache_ai,
+        mango_account_ai,
+    )?;
 
-        Ok(())
-    }
+    leverage_token.get_native_price(token_mint_ai, &mango_group, &mango_account, &mango_cache)
 }
 
 fn create_account<'a>(
@@ -595,8 +3695,9 @@ fn create_account<'a>(
     space: usize,
     owner_ai: &AccountInfo<'a>,
     system_program_ai: &AccountInfo<'a>,
+    rent: &Rent,
 ) -> ProgramResult {
-    let rent = Rent::default().minimum_balance(space);
+    let lamports = rent.minimum_balance(space);
 
     check_eq!(
         *system_program_ai.key,
@@ -607,7 +3708,7 @@ fn create_account<'a>(
     let instruction = solana_program::system_instruction::create_account(
         signer_ai.key,
         new_account_ai.key,
-        rent,
+        lamports,
         space as u64,
         owner_ai.key,
     );
@@ -629,6 +3730,14 @@ fn invoke_mint_to<'a>(
     signer_seeds: &[&[&[u8]]],
     quantity: u64,
 ) -> ProgramResult {
+    // The instruction always targets the SPL token program, so the account passed along
+    // must be it too
+    check_eq!(
+        *token_program_ai.key,
+        spl_token::id(),
+        QuasarErrorCode::InvalidProgramId
+    )?;
+
     let instruction = spl_token::instruction::mint_to(
         &spl_token::ID,
         mint_ai.key,
@@ -648,6 +3757,41 @@ fn invoke_mint_to<'a>(
     solana_program::program::invoke_signed(&instruction, &account_infos, signer_seeds)
 }
 
+fn invoke_transfer<'a>(
+    token_program_ai: &AccountInfo<'a>,
+    source_ai: &AccountInfo<'a>,
+    destination_ai: &AccountInfo<'a>,
+    owner_ai: &AccountInfo<'a>,
+    signer_seeds: &[&[&[u8]]],
+    quantity: u64,
+) -> ProgramResult {
+    // The instruction always targets the SPL token program, so the account passed along
+    // must be it too
+    check_eq!(
+        *token_program_ai.key,
+        spl_token::id(),
+        QuasarErrorCode::InvalidProgramId
+    )?;
+
+    let instruction = spl_token::instruction::transfer(
+        &spl_token::ID,
+        source_ai.key,
+        destination_ai.key,
+        owner_ai.key,
+        &[],
+        quantity,
+    )?;
+
+    let account_infos = [
+        token_program_ai.clone(),
+        source_ai.clone(),
+        destination_ai.clone(),
+        owner_ai.clone(),
+    ];
+
+    solana_program::program::invoke_signed(&instruction, &account_infos, signer_seeds)
+}
+
 fn invoke_burn<'a>(
     token_program_ai: &AccountInfo<'a>,
     account_ai: &AccountInfo<'a>,
@@ -656,6 +3800,14 @@ fn invoke_burn<'a>(
     signer_seeds: &[&[&[u8]]],
     quantity: u64,
 ) -> ProgramResult {
+    // The instruction always targets the SPL token program, so the account passed along
+    // must be it too
+    check_eq!(
+        *token_program_ai.key,
+        spl_token::id(),
+        QuasarErrorCode::InvalidProgramId
+    )?;
+
     let instruction = spl_token::instruction::burn(
         &spl_token::ID,
         account_ai.key,
@@ -681,7 +3833,7 @@ fn init_mango_account<'a>(
     mango_account_ai: &AccountInfo<'a>,
     owner_ai: &AccountInfo<'a>,
     signers_seeds: &[&[&[u8]]],
-) -> ProgramResult {
+) -> QuasarResult {
     let instruction = Instruction {
         program_id: *mango_program_ai.key,
         data: mango::instruction::MangoInstruction::InitMangoAccount.pack(),
@@ -700,6 +3852,7 @@ fn init_mango_account<'a>(
     ];
 
     invoke_signed(&instruction, &account_infos, signers_seeds)
+        .map_err(|e| QuasarError::mango_cpi(MangoCpi::InitMangoAccount, e))
 }
 
 fn deposit_to_mango_account<'a>(
@@ -715,7 +3868,7 @@ fn deposit_to_mango_account<'a>(
     owner_token_account_ai: &AccountInfo<'a>,
     signers_seeds: &[&[&[u8]]],
     quantity: u64,
-) -> ProgramResult {
+) -> QuasarResult {
     let instruction = Instruction {
         program_id: *mango_program_ai.key,
         data: mango::instruction::MangoInstruction::Deposit { quantity }.pack(),
@@ -746,6 +3899,7 @@ fn deposit_to_mango_account<'a>(
     ];
 
     invoke_signed(&instruction, &account_infos, signers_seeds)
+        .map_err(|e| QuasarError::mango_cpi(MangoCpi::Deposit, e))
 }
 
 fn withdraw_from_mango_account<'a>(
@@ -760,11 +3914,11 @@ fn withdraw_from_mango_account<'a>(
     owner_token_account_ai: &AccountInfo<'a>,
     signer_ai: &AccountInfo<'a>,
     token_program_ai: &AccountInfo<'a>,
-    mango_open_orders_ais: &[AccountInfo<'a>; MAX_PAIRS],
+    mango_open_orders_ais: &[AccountInfo<'a>],
     signers_seeds: &[&[&[u8]]],
     quantity: u64,
     allow_borrow: bool,
-) -> ProgramResult {
+) -> QuasarResult {
     let mut accounts = vec![
         AccountMeta::new_readonly(*mango_group_ai.key, false),
         AccountMeta::new(*mango_account_ai.key, false),
@@ -811,8 +3965,12 @@ fn withdraw_from_mango_account<'a>(
     let account_infos = account_infos.as_slice();
 
     invoke_signed(&instruction, account_infos, signers_seeds)
+        .map_err(|e| QuasarError::mango_cpi(MangoCpi::Withdraw, e))
 }
 
+/// Places a perp order on Mango. The `PlacePerpOrder` of the Mango version this program
+/// builds against takes no expiry, so an order resting at a stale price stays on the book
+/// until `CancelRebalanceOrders` cancels it
 fn place_mango_perp_order<'a>(
     mango_program_ai: &AccountInfo<'a>,
     mango_group_ai: &AccountInfo<'a>,
@@ -830,7 +3988,7 @@ fn place_mango_perp_order<'a>(
     client_order_id: u64,
     side: Side,
     order_type: OrderType,
-) -> ProgramResult {
+) -> QuasarResult {
     let mut accounts = vec![
         AccountMeta::new_readonly(*mango_group_ai.key, false),
         AccountMeta::new(*mango_account_ai.key, false),
@@ -876,6 +4034,263 @@ fn place_mango_perp_order<'a>(
     };
 
     invoke_signed(&instruction, &account_infos, signers_seeds)
+        .map_err(|e| QuasarError::mango_cpi(MangoCpi::PlacePerpOrder, e))
+}
+
+fn cancel_all_mango_perp_orders<'a>(
+    mango_program_ai: &AccountInfo<'a>,
+    mango_group_ai: &AccountInfo<'a>,
+    mango_account_ai: &AccountInfo<'a>,
+    owner_ai: &AccountInfo<'a>,
+    mango_perp_market_ai: &AccountInfo<'a>,
+    mango_bids_ai: &AccountInfo<'a>,
+    mango_asks_ai: &AccountInfo<'a>,
+    signers_seeds: &[&[&[u8]]],
+    limit: u8,
+) -> QuasarResult {
+    let accounts = vec![
+        AccountMeta::new_readonly(*mango_group_ai.key, false),
+        AccountMeta::new(*mango_account_ai.key, false),
+        AccountMeta::new_readonly(*owner_ai.key, true),
+        AccountMeta::new(*mango_perp_market_ai.key, false),
+        AccountMeta::new(*mango_bids_ai.key, false),
+        AccountMeta::new(*mango_asks_ai.key, false),
+    ];
+
+    let account_infos = [
+        mango_program_ai.clone(),
+        mango_group_ai.clone(),
+        mango_account_ai.clone(),
+        owner_ai.clone(),
+        mango_perp_market_ai.clone(),
+        mango_bids_ai.clone(),
+        mango_asks_ai.clone(),
+    ];
+
+    let instruction = Instruction {
+        program_id: *mango_program_ai.key,
+        data: mango::instruction::MangoInstruction::CancelAllPerpOrders { limit }.pack(),
+        accounts,
+    };
+
+    invoke_signed(&instruction, &account_infos, signers_seeds)
+        .map_err(|e| QuasarError::mango_cpi(MangoCpi::CancelAllPerpOrders, e))
+}
+
+fn place_mango_spot_order<'a>(
+    mango_program_ai: &AccountInfo<'a>,
+    mango_group_ai: &AccountInfo<'a>,
+    mango_account_ai: &AccountInfo<'a>,
+    owner_ai: &AccountInfo<'a>,
+    mango_cache_ai: &AccountInfo<'a>,
+    dex_program_ai: &AccountInfo<'a>,
+    spot_market_ai: &AccountInfo<'a>,
+    spot_bids_ai: &AccountInfo<'a>,
+    spot_asks_ai: &AccountInfo<'a>,
+    dex_request_queue_ai: &AccountInfo<'a>,
+    dex_event_queue_ai: &AccountInfo<'a>,
+    dex_base_ai: &AccountInfo<'a>,
+    dex_quote_ai: &AccountInfo<'a>,
+    base_root_bank_ai: &AccountInfo<'a>,
+    base_node_bank_ai: &AccountInfo<'a>,
+    base_vault_ai: &AccountInfo<'a>,
+    quote_root_bank_ai: &AccountInfo<'a>,
+    quote_node_bank_ai: &AccountInfo<'a>,
+    quote_vault_ai: &AccountInfo<'a>,
+    token_program_ai: &AccountInfo<'a>,
+    mango_signer_ai: &AccountInfo<'a>,
+    rent_ai: &AccountInfo<'a>,
+    dex_signer_ai: &AccountInfo<'a>,
+    msrm_or_srm_vault_ai: &AccountInfo<'a>,
+    mango_open_orders_ais: &[AccountInfo<'a>],
+    market_index: usize,
+    signers_seeds: &[&[&[u8]]],
+    order: NewOrderInstructionV3,
+) -> QuasarResult {
+    let open_orders_pks: Vec<Pubkey> = mango_open_orders_ais.iter().map(|ai| *ai.key).collect();
+    let instruction = mango::instruction::place_spot_order(
+        mango_program_ai.key,
+        mango_group_ai.key,
+        mango_account_ai.key,
+        owner_ai.key,
+        mango_cache_ai.key,
+        dex_program_ai.key,
+        spot_market_ai.key,
+        spot_bids_ai.key,
+        spot_asks_ai.key,
+        dex_request_queue_ai.key,
+        dex_event_queue_ai.key,
+        dex_base_ai.key,
+        dex_quote_ai.key,
+        base_root_bank_ai.key,
+        base_node_bank_ai.key,
+        base_vault_ai.key,
+        quote_root_bank_ai.key,
+        quote_node_bank_ai.key,
+        quote_vault_ai.key,
+        mango_signer_ai.key,
+        dex_signer_ai.key,
+        msrm_or_srm_vault_ai.key,
+        &open_orders_pks,
+        market_index,
+        order,
+    )?;
+
+    let mut account_infos = [
+        mango_program_ai.clone(),
+        mango_group_ai.clone(),
+        mango_account_ai.clone(),
+        owner_ai.clone(),
+        mango_cache_ai.clone(),
+        dex_program_ai.clone(),
+        spot_market_ai.clone(),
+        spot_bids_ai.clone(),
+        spot_asks_ai.clone(),
+        dex_request_queue_ai.clone(),
+        dex_event_queue_ai.clone(),
+        dex_base_ai.clone(),
+        dex_quote_ai.clone(),
+        base_root_bank_ai.clone(),
+        base_node_bank_ai.clone(),
+        base_vault_ai.clone(),
+        quote_root_bank_ai.clone(),
+        quote_node_bank_ai.clone(),
+        quote_vault_ai.clone(),
+        token_program_ai.clone(),
+        mango_signer_ai.clone(),
+        rent_ai.clone(),
+        dex_signer_ai.clone(),
+        msrm_or_srm_vault_ai.clone(),
+    ]
+    .to_vec();
+    account_infos.extend(mango_open_orders_ais.iter().cloned());
+
+    invoke_signed(&instruction, &account_infos, signers_seeds)
+        .map_err(|e| QuasarError::mango_cpi(MangoCpi::PlaceSpotOrder, e))
+}
+
+fn settle_mango_spot_funds<'a>(
+    mango_program_ai: &AccountInfo<'a>,
+    mango_group_ai: &AccountInfo<'a>,
+    mango_cache_ai: &AccountInfo<'a>,
+    owner_ai: &AccountInfo<'a>,
+    mango_account_ai: &AccountInfo<'a>,
+    dex_program_ai: &AccountInfo<'a>,
+    spot_market_ai: &AccountInfo<'a>,
+    spot_open_orders_ai: &AccountInfo<'a>,
+    mango_signer_ai: &AccountInfo<'a>,
+    dex_base_ai: &AccountInfo<'a>,
+    dex_quote_ai: &AccountInfo<'a>,
+    base_root_bank_ai: &AccountInfo<'a>,
+    base_node_bank_ai: &AccountInfo<'a>,
+    quote_root_bank_ai: &AccountInfo<'a>,
+    quote_node_bank_ai: &AccountInfo<'a>,
+    base_vault_ai: &AccountInfo<'a>,
+    quote_vault_ai: &AccountInfo<'a>,
+    dex_signer_ai: &AccountInfo<'a>,
+    token_program_ai: &AccountInfo<'a>,
+    signers_seeds: &[&[&[u8]]],
+) -> QuasarResult {
+    let instruction = mango::instruction::settle_funds(
+        mango_program_ai.key,
+        mango_group_ai.key,
+        mango_cache_ai.key,
+        owner_ai.key,
+        mango_account_ai.key,
+        dex_program_ai.key,
+        spot_market_ai.key,
+        spot_open_orders_ai.key,
+        mango_signer_ai.key,
+        dex_base_ai.key,
+        dex_quote_ai.key,
+        base_root_bank_ai.key,
+        base_node_bank_ai.key,
+        quote_root_bank_ai.key,
+        quote_node_bank_ai.key,
+        base_vault_ai.key,
+        quote_vault_ai.key,
+        dex_signer_ai.key,
+    )?;
+
+    let account_infos = [
+        mango_program_ai.clone(),
+        mango_group_ai.clone(),
+        mango_cache_ai.clone(),
+        owner_ai.clone(),
+        mango_account_ai.clone(),
+        dex_program_ai.clone(),
+        spot_market_ai.clone(),
+        spot_open_orders_ai.clone(),
+        mango_signer_ai.clone(),
+        dex_base_ai.clone(),
+        dex_quote_ai.clone(),
+        base_root_bank_ai.clone(),
+        base_node_bank_ai.clone(),
+        quote_root_bank_ai.clone(),
+        quote_node_bank_ai.clone(),
+        base_vault_ai.clone(),
+        quote_vault_ai.clone(),
+        dex_signer_ai.clone(),
+        token_program_ai.clone(),
+    ];
+
+    invoke_signed(&instruction, &account_infos, signers_seeds)
+        .map_err(|e| QuasarError::mango_cpi(MangoCpi::SettleFunds, e))
+}
+
+/// Coin and pc lot sizes of a Serum market
+fn load_spot_lot_sizes(
+    spot_market_ai: &AccountInfo,
+    dex_program_ai: &AccountInfo,
+) -> QuasarResult<(u64, u64)> {
+    check_eq!(
+        spot_market_ai.owner,
+        dex_program_ai.key,
+        QuasarErrorCode::InvalidOwner
+    )?;
+
+    // Serum accounts are framed by 5 bytes of head and 7 bytes of tail padding
+    let data = spot_market_ai.try_borrow_data()?;
+    check!(
+        data.len() >= size_of::<MarketState>() + 12,
+        QuasarErrorCode::InvalidAccount
+    )?;
+    let market: &MarketState = bytemuck::from_bytes(&data[5..5 + size_of::<MarketState>()]);
+
+    Ok((market.coin_lot_size, market.pc_lot_size))
+}
+
+/// Lays out the given open orders accounts at the index of their market in the Mango account's
+/// margin basket, filling every other slot with `placeholder_ai` which Mango doesn't read
+fn pad_open_orders<'a>(
+    mango_account: &MangoAccount,
+    open_orders_ais: &[AccountInfo<'a>],
+    placeholder_ai: &AccountInfo<'a>,
+) -> QuasarResult<Vec<AccountInfo<'a>>> {
+    let mut open_orders_iter = open_orders_ais.iter();
+    let mut padded = Vec::with_capacity(MAX_PAIRS);
+
+    for i in 0..MAX_PAIRS {
+        if mango_account.in_margin_basket[i] {
+            let open_orders_ai = open_orders_iter
+                .next()
+                .ok_or(throw_err!(QuasarErrorCode::InvalidAccount))?;
+            check_eq!(
+                *open_orders_ai.key,
+                mango_account.spot_open_orders[i],
+                QuasarErrorCode::InvalidAccount
+            )?;
+            padded.push(open_orders_ai.clone());
+        } else {
+            padded.push(placeholder_ai.clone());
+        }
+    }
+    check!(
+        open_orders_iter.next().is_none(),
+        QuasarErrorCode::InvalidAccount
+    )?;
+
+    Ok(padded)
 }
 
 fn create_and_initialize_mint_account<'a>(
@@ -912,6 +4327,7 @@ fn create_and_initialize_mint_account<'a>(
         Mint::LEN,
         &token_program_ai,
         &system_program_ai,
+        &Rent::get()?,
     )?;
 
     msg!("mint account {} created", mint_ai.key.to_string());
@@ -937,35 +4353,22 @@ fn create_and_initialize_mint_account<'a>(
     Ok(())
 }
 
-#[inline(never)]
-fn read_oracle(base_token: &BaseToken, oracle_ai: &AccountInfo) -> QuasarResult<I80F48> {
-    let quote_decimals: u8 = base_token.decimals;
-    let oracle_type = determine_oracle_type(oracle_ai);
-    let price = match oracle_type {
-        OracleType::Pyth => {
-            let price_account = Price::get_price(oracle_ai).unwrap();
-            let value = I80F48::from_num(price_account.agg.price);
-
-            let decimals = (quote_decimals as i32)
-                .checked_add(price_account.expo)
-                .unwrap()
-                .checked_sub(quote_decimals as i32)
-                .unwrap();
+/// Price of `quote_mint` in the reference unit. One while no quote oracle is set, or for
+/// quotes other than the group's own, which the oracle doesn't price
+fn load_quote_price(
+    quasar_group: &QuasarGroup,
+    quote_mint: &Pubkey,
+    quote_oracle_ai: Option<&AccountInfo>,
+) -> QuasarResult<I80F48> {
+    if !quasar_group.uses_quote_oracle(quote_mint) {
+        return Ok(I80F48::ONE);
+    }
 
-            let decimal_adj = I80F48::from_num(10u64.pow(decimals.abs() as u32));
-            if decimals < 0 {
-                value.checked_div(decimal_adj).unwrap()
-            } else {
-                value.checked_mul(decimal_adj).unwrap()
-            }
-        }
-        OracleType::Stub => {
-            let oracle = StubOracle::load(oracle_ai)?;
-            I80F48::from_num(oracle.price)
-        }
-        OracleType::Unknown => {
-            panic!("Unknown oracle");
-        }
-    };
-    Ok(price)
+    let quote_oracle_ai = quote_oracle_ai.ok_or(throw_err!(QuasarErrorCode::InvalidAccount))?;
+    check_eq!(
+        *quote_oracle_ai.key,
+        quasar_group.quote_oracle,
+        QuasarErrorCode::InvalidAccount
+    )?;
+    read_oracle_price(quote_oracle_ai)
 }
@@ -6,27 +6,89 @@ use num_enum::{IntoPrimitive, TryFromPrimitive};
 
 use solana_program::{
     account_info::AccountInfo,
+    clock::Clock,
     program_error::ProgramError,
     program_pack::{IsInitialized, Pack, Sealed},
     pubkey::Pubkey,
+    sysvar::Sysvar,
 };
 
 use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
+use spl_associated_token_account::get_associated_token_address;
 use spl_token::state::Mint;
 
+use bytemuck::Zeroable;
+use static_assertions::const_assert_eq;
 use std::cell::{Ref, RefMut};
+use std::convert::TryFrom;
+use std::mem::size_of;
 
 use crate::{
     error::{check_assert, QuasarError, QuasarErrorCode, QuasarResult, SourceFileId},
-    utils::get_mango_spot_value,
+    oracle::{read_oracle_price, read_typed_oracle_price, OracleType},
+    utils::{
+        check_mango_cache_fresh, get_quote_amount, mango_net_asset_value, quote_to_reference,
+        safe_div, sqrt_ratio, BPS_UNIT,
+    },
 };
 
 declare_check_assert_macros!(SourceFileId::State);
 
 pub const MAX_BASE_TOKENS: usize = 16;
 pub const MAX_LEVERAGE_TOKENS: usize = 32;
+
+/// Largest mint decimals whose native unit `10u64.pow(decimals)` still fits in a u64
+pub const MAX_BASE_TOKEN_DECIMALS: u8 = 19;
+
+/// Decimals of the reference unit net deposits are counted in, so that deposits in quote
+/// mints of different decimals add up
+pub const NET_DEPOSITS_DECIMALS: u8 = 6;
+
+/// Fee override sentinel of a leveraged token meaning the group's fee applies
+pub const USE_GROUP_FEE: u16 = u16::MAX;
 pub const LEVERGAE_TOKEN_DECIMALS: u8 = 0;
 pub const INITIAL_LEVERAGE_TOKEN_PRICE: u64 = 1;
+/// Highest multiple of the initial price a leveraged token is priced at. Far beyond what
+/// leverage makes of a price history, it only stops quote donated to a dust supply from
+/// pricing the next minter out
+pub const MAX_PRICE_MULTIPLE: u64 = 1_000_000;
+/// Rebalances kept in the history of a leveraged token
+pub const REBALANCE_HISTORY_LEN: usize = 4;
+/// Weight of the latest return in the volatility EMA of a leveraged token, in bps
+pub const VOLATILITY_EMA_BPS: u64 = 1_000;
+/// Slots between volatility samples, about an hour. Rebalances sooner than this after the
+/// last sample leave the EMA as is, whoever calls them
+pub const VOLATILITY_SAMPLE_SLOTS: u64 = 9_000;
+
+/// Layout of `QuasarGroup` written to `meta_data.version` on init and required on load.
+/// Version 0 groups were created with a smaller account, which solana 1.7 can't grow, and
+/// can't move to a bigger one either, since the group key seeds the signer owning its
+/// mints and Mango accounts. They have to be wound down and listed again in a new group
+pub const QUASAR_GROUP_VERSION: u8 = 1;
+/// Bytes kept zeroed at the end of `LeverageToken` and `QuasarGroup`, so fields can be
+/// added out of them without changing the layout version
+pub const LEVERAGE_TOKEN_RESERVED: usize = 48;
+pub const QUASAR_GROUP_RESERVED: usize = 256;
+
+// Accounts are stored as raw bytes, so these sizes only change together with an explicit
+// migration of the existing accounts
+pub const BASE_TOKEN_SIZE: usize = 72;
+pub const LEVERAGE_TOKEN_SIZE: usize = 624;
+pub const QUASAR_GROUP_SIZE: usize = 21744;
+pub const REBALANCE_CONFIG_SIZE: usize = 176;
+pub const TOKEN_LEDGER_SIZE: usize = 96;
+const_assert_eq!(size_of::<BaseToken>(), BASE_TOKEN_SIZE);
+const_assert_eq!(size_of::<LeverageToken>(), LEVERAGE_TOKEN_SIZE);
+const_assert_eq!(size_of::<QuasarGroup>(), QUASAR_GROUP_SIZE);
+const_assert_eq!(size_of::<RebalanceConfig>(), REBALANCE_CONFIG_SIZE);
+const_assert_eq!(size_of::<TokenLedger>(), TOKEN_LEDGER_SIZE);
+/// Size of the rebalance configs created before they had a keeper, which is the current
+/// layout without the trailing `keeper`
+pub const LEGACY_REBALANCE_CONFIG_SIZE: usize = 144;
+const_assert_eq!(
+    LEGACY_REBALANCE_CONFIG_SIZE + size_of::<Pubkey>(),
+    REBALANCE_CONFIG_SIZE
+);
 
 #[repr(u8)]
 #[derive(IntoPrimitive, TryFromPrimitive)]
@@ -34,6 +96,30 @@ pub enum DataType {
     QuasarGroup = 0,
     BaseToken = 1,
     LeverageToken = 2,
+    RebalanceConfig = 3,
+    TokenLedger = 4,
+}
+
+/// Lifecycle state of a leveraged token. A frozen token can't be minted, burned or
+/// rebalanced, e.g. while an incident is investigated
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, IntoPrimitive, TryFromPrimitive)]
+pub enum TokenState {
+    Active = 0,
+    Frozen = 1,
+}
+
+/// Price a rebalance converts exposure into perp lots and places its order at. The NAV and
+/// the exposure are valued at the Mango cache prices under either source, the prices Mango
+/// checks the account's health at, so a token trading off its oracle targets the leverage
+/// Mango and the NAV quotes see and only its order moves with the oracle
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, IntoPrimitive, TryFromPrimitive)]
+pub enum RebalancePriceSource {
+    /// Price Mango caches for the perp market
+    MangoCache = 0,
+    /// Oracle of the base token, independent of Mango
+    Oracle = 1,
 }
 
 #[derive(Copy, Clone, Pod, Default)]
@@ -66,12 +152,50 @@ pub struct QuasarGroup {
     pub base_tokens: [BaseToken; MAX_BASE_TOKENS],
 
     pub num_leverage_tokens: usize,
+    pub padding: [u8; 8], // Keeps leverage_tokens 16 byte aligned whatever the alignment of I80F48
     pub leverage_tokens: [LeverageToken; MAX_LEVERAGE_TOKENS],
 
     pub signer_nonce: u64,
     pub signer_key: Pubkey,
     pub admin_key: Pubkey,
     pub mango_program_id: Pubkey,
+
+    /// Only this key may call rebalance. `Pubkey::default()` keeps rebalance permissionless
+    pub rebalance_authority: Pubkey,
+
+    /// Mint of `fee_vault` and `treasury`, the quote `quote_oracle` prices
+    pub quote_mint: Pubkey,
+    /// Quote token account owned by the group signer collecting protocol fees
+    pub fee_vault: Pubkey,
+    /// Quote token account fees are withdrawn to
+    pub treasury: Pubkey,
+    /// Owner of `treasury`, whose associated token accounts receive fees in other quotes
+    pub treasury_owner: Pubkey,
+    /// Oracle pricing the quote token in the reference unit NAV is expressed in.
+    /// `Pubkey::default()` treats the quote as worth exactly one reference unit
+    pub quote_oracle: Pubkey,
+    pub total_fees_withdrawn: u64,
+
+    /// Fees charged on mints and burns unless a leveraged token overrides them, in bps
+    pub mint_fee_bps: u16,
+    pub burn_fee_bps: u16,
+
+    /// Mints and burns moving the native price by more than this, in bps, are rejected.
+    /// Zero disables the check
+    pub max_nav_move_bps: u16,
+    /// Premium on the price of a mint, and discount on that of a burn, for one the size of
+    /// the whole supply, in bps. Smaller ones pay proportionally less
+    pub size_premium_bps: u16,
+    /// Slots between listing a leveraged token and its first mint
+    pub listing_grace_slots: u64,
+
+    /// Cap on `total_net_deposits` for guarded launches. Zero disables it
+    pub max_net_deposits: u64,
+    /// Sum of the `net_deposits` of every token ledger, only kept while a cap is set so that
+    /// mints and burns don't need the group writable otherwise
+    pub total_net_deposits: u64,
+
+    pub reserved: [u8; QUASAR_GROUP_RESERVED],
 }
 
 impl QuasarGroup {
@@ -91,6 +215,12 @@ impl QuasarGroup {
             DataType::QuasarGroup as u8,
             QuasarErrorCode::InvalidAccount
         )?;
+        check_eq!(
+            quasar_group.meta_data.version,
+            QUASAR_GROUP_VERSION,
+            QuasarErrorCode::UnsupportedVersion
+        )?;
+        quasar_group.check_token_counts()?;
 
         Ok(quasar_group)
     }
@@ -111,30 +241,160 @@ impl QuasarGroup {
             DataType::QuasarGroup as u8,
             QuasarErrorCode::InvalidAccount
         )?;
+        check_eq!(
+            quasar_group.meta_data.version,
+            QUASAR_GROUP_VERSION,
+            QuasarErrorCode::UnsupportedVersion
+        )?;
+        quasar_group.check_token_counts()?;
 
         Ok(quasar_group)
     }
 
+    /// Rejects accounts whose token counts would make iteration read past the arrays
+    fn check_token_counts(&self) -> QuasarResult {
+        check!(
+            self.num_base_tokens <= MAX_BASE_TOKENS,
+            QuasarErrorCode::InvalidAccount
+        )?;
+        check!(
+            self.num_leverage_tokens <= MAX_LEVERAGE_TOKENS,
+            QuasarErrorCode::InvalidAccount
+        )
+    }
+
+    /// Whether `quote_oracle` prices tokens quoted in `quote_mint`, which then pass it
+    pub fn uses_quote_oracle(&self, quote_mint: &Pubkey) -> bool {
+        self.quote_oracle != Pubkey::default() && *quote_mint == self.quote_mint
+    }
+
+    pub fn is_rebalance_permissionless(&self) -> bool {
+        self.rebalance_authority == Pubkey::default()
+    }
+
+    /// Token account collecting the fees of tokens quoted in `quote_mint`. Quotes other
+    /// than `self.quote_mint` go to the associated token account of the group signer
+    pub fn get_fee_vault(&self, quote_mint: &Pubkey) -> Pubkey {
+        if *quote_mint == self.quote_mint {
+            self.fee_vault
+        } else {
+            get_associated_token_address(&self.signer_key, quote_mint)
+        }
+    }
+
+    /// Token account fees in `quote_mint` are withdrawn to
+    pub fn get_treasury(&self, quote_mint: &Pubkey) -> Pubkey {
+        if *quote_mint == self.quote_mint {
+            self.treasury
+        } else {
+            get_associated_token_address(&self.treasury_owner, quote_mint)
+        }
+    }
+
+    /// Rejects a mint adding `reference_amount` to the group's net deposits that would take
+    /// them past `max_net_deposits`
+    pub fn check_group_cap(&self, reference_amount: u64) -> QuasarResult {
+        if self.max_net_deposits == 0 {
+            return Ok(());
+        }
+        let total_net_deposits = self
+            .total_net_deposits
+            .checked_add(reference_amount)
+            .ok_or(math_err!())?;
+        check!(
+            total_net_deposits <= self.max_net_deposits,
+            QuasarErrorCode::GroupCapExceeded
+        )
+    }
+
+    pub fn add_net_deposits(&mut self, reference_amount: u64) -> QuasarResult {
+        self.total_net_deposits = self
+            .total_net_deposits
+            .checked_add(reference_amount)
+            .ok_or(math_err!())?;
+        Ok(())
+    }
+
+    pub fn remove_net_deposits(&mut self, reference_amount: u64) -> QuasarResult {
+        self.total_net_deposits = self
+            .total_net_deposits
+            .checked_sub(reference_amount)
+            .ok_or(math_err!())?;
+        Ok(())
+    }
+
+    /// Removes the leverage token at `index`, moving the last one into its slot so that
+    /// the tokens stay packed at the front of the array
+    pub fn remove_leverage_token(&mut self, index: usize) {
+        let last_index = self.num_leverage_tokens - 1;
+        self.leverage_tokens[index] = self.leverage_tokens[last_index];
+        self.leverage_tokens[last_index] = LeverageToken::zeroed();
+        self.num_leverage_tokens -= 1;
+    }
+
+    /// Removes the base token at `index` the same way, so `base_tokens` stays in the order
+    /// the remaining tokens' oracles are passed in
+    pub fn remove_base_token(&mut self, index: usize) {
+        let last_index = self.num_base_tokens - 1;
+        self.base_tokens[index] = self.base_tokens[last_index];
+        self.base_tokens[last_index] = BaseToken::zeroed();
+        self.num_base_tokens -= 1;
+    }
+
+    /// Whether a listed leveraged token still references the base token of `base_token_mint`
+    pub fn is_base_token_in_use(&self, base_token_mint: &Pubkey) -> bool {
+        self.leverage_tokens
+            .iter()
+            .any(|lt| !lt.is_empty() && lt.base_token_mint == *base_token_mint)
+    }
+
+    // The find_* lookups skip empty slots so that `Pubkey::default()` never matches one
     pub fn find_leverage_token_index(
         &self,
         base_token_mint: &Pubkey,
         target_leverage: I80F48,
     ) -> Option<usize> {
         self.leverage_tokens.iter().position(|lt| {
-            lt.base_token_mint == *base_token_mint && lt.target_leverage == target_leverage
+            !lt.is_empty()
+                && lt.base_token_mint == *base_token_mint
+                && lt.target_leverage == target_leverage
         })
     }
 
     pub fn find_leverage_token_index_by_mint(&self, token_mint: &Pubkey) -> Option<usize> {
         self.leverage_tokens
             .iter()
-            .position(|lt| lt.mint == *token_mint)
+            .position(|lt| !lt.is_empty() && lt.mint == *token_mint)
     }
 
     pub fn find_base_token_index(&self, base_token_mint: &Pubkey) -> Option<usize> {
         self.base_tokens
             .iter()
-            .position(|bt| bt.mint == *base_token_mint)
+            .position(|bt| !bt.is_empty() && bt.mint == *base_token_mint)
+    }
+
+    /// Mint, oracle and little endian oracle price of every base token, 80 bytes each,
+    /// reading the prices from `oracle_ais` passed in base token order
+    pub fn pack_base_token_prices(&self, oracle_ais: &[AccountInfo]) -> QuasarResult<Vec<u8>> {
+        check_eq!(
+            oracle_ais.len(),
+            self.num_base_tokens,
+            QuasarErrorCode::InvalidAccount
+        )?;
+
+        let mut data = Vec::with_capacity(self.num_base_tokens * 80);
+        for (base_token, oracle_ai) in self.base_tokens.iter().zip(oracle_ais.iter()) {
+            check_eq!(
+                *oracle_ai.key,
+                base_token.oracle,
+                QuasarErrorCode::InvalidAccount
+            )?;
+            let price = base_token.read_oracle_price(oracle_ai)?;
+            data.extend_from_slice(base_token.mint.as_ref());
+            data.extend_from_slice(base_token.oracle.as_ref());
+            data.extend_from_slice(&price.to_le_bytes());
+        }
+        Ok(data)
     }
 }
 
@@ -144,13 +404,36 @@ pub struct BaseToken {
     pub mint: Pubkey,
     pub decimals: u8,
     pub oracle: Pubkey,
-    pub padding: [u8; 7],
+    /// `OracleType` of the oracle, resolved when the token is added
+    pub oracle_type: u8,
+    pub padding: [u8; 6],
 }
 
 impl BaseToken {
     pub fn is_empty(&self) -> bool {
         self.mint == Pubkey::default()
     }
+
+    /// Recorded oracle type, `Unknown` for tokens added before it was recorded
+    pub fn oracle_type(&self) -> OracleType {
+        OracleType::try_from(self.oracle_type).unwrap_or(OracleType::Unknown)
+    }
+
+    /// Price of the token's oracle read as its recorded type, only detecting the type of
+    /// tokens that have none recorded yet
+    pub fn read_oracle_price(&self, oracle_ai: &AccountInfo) -> QuasarResult<I80F48> {
+        match self.oracle_type() {
+            OracleType::Unknown => read_oracle_price(oracle_ai),
+            oracle_type => read_typed_oracle_price(oracle_ai, oracle_type),
+        }
+    }
+
+    pub fn check_decimals(decimals: u8) -> QuasarResult {
+        check!(
+            decimals <= MAX_BASE_TOKEN_DECIMALS,
+            QuasarErrorCode::InvalidParam
+        )
+    }
 }
 
 #[derive(Copy, Clone, Pod)]
@@ -161,6 +444,110 @@ pub struct LeverageToken {
     pub target_leverage: I80F48,
     pub mango_account: Pubkey,
     pub mango_perp_market: Pubkey,
+    /// Quote mint of the Mango group, which mints are paid and burns redeemed in
+    pub quote_mint: Pubkey,
+
+    // Updated on every rebalance, at `last_update_slot`
+    pub last_nav: I80F48,
+    pub last_effective_leverage: I80F48,
+    /// Slot of the last rebalance that traded, which the cooldown runs from. Rebalances
+    /// trading nothing leave it, so they can't hold the token in its cooldown
+    pub last_rebalance_slot: u64,
+    /// Signed base lots the last rebalance order left unfilled, zero when on target
+    pub pending_rebalance_quantity: i64,
+
+    /// Mints are blocked while the native price is below this floor
+    pub min_native_price: I80F48,
+
+    /// `RebalanceConfig` supplying the target leverage of rebalances instead of
+    /// `target_leverage`. `Pubkey::default()` when the target is fixed
+    pub rebalance_config: Pubkey,
+
+    /// Share of every mint deposit swapped into base spot collateral, in bps.
+    /// Zero keeps the whole deposit as quote
+    pub spot_swap_bps: u16,
+
+    /// Fee overrides in bps, `USE_GROUP_FEE` falls back to the group's fee
+    pub mint_fee_bps: u16,
+    pub burn_fee_bps: u16,
+    /// `TokenState` of the token
+    pub state: u8,
+    /// `RebalancePriceSource` of the token's rebalances
+    pub rebalance_price_source: u8,
+    /// Mints are rejected before this slot, leaving keepers time to seed and rebalance
+    /// a new listing
+    pub active_from_slot: u64,
+
+    /// `TokenLedger` counting the token's mints, burns and net deposits, which mints and
+    /// burns then require. `Pubkey::default()` until the admin creates one
+    pub token_ledger: Pubkey,
+
+    /// Effective leverage past which a rebalance deleverages right away, whatever the
+    /// cooldown. Zero disables the cap
+    pub max_effective_leverage: I80F48,
+    /// Slots a rebalance waits after the previous one while under the cap
+    pub rebalance_cooldown_slots: u64,
+    /// Whether burns may borrow quote on Mango when its deposits fall short, up to
+    /// `max_borrow_bps` of the NAV. Zero keeps burns from borrowing
+    pub allow_borrow: u8,
+    /// Whether burns are rejected in a transaction that also mints the token, so a price
+    /// moved within one transaction can't be captured by minting and burning around it.
+    /// Burns then need the instructions sysvar and can't be made through other programs,
+    /// so it is off by default
+    pub same_slot_burn_guard: u8,
+    pub max_borrow_bps: u16,
+    /// Mints must be a multiple of this many native units, so holdings don't splinter into
+    /// dust. Zero allows any quantity
+    pub min_mint_increment: u32,
+
+    /// Ring buffer of the last `REBALANCE_HISTORY_LEN` rebalances, the next one written at
+    /// `num_rebalances % REBALANCE_HISTORY_LEN`
+    pub rebalance_history: [RebalanceRecord; REBALANCE_HISTORY_LEN],
+    pub num_rebalances: u64,
+    /// Epoch `epoch_rebalances` counts the rebalances of
+    pub rebalance_epoch: u64,
+    /// Most native units a single burn may redeem, pacing large exits whose perp trade would
+    /// otherwise hit the book at once on the next rebalance. Zero lifts the cap
+    pub max_redemption_per_tx: u64,
+    /// Offset of rebalance orders' limit price from the market price in the passive
+    /// direction, in bps
+    pub price_offset_bps: u16,
+    /// Rebalances allowed per Solana epoch, bounding the trading cost and MEV exposure of a
+    /// token. Deleverages past `max_effective_leverage` are always allowed. Zero lifts the cap
+    pub max_rebalances_per_epoch: u16,
+    /// Rebalances that traded so far in `rebalance_epoch`
+    pub epoch_rebalances: u16,
+    pub padding3: [u8; 2],
+
+    /// EMA of the absolute return of the rebalance price over `VOLATILITY_SAMPLE_SLOTS`,
+    /// weighting the latest return by `VOLATILITY_EMA_BPS`
+    pub volatility_ema: I80F48,
+    /// Rebalance price the next return is measured from, zero before the first rebalance
+    pub volatility_price: I80F48,
+    /// Volatility above which rebalances scale the target leverage down by
+    /// `volatility_target / volatility_ema`, so the base target is only reached in calm
+    /// markets. Zero keeps the target fixed
+    pub volatility_target: I80F48,
+    /// Slot `volatility_price` was sampled at
+    pub volatility_slot: u64,
+    /// Slot of the last rebalance, whether it traded or not
+    pub last_update_slot: u64,
+
+    pub reserved: [u8; LEVERAGE_TOKEN_RESERVED],
+}
+
+/// A rebalance as kept in the history of a leveraged token
+#[derive(Copy, Clone, Debug, PartialEq, Pod)]
+#[repr(C)]
+pub struct RebalanceRecord {
+    pub slot: u64,
+    /// Limit price of the perp order in quote lots per base lot
+    pub price: i64,
+    /// Perp base lots filled, unsigned
+    pub quantity: i64,
+    /// `Side` of the perp order
+    pub side: u8,
+    pub padding: [u8; 7],
 }
 
 impl LeverageToken {
@@ -168,6 +555,221 @@ impl LeverageToken {
         self.mint == Pubkey::default()
     }
 
+    pub fn uses_oracle_price(&self) -> bool {
+        self.rebalance_price_source == RebalancePriceSource::Oracle as u8
+    }
+
+    /// Quote decimals are read from Mango rather than stored, so they are checked against
+    /// the same bound as base decimals when the quote mint is stored
+    pub fn check_quote_decimals(quote_decimals: u8) -> QuasarResult {
+        check!(
+            quote_decimals <= MAX_BASE_TOKEN_DECIMALS,
+            QuasarErrorCode::InvalidParam
+        )
+    }
+
+    pub fn check_active(&self) -> QuasarResult {
+        check_eq!(
+            self.state,
+            TokenState::Active as u8,
+            QuasarErrorCode::TokenFrozen
+        )
+    }
+
+    pub fn check_mint_active(&self, slot: u64) -> QuasarResult {
+        check!(
+            slot >= self.active_from_slot,
+            QuasarErrorCode::TokenNotActive
+        )
+    }
+
+    pub fn is_over_max_leverage(&self, effective_leverage: I80F48) -> bool {
+        self.max_effective_leverage > ZERO_I80F48
+            && effective_leverage.abs() > self.max_effective_leverage
+    }
+
+    pub fn record_rebalance(&mut self, record: RebalanceRecord) {
+        let index = (self.num_rebalances % REBALANCE_HISTORY_LEN as u64) as usize;
+        self.rebalance_history[index] = record;
+        self.num_rebalances = self.num_rebalances.wrapping_add(1);
+    }
+
+    /// Rebalances still in the history, oldest first
+    pub fn recent_rebalances(&self) -> Vec<RebalanceRecord> {
+        let len = self.num_rebalances.min(REBALANCE_HISTORY_LEN as u64);
+        (self.num_rebalances - len..self.num_rebalances)
+            .map(|n| self.rebalance_history[(n % REBALANCE_HISTORY_LEN as u64) as usize])
+            .collect()
+    }
+
+    /// Rebalances wait out the cooldown, except when the effective leverage breaches the cap
+    pub fn check_rebalance_due(&self, effective_leverage: I80F48, slot: u64) -> QuasarResult {
+        if self.is_over_max_leverage(effective_leverage) {
+            return Ok(());
+        }
+        check!(
+            slot >= self
+                .last_rebalance_slot
+                .saturating_add(self.rebalance_cooldown_slots),
+            QuasarErrorCode::RebalanceCooldown
+        )
+    }
+
+    /// Rejects a rebalance once `max_rebalances_per_epoch` traded in `epoch`, unless the
+    /// effective leverage breaches the cap
+    pub fn check_epoch_rebalance(&self, effective_leverage: I80F48, epoch: u64) -> QuasarResult {
+        let epoch_rebalances = if epoch == self.rebalance_epoch {
+            self.epoch_rebalances
+        } else {
+            0
+        };
+        check!(
+            self.max_rebalances_per_epoch == 0
+                || epoch_rebalances < self.max_rebalances_per_epoch
+                || self.is_over_max_leverage(effective_leverage),
+            QuasarErrorCode::RebalanceLimitReached
+        )
+    }
+
+    /// Counts a rebalance that traded in `epoch`
+    pub fn record_epoch_rebalance(&mut self, epoch: u64) {
+        if epoch != self.rebalance_epoch {
+            self.rebalance_epoch = epoch;
+            self.epoch_rebalances = 0;
+        }
+        self.epoch_rebalances = self.epoch_rebalances.saturating_add(1);
+    }
+
+    /// Whether a burn paying out `quote_amount` needs to borrow, given the net `quote_deposit`
+    /// of the Mango account. Fails when the borrow isn't allowed or would leave more than
+    /// `max_borrow_bps` of `nav` borrowed
+    pub fn check_burn_borrow(
+        &self,
+        quote_amount: u64,
+        quote_deposit: I80F48,
+        nav: I80F48,
+    ) -> QuasarResult<bool> {
+        let borrow = I80F48::from_num(quote_amount)
+            .checked_sub(quote_deposit)
+            .ok_or(math_err!())?;
+        if borrow <= ZERO_I80F48 {
+            return Ok(false);
+        }
+
+        check!(self.allow_borrow != 0, QuasarErrorCode::InsufficientFunds)?;
+        let max_borrow = safe_div(
+            nav.checked_mul(I80F48::from_num(self.max_borrow_bps))
+                .ok_or(math_err!())?,
+            I80F48::from_num(BPS_UNIT),
+        )?;
+        check!(borrow <= max_borrow, QuasarErrorCode::InsufficientFunds)?;
+
+        Ok(true)
+    }
+
+    pub fn check_mint_increment(&self, quantity: u64) -> QuasarResult {
+        if self.min_mint_increment == 0 {
+            return Ok(());
+        }
+        check!(
+            quantity % u64::from(self.min_mint_increment) == 0,
+            QuasarErrorCode::InvalidParam
+        )
+    }
+
+    pub fn check_redemption_size(&self, quantity: u64) -> QuasarResult {
+        check!(
+            self.max_redemption_per_tx == 0 || quantity <= self.max_redemption_per_tx,
+            QuasarErrorCode::InvalidParam
+        )
+    }
+
+    pub fn has_pending_rebalance(&self) -> bool {
+        self.pending_rebalance_quantity != 0
+    }
+
+    /// Target leverage the next rebalance aims for, read from the config when the token
+    /// follows one
+    pub fn get_target_leverage(
+        &self,
+        rebalance_config: Option<&RebalanceConfig>,
+    ) -> QuasarResult<I80F48> {
+        if self.rebalance_config == Pubkey::default() {
+            return Ok(self.target_leverage);
+        }
+
+        let rebalance_config =
+            rebalance_config.ok_or(throw_err!(QuasarErrorCode::InvalidAccount))?;
+        check_eq!(
+            rebalance_config.leverage_token_mint,
+            self.mint,
+            QuasarErrorCode::InvalidAccount
+        )?;
+        Ok(rebalance_config.target_leverage)
+    }
+
+    /// Folds the return from the last sampled price to `price` into the volatility EMA once
+    /// `VOLATILITY_SAMPLE_SLOTS` have passed, and samples `price` for the next return.
+    /// A return over a longer gap is scaled down to one sampling interval by the square root
+    /// of the intervals elapsed, as for a random walk, so rare rebalances don't inflate the
+    /// EMA and frequent ones don't deflate it
+    pub fn update_volatility(&mut self, price: I80F48, slot: u64) -> QuasarResult {
+        if self.volatility_price > ZERO_I80F48 {
+            let elapsed_slots = slot.saturating_sub(self.volatility_slot);
+            if elapsed_slots < VOLATILITY_SAMPLE_SLOTS {
+                return Ok(());
+            }
+
+            let price_return = safe_div(
+                price
+                    .checked_sub(self.volatility_price)
+                    .ok_or(math_err!())?,
+                self.volatility_price,
+            )?
+            .abs();
+            let interval_return = safe_div(
+                price_return,
+                sqrt_ratio(elapsed_slots, VOLATILITY_SAMPLE_SLOTS)?,
+            )?;
+            let weight = I80F48::from_num(VOLATILITY_EMA_BPS) / I80F48::from_num(BPS_UNIT);
+            let step = interval_return
+                .checked_sub(self.volatility_ema)
+                .and_then(|delta| delta.checked_mul(weight))
+                .ok_or(math_err!())?;
+            self.volatility_ema = self.volatility_ema.checked_add(step).ok_or(math_err!())?;
+        }
+        self.volatility_price = price;
+        self.volatility_slot = slot;
+        Ok(())
+    }
+
+    /// `target_leverage` scaled down while the volatility EMA exceeds a non zero
+    /// `volatility_target`. The base target is left as is
+    pub fn adapt_target_leverage(&self, target_leverage: I80F48) -> QuasarResult<I80F48> {
+        if self.volatility_target == ZERO_I80F48 || self.volatility_ema <= self.volatility_target {
+            return Ok(target_leverage);
+        }
+
+        let scale = safe_div(self.volatility_target, self.volatility_ema)?;
+        target_leverage.checked_mul(scale).ok_or(math_err!())
+    }
+
+    pub fn get_mint_fee_bps(&self, quasar_group: &QuasarGroup) -> u16 {
+        if self.mint_fee_bps == USE_GROUP_FEE {
+            quasar_group.mint_fee_bps
+        } else {
+            self.mint_fee_bps
+        }
+    }
+
+    pub fn get_burn_fee_bps(&self, quasar_group: &QuasarGroup) -> u16 {
+        if self.burn_fee_bps == USE_GROUP_FEE {
+            quasar_group.burn_fee_bps
+        } else {
+            self.burn_fee_bps
+        }
+    }
+
     pub fn get_native_price(
         &self,
         mint_ai: &AccountInfo,
@@ -177,40 +779,287 @@ impl LeverageToken {
     ) -> Result<I80F48, QuasarError> {
         let mint = Mint::unpack(&mint_ai.try_borrow_data()?)?;
         let supply = mint.supply;
+        let quote_decimals = mango_group.tokens[QUOTE_INDEX].decimals;
 
         if supply == 0 {
-            let quote_decimals = mango_group.tokens[QUOTE_INDEX].decimals;
-            let quote_unit = 10u64.pow(quote_decimals.into());
-            return Ok(I80F48::from_num(INITIAL_LEVERAGE_TOKEN_PRICE * quote_unit));
+            return native_price_from_nav(ZERO_I80F48, 0, mint.decimals, quote_decimals);
         }
 
-        let mut net_asset_value = ZERO_I80F48;
+        let now_ts = Clock::get()?.unix_timestamp as u64;
+        check_mango_cache_fresh(mango_group, mango_cache, now_ts)?;
 
-        for i in 0..mango_group.num_oracles {
-            let spot_value = get_mango_spot_value(
-                &mango_account,
-                &mango_cache.root_bank_cache[i],
-                mango_cache.price_cache[i].price,
-                i,
-            )?;
+        let net_asset_value = mango_net_asset_value(mango_group, mango_cache, mango_account)?;
+        native_price_from_nav(net_asset_value, supply, mint.decimals, quote_decimals)
+    }
+}
 
-            let (perp_base_value, perp_quote_value) = mango_account.perp_accounts[i].get_val(
-                &mango_group.perp_markets[i],
-                &mango_cache.perp_market_cache[i],
-                mango_cache.price_cache[i].price,
-            )?;
+/// Native quote per native unit of a leveraged token with `decimals` decimals whose
+/// `supply` native units are backed by `net_asset_value` native quote. Without supply, one
+/// whole token is priced at `INITIAL_LEVERAGE_TOKEN_PRICE` whole quote tokens
+pub fn native_price_from_nav(
+    net_asset_value: I80F48,
+    supply: u64,
+    decimals: u8,
+    quote_decimals: u8,
+) -> QuasarResult<I80F48> {
+    if supply == 0 {
+        return initial_native_price(decimals, quote_decimals);
+    }
+    safe_div(net_asset_value, I80F48::from_num(supply))
+}
 
-            net_asset_value = net_asset_value
-                .checked_add(
-                    spot_value
-                        .checked_add(perp_base_value.checked_add(perp_quote_value).unwrap())
-                        .unwrap(),
-                )
-                .unwrap();
-        }
+/// Rejects minting at a native price over `MAX_PRICE_MULTIPLE` times the initial one with
+/// `PriceOutOfBand`. Burns always redeem at the NAV price, so holders of a dust supply priced
+/// up by donated quote can still get it out
+pub fn check_mint_price_band(
+    native_price: I80F48,
+    decimals: u8,
+    quote_decimals: u8,
+) -> QuasarResult {
+    let max_price = initial_native_price(decimals, quote_decimals)?
+        .checked_mul(I80F48::from_num(MAX_PRICE_MULTIPLE))
+        .ok_or(math_err!())?;
+    check!(native_price <= max_price, QuasarErrorCode::PriceOutOfBand)
+}
+
+/// Native quote paying for `quantity` native units of a leveraged token with no supply yet,
+/// rounded up
+pub fn initial_quote_amount(quantity: u64, decimals: u8, quote_decimals: u8) -> QuasarResult<u64> {
+    get_quote_amount(
+        quantity,
+        initial_native_price(decimals, quote_decimals)?,
+        true,
+    )
+}
+
+/// Native reference unit of `NET_DEPOSITS_DECIMALS` worth `quote_amount` native quote of
+/// `quote_decimals`, with the quote worth `quote_price` reference units. Rounded down
+pub fn net_deposit_amount(
+    quote_amount: u64,
+    quote_decimals: u8,
+    quote_price: I80F48,
+) -> QuasarResult<u64> {
+    let reference_value = quote_to_reference(I80F48::from_num(quote_amount), quote_price)?;
+    let net_deposit = if quote_decimals <= NET_DEPOSITS_DECIMALS {
+        let scale = 10u64
+            .checked_pow((NET_DEPOSITS_DECIMALS - quote_decimals).into())
+            .ok_or(math_err!())?;
+        reference_value
+            .checked_mul(I80F48::from_num(scale))
+            .ok_or(math_err!())?
+    } else {
+        let scale = 10u64
+            .checked_pow((quote_decimals - NET_DEPOSITS_DECIMALS).into())
+            .ok_or(math_err!())?;
+        safe_div(reference_value, I80F48::from_num(scale))?
+    };
+    net_deposit.checked_to_num().ok_or(math_err!())
+}
+
+fn initial_native_price(decimals: u8, quote_decimals: u8) -> QuasarResult<I80F48> {
+    let quote_unit = 10u64
+        .checked_pow(quote_decimals.into())
+        .ok_or(math_err!())?;
+    let token_unit = 10u64.checked_pow(decimals.into()).ok_or(math_err!())?;
+    let initial_price = INITIAL_LEVERAGE_TOKEN_PRICE
+        .checked_mul(quote_unit)
+        .ok_or(math_err!())?;
+    safe_div(
+        I80F48::from_num(initial_price),
+        I80F48::from_num(token_unit),
+    )
+}
+
+/// Rebalance target of a leveraged token maintained off chain, e.g. following the weights
+/// of an index, within the bound set by the admin
+#[derive(Copy, Clone, Pod, Loadable)]
+#[repr(C)]
+pub struct RebalanceConfig {
+    pub meta_data: MetaData,
+    pub last_update: u64,
+
+    pub quasar_group: Pubkey,
+    pub leverage_token_mint: Pubkey,
+    /// Only this key may update the target
+    pub update_authority: Pubkey,
+
+    /// Targets beyond this leverage in either direction are rejected
+    pub max_target_leverage: I80F48,
+    pub target_leverage: I80F48,
+
+    /// Signs target exposures computed off chain, used by `RebalanceToExposure` in place of
+    /// the target leverage. The default pubkey disables it
+    pub keeper: Pubkey,
+}
+
+impl RebalanceConfig {
+    /// Reads a config stored in the legacy layout, leaving it without a keeper
+    pub fn from_legacy(data: &[u8]) -> QuasarResult<Self> {
+        check_eq!(
+            data.len(),
+            LEGACY_REBALANCE_CONFIG_SIZE,
+            QuasarErrorCode::InvalidAccount
+        )?;
+        let mut rebalance_config = Self::zeroed();
+        bytemuck::bytes_of_mut(&mut rebalance_config)[..LEGACY_REBALANCE_CONFIG_SIZE]
+            .copy_from_slice(data);
+        check!(
+            rebalance_config.meta_data.is_initialized,
+            QuasarErrorCode::InvalidAccount
+        )?;
+        check_eq!(
+            rebalance_config.meta_data.data_type,
+            DataType::RebalanceConfig as u8,
+            QuasarErrorCode::InvalidAccount
+        )?;
+
+        Ok(rebalance_config)
+    }
+
+    pub fn load_mut_checked<'a>(
+        account: &'a AccountInfo,
+        program_id: &Pubkey,
+    ) -> QuasarResult<RefMut<'a, Self>> {
+        check_eq!(account.owner, program_id, QuasarErrorCode::InvalidOwner)?;
+
+        let rebalance_config: RefMut<'a, Self> = Self::load_mut(account)?;
+        check!(
+            rebalance_config.meta_data.is_initialized,
+            QuasarErrorCode::InvalidAccount
+        )?;
+        check_eq!(
+            rebalance_config.meta_data.data_type,
+            DataType::RebalanceConfig as u8,
+            QuasarErrorCode::InvalidAccount
+        )?;
+
+        Ok(rebalance_config)
+    }
+
+    pub fn load_checked<'a>(
+        account: &'a AccountInfo,
+        program_id: &Pubkey,
+    ) -> QuasarResult<Ref<'a, Self>> {
+        check_eq!(account.owner, program_id, QuasarErrorCode::InvalidOwner)?;
+
+        let rebalance_config: Ref<'a, Self> = Self::load(account)?;
+        check!(
+            rebalance_config.meta_data.is_initialized,
+            QuasarErrorCode::InvalidAccount
+        )?;
+        check_eq!(
+            rebalance_config.meta_data.data_type,
+            DataType::RebalanceConfig as u8,
+            QuasarErrorCode::InvalidAccount
+        )?;
+
+        Ok(rebalance_config)
+    }
+
+    pub fn check_target_leverage(&self, target_leverage: I80F48) -> QuasarResult {
+        check!(
+            target_leverage.abs() <= self.max_target_leverage,
+            QuasarErrorCode::InvalidParam
+        )
+    }
+}
+
+/// Running totals of the mints and burns of a leveraged token, kept apart from the quasar
+/// group so that mints and burns of different tokens don't contend for one account
+#[derive(Copy, Clone, Pod, Loadable)]
+#[repr(C)]
+pub struct TokenLedger {
+    pub meta_data: MetaData,
+
+    pub quasar_group: Pubkey,
+    pub leverage_token_mint: Pubkey,
+
+    /// Native units minted and burned by this program, which the mint supply must match
+    pub total_minted: u64,
+    pub total_burned: u64,
+    /// Quote deposited by the mints of the outstanding supply, in native reference unit of
+    /// `NET_DEPOSITS_DECIMALS`. A burn takes out its share of the supply
+    pub net_deposits: u64,
+}
+
+impl TokenLedger {
+    pub fn load_mut_checked<'a>(
+        account: &'a AccountInfo,
+        program_id: &Pubkey,
+    ) -> QuasarResult<RefMut<'a, Self>> {
+        check_eq!(account.owner, program_id, QuasarErrorCode::InvalidOwner)?;
+
+        let token_ledger: RefMut<'a, Self> = Self::load_mut(account)?;
+        check!(
+            token_ledger.meta_data.is_initialized,
+            QuasarErrorCode::InvalidAccount
+        )?;
+        check_eq!(
+            token_ledger.meta_data.data_type,
+            DataType::TokenLedger as u8,
+            QuasarErrorCode::InvalidAccount
+        )?;
+
+        Ok(token_ledger)
+    }
+
+    pub fn load_checked<'a>(
+        account: &'a AccountInfo,
+        program_id: &Pubkey,
+    ) -> QuasarResult<Ref<'a, Self>> {
+        check_eq!(account.owner, program_id, QuasarErrorCode::InvalidOwner)?;
+
+        let token_ledger: Ref<'a, Self> = Self::load(account)?;
+        check!(
+            token_ledger.meta_data.is_initialized,
+            QuasarErrorCode::InvalidAccount
+        )?;
+        check_eq!(
+            token_ledger.meta_data.data_type,
+            DataType::TokenLedger as u8,
+            QuasarErrorCode::InvalidAccount
+        )?;
+
+        Ok(token_ledger)
+    }
+
+    /// Supply the mint should report after every mint and burn made by this program
+    pub fn get_expected_supply(&self) -> QuasarResult<u64> {
+        self.total_minted
+            .checked_sub(self.total_burned)
+            .ok_or(math_err!())
+    }
+
+    pub fn check_supply(&self, supply: u64) -> QuasarResult {
+        check_eq!(
+            supply,
+            self.get_expected_supply()?,
+            QuasarErrorCode::SupplyMismatch
+        )
+    }
+
+    pub fn record_mint(&mut self, quantity: u64, net_deposit: u64) -> QuasarResult {
+        self.total_minted = self.total_minted.checked_add(quantity).ok_or(math_err!())?;
+        self.net_deposits = self
+            .net_deposits
+            .checked_add(net_deposit)
+            .ok_or(math_err!())?;
+        Ok(())
+    }
 
-        Ok(net_asset_value
-            .checked_div(I80F48::from_num(supply))
-            .unwrap())
+    /// Records a burn of `quantity` out of `supply`, returning the share of the net deposits
+    /// it takes out. Burns redeem at the NAV rather than the deposits, so this removes what
+    /// the burnt tokens were minted for, whatever they pay out
+    pub fn record_burn(&mut self, quantity: u64, supply: u64) -> QuasarResult<u64> {
+        check!(quantity <= supply, QuasarErrorCode::MathError)?;
+        let removed = if quantity == supply {
+            self.net_deposits
+        } else {
+            let removed = u128::from(self.net_deposits) * u128::from(quantity) / u128::from(supply);
+            u64::try_from(removed).map_err(|_| math_err!())?
+        };
+        self.total_burned = self.total_burned.checked_add(quantity).ok_or(math_err!())?;
+        self.net_deposits = self.net_deposits.checked_sub(removed).ok_or(math_err!())?;
+        Ok(removed)
     }
 }
@@ -1,16 +1,51 @@
 use fixed::types::I80F48;
-use mango::state::{MangoAccount, RootBankCache, ZERO_I80F48};
+use mango::matching::Side;
+use mango::state::{
+    MangoAccount, MangoCache, MangoGroup, MetaData, RootBankCache, QUOTE_INDEX, ZERO_I80F48,
+};
+use solana_program::account_info::AccountInfo;
+use solana_program::msg;
 use solana_program::program_error::ProgramError;
 use solana_program::pubkey::Pubkey;
+use solana_program::sysvar;
 
-use bytemuck::{bytes_of, cast_slice_mut, from_bytes_mut, Contiguous, Pod};
+use bytemuck::{bytes_of, cast_slice_mut, from_bytes_mut, try_from_bytes, Contiguous, Pod};
+use std::cell::Ref;
+use std::mem::size_of;
 
-use crate::error::QuasarResult;
+use crate::error::{check_assert, QuasarError, QuasarErrorCode, QuasarResult, SourceFileId};
+use crate::instruction::QuasarInstruction;
+
+declare_check_assert_macros!(SourceFileId::Utils);
+
+/// Maximum number of items a batch instruction processes in one call. Larger batches are
+/// rejected up front instead of aborting halfway through on the compute budget
+pub const MAX_BATCH_SIZE: usize = 8;
+
+pub fn check_batch_size(num_items: usize) -> QuasarResult {
+    check!(
+        num_items > 0 && num_items <= MAX_BATCH_SIZE,
+        QuasarErrorCode::InvalidParam
+    )
+}
+
+pub const BPS_UNIT: u64 = 10_000;
+
+/// Highest price above the oracle a mint's spot swap accepts, in bps
+pub const MAX_SPOT_SWAP_SLIPPAGE_BPS: u64 = 100;
+/// Largest offset of a rebalance order's limit price away from the market, in bps
+pub const MAX_REBALANCE_PRICE_OFFSET_BPS: u16 = 500;
+
+/// SPL governance program, the only owner an admin that doesn't sign the init can have
+pub mod governance_program {
+    solana_program::declare_id!("GovER5Lthms3bLBqWub97yVrMmEogzX7xNjdXpPPCVZw");
+}
 
 pub fn gen_signer_seeds<'a>(nonce: &'a u64, acc_pk: &'a Pubkey) -> [&'a [u8]; 2] {
     [acc_pk.as_ref(), bytes_of(nonce)]
 }
 
+/// PDA of `acc_pk` and `nonce`. Errors when the derived address is on the curve
 pub fn gen_signer_key(
     nonce: u64,
     acc_pk: &Pubkey,
@@ -20,23 +55,995 @@ pub fn gen_signer_key(
     Ok(Pubkey::create_program_address(&seeds, program_id)?)
 }
 
+/// Signer PDA of an initialized quasar group, from the `signer_nonce` stored in it.
+/// Mint, burn and rebalance take it as `pda_ai`
+pub fn group_signer(
+    group_key: &Pubkey,
+    nonce: u64,
+    program_id: &Pubkey,
+) -> Result<Pubkey, ProgramError> {
+    gen_signer_key(nonce, group_key, program_id)
+}
+
+/// First `(signer PDA, nonce)` of a quasar group account that is not initialized yet,
+/// as `InitQuasarGroup` expects them
+pub fn simulate_init(group_key: &Pubkey, program_id: &Pubkey) -> (Pubkey, u64) {
+    (0..u64::MAX)
+        .find_map(|nonce| {
+            group_signer(group_key, nonce, program_id)
+                .ok()
+                .map(|key| (key, nonce))
+        })
+        .unwrap()
+}
+
+/// Publishes the result of a view instruction. solana-program 1.7 has no return data syscall,
+/// so the bytes are logged as hex for clients to read back from the (simulated) transaction logs
+pub fn set_return_data(data: &[u8]) {
+    let hex: String = data.iter().map(|b| format!("{:02x}", b)).collect();
+    msg!("return data: {}", hex);
+}
+
+/// Formats a leverage for logs rounded to two decimals, e.g. `3.00x` or `-1.50x`
+pub fn format_leverage(leverage: I80F48) -> String {
+    let hundredths = leverage
+        .saturating_mul(I80F48::from_num(100))
+        .round()
+        .to_num::<i128>();
+    let sign = if hundredths < 0 { "-" } else { "" };
+    let hundredths = hundredths.abs();
+
+    format!("{}{}.{:02}x", sign, hundredths / 100, hundredths % 100)
+}
+
+/// Leverage in bps of exposure per NAV, e.g. `30_000` for `3x` and `-15_000` for `-1.5x`,
+/// rounded to the nearest bps
+pub fn leverage_to_bps(leverage: I80F48) -> QuasarResult<i32> {
+    leverage
+        .checked_mul(I80F48::from_num(BPS_UNIT))
+        .and_then(|bps| bps.checked_round())
+        .and_then(|bps| bps.checked_to_num())
+        .ok_or(math_err!())
+}
+
+/// Leverage of `bps`, the inverse of `leverage_to_bps`
+pub fn bps_to_leverage(bps: i32) -> I80F48 {
+    I80F48::from_num(bps) / I80F48::from_num(BPS_UNIT)
+}
+
+/// Divides `a` by `b`, returning `MathError` instead of panicking on a zero divisor or overflow
+pub fn safe_div(a: I80F48, b: I80F48) -> QuasarResult<I80F48> {
+    check!(b != ZERO_I80F48, QuasarErrorCode::MathError)?;
+    a.checked_div(b).ok_or(math_err!())
+}
+
+/// Square root of `numerator / denominator` to six decimals, rounded down
+pub fn sqrt_ratio(numerator: u64, denominator: u64) -> QuasarResult<I80F48> {
+    const SCALE: u128 = 1_000_000;
+    check!(denominator != 0, QuasarErrorCode::MathError)?;
+
+    let n = u128::from(numerator) * SCALE * SCALE / u128::from(denominator);
+    // Newton's method from above, stopping once the estimate no longer decreases
+    let mut root = n;
+    let mut next = n / 2 + n % 2;
+    while next < root {
+        root = next;
+        next = (root + n / root) / 2;
+    }
+    safe_div(I80F48::from_num(root), I80F48::from_num(SCALE))
+}
+
+/// Makes sure every cache entry a NAV computation reads was updated within the Mango group's
+/// `valid_interval` of `now_ts`
+pub fn check_mango_cache_fresh(
+    mango_group: &MangoGroup,
+    mango_cache: &MangoCache,
+    now_ts: u64,
+) -> QuasarResult {
+    let is_fresh =
+        |last_update: u64| now_ts <= last_update.saturating_add(mango_group.valid_interval);
+
+    for i in 0..mango_group.num_oracles {
+        check!(
+            is_fresh(mango_cache.price_cache[i].last_update),
+            QuasarErrorCode::StaleOracle
+        )?;
+        if mango_group.tokens[i].root_bank != Pubkey::default() {
+            check!(
+                is_fresh(mango_cache.root_bank_cache[i].last_update),
+                QuasarErrorCode::StaleOracle
+            )?;
+        }
+        if mango_group.perp_markets[i].perp_market != Pubkey::default() {
+            check!(
+                is_fresh(mango_cache.perp_market_cache[i].last_update),
+                QuasarErrorCode::StaleOracle
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
 pub fn get_mango_spot_value(
     mango_account: &MangoAccount,
     bank_cache: &RootBankCache,
     price: I80F48,
     market_index: usize,
 ) -> QuasarResult<I80F48> {
+    // Feeds every NAV, so an overflow has to surface as an error rather than a panic
     let base_net = if mango_account.deposits[market_index].is_positive() {
         mango_account.deposits[market_index]
             .checked_mul(bank_cache.deposit_index)
-            .unwrap()
+            .ok_or(math_err!())?
     } else if mango_account.borrows[market_index].is_positive() {
         -mango_account.borrows[market_index]
             .checked_mul(bank_cache.borrow_index)
-            .unwrap()
+            .ok_or(math_err!())?
     } else {
         ZERO_I80F48
     };
 
-    Ok(base_net * price)
+    base_net.checked_mul(price).ok_or(math_err!())
+}
+
+/// Net quote held in the Mango account at face value, quote being the unit of every NAV.
+/// `QUOTE_INDEX` is past the last market index, so loops over `num_oracles` never see it
+/// and NAVs add this exactly once
+pub fn get_mango_quote_value(
+    mango_account: &MangoAccount,
+    mango_cache: &MangoCache,
+) -> QuasarResult<I80F48> {
+    get_mango_spot_value(
+        mango_account,
+        &mango_cache.root_bank_cache[QUOTE_INDEX],
+        I80F48::ONE,
+        QUOTE_INDEX,
+    )
+}
+
+/// Adds the value of one market to a running NAV `total`. The perp base and quote values
+/// are large and of opposite signs, so they are netted before touching the total to keep
+/// intermediate sums small. Overflows surface as `MathError`
+pub fn add_market_value(
+    total: I80F48,
+    spot_value: I80F48,
+    perp_base_value: I80F48,
+    perp_quote_value: I80F48,
+) -> QuasarResult<I80F48> {
+    perp_base_value
+        .checked_add(perp_quote_value)
+        .and_then(|perp_value| perp_value.checked_add(spot_value))
+        .and_then(|market_value| total.checked_add(market_value))
+        .ok_or(math_err!())
+}
+
+fn gcd(a: i128, b: i128) -> i128 {
+    let (mut a, mut b) = (a.abs(), b.abs());
+    while b != 0 {
+        let r = a % b;
+        a = b;
+        b = r;
+    }
+    a
+}
+
+/// Client order id of the perp order a rebalance places, unique per leveraged token and
+/// slot so keepers can tell rebalances apart and cancel a single one
+pub fn rebalance_client_order_id(slot: u64, leverage_token_index: usize) -> u64 {
+    (slot << 8) | leverage_token_index as u64
+}
+
+/// Perp position in base lots including taker fills whose events are not consumed yet
+pub fn get_perp_base_position(mango_account: &MangoAccount, market_index: usize) -> i64 {
+    let perp_account = &mango_account.perp_accounts[market_index];
+    perp_account.base_position + perp_account.taker_base
+}
+
+/// Mango group, cache and account of a leveraged token, loaded together so the cache and the
+/// account are always checked to belong to the group
+pub fn load_mango_context<'a, 'b>(
+    mango_program_id: &Pubkey,
+    mango_group_ai: &'a AccountInfo<'b>,
+    mango_cache_ai: &'a AccountInfo<'b>,
+    mango_account_ai: &'a AccountInfo<'b>,
+) -> QuasarResult<(
+    Ref<'a, MangoGroup>,
+    Ref<'a, MangoCache>,
+    Ref<'a, MangoAccount>,
+)> {
+    let mango_group = MangoGroup::load_checked(mango_group_ai, mango_program_id)?;
+    // Mango checks this as well, but a Quasar error tells a bad cache from a bad Mango group
+    check_eq!(
+        *mango_cache_ai.key,
+        mango_group.mango_cache,
+        QuasarErrorCode::InvalidAccount
+    )?;
+    let mango_cache = MangoCache::load_checked(mango_cache_ai, mango_program_id, &mango_group)?;
+    let mango_account =
+        MangoAccount::load_checked(mango_account_ai, mango_program_id, mango_group_ai.key)?;
+
+    Ok((mango_group, mango_cache, mango_account))
+}
+
+/// Net asset value in native quote of a Mango account, its quote deposits net of borrows
+/// plus the spot and perp value of every market at the Mango cache prices. Every NAV of a
+/// leveraged token is valued here, so they agree with each other
+pub fn mango_net_asset_value(
+    mango_group: &MangoGroup,
+    mango_cache: &MangoCache,
+    mango_account: &MangoAccount,
+) -> QuasarResult<I80F48> {
+    let mut net_asset_value = ZERO_I80F48;
+    for i in 0..mango_group.num_oracles {
+        let price = mango_cache.price_cache[i].price;
+        let spot_value =
+            get_mango_spot_value(mango_account, &mango_cache.root_bank_cache[i], price, i)?;
+        let (perp_base_value, perp_quote_value) = mango_account.perp_accounts[i].get_val(
+            &mango_group.perp_markets[i],
+            &mango_cache.perp_market_cache[i],
+            price,
+        )?;
+        net_asset_value = add_market_value(
+            net_asset_value,
+            spot_value,
+            perp_base_value,
+            perp_quote_value,
+        )?;
+    }
+    net_asset_value
+        .checked_add(get_mango_quote_value(mango_account, mango_cache)?)
+        .ok_or(math_err!())
+}
+
+/// Value of a leveraged token's Mango account in native quote, with the exposure on its
+/// market split between what is filled and what its resting perp orders would add
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct NavBreakdown {
+    pub net_asset_value: I80F48,
+    /// Perp position and spot collateral on the token's market
+    pub exposure: I80F48,
+    /// Notional of the resting perp orders, positive when bids outweigh asks
+    pub pending_exposure: I80F48,
+}
+
+impl NavBreakdown {
+    pub fn load(
+        mango_group: &MangoGroup,
+        mango_cache: &MangoCache,
+        mango_account: &MangoAccount,
+        market_index: usize,
+    ) -> QuasarResult<Self> {
+        let net_asset_value = mango_net_asset_value(mango_group, mango_cache, mango_account)?;
+
+        let price = mango_cache.price_cache[market_index].price;
+        let spot_value = get_mango_spot_value(
+            mango_account,
+            &mango_cache.root_bank_cache[market_index],
+            price,
+            market_index,
+        )?;
+        let (perp_base_value, _) = mango_account.perp_accounts[market_index].get_val(
+            &mango_group.perp_markets[market_index],
+            &mango_cache.perp_market_cache[market_index],
+            price,
+        )?;
+        let exposure = spot_value.checked_add(perp_base_value).ok_or(math_err!())?;
+
+        let perp_account = &mango_account.perp_accounts[market_index];
+        let pending_base_lots = I80F48::from_num(perp_account.bids_quantity)
+            .checked_sub(I80F48::from_num(perp_account.asks_quantity))
+            .ok_or(math_err!())?;
+        let pending_exposure = pending_base_lots
+            .checked_mul(I80F48::from_num(
+                mango_group.perp_markets[market_index].base_lot_size,
+            ))
+            .and_then(|value| value.checked_mul(price))
+            .ok_or(math_err!())?;
+
+        Ok(Self {
+            net_asset_value,
+            exposure,
+            pending_exposure,
+        })
+    }
+
+    pub fn effective_leverage(&self) -> QuasarResult<I80F48> {
+        safe_div(self.exposure, self.net_asset_value)
+    }
+
+    /// Leverage once every resting perp order is filled
+    pub fn intended_leverage(&self) -> QuasarResult<I80F48> {
+        let exposure = self
+            .exposure
+            .checked_add(self.pending_exposure)
+            .ok_or(math_err!())?;
+        safe_div(exposure, self.net_asset_value)
+    }
+
+    /// Little endian `net_asset_value`, `exposure` and `pending_exposure`
+    pub fn pack(&self) -> [u8; 48] {
+        let mut data = [0u8; 48];
+        data[..16].copy_from_slice(&self.net_asset_value.to_le_bytes());
+        data[16..32].copy_from_slice(&self.exposure.to_le_bytes());
+        data[32..].copy_from_slice(&self.pending_exposure.to_le_bytes());
+        data
+    }
+}
+
+/// Exposure of a Mango account on `market_index` as it counts towards maintenance health,
+/// weighted by Mango's maintenance asset weights when long and liability weights when short
+pub fn get_maint_weighted_exposure(
+    mango_group: &MangoGroup,
+    mango_cache: &MangoCache,
+    mango_account: &MangoAccount,
+    market_index: usize,
+) -> QuasarResult<I80F48> {
+    let price = mango_cache.price_cache[market_index].price;
+    let spot_value = get_mango_spot_value(
+        mango_account,
+        &mango_cache.root_bank_cache[market_index],
+        price,
+        market_index,
+    )?;
+    let (perp_base_value, _) = mango_account.perp_accounts[market_index].get_val(
+        &mango_group.perp_markets[market_index],
+        &mango_cache.perp_market_cache[market_index],
+        price,
+    )?;
+
+    let spot_market = &mango_group.spot_markets[market_index];
+    let spot_weight = if spot_value.is_negative() {
+        spot_market.maint_liab_weight
+    } else {
+        spot_market.maint_asset_weight
+    };
+    let perp_market = &mango_group.perp_markets[market_index];
+    let perp_weight = if perp_base_value.is_negative() {
+        perp_market.maint_liab_weight
+    } else {
+        perp_market.maint_asset_weight
+    };
+    spot_value
+        .checked_mul(spot_weight)
+        .and_then(|spot| Some((spot, perp_base_value.checked_mul(perp_weight)?)))
+        .and_then(|(spot, perp)| spot.checked_add(perp))
+        .ok_or(math_err!())
+}
+
+/// Fraction the price of a market can move against a position before the account's
+/// maintenance health reaches zero, with the rest of the NAV counted at face value.
+/// `I80F48::MAX` when the position can't be liquidated by a price move, zero when the
+/// account is already liquidatable
+pub fn liquidation_distance(
+    net_asset_value: I80F48,
+    exposure: I80F48,
+    weighted_exposure: I80F48,
+) -> QuasarResult<I80F48> {
+    if net_asset_value <= ZERO_I80F48 {
+        return Ok(ZERO_I80F48);
+    }
+    if weighted_exposure == ZERO_I80F48 {
+        return Ok(I80F48::MAX);
+    }
+
+    // Health at a price `ratio` times the current one is
+    // `net_asset_value - exposure + ratio * weighted_exposure`
+    let ratio = safe_div(
+        exposure.checked_sub(net_asset_value).ok_or(math_err!())?,
+        weighted_exposure,
+    )?;
+    let distance = if weighted_exposure.is_positive() {
+        if !ratio.is_positive() {
+            return Ok(I80F48::MAX);
+        }
+        I80F48::from_num(1).checked_sub(ratio)
+    } else {
+        ratio.checked_sub(I80F48::from_num(1))
+    }
+    .ok_or(math_err!())?;
+    Ok(distance.max(ZERO_I80F48))
+}
+
+/// Outcome of a rebalance, published as its return data so keepers don't have to parse logs
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RebalanceResult {
+    /// Whether any perp base lots filled or spot collateral was sold
+    pub traded: bool,
+    /// Side of the perp order, `Side::Bid` when none was placed
+    pub side: Side,
+    /// Limit price of the perp order in quote lots per base lot
+    pub price: i64,
+    /// Perp base lots filled, unsigned
+    pub quantity: i64,
+    pub effective_leverage_before: I80F48,
+    pub effective_leverage_after: I80F48,
+}
+
+impl RebalanceResult {
+    /// `traded` and `side` as one byte each, then little endian `price`, `quantity`,
+    /// `effective_leverage_before` and `effective_leverage_after`
+    pub fn pack(&self) -> [u8; 50] {
+        let mut data = [0u8; 50];
+        data[0] = self.traded as u8;
+        data[1] = self.side as u8;
+        data[2..10].copy_from_slice(&self.price.to_le_bytes());
+        data[10..18].copy_from_slice(&self.quantity.to_le_bytes());
+        data[18..34].copy_from_slice(&self.effective_leverage_before.to_le_bytes());
+        data[34..].copy_from_slice(&self.effective_leverage_after.to_le_bytes());
+        data
+    }
+}
+
+/// Amount in native quote unit, e.g. 1_000_000 for 1 USDC
+///
+/// The unit types can't be mixed up without an explicit conversion:
+///
+/// ```compile_fail
+/// use fixed::types::I80F48;
+/// use quasar::utils::{PerpMarketUnits, QuoteLots};
+///
+/// let units = PerpMarketUnits::new(9, 6, 100, 10).unwrap();
+/// units.native_quote_to_quote_lots(QuoteLots(I80F48::from_num(1)));
+/// ```
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub struct QuoteNative(pub I80F48);
+
+/// Amount in quote lots of a perp market
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub struct QuoteLots(pub I80F48);
+
+/// Quantity in base lots of a perp market, as used by perp orders and positions
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub struct BaseLots(pub I80F48);
+
+/// Lot sizes and native units of a Mango perp market, computed once and reused
+/// for every price and quantity conversion against that market
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PerpMarketUnits {
+    pub base_lot_size: I80F48,
+    pub quote_lot_size: I80F48,
+    pub base_unit: I80F48,
+    pub quote_unit: I80F48,
+}
+
+impl PerpMarketUnits {
+    pub fn new(
+        base_decimals: u8,
+        quote_decimals: u8,
+        base_lot_size: i64,
+        quote_lot_size: i64,
+    ) -> QuasarResult<Self> {
+        // Every conversion divides by a lot size, so an unlisted market must not get this far
+        check!(
+            base_lot_size > 0 && quote_lot_size > 0,
+            QuasarErrorCode::InvalidParam
+        )?;
+
+        let base_unit = 10u64.checked_pow(base_decimals.into()).ok_or(math_err!())?;
+        let quote_unit = 10u64
+            .checked_pow(quote_decimals.into())
+            .ok_or(math_err!())?;
+
+        Ok(Self {
+            base_lot_size: I80F48::from_num(base_lot_size),
+            quote_lot_size: I80F48::from_num(quote_lot_size),
+            base_unit: I80F48::from_num(base_unit),
+            quote_unit: I80F48::from_num(quote_unit),
+        })
+    }
+
+    /// Units of the perp market at `market_index`. A market without lot sizes is
+    /// misconfigured or not listed, so it is reported as an invalid Mango group
+    pub fn load(mango_group: &MangoGroup, market_index: usize) -> QuasarResult<Self> {
+        let perp_market_info = &mango_group.perp_markets[market_index];
+        check!(
+            perp_market_info.base_lot_size > 0,
+            QuasarErrorCode::InvalidAccount
+        )?;
+        check!(
+            perp_market_info.quote_lot_size > 0,
+            QuasarErrorCode::InvalidAccount
+        )?;
+
+        Self::new(
+            mango_group.tokens[market_index].decimals,
+            mango_group.tokens[QUOTE_INDEX].decimals,
+            perp_market_info.base_lot_size,
+            perp_market_info.quote_lot_size,
+        )
+    }
+
+    /// Converts a price of quote per base into quote lots per base lot
+    ///
+    /// The integer scale factors are combined and reduced first so the price is only rounded
+    /// once. Multiplying before dividing keeps the most precision, so the division is only
+    /// moved in front when the scaled price would still overflow
+    pub fn price_to_quote_lots(&self, price: I80F48) -> QuasarResult<I80F48> {
+        let numerator = self
+            .quote_unit
+            .to_num::<i128>()
+            .checked_mul(self.base_lot_size.to_num::<i128>())
+            .ok_or(math_err!())?;
+        let denominator = self
+            .quote_lot_size
+            .to_num::<i128>()
+            .checked_mul(self.base_unit.to_num::<i128>())
+            .ok_or(math_err!())?;
+
+        let divisor = gcd(numerator, denominator);
+        let (numerator, denominator) = if divisor > 1 {
+            (numerator / divisor, denominator / divisor)
+        } else {
+            (numerator, denominator)
+        };
+        let numerator = I80F48::checked_from_num(numerator).ok_or(math_err!())?;
+        let denominator = I80F48::checked_from_num(denominator).ok_or(math_err!())?;
+
+        match price.checked_mul(numerator) {
+            Some(scaled_price) => safe_div(scaled_price, denominator),
+            None => safe_div(price, denominator)?
+                .checked_mul(numerator)
+                .ok_or(math_err!()),
+        }
+    }
+
+    /// Rejects a price in quote lots per base lot that the orderbook would see as zero.
+    /// Orders are placed at whole quote lots, so a cheap base on coarse lot sizes can't be
+    /// traded at all
+    pub fn check_order_price(&self, price: I80F48) -> QuasarResult {
+        if price < I80F48::ONE {
+            msg!(
+                "price of {} quote lots rounds to zero, the perp market needs finer lot sizes",
+                price
+            );
+            return Err(throw_err!(QuasarErrorCode::PriceRoundsToZero));
+        }
+        Ok(())
+    }
+
+    pub fn native_quote_to_quote_lots(&self, amount: QuoteNative) -> QuasarResult<QuoteLots> {
+        Ok(QuoteLots(safe_div(amount.0, self.quote_lot_size)?))
+    }
+
+    pub fn quote_lots_to_native_quote(&self, amount: QuoteLots) -> QuasarResult<QuoteNative> {
+        Ok(QuoteNative(
+            amount
+                .0
+                .checked_mul(self.quote_lot_size)
+                .ok_or(math_err!())?,
+        ))
+    }
+
+    /// Base lots worth `amount` at `price`, given in quote lots per base lot
+    /// as returned by `price_to_quote_lots`
+    pub fn quote_lots_to_base_lots(
+        &self,
+        amount: QuoteLots,
+        price: I80F48,
+    ) -> QuasarResult<BaseLots> {
+        Ok(BaseLots(safe_div(amount.0, price)?))
+    }
+
+    /// Quote lots worth `quantity` at `price`, given in quote lots per base lot
+    pub fn base_lots_to_quote_lots(
+        &self,
+        quantity: BaseLots,
+        price: I80F48,
+    ) -> QuasarResult<QuoteLots> {
+        Ok(QuoteLots(quantity.0.checked_mul(price).ok_or(math_err!())?))
+    }
+}
+
+/// Taker fee in native quote unit of a perp order of `quantity` at `price`, given in quote
+/// lots per base lot, on a market charging `taker_fee` of the notional
+pub fn estimate_taker_fee(
+    units: &PerpMarketUnits,
+    quantity: BaseLots,
+    price: I80F48,
+    taker_fee: I80F48,
+) -> QuasarResult<QuoteNative> {
+    let notional =
+        units.quote_lots_to_native_quote(units.base_lots_to_quote_lots(quantity, price)?)?;
+    let fee = notional.0.abs().checked_mul(taker_fee).ok_or(math_err!())?;
+    Ok(QuoteNative(fee))
+}
+
+/// Splits a mint deposit in native quote unit into the part kept as quote collateral and
+/// the part swapped into base spot collateral
+pub fn split_mint_deposit(quote_amount: u64, spot_swap_bps: u16) -> QuasarResult<(u64, u64)> {
+    check!(
+        u64::from(spot_swap_bps) <= BPS_UNIT,
+        QuasarErrorCode::InvalidParam
+    )?;
+
+    let swap_amount = (quote_amount as u128 * spot_swap_bps as u128 / BPS_UNIT as u128) as u64;
+    Ok((quote_amount - swap_amount, swap_amount))
+}
+
+/// `price` moved `offset_bps` in the passive direction of a bid (`is_bid`) or an ask, so a
+/// rebalance order rests on the book as a maker instead of crossing at the market price
+pub fn apply_price_offset(price: I80F48, is_bid: bool, offset_bps: u16) -> QuasarResult<I80F48> {
+    let offset_bps = u64::from(offset_bps);
+    let scale = if is_bid {
+        BPS_UNIT.checked_sub(offset_bps).ok_or(math_err!())?
+    } else {
+        BPS_UNIT + offset_bps
+    };
+    let price = price
+        .checked_mul(I80F48::from_num(scale))
+        .ok_or(math_err!())?;
+    safe_div(price, I80F48::from_num(BPS_UNIT))
+}
+
+/// Limit price in pc lots per coin lot and size in coin lots of an immediate-or-cancel
+/// spot bid spending at most `quote_amount` at up to `max_slippage_bps` above `price`,
+/// given in native quote per native base
+///
+/// Either is zero when the amount is too small to buy a single lot
+pub fn get_spot_bid_limits(
+    quote_amount: u64,
+    price: I80F48,
+    coin_lot_size: u64,
+    pc_lot_size: u64,
+    max_slippage_bps: u64,
+) -> QuasarResult<(u64, u64)> {
+    check!(
+        coin_lot_size > 0 && pc_lot_size > 0,
+        QuasarErrorCode::InvalidParam
+    )?;
+
+    let coin_lot_price = price
+        .checked_mul(I80F48::from_num(coin_lot_size))
+        .ok_or(math_err!())?;
+    let limit_price = safe_div(coin_lot_price, I80F48::from_num(pc_lot_size))?
+        .checked_mul(I80F48::from_num(BPS_UNIT + max_slippage_bps))
+        .ok_or(math_err!())?;
+    let limit_price = safe_div(limit_price, I80F48::from_num(BPS_UNIT))?;
+    let max_coin_qty = safe_div(I80F48::from_num(quote_amount), coin_lot_price)?;
+
+    Ok((
+        limit_price.checked_to_num().ok_or(math_err!())?,
+        max_coin_qty.checked_to_num().ok_or(math_err!())?,
+    ))
+}
+
+/// Limit price in pc lots per coin lot and size in coin lots of an immediate-or-cancel
+/// spot ask selling about `quote_amount` worth of base at down to `max_slippage_bps` below
+/// `price`, given in native quote per native base
+///
+/// The size is zero when the amount is too small to sell a single lot
+pub fn get_spot_ask_limits(
+    quote_amount: u64,
+    price: I80F48,
+    coin_lot_size: u64,
+    pc_lot_size: u64,
+    max_slippage_bps: u64,
+) -> QuasarResult<(u64, u64)> {
+    check!(
+        coin_lot_size > 0 && pc_lot_size > 0 && max_slippage_bps <= BPS_UNIT,
+        QuasarErrorCode::InvalidParam
+    )?;
+
+    let coin_lot_price = price
+        .checked_mul(I80F48::from_num(coin_lot_size))
+        .ok_or(math_err!())?;
+    let limit_price = safe_div(coin_lot_price, I80F48::from_num(pc_lot_size))?
+        .checked_mul(I80F48::from_num(BPS_UNIT - max_slippage_bps))
+        .ok_or(math_err!())?;
+    let limit_price = safe_div(limit_price, I80F48::from_num(BPS_UNIT))?;
+    let max_coin_qty = safe_div(I80F48::from_num(quote_amount), coin_lot_price)?;
+
+    Ok((
+        limit_price.checked_to_num().ok_or(math_err!())?,
+        max_coin_qty.checked_to_num().ok_or(math_err!())?,
+    ))
+}
+
+/// Split of the correction taking a leveraged token's exposure from `spot_exposure` plus
+/// `perp_exposure` to `target_exposure`, as the change of the spot and perp legs
+///
+/// Every split moving both legs the same way trades the same notional, the whole
+/// correction. Among those, a correction reducing exposure sells spot collateral first,
+/// which also shrinks the gross position, and spot is never bought or sold below zero
+pub fn split_rebalance_exposure(
+    spot_exposure: QuoteNative,
+    perp_exposure: QuoteNative,
+    target_exposure: QuoteNative,
+) -> QuasarResult<(QuoteNative, QuoteNative)> {
+    let delta = target_exposure
+        .0
+        .checked_sub(spot_exposure.0)
+        .and_then(|delta| delta.checked_sub(perp_exposure.0))
+        .ok_or(math_err!())?;
+
+    let spot_delta = if delta < ZERO_I80F48 && spot_exposure.0 > ZERO_I80F48 {
+        delta.max(-spot_exposure.0)
+    } else {
+        ZERO_I80F48
+    };
+    let perp_delta = delta.checked_sub(spot_delta).ok_or(math_err!())?;
+
+    Ok((QuoteNative(spot_delta), QuoteNative(perp_delta)))
+}
+
+/// Amount of native quote token exchanged for `quantity` leverage tokens at `native_price`,
+/// shared by mint, burn and their quotes so they can never disagree.
+/// Rounding always favours the remaining holders: up when paying in, down when paying out
+pub fn get_quote_amount(quantity: u64, native_price: I80F48, round_up: bool) -> QuasarResult<u64> {
+    let amount = native_price
+        .checked_mul(I80F48::from_num(quantity))
+        .ok_or(math_err!())?;
+    let amount = if round_up {
+        amount.checked_ceil()
+    } else {
+        amount.checked_floor()
+    }
+    .ok_or(math_err!())?;
+
+    amount.checked_to_num::<u64>().ok_or(math_err!())
+}
+
+/// Fee of `fee_bps` on `amount`, rounded up so that splitting an amount never lowers the fee
+pub fn get_fee_amount(amount: u64, fee_bps: u16) -> QuasarResult<u64> {
+    check!(
+        u64::from(fee_bps) <= BPS_UNIT,
+        QuasarErrorCode::InvalidParam
+    )?;
+
+    let fee = (amount as u128 * fee_bps as u128 + BPS_UNIT as u128 - 1) / BPS_UNIT as u128;
+    Ok(fee as u64)
+}
+
+/// Rejects mints and burns while the NAV behind a leveraged token isn't positive. A mint
+/// would pay into the deficit of the existing holders, and a burn would destroy tokens for a
+/// price rounding to nothing. Losses aren't socialized: burns resume once the position
+/// recovers or the deficit is covered
+pub fn check_nav_positive(native_price: I80F48) -> QuasarResult {
+    check!(native_price > ZERO_I80F48, QuasarErrorCode::NonPositiveNav)
+}
+
+/// Rejects a mint whose `deposit` plus `fee` exceeds the `balance` of the minter's quote
+/// token account, which would otherwise fail deep inside the token program
+pub fn check_mint_funds(balance: u64, deposit: u64, fee: u64) -> QuasarResult {
+    let required = deposit.checked_add(fee).ok_or(math_err!())?;
+    check!(balance >= required, QuasarErrorCode::InsufficientFunds)
+}
+
+/// Rejects a native price moving from `before` to `after` by more than `max_move_bps`
+/// of `before`. A zero `max_move_bps` disables the check
+pub fn check_nav_move(before: I80F48, after: I80F48, max_move_bps: u16) -> QuasarResult {
+    if max_move_bps == 0 {
+        return Ok(());
+    }
+
+    let max_move = before
+        .checked_mul(I80F48::from_num(max_move_bps))
+        .ok_or(math_err!())?;
+    let max_move = safe_div(max_move, I80F48::from_num(BPS_UNIT))?;
+    let nav_move = after.checked_sub(before).ok_or(math_err!())?.abs();
+    check!(nav_move <= max_move, QuasarErrorCode::NavMoveTooLarge)
+}
+
+/// Rejects a `target_exposure` on the other side of the market than `target_leverage`,
+/// which would have rebalance build exposure in the wrong direction. Zero exposure passes
+pub fn check_exposure_sign(target_leverage: I80F48, target_exposure: I80F48) -> QuasarResult {
+    check!(
+        target_exposure == ZERO_I80F48
+            || target_exposure.is_negative() == target_leverage.is_negative(),
+        QuasarErrorCode::MathError
+    )
+}
+
+/// Leverage implied by a `target_exposure` attested by a keeper, rejected beyond
+/// `max_target_leverage` in either direction like any other target, and on the other side
+/// of the market than the token's own `target_leverage`
+pub fn check_attested_exposure(
+    target_exposure: I80F48,
+    net_asset_value: I80F48,
+    target_leverage: I80F48,
+    max_target_leverage: I80F48,
+) -> QuasarResult<I80F48> {
+    check_exposure_sign(target_leverage, target_exposure)?;
+    let target_leverage = safe_div(target_exposure, net_asset_value)?;
+    check!(
+        target_leverage.abs() <= max_target_leverage,
+        QuasarErrorCode::InvalidParam
+    )?;
+    Ok(target_leverage)
+}
+
+/// Native quote per native base of an oracle `price` in whole quote per whole base
+pub fn oracle_to_native_price(
+    price: I80F48,
+    base_decimals: u8,
+    quote_decimals: u8,
+) -> QuasarResult<I80F48> {
+    let base_unit = 10u64.checked_pow(base_decimals.into()).ok_or(math_err!())?;
+    let quote_unit = 10u64
+        .checked_pow(quote_decimals.into())
+        .ok_or(math_err!())?;
+    let price = price
+        .checked_mul(I80F48::from_num(quote_unit))
+        .ok_or(math_err!())?;
+    safe_div(price, I80F48::from_num(base_unit))
+}
+
+/// Price of one whole token with `decimals` decimals in whole quote, from a `native_price`
+/// in native quote per native unit
+pub fn native_to_ui_price(
+    native_price: I80F48,
+    decimals: u8,
+    quote_decimals: u8,
+) -> QuasarResult<I80F48> {
+    let token_unit = 10u64.checked_pow(decimals.into()).ok_or(math_err!())?;
+    let quote_unit = 10u64
+        .checked_pow(quote_decimals.into())
+        .ok_or(math_err!())?;
+    let price = native_price
+        .checked_mul(I80F48::from_num(token_unit))
+        .ok_or(math_err!())?;
+    safe_div(price, I80F48::from_num(quote_unit))
+}
+
+/// Whether `mango_account_ai` still needs `InitMangoAccount`. A Mango account initialized
+/// earlier, e.g. for a token since removed, is reused as long as `owner` owns it
+pub fn needs_mango_account_init(
+    mango_account_ai: &AccountInfo,
+    mango_program_id: &Pubkey,
+    mango_group_key: &Pubkey,
+    owner: &Pubkey,
+) -> QuasarResult<bool> {
+    let is_initialized = {
+        let data = mango_account_ai.try_borrow_data()?;
+        data.get(..size_of::<MetaData>())
+            .and_then(|meta_data| try_from_bytes::<MetaData>(meta_data).ok())
+            .map_or(false, |meta_data| meta_data.is_initialized)
+    };
+    if !is_initialized {
+        return Ok(true);
+    }
+
+    let mango_account =
+        MangoAccount::load_checked(mango_account_ai, mango_program_id, mango_group_key)?;
+    check_eq!(mango_account.owner, *owner, QuasarErrorCode::InvalidOwner)?;
+    Ok(false)
+}
+
+/// Makes sure the Mango account has no perp position, counting unconsumed taker fills, or
+/// resting perp order on any market
+pub fn check_perp_flat(mango_account: &MangoAccount) -> QuasarResult {
+    for (market_index, perp_account) in mango_account.perp_accounts.iter().enumerate() {
+        check!(
+            get_perp_base_position(mango_account, market_index) == 0
+                && perp_account.bids_quantity == 0
+                && perp_account.asks_quantity == 0,
+            QuasarErrorCode::PositionNotFlat
+        )?;
+    }
+    Ok(())
+}
+
+/// Rejects a burn of `token_mint` sharing its transaction with a mint of the same token,
+/// read from the instructions sysvar. Any mint counts, not only the burner's, as the tokens
+/// may be transferred in between. A burn made through another program could hide such a
+/// mint in its own instruction, so only burns made directly are accepted
+pub fn check_no_mint_in_transaction(
+    program_id: &Pubkey,
+    token_mint: &Pubkey,
+    instructions_sysvar_ai: &AccountInfo,
+) -> QuasarResult {
+    check_eq!(
+        *instructions_sysvar_ai.key,
+        sysvar::instructions::id(),
+        QuasarErrorCode::InvalidAccount
+    )?;
+    let data = instructions_sysvar_ai.try_borrow_data()?;
+
+    let current_index = sysvar::instructions::load_current_index(&data) as usize;
+    let current_instruction = sysvar::instructions::load_instruction_at(current_index, &data)
+        .map_err(|_| throw_err!(QuasarErrorCode::InvalidAccount))?;
+    check_eq!(
+        current_instruction.program_id,
+        *program_id,
+        QuasarErrorCode::SameSlotBurn
+    )?;
+
+    let mut index = 0;
+    while let Ok(instruction) = sysvar::instructions::load_instruction_at(index, &data) {
+        let is_mint = instruction.program_id == *program_id
+            && instruction.accounts.get(1).map(|meta| meta.pubkey) == Some(*token_mint)
+            && matches!(
+                QuasarInstruction::unpack(&instruction.data),
+                Some(QuasarInstruction::MintLeverageToken { .. })
+            );
+        check!(!is_mint, QuasarErrorCode::SameSlotBurn)?;
+        index += 1;
+    }
+    Ok(())
+}
+
+/// Makes sure the Mango account holds nothing a leverage token listed on it later would
+/// inherit: no deposit, borrow, perp position, resting order or unsettled perp PnL. Amounts
+/// below one unit are the dust Mango leaves behind on withdrawals and are ignored
+pub fn check_mango_account_empty(mango_account: &MangoAccount) -> QuasarResult {
+    check_perp_flat(mango_account)?;
+
+    let is_dust = |amount: I80F48| amount.abs() < I80F48::ONE;
+    for (deposit, borrow) in mango_account
+        .deposits
+        .iter()
+        .zip(mango_account.borrows.iter())
+    {
+        check!(
+            is_dust(*deposit) && is_dust(*borrow),
+            QuasarErrorCode::MangoAccountNotEmpty
+        )?;
+    }
+    for perp_account in mango_account.perp_accounts.iter() {
+        check!(
+            is_dust(perp_account.quote_position) && perp_account.taker_quote == 0,
+            QuasarErrorCode::MangoAccountNotEmpty
+        )?;
+    }
+    Ok(())
+}
+
+/// Rejects a Mango deposit of `quantity` that didn't raise the net native quote of the
+/// account from `before` to at least `after`. Mango stores deposits divided by the deposit
+/// index, so up to one native unit may be lost to rounding
+pub fn check_deposit_credited(before: I80F48, after: I80F48, quantity: u64) -> QuasarResult {
+    let credited = after.checked_sub(before).ok_or(math_err!())?;
+    let expected = I80F48::from_num(quantity)
+        .checked_sub(I80F48::ONE)
+        .ok_or(math_err!())?;
+    check!(credited >= expected, QuasarErrorCode::DepositNotCredited)
+}
+
+/// Native price a mint (`is_mint`) or burn of `quantity` tokens settles at, moved against
+/// the caller by `size_premium_bps` scaled by the share of `supply` it represents and
+/// capped at `size_premium_bps`. The rebalance trading the resulting exposure is then paid
+/// for by the minter or burner instead of the existing holders
+pub fn get_size_adjusted_price(
+    native_price: I80F48,
+    quantity: u64,
+    supply: u64,
+    size_premium_bps: u16,
+    is_mint: bool,
+) -> QuasarResult<I80F48> {
+    check!(
+        u64::from(size_premium_bps) <= BPS_UNIT,
+        QuasarErrorCode::InvalidParam
+    )?;
+    if supply == 0 {
+        return Ok(native_price);
+    }
+
+    // Rounded down, so mints and burns small relative to the supply pay nothing
+    let premium_bps = (size_premium_bps as u128 * quantity as u128 / supply as u128)
+        .min(size_premium_bps as u128) as u64;
+    let factor_bps = if is_mint {
+        BPS_UNIT + premium_bps
+    } else {
+        BPS_UNIT - premium_bps
+    };
+
+    let price = native_price
+        .checked_mul(I80F48::from_num(factor_bps))
+        .ok_or(math_err!())?;
+    safe_div(price, I80F48::from_num(BPS_UNIT))
+}
+
+/// Value in the reference unit of `quote_value` in native quote, with the quote token
+/// worth `quote_price` reference units
+pub fn quote_to_reference(quote_value: I80F48, quote_price: I80F48) -> QuasarResult<I80F48> {
+    check!(quote_price > ZERO_I80F48, QuasarErrorCode::UnreliableOracle)?;
+    quote_value.checked_mul(quote_price).ok_or(math_err!())
+}
+
+/// Native quote worth `reference_value` in the reference unit, the inverse of
+/// `quote_to_reference`
+pub fn reference_to_quote(reference_value: I80F48, quote_price: I80F48) -> QuasarResult<I80F48> {
+    check!(quote_price > ZERO_I80F48, QuasarErrorCode::UnreliableOracle)?;
+    safe_div(reference_value, quote_price)
 }
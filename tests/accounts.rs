@@ -0,0 +1,120 @@
+use mango::state::MAX_PAIRS;
+use quasar::{
+    accounts::{BurnAccounts, MintAccounts, RebalanceAccounts},
+    error::{QuasarError, QuasarErrorCode},
+};
+use solana_program::{account_info::AccountInfo, pubkey::Pubkey};
+
+fn is_quasar_error<T>(result: Result<T, QuasarError>, code: QuasarErrorCode) -> bool {
+    matches!(
+        result,
+        Err(QuasarError::QuasarErrorCode { quasar_error_code, .. }) if quasar_error_code == code
+    )
+}
+
+/// Keys, lamports and data backing `n` account infos
+struct Backing {
+    keys: Vec<Pubkey>,
+    lamports: Vec<u64>,
+    data: Vec<Vec<u8>>,
+    owner: Pubkey,
+}
+
+impl Backing {
+    fn new(n: usize) -> Self {
+        Self {
+            keys: (0..n).map(|_| Pubkey::new_unique()).collect(),
+            lamports: vec![0; n],
+            data: vec![vec![]; n],
+            owner: Pubkey::default(),
+        }
+    }
+
+    /// Account infos that are all signers and writable
+    fn account_infos(&mut self) -> Vec<AccountInfo> {
+        let owner = &self.owner;
+        self.keys
+            .iter()
+            .zip(self.lamports.iter_mut())
+            .zip(self.data.iter_mut())
+            .map(|((key, lamports), data)| {
+                AccountInfo::new(key, true, true, lamports, data, owner, false, 0)
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn test_short_account_lists_are_rejected() {
+    let mut backing = Backing::new(MintAccounts::NUM_FIXED - 1);
+    let accounts = backing.account_infos();
+    assert!(is_quasar_error(
+        MintAccounts::load(&accounts),
+        QuasarErrorCode::InvalidAccount
+    ));
+
+    let mut backing = Backing::new(BurnAccounts::NUM_FIXED - 1);
+    let accounts = backing.account_infos();
+    assert!(is_quasar_error(
+        BurnAccounts::load(&accounts),
+        QuasarErrorCode::InvalidAccount
+    ));
+
+    // the open orders of every market are required
+    let mut backing = Backing::new(RebalanceAccounts::NUM_FIXED + MAX_PAIRS - 1);
+    let accounts = backing.account_infos();
+    assert!(is_quasar_error(
+        RebalanceAccounts::load(&accounts),
+        QuasarErrorCode::InvalidAccount
+    ));
+}
+
+#[test]
+fn test_malformed_accounts_are_rejected() {
+    // the mango account must be writable
+    let mut backing = Backing::new(RebalanceAccounts::NUM_FIXED + MAX_PAIRS);
+    let mut accounts = backing.account_infos();
+    accounts[5].is_writable = false;
+    assert!(is_quasar_error(
+        RebalanceAccounts::load(&accounts),
+        QuasarErrorCode::AccountNotWritable
+    ));
+
+    // the owner must sign
+    let mut backing = Backing::new(MintAccounts::NUM_FIXED);
+    let mut accounts = backing.account_infos();
+    accounts[6].is_signer = false;
+    assert!(is_quasar_error(
+        MintAccounts::load(&accounts),
+        QuasarErrorCode::SignerNecessary
+    ));
+}
+
+#[test]
+fn test_oversized_account_lists_are_rejected() {
+    // every open orders account of the margin basket
+    let mut backing = Backing::new(BurnAccounts::MAX_ACCOUNTS);
+    backing.keys[11] = spl_token::id();
+    let accounts = backing.account_infos();
+    assert!(BurnAccounts::load(&accounts).is_ok());
+
+    let mut backing = Backing::new(BurnAccounts::MAX_ACCOUNTS + 1);
+    backing.keys[11] = spl_token::id();
+    let accounts = backing.account_infos();
+    assert!(is_quasar_error(
+        BurnAccounts::load(&accounts),
+        QuasarErrorCode::InvalidParam
+    ));
+
+    // a rebalance config, the base oracle and the spot trade accounts
+    let mut backing = Backing::new(RebalanceAccounts::MAX_ACCOUNTS);
+    let accounts = backing.account_infos();
+    assert!(RebalanceAccounts::load(&accounts).is_ok());
+
+    let mut backing = Backing::new(RebalanceAccounts::MAX_ACCOUNTS + 1);
+    let accounts = backing.account_infos();
+    assert!(is_quasar_error(
+        RebalanceAccounts::load(&accounts),
+        QuasarErrorCode::InvalidParam
+    ));
+}
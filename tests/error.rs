@@ -0,0 +1,45 @@
+use mango::error::{MangoError, MangoErrorCode, SourceFileId as MangoSourceFileId};
+use quasar::error::{MangoCpi, QuasarError};
+use solana_program::program_error::ProgramError;
+
+fn mango_error() -> MangoError {
+    MangoError::MangoErrorCode {
+        mango_error_code: MangoErrorCode::InvalidOwner,
+        line: 42,
+        source_file_id: MangoSourceFileId::Processor,
+    }
+}
+
+#[test]
+fn test_mango_error_keeps_source_line() {
+    let error: QuasarError = mango_error().into();
+
+    assert_eq!(
+        error,
+        QuasarError::MangoError {
+            mango_error: mango_error(),
+            mango_cpi: MangoCpi::Load,
+        }
+    );
+    let message = error.to_string();
+    assert!(message.contains(":42"), "{}", message);
+    assert!(message.ends_with("mango Load"), "{}", message);
+
+    let expected: ProgramError = mango_error().into();
+    assert_eq!(ProgramError::from(error), expected);
+}
+
+#[test]
+fn test_mango_cpi_error_records_instruction() {
+    let error = QuasarError::mango_cpi(MangoCpi::Deposit, ProgramError::InsufficientFunds);
+
+    assert!(error.to_string().ends_with("mango Deposit"));
+    assert!(matches!(
+        error,
+        QuasarError::MangoError {
+            mango_cpi: MangoCpi::Deposit,
+            ..
+        }
+    ));
+    assert_eq!(ProgramError::from(error), ProgramError::InsufficientFunds);
+}
@@ -0,0 +1,82 @@
+use std::mem::size_of;
+
+use fixed::types::I80F48;
+use quasar::{
+    error::{QuasarError, QuasarErrorCode, QuasarResult},
+    oracle::{
+        check_oracle_price, read_oracle_price, scale_by_expo, AccountType, Price, StubOracle,
+        MAGIC, STUB_MAGIC, VERSION_2,
+    },
+};
+use solana_program::{account_info::AccountInfo, pubkey::Pubkey};
+
+#[test]
+fn test_check_oracle_price() {
+    assert!(check_oracle_price(I80F48::from_num(0.04512)).is_ok());
+
+    for &price in [-45_120i64, 0].iter() {
+        // the check runs on the scaled price, whichever oracle type it came from
+        let price = scale_by_expo(I80F48::from_num(price), -6).unwrap();
+        assert!(matches!(
+            check_oracle_price(price),
+            Err(QuasarError::QuasarErrorCode { quasar_error_code, .. })
+                if quasar_error_code == QuasarErrorCode::UnreliableOracle
+        ));
+    }
+}
+
+/// Price of an oracle account holding `data`, kept 16 byte aligned as account data is
+fn read_price(data: &[u8]) -> QuasarResult<I80F48> {
+    let key = Pubkey::new_unique();
+    let owner = Pubkey::new_unique();
+    let mut lamports = 0;
+    let mut aligned = vec![0u128; (data.len() + 15) / 16];
+    let aligned_data = &mut bytemuck::cast_slice_mut::<u128, u8>(&mut aligned)[..data.len()];
+    aligned_data.copy_from_slice(data);
+    let oracle_ai = AccountInfo::new(
+        &key,
+        false,
+        false,
+        &mut lamports,
+        aligned_data,
+        &owner,
+        false,
+        0,
+    );
+    read_oracle_price(&oracle_ai)
+}
+
+#[test]
+fn test_stub_mirroring_a_pyth_feed_reads_the_same_price() {
+    // a pyth feed publishing 45_120 at an exponent of -6
+    let mut price: Price = unsafe { std::mem::zeroed() };
+    price.magic = MAGIC;
+    price.ver = VERSION_2;
+    price.atype = AccountType::Price as u32;
+    price.expo = -6;
+    price.agg.price = 45_120;
+    let price_data = unsafe {
+        std::slice::from_raw_parts(&price as *const Price as *const u8, size_of::<Price>())
+    };
+
+    let stub = StubOracle {
+        magic: STUB_MAGIC,
+        expo: -6,
+        price: I80F48::from_num(45_120),
+        last_update: 0,
+    };
+
+    let pyth_price = read_price(price_data).unwrap();
+    assert_eq!(
+        pyth_price,
+        I80F48::from_num(45_120) / I80F48::from_num(1_000_000)
+    );
+    assert_eq!(read_price(bytemuck::bytes_of(&stub)).unwrap(), pyth_price);
+
+    // without the exponent the stub reads a million times the price
+    let unscaled = StubOracle { expo: 0, ..stub };
+    assert_eq!(
+        read_price(bytemuck::bytes_of(&unscaled)).unwrap(),
+        I80F48::from_num(45_120)
+    );
+}
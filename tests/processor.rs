@@ -0,0 +1,2603 @@
+use std::{cell::RefCell, convert::TryInto, mem::size_of, str::FromStr, sync::Once};
+
+use bytemuck::Zeroable;
+use fixed::types::I80F48;
+use mango::{
+    instruction::MangoInstruction,
+    matching::Side,
+    state::{
+        DataType as MangoDataType, MangoAccount, MangoCache, MangoGroup, MetaData as MangoMetaData,
+        MAX_PAIRS, QUOTE_INDEX,
+    },
+};
+use quasar::{
+    error::{QuasarError, QuasarErrorCode, QuasarResult},
+    instruction::{
+        add_base_tokens, add_leverage_token, burn_leverage_token, cancel_rebalance_orders,
+        close_quasar_group, estimate_rebalance_cost, init_rebalance_config, init_spot_open_orders,
+        init_token_ledger, liquidation_distance, list_base_token_prices, migrate_rebalance_config,
+        mint_leverage_token, quote_nav_breakdown, quote_redeem, quote_reference_price, rebalance,
+        reconcile, remove_base_token, remove_leverage_token, set_base_token_oracle_type, set_fees,
+        set_leverage_token_params, set_listing_grace_period, set_mango_perp_market,
+        set_mango_program, set_max_nav_move, set_max_net_deposits, set_quote_oracle,
+        set_rebalance_authority, set_rebalance_keeper, set_rebalance_price_source,
+        set_size_premium, set_token_state, set_treasury, update_rebalance_config, withdraw_fees,
+        AddLeverageTokenKeys, InitSpotOpenOrdersKeys, LeverageTokenParams, MintBurnKeys,
+        PerpBookKeys, QuasarInstruction, RebalanceKeys, TokenKeys,
+    },
+    oracle::{OracleType, StubOracle, MAGIC, STUB_MAGIC},
+    processor::{compute_rebalance_order, Processor, RebalanceTarget},
+    state::{
+        DataType, LeverageToken, MetaData, QuasarGroup, RebalanceConfig, RebalancePriceSource,
+        TokenLedger, TokenState, LEGACY_REBALANCE_CONFIG_SIZE, QUASAR_GROUP_VERSION,
+        VOLATILITY_SAMPLE_SLOTS,
+    },
+    utils::{RebalanceResult, MAX_REBALANCE_PRICE_OFFSET_BPS},
+};
+use solana_program::{
+    account_info::AccountInfo,
+    clock::Clock,
+    entrypoint::{ProgramResult, SUCCESS, UNSUPPORTED_SYSVAR},
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    program_option::COption,
+    program_pack::Pack,
+    program_stubs::{set_syscall_stubs, SyscallStubs},
+    pubkey::Pubkey,
+    rent::Rent,
+    system_program, sysvar,
+};
+use spl_associated_token_account::get_associated_token_address;
+
+fn is_quasar_error<T>(result: Result<T, QuasarError>, code: QuasarErrorCode) -> bool {
+    matches!(
+        result,
+        Err(QuasarError::QuasarErrorCode { quasar_error_code, .. }) if quasar_error_code == code
+    )
+}
+
+/// Backing of an account info, its data kept 16 byte aligned so zero copy accounts load from it
+#[derive(Clone)]
+struct TestAccount {
+    key: Pubkey,
+    owner: Pubkey,
+    is_signer: bool,
+    is_writable: bool,
+    executable: bool,
+    lamports: u64,
+    data: Vec<u128>,
+    data_len: usize,
+}
+
+impl TestAccount {
+    /// A signer and writable account without data
+    fn blank() -> Self {
+        Self {
+            key: Pubkey::new_unique(),
+            owner: Pubkey::default(),
+            is_signer: true,
+            is_writable: true,
+            executable: false,
+            lamports: 0,
+            data: vec![],
+            data_len: 0,
+        }
+    }
+
+    /// A writable account holding `data`, owned by `owner`
+    fn owned_by(owner: Pubkey, data: &[u8]) -> Self {
+        let mut account = Self {
+            owner,
+            is_signer: false,
+            ..Self::blank()
+        };
+        account.set_data(data);
+        account
+    }
+
+    fn set_data(&mut self, data: &[u8]) {
+        self.data = vec![0u128; (data.len() + 15) / 16];
+        self.data_len = data.len();
+        bytemuck::cast_slice_mut::<u128, u8>(&mut self.data)[..data.len()].copy_from_slice(data);
+    }
+
+    fn data(&self) -> &[u8] {
+        &bytemuck::cast_slice::<u128, u8>(&self.data)[..self.data_len]
+    }
+
+    fn account_info(&mut self) -> AccountInfo {
+        AccountInfo::new(
+            &self.key,
+            self.is_signer,
+            self.is_writable,
+            &mut self.lamports,
+            &mut bytemuck::cast_slice_mut::<u128, u8>(&mut self.data)[..self.data_len],
+            &self.owner,
+            self.executable,
+            0,
+        )
+    }
+}
+
+/// Accounts an instruction runs on, edited in place before `process`
+struct Fixture {
+    program_id: Pubkey,
+    accounts: Vec<TestAccount>,
+}
+
+impl Fixture {
+    fn new(num_accounts: usize) -> Self {
+        Self {
+            program_id: Pubkey::new_unique(),
+            accounts: (0..num_accounts).map(|_| TestAccount::blank()).collect(),
+        }
+    }
+
+    /// `quasar_group` owned by the program first, then blank accounts
+    fn with_group(quasar_group: &QuasarGroup, num_accounts: usize) -> Self {
+        let mut fixture = Self::new(num_accounts);
+        fixture.accounts[0] =
+            TestAccount::owned_by(fixture.program_id, bytemuck::bytes_of(quasar_group));
+        fixture
+    }
+
+    /// A read-only account owned by the program
+    fn program_account(&self, data: &[u8]) -> TestAccount {
+        TestAccount {
+            is_writable: false,
+            ..TestAccount::owned_by(self.program_id, data)
+        }
+    }
+
+    fn process(&mut self, instruction: QuasarInstruction) -> QuasarResult {
+        self.process_data(&instruction.pack())
+    }
+
+    fn process_data(&mut self, instruction_data: &[u8]) -> QuasarResult {
+        let accounts: Vec<AccountInfo> = self
+            .accounts
+            .iter_mut()
+            .map(|account| account.account_info())
+            .collect();
+        Processor::process(&self.program_id, &accounts, instruction_data)
+    }
+
+    fn quasar_group(&self) -> QuasarGroup {
+        *bytemuck::from_bytes(self.accounts[0].data())
+    }
+}
+
+/// An initialized quasar group with nothing listed
+fn new_quasar_group() -> QuasarGroup {
+    let mut quasar_group = QuasarGroup::zeroed();
+    quasar_group.meta_data = MetaData::new(DataType::QuasarGroup, QUASAR_GROUP_VERSION, true);
+    quasar_group
+}
+
+/// A group listing a single active token minted by `token_mint`
+fn group_listing(token_mint: Pubkey) -> QuasarGroup {
+    let mut quasar_group = new_quasar_group();
+    quasar_group.num_leverage_tokens = 1;
+    quasar_group.leverage_tokens[0].mint = token_mint;
+    quasar_group
+}
+
+/// Runs `instruction` with a blank quasar group owned by the program first, then signer and
+/// writable blank accounts, `keys` giving the key of some of them by account index
+fn process_on_blank_group(
+    instruction: QuasarInstruction,
+    num_accounts: usize,
+    keys: &[(usize, Pubkey)],
+) -> QuasarResult {
+    let mut fixture = Fixture::with_group(&new_quasar_group(), num_accounts);
+    for (index, key) in keys.iter() {
+        fixture.accounts[*index].key = *key;
+    }
+    fixture.process(instruction)
+}
+
+/// Runs `instruction` on blank accounts carrying the signer and writable flags of its metas
+fn process_on_blank_accounts(instruction: &Instruction) -> QuasarResult {
+    let mut fixture = Fixture::new(instruction.accounts.len());
+    fixture.program_id = instruction.program_id;
+    for (account, meta) in fixture.accounts.iter_mut().zip(instruction.accounts.iter()) {
+        account.key = meta.pubkey;
+        account.is_signer = meta.is_signer;
+        account.is_writable = meta.is_writable;
+    }
+    fixture.process_data(&instruction.data)
+}
+
+#[test]
+fn test_mint_rejects_wrong_associated_token_program() {
+    // an optional program passed last, here a random key
+    let mut fixture = Fixture::new(16);
+    fixture.accounts[15].executable = true;
+    assert!(is_quasar_error(
+        fixture.process(QuasarInstruction::MintLeverageToken { quantity: 1 }),
+        QuasarErrorCode::InvalidProgramId
+    ));
+
+    // the real one, or none at all, gets as far as the blank quasar group
+    fixture.accounts[15].key = spl_associated_token_account::id();
+    assert!(is_quasar_error(
+        fixture.process(QuasarInstruction::MintLeverageToken { quantity: 1 }),
+        QuasarErrorCode::InvalidOwner
+    ));
+    assert!(is_quasar_error(
+        Fixture::new(15).process(QuasarInstruction::MintLeverageToken { quantity: 1 }),
+        QuasarErrorCode::InvalidOwner
+    ));
+}
+
+#[test]
+fn test_mint_takes_the_quote_oracle_only_for_the_quote_it_prices() {
+    let token_mint = Pubkey::new_unique();
+    let mut quasar_group = group_listing(token_mint);
+    quasar_group.quote_oracle = Pubkey::new_unique();
+
+    // the token is quoted in the group's quote, so the oracle has to follow the fixed accounts
+    let mut fixture = Fixture::with_group(&quasar_group, 15);
+    fixture.accounts[1].key = token_mint;
+    assert!(is_quasar_error(
+        fixture.process(QuasarInstruction::MintLeverageToken { quantity: 1 }),
+        QuasarErrorCode::InvalidAccount
+    ));
+
+    // another quote isn't priced by it, so the mint goes on to read the blank quote account
+    quasar_group.leverage_tokens[0].quote_mint = Pubkey::new_unique();
+    let mut fixture = Fixture::with_group(&quasar_group, 15);
+    fixture.accounts[1].key = token_mint;
+    assert!(matches!(
+        fixture.process(QuasarInstruction::MintLeverageToken { quantity: 1 }),
+        Err(QuasarError::ProgramError(ProgramError::InvalidAccountData))
+    ));
+}
+
+#[test]
+fn test_zero_quantity_is_rejected() {
+    let program_id = Pubkey::new_unique();
+
+    for instruction in [
+        QuasarInstruction::MintLeverageToken { quantity: 0 },
+        QuasarInstruction::BurnLeverageToken { quantity: 0 },
+    ]
+    .iter()
+    {
+        // rejected before any account is read
+        assert!(is_quasar_error(
+            Processor::process(&program_id, &[], &instruction.pack()),
+            QuasarErrorCode::InvalidParam
+        ));
+    }
+}
+
+/// Runs `CancelRebalanceOrders` signed by `caller` against a group with the given admin and
+/// rebalance authority. The Mango accounts are placeholders, so only the caller checks pass
+fn cancel_rebalance_orders(
+    admin: Pubkey,
+    rebalance_authority: Pubkey,
+    caller: Pubkey,
+    caller_is_signer: bool,
+) -> QuasarResult {
+    let mut quasar_group = new_quasar_group();
+    quasar_group.admin_key = admin;
+    quasar_group.rebalance_authority = rebalance_authority;
+
+    let mut fixture = Fixture::with_group(&quasar_group, 10);
+    for account in fixture.accounts[1..].iter_mut() {
+        account.is_signer = false;
+    }
+    fixture.accounts[9].key = caller;
+    fixture.accounts[9].is_signer = caller_is_signer;
+    fixture.process(QuasarInstruction::CancelRebalanceOrders)
+}
+
+#[test]
+fn test_cancel_rebalance_orders_is_guarded() {
+    let admin = Pubkey::new_unique();
+    let keeper = Pubkey::new_unique();
+    let stranger = Pubkey::new_unique();
+
+    assert!(is_quasar_error(
+        cancel_rebalance_orders(admin, keeper, keeper, false),
+        QuasarErrorCode::SignerNecessary
+    ));
+    assert!(is_quasar_error(
+        cancel_rebalance_orders(admin, keeper, stranger, true),
+        QuasarErrorCode::InvalidSignerKey
+    ));
+    // the admin only stands in while rebalance is permissionless
+    assert!(is_quasar_error(
+        cancel_rebalance_orders(admin, keeper, admin, true),
+        QuasarErrorCode::InvalidSignerKey
+    ));
+    assert!(is_quasar_error(
+        cancel_rebalance_orders(admin, Pubkey::default(), stranger, true),
+        QuasarErrorCode::InvalidSignerKey
+    ));
+
+    // past the caller checks, the placeholder Mango program is rejected
+    assert!(is_quasar_error(
+        cancel_rebalance_orders(admin, keeper, keeper, true),
+        QuasarErrorCode::InvalidProgramId
+    ));
+    assert!(is_quasar_error(
+        cancel_rebalance_orders(admin, Pubkey::default(), admin, true),
+        QuasarErrorCode::InvalidProgramId
+    ));
+}
+
+#[test]
+fn test_mint_rejects_readonly_vault() {
+    let mut fixture = Fixture::new(15);
+    fixture.accounts[10].is_writable = false;
+
+    // rejected before the vault reaches Mango
+    assert!(is_quasar_error(
+        fixture.process(QuasarInstruction::MintLeverageToken { quantity: 1 }),
+        QuasarErrorCode::AccountNotWritable
+    ));
+}
+
+#[test]
+fn test_init_rejects_non_executable_mango_program() {
+    assert!(is_quasar_error(
+        Fixture::new(4).process(QuasarInstruction::InitQuasarGroup { signer_nonce: 0 }),
+        QuasarErrorCode::InvalidProgramId
+    ));
+}
+
+#[test]
+fn test_set_token_state_is_admin_only() {
+    let mut quasar_group = new_quasar_group();
+    quasar_group.admin_key = Pubkey::new_unique();
+
+    // a signer other than the admin
+    assert!(is_quasar_error(
+        Fixture::with_group(&quasar_group, 10).process(QuasarInstruction::SetTokenState {
+            state: TokenState::Frozen
+        }),
+        QuasarErrorCode::InvalidAdminKey
+    ));
+
+    // unknown states don't unpack
+    assert!(QuasarInstruction::unpack(&[21, 0, 0, 0, 2]).is_none());
+}
+
+#[test]
+fn test_burn_rejects_fake_token_program() {
+    // every account is a random key, the token program included
+    assert!(is_quasar_error(
+        Fixture::new(16).process(QuasarInstruction::BurnLeverageToken { quantity: 1 }),
+        QuasarErrorCode::InvalidProgramId
+    ));
+}
+
+#[test]
+fn test_builders_match_processor_accounts() {
+    let program_id = Pubkey::new_unique();
+    let k = || Pubkey::new_unique();
+    let token = || TokenKeys {
+        quasar_group: k(),
+        token_mint: k(),
+        mango_program: k(),
+        mango_group: k(),
+        mango_account: k(),
+    };
+    let book = || PerpBookKeys {
+        perp_market: k(),
+        bids: k(),
+        asks: k(),
+    };
+    let mint_burn = |token_ledger, group_capped| MintBurnKeys {
+        token: token(),
+        owner: k(),
+        mango_cache: k(),
+        root_bank: k(),
+        node_bank: k(),
+        vault: k(),
+        owner_quote_token_account: k(),
+        signer: k(),
+        fee_vault: k(),
+        token_ledger,
+        group_capped,
+    };
+
+    // signer, writable and program id checks all pass, so each instruction gets as far as
+    // loading the blank quasar group or rebalance config, which isn't owned by the program
+    let mint = mint_leverage_token(&program_id, &mint_burn(None, false), None, None, 1);
+    assert_eq!(mint.accounts.len(), 15);
+    assert!(!mint.accounts[0].is_writable);
+
+    let burn = burn_leverage_token(&program_id, &mint_burn(Some(k()), true), &k(), &[k()], 1);
+    assert_eq!(burn.accounts.len(), 19);
+    assert!(burn.accounts[0].is_writable);
+    assert!(burn.accounts[16].is_writable);
+
+    let rebalance_keys = RebalanceKeys {
+        token: token(),
+        signer: k(),
+        caller: k(),
+        caller_is_signer: true,
+        mango_cache: k(),
+        book: book(),
+        event_queue: k(),
+    };
+    let open_orders = [Pubkey::default(); MAX_PAIRS];
+    let rebalance_ix = rebalance(
+        &program_id,
+        &rebalance_keys,
+        &open_orders,
+        Some(&k()),
+        None,
+        None,
+        None,
+    );
+    assert_eq!(rebalance_ix.accounts.len(), 12 + MAX_PAIRS + 1);
+    let rebalance_to_exposure = rebalance(
+        &program_id,
+        &rebalance_keys,
+        &open_orders,
+        Some(&k()),
+        None,
+        None,
+        Some(I80F48::from_num(2)),
+    );
+
+    let add_leverage_token_keys = AddLeverageTokenKeys {
+        quasar_group: k(),
+        mint: k(),
+        base_token_mint: k(),
+        mango_program: k(),
+        mango_group: k(),
+        mango_account: k(),
+        mango_perp_market: k(),
+        admin: k(),
+        signer: k(),
+        quote_mint: Some(k()),
+    };
+    let spot_open_orders_keys = InitSpotOpenOrdersKeys {
+        token: token(),
+        signer: k(),
+        dex_program: k(),
+        spot_open_orders: k(),
+        spot_market: k(),
+        mango_signer: k(),
+        admin: k(),
+    };
+    let params = LeverageTokenParams {
+        mint_fee_bps: Some(10),
+        ..LeverageTokenParams::default()
+    };
+
+    let instructions = vec![
+        mint,
+        burn,
+        rebalance_ix,
+        rebalance_to_exposure,
+        add_leverage_token(&program_id, &add_leverage_token_keys, 20_000, None),
+        set_rebalance_authority(&program_id, &k(), &k(), &k()),
+        quote_redeem(&program_id, &token(), &k(), 1),
+        set_treasury(&program_id, &k(), &k(), &k(), &k()),
+        withdraw_fees(&program_id, &k(), &k(), &k(), &k(), &k(), 1),
+        set_leverage_token_params(&program_id, &k(), &k(), &k(), &params),
+        remove_leverage_token(&program_id, &token(), &k(), None),
+        close_quasar_group(&program_id, &k(), &k(), &k(), &k()),
+        init_spot_open_orders(&program_id, &spot_open_orders_keys),
+        set_fees(&program_id, &k(), &k(), 10, 10),
+        set_max_nav_move(&program_id, &k(), &k(), 100),
+        cancel_rebalance_orders(&program_id, &token(), &k(), &book(), &k()),
+        estimate_rebalance_cost(&program_id, &token(), &k(), Some(&k()), None),
+        init_rebalance_config(
+            &program_id,
+            &k(),
+            &k(),
+            &k(),
+            &k(),
+            &k(),
+            I80F48::from_num(3),
+        ),
+        update_rebalance_config(&program_id, &k(), &k(), I80F48::from_num(2)),
+        set_size_premium(&program_id, &k(), &k(), 10),
+        set_token_state(
+            &program_id,
+            &token(),
+            &k(),
+            &book(),
+            &k(),
+            TokenState::Frozen,
+        ),
+        set_quote_oracle(&program_id, &k(), &k(), &k()),
+        quote_reference_price(&program_id, &token(), &k(), &k()),
+        set_listing_grace_period(&program_id, &k(), &k(), 100),
+        reconcile(&program_id, &k(), &k(), &k()),
+        set_mango_perp_market(&program_id, &token(), &k(), &k()),
+        quote_nav_breakdown(&program_id, &token(), &k()),
+        set_rebalance_price_source(&program_id, &k(), &k(), &k(), RebalancePriceSource::Oracle),
+        set_max_net_deposits(&program_id, &k(), &k(), &[k()], 1),
+        add_base_tokens(&program_id, &k(), &k(), &k(), &[(k(), k())]),
+        list_base_token_prices(&program_id, &k(), &[k()]),
+        set_mango_program(&program_id, &k(), &k(), &k()),
+        set_rebalance_keeper(&program_id, &k(), &k(), &k()),
+        liquidation_distance(&program_id, &token(), &k()),
+        set_base_token_oracle_type(&program_id, &k(), &k(), &k(), &k(), OracleType::Stub),
+        remove_base_token(&program_id, &k(), &k(), &k()),
+        migrate_rebalance_config(&program_id, &k(), &k(), &k(), &k(), &k()),
+        init_token_ledger(&program_id, &token(), &k(), &k(), &k(), None),
+    ];
+    for instruction in instructions.iter() {
+        assert!(
+            is_quasar_error(
+                process_on_blank_accounts(instruction),
+                QuasarErrorCode::InvalidOwner
+            ),
+            "{:?}",
+            QuasarInstruction::unpack(&instruction.data)
+        );
+    }
+}
+
+#[test]
+fn test_unregistered_mint_is_rejected() {
+    assert!(is_quasar_error(
+        process_on_blank_group(
+            QuasarInstruction::MintLeverageToken { quantity: 1 },
+            15,
+            &[],
+        ),
+        QuasarErrorCode::InvalidToken
+    ));
+    assert!(is_quasar_error(
+        process_on_blank_group(
+            QuasarInstruction::BurnLeverageToken { quantity: 1 },
+            16,
+            &[(11, spl_token::id())],
+        ),
+        QuasarErrorCode::InvalidToken
+    ));
+}
+
+#[test]
+fn test_rebalance_rejects_unregistered_mint() {
+    // a permissionless group, so the caller checks pass
+    assert!(is_quasar_error(
+        process_on_blank_group(QuasarInstruction::Rebalance, 12 + MAX_PAIRS, &[]),
+        QuasarErrorCode::InvalidToken
+    ));
+}
+
+/// Rebalances a token listed with its own Mango account and perp market, passing them or
+/// random accounts in their place
+fn rebalance_listed_token(pass_mango_account: bool, pass_perp_market: bool) -> QuasarResult {
+    let token_mint = Pubkey::new_unique();
+    let mango_account = Pubkey::new_unique();
+    let mango_perp_market = Pubkey::new_unique();
+
+    let mut quasar_group = group_listing(token_mint);
+    quasar_group.leverage_tokens[0].mango_account = mango_account;
+    quasar_group.leverage_tokens[0].mango_perp_market = mango_perp_market;
+    let mut fixture = Fixture::with_group(&quasar_group, 12 + MAX_PAIRS);
+    fixture.accounts[1].key = token_mint;
+    if pass_mango_account {
+        fixture.accounts[5].key = mango_account;
+    }
+    if pass_perp_market {
+        fixture.accounts[8].key = mango_perp_market;
+    }
+    fixture.process(QuasarInstruction::Rebalance)
+}
+
+/// Burns a token listed with its own Mango account from the minter's associated token
+/// account, passing the Mango account or a random one in its place
+fn burn_listed_token(pass_mango_account: bool) -> QuasarResult {
+    let token_mint = Pubkey::new_unique();
+    let mango_account = Pubkey::new_unique();
+
+    let mut quasar_group = group_listing(token_mint);
+    quasar_group.leverage_tokens[0].mango_account = mango_account;
+    let mut fixture = Fixture::with_group(&quasar_group, 16);
+    fixture.accounts[1].key = token_mint;
+    fixture.accounts[2].key = get_associated_token_address(&fixture.accounts[6].key, &token_mint);
+    fixture.accounts[11].key = spl_token::id();
+    if pass_mango_account {
+        fixture.accounts[5].key = mango_account;
+    }
+    fixture.process(QuasarInstruction::BurnLeverageToken { quantity: 1 })
+}
+
+#[test]
+fn test_foreign_mango_accounts_are_rejected() {
+    assert!(is_quasar_error(
+        rebalance_listed_token(false, true),
+        QuasarErrorCode::InvalidAccount
+    ));
+    assert!(is_quasar_error(
+        rebalance_listed_token(true, false),
+        QuasarErrorCode::InvalidAccount
+    ));
+    assert!(is_quasar_error(
+        burn_listed_token(false),
+        QuasarErrorCode::InvalidAccount
+    ));
+
+    // past the account checks, the blank Mango group is rejected
+    for result in vec![rebalance_listed_token(true, true), burn_listed_token(true)] {
+        assert!(result.is_err());
+        assert!(!is_quasar_error(result, QuasarErrorCode::InvalidAccount));
+    }
+}
+
+/// Mints a listed token with everything the handler checks before reaching Mango filled in
+fn mint_on_listed_token(
+    quote_owner_is_minter: bool,
+    authority_is_group_signer: bool,
+) -> QuasarResult {
+    let token_mint = Pubkey::new_unique();
+    let signer_key = Pubkey::new_unique();
+
+    let mut quasar_group = group_listing(token_mint);
+    quasar_group.signer_key = signer_key;
+    let mut fixture = Fixture::with_group(&quasar_group, 15);
+    fixture.accounts[1].key = token_mint;
+    let owner_key = fixture.accounts[6].key;
+
+    let mint = spl_token::state::Mint {
+        mint_authority: COption::Some(if authority_is_group_signer {
+            signer_key
+        } else {
+            Pubkey::new_unique()
+        }),
+        is_initialized: true,
+        ..spl_token::state::Mint::default()
+    };
+    let mut mint_data = vec![0u8; spl_token::state::Mint::LEN];
+    spl_token::state::Mint::pack(mint, &mut mint_data).unwrap();
+    fixture.accounts[1].set_data(&mint_data);
+
+    let quote_token_account = spl_token::state::Account {
+        owner: if quote_owner_is_minter {
+            owner_key
+        } else {
+            Pubkey::new_unique()
+        },
+        state: spl_token::state::AccountState::Initialized,
+        ..spl_token::state::Account::default()
+    };
+    let mut account_data = vec![0u8; spl_token::state::Account::LEN];
+    spl_token::state::Account::pack(quote_token_account, &mut account_data).unwrap();
+    fixture.accounts[12].set_data(&account_data);
+
+    fixture.process(QuasarInstruction::MintLeverageToken { quantity: 1 })
+}
+
+#[test]
+fn test_mint_rejects_quote_account_of_another_owner() {
+    // the owner only signs for the quote account of a third party
+    assert!(is_quasar_error(
+        mint_on_listed_token(false, true),
+        QuasarErrorCode::InvalidOwner
+    ));
+}
+
+#[test]
+fn test_mint_rejects_token_with_foreign_mint_authority() {
+    assert!(is_quasar_error(
+        mint_on_listed_token(true, false),
+        QuasarErrorCode::InvalidToken
+    ));
+    // past the authority check, the blank Mango group is rejected
+    assert!(!is_quasar_error(
+        mint_on_listed_token(true, true),
+        QuasarErrorCode::InvalidToken
+    ));
+}
+
+/// Points a group listing `num_leverage_tokens` tokens at a new Mango program
+fn set_mango_program(num_leverage_tokens: usize) -> QuasarResult {
+    let admin = Pubkey::new_unique();
+    let mut quasar_group = new_quasar_group();
+    quasar_group.admin_key = admin;
+    quasar_group.num_leverage_tokens = num_leverage_tokens;
+
+    let mut fixture = Fixture::with_group(&quasar_group, 3);
+    fixture.accounts[1].executable = true;
+    fixture.accounts[2].key = admin;
+
+    fixture.process(QuasarInstruction::SetMangoProgram)?;
+    assert_eq!(
+        fixture.quasar_group().mango_program_id,
+        fixture.accounts[1].key
+    );
+    Ok(())
+}
+
+#[test]
+fn test_mango_program_only_changes_without_tokens() {
+    assert!(is_quasar_error(
+        set_mango_program(1),
+        QuasarErrorCode::MangoProgramInUse
+    ));
+    assert!(set_mango_program(0).is_ok());
+}
+
+/// Repoints a token to a new perp market on the Mango market of its base while its
+/// Mango account holds the given position on another market
+fn set_mango_perp_market(base_position: i64, taker_base: i64) -> QuasarResult {
+    let token_mint = Pubkey::new_unique();
+    let base_mint = Pubkey::new_unique();
+    let admin = Pubkey::new_unique();
+    let mango_program = Pubkey::new_unique();
+    let mango_group_key = Pubkey::new_unique();
+    let mango_account_key = Pubkey::new_unique();
+    let new_perp_market = Pubkey::new_unique();
+
+    let mut quasar_group = group_listing(token_mint);
+    quasar_group.admin_key = admin;
+    quasar_group.mango_program_id = mango_program;
+    quasar_group.leverage_tokens[0].base_token_mint = base_mint;
+    quasar_group.leverage_tokens[0].mango_account = mango_account_key;
+
+    let mut mango_group = MangoGroup::zeroed();
+    mango_group.meta_data = MangoMetaData::new(MangoDataType::MangoGroup, 0, true);
+    mango_group.tokens[1].mint = base_mint;
+    mango_group.perp_markets[1].perp_market = new_perp_market;
+
+    let mut mango_account = MangoAccount::zeroed();
+    mango_account.meta_data = MangoMetaData::new(MangoDataType::MangoAccount, 0, true);
+    mango_account.mango_group = mango_group_key;
+    mango_account.perp_accounts[0].base_position = base_position;
+    mango_account.perp_accounts[0].taker_base = taker_base;
+
+    let mut fixture = Fixture::with_group(&quasar_group, 7);
+    fixture.accounts[1].key = token_mint;
+    fixture.accounts[2].key = mango_program;
+    fixture.accounts[3] = TestAccount {
+        key: mango_group_key,
+        ..TestAccount::owned_by(mango_program, bytemuck::bytes_of(&mango_group))
+    };
+    fixture.accounts[4] = TestAccount {
+        key: mango_account_key,
+        ..TestAccount::owned_by(mango_program, bytemuck::bytes_of(&mango_account))
+    };
+    fixture.accounts[5].key = new_perp_market;
+    fixture.accounts[6].key = admin;
+
+    fixture.process(QuasarInstruction::SetMangoPerpMarket)?;
+    assert_eq!(
+        fixture.quasar_group().leverage_tokens[0].mango_perp_market,
+        new_perp_market
+    );
+    Ok(())
+}
+
+#[test]
+fn test_perp_market_repoint_nets_unconsumed_fills() {
+    assert!(set_mango_perp_market(0, 0).is_ok());
+    assert!(is_quasar_error(
+        set_mango_perp_market(-5, 0),
+        QuasarErrorCode::PositionNotFlat
+    ));
+    assert!(is_quasar_error(
+        set_mango_perp_market(0, 5),
+        QuasarErrorCode::PositionNotFlat
+    ));
+    // a taker fill not consumed yet closes the position
+    assert!(set_mango_perp_market(-5, 5).is_ok());
+}
+
+/// Rebalances a token whose rebalance config names `keeper` towards an attested exposure,
+/// with `caller` as the caller account
+fn rebalance_to_exposure(keeper: Pubkey, caller: Pubkey, caller_is_signer: bool) -> QuasarResult {
+    let token_mint = Pubkey::new_unique();
+    let config_key = Pubkey::new_unique();
+
+    let mut rebalance_config = RebalanceConfig::zeroed();
+    rebalance_config.meta_data = MetaData::new(DataType::RebalanceConfig, 0, true);
+    rebalance_config.leverage_token_mint = token_mint;
+    rebalance_config.keeper = keeper;
+    rebalance_config.max_target_leverage = I80F48::from_num(3);
+
+    let mut quasar_group = group_listing(token_mint);
+    quasar_group.leverage_tokens[0].rebalance_config = config_key;
+
+    let mut fixture = Fixture::with_group(&quasar_group, 12 + MAX_PAIRS);
+    fixture.accounts[1].key = token_mint;
+    fixture.accounts[6].key = caller;
+    fixture.accounts[6].is_signer = caller_is_signer;
+    let config = TestAccount {
+        key: config_key,
+        ..fixture.program_account(bytemuck::bytes_of(&rebalance_config))
+    };
+    fixture.accounts.push(config);
+
+    fixture.process(QuasarInstruction::RebalanceToExposure {
+        target_exposure: I80F48::from_num(1_000),
+    })
+}
+
+#[test]
+fn test_only_the_keeper_attests_exposure() {
+    let keeper = Pubkey::new_unique();
+    assert!(is_quasar_error(
+        rebalance_to_exposure(keeper, Pubkey::new_unique(), true),
+        QuasarErrorCode::InvalidSignerKey
+    ));
+    assert!(is_quasar_error(
+        rebalance_to_exposure(keeper, keeper, false),
+        QuasarErrorCode::SignerNecessary
+    ));
+    // a config without a keeper accepts no attestation at all
+    assert!(is_quasar_error(
+        rebalance_to_exposure(Pubkey::default(), Pubkey::default(), true),
+        QuasarErrorCode::InvalidSignerKey
+    ));
+
+    // the keeper gets past the attestation, as far as loading the blank Mango group
+    let result = rebalance_to_exposure(keeper, keeper, true);
+    assert!(result.is_err());
+    assert!(!is_quasar_error(result, QuasarErrorCode::InvalidSignerKey));
+}
+
+/// Migrates a token off the legacy rebalance config holding `legacy_data`, which the token
+/// follows or not
+fn migrate_rebalance_config(legacy_data: &[u8], token_follows_config: bool) -> QuasarResult {
+    let admin = Pubkey::new_unique();
+    let token_mint = Pubkey::new_unique();
+    let legacy_key = Pubkey::new_unique();
+
+    let mut quasar_group = group_listing(token_mint);
+    quasar_group.admin_key = admin;
+    quasar_group.leverage_tokens[0].rebalance_config = if token_follows_config {
+        legacy_key
+    } else {
+        Pubkey::new_unique()
+    };
+
+    let mut fixture = Fixture::with_group(&quasar_group, 5);
+    fixture.accounts[1].key = token_mint;
+    fixture.accounts[2] = TestAccount {
+        key: legacy_key,
+        is_writable: true,
+        ..fixture.program_account(legacy_data)
+    };
+    fixture.accounts[3] = TestAccount {
+        is_writable: true,
+        ..fixture.program_account(bytemuck::bytes_of(&RebalanceConfig::zeroed()))
+    };
+    fixture.accounts[4].key = admin;
+    fixture.process(QuasarInstruction::MigrateRebalanceConfig)
+}
+
+#[test]
+fn test_only_legacy_configs_are_migrated() {
+    let mut rebalance_config = RebalanceConfig::zeroed();
+    rebalance_config.meta_data = MetaData::new(DataType::RebalanceConfig, 0, true);
+    let current_data = bytemuck::bytes_of(&rebalance_config);
+    let legacy_data = &current_data[..LEGACY_REBALANCE_CONFIG_SIZE];
+
+    assert!(is_quasar_error(
+        migrate_rebalance_config(legacy_data, false),
+        QuasarErrorCode::InvalidAccount
+    ));
+    assert!(is_quasar_error(
+        migrate_rebalance_config(current_data, true),
+        QuasarErrorCode::InvalidAccount
+    ));
+}
+
+/// Sets the rebalance price offset of a listed token
+fn set_price_offset(price_offset_bps: u16) -> QuasarResult<u16> {
+    let admin = Pubkey::new_unique();
+    let token_mint = Pubkey::new_unique();
+    let mut quasar_group = group_listing(token_mint);
+    quasar_group.admin_key = admin;
+
+    let mut fixture = Fixture::with_group(&quasar_group, 3);
+    fixture.accounts[1].key = token_mint;
+    fixture.accounts[2].key = admin;
+
+    fixture.process(QuasarInstruction::SetLeverageTokenParams {
+        min_native_price: None,
+        spot_swap_bps: None,
+        mint_fee_bps: None,
+        burn_fee_bps: None,
+        max_effective_leverage: None,
+        rebalance_cooldown_slots: None,
+        allow_borrow: None,
+        max_borrow_bps: None,
+        min_mint_increment: None,
+        same_slot_burn_guard: None,
+        max_redemption_per_tx: None,
+        volatility_target: None,
+        price_offset_bps: Some(price_offset_bps),
+        max_rebalances_per_epoch: None,
+    })?;
+    Ok(fixture.quasar_group().leverage_tokens[0].price_offset_bps)
+}
+
+#[test]
+fn test_price_offset_is_bounded_when_set() {
+    assert_eq!(
+        set_price_offset(MAX_REBALANCE_PRICE_OFFSET_BPS).unwrap(),
+        MAX_REBALANCE_PRICE_OFFSET_BPS
+    );
+    assert!(is_quasar_error(
+        set_price_offset(MAX_REBALANCE_PRICE_OFFSET_BPS + 1),
+        QuasarErrorCode::InvalidParam
+    ));
+}
+
+/// An initialized ledger of `token_mint` that counted mints of `total_minted`
+fn token_ledger(token_mint: Pubkey, total_minted: u64, net_deposits: u64) -> TokenLedger {
+    let mut token_ledger = TokenLedger::zeroed();
+    token_ledger.meta_data = MetaData::new(DataType::TokenLedger, 0, true);
+    token_ledger.leverage_token_mint = token_mint;
+    token_ledger
+        .record_mint(total_minted, net_deposits)
+        .unwrap();
+    token_ledger
+}
+
+#[test]
+fn test_set_max_net_deposits_counts_the_token_ledgers() {
+    let admin = Pubkey::new_unique();
+    let ledger_keys = [Pubkey::new_unique(), Pubkey::new_unique()];
+    let mut quasar_group = new_quasar_group();
+    quasar_group.admin_key = admin;
+    // the middle token has no ledger, so nothing it minted is counted
+    quasar_group.num_leverage_tokens = 3;
+    quasar_group.leverage_tokens[0].token_ledger = ledger_keys[0];
+    quasar_group.leverage_tokens[2].token_ledger = ledger_keys[1];
+
+    let set_cap = |quasar_group: &QuasarGroup, ledgers: &[(Pubkey, u64)], cap: u64| {
+        let mut fixture = Fixture::with_group(quasar_group, 2);
+        fixture.accounts[1].key = admin;
+        for &(key, net_deposits) in ledgers.iter() {
+            let ledger = token_ledger(Pubkey::new_unique(), 1, net_deposits);
+            let mut account = fixture.program_account(bytemuck::bytes_of(&ledger));
+            account.key = key;
+            fixture.accounts.push(account);
+        }
+        fixture
+            .process(QuasarInstruction::SetMaxNetDeposits {
+                max_net_deposits: cap,
+            })
+            .map(|_| fixture.quasar_group())
+    };
+
+    let ledgers = [(ledger_keys[0], 600), (ledger_keys[1], 300)];
+    let capped = set_cap(&quasar_group, &ledgers, 1_000).unwrap();
+    assert_eq!(capped.max_net_deposits, 1_000);
+    assert_eq!(capped.total_net_deposits, 900);
+    assert!(capped.check_group_cap(100).is_ok());
+    assert!(capped.check_group_cap(101).is_err());
+
+    // changing the cap keeps the count, lifting it drops it
+    let raised = set_cap(&capped, &[], 2_000).unwrap();
+    assert_eq!(raised.total_net_deposits, 900);
+    let lifted = set_cap(&capped, &[], 0).unwrap();
+    assert_eq!(lifted.total_net_deposits, 0);
+
+    // every ledger has to be counted, in token order
+    assert!(is_quasar_error(
+        set_cap(&quasar_group, &ledgers[..1], 1_000),
+        QuasarErrorCode::InvalidAccount
+    ));
+    assert!(is_quasar_error(
+        set_cap(&quasar_group, &[ledgers[1], ledgers[0]], 1_000),
+        QuasarErrorCode::InvalidAccount
+    ));
+
+    let mut fixture = Fixture::with_group(&quasar_group, 2);
+    fixture.accounts[1].key = Pubkey::new_unique();
+    assert!(is_quasar_error(
+        fixture.process(QuasarInstruction::SetMaxNetDeposits {
+            max_net_deposits: 0
+        }),
+        QuasarErrorCode::InvalidAdminKey
+    ));
+}
+
+#[test]
+fn test_reconcile_checks_supply_against_the_token_ledger() {
+    let token_mint = Pubkey::new_unique();
+    let ledger_key = Pubkey::new_unique();
+    let mut quasar_group = group_listing(token_mint);
+    quasar_group.leverage_tokens[0].token_ledger = ledger_key;
+
+    let reconcile = |supply: u64, ledger_key: Pubkey| {
+        let mut fixture = Fixture::with_group(&quasar_group, 3);
+        let mint = spl_token::state::Mint {
+            supply,
+            is_initialized: true,
+            ..spl_token::state::Mint::default()
+        };
+        let mut mint_data = vec![0u8; spl_token::state::Mint::LEN];
+        spl_token::state::Mint::pack(mint, &mut mint_data).unwrap();
+        fixture.accounts[1] = TestAccount::owned_by(spl_token::id(), &mint_data);
+        fixture.accounts[1].key = token_mint;
+
+        let mut ledger = token_ledger(token_mint, 1_000, 0);
+        ledger.record_burn(400, 1_000).unwrap();
+        fixture.accounts[2] = fixture.program_account(bytemuck::bytes_of(&ledger));
+        fixture.accounts[2].key = ledger_key;
+        fixture.process(QuasarInstruction::Reconcile)
+    };
+
+    assert!(reconcile(600, ledger_key).is_ok());
+    assert!(is_quasar_error(
+        reconcile(601, ledger_key),
+        QuasarErrorCode::SupplyMismatch
+    ));
+    assert!(is_quasar_error(
+        reconcile(600, Pubkey::new_unique()),
+        QuasarErrorCode::InvalidAccount
+    ));
+}
+
+/// Accounts of a withdrawal from a fee vault holding 100 native quote, with a random key
+/// for the token program
+fn withdraw_fees_fixture() -> Fixture {
+    let mut fixture = Fixture::new(6);
+    let mut quasar_group = new_quasar_group();
+    quasar_group.admin_key = fixture.accounts[5].key;
+    quasar_group.signer_key = fixture.accounts[3].key;
+    quasar_group.quote_mint = Pubkey::new_unique();
+    quasar_group.fee_vault = fixture.accounts[1].key;
+    quasar_group.treasury = fixture.accounts[2].key;
+    fixture.accounts[0] =
+        TestAccount::owned_by(fixture.program_id, bytemuck::bytes_of(&quasar_group));
+
+    let fee_vault_data = token_account_data(quasar_group.quote_mint, quasar_group.signer_key, 100);
+    fixture.accounts[1] = TestAccount {
+        key: quasar_group.fee_vault,
+        ..TestAccount::owned_by(spl_token::id(), &fee_vault_data)
+    };
+    fixture
+}
+
+#[test]
+fn test_withdraw_fees_rejects_fake_token_program() {
+    assert!(is_quasar_error(
+        withdraw_fees_fixture().process(QuasarInstruction::WithdrawFees { quantity: 100 }),
+        QuasarErrorCode::InvalidProgramId
+    ));
+}
+
+#[test]
+fn test_only_the_admin_withdraws_accrued_fees() {
+    let withdraw = |fixture: &mut Fixture, quantity| {
+        process_stubbed(
+            fixture,
+            QuasarInstruction::WithdrawFees { quantity },
+            Clock::default(),
+            false,
+        )
+    };
+
+    let mut fixture = withdraw_fees_fixture();
+    fixture.accounts[4].key = spl_token::id();
+    fixture.accounts[5].is_signer = false;
+    assert!(is_quasar_error(
+        withdraw(&mut fixture, 100).0,
+        QuasarErrorCode::SignerNecessary
+    ));
+    let admin = fixture.accounts[5].key;
+    fixture.accounts[5] = TestAccount::blank();
+    assert!(is_quasar_error(
+        withdraw(&mut fixture, 100).0,
+        QuasarErrorCode::InvalidAdminKey
+    ));
+
+    // the admin takes out at most what the vault accrued
+    fixture.accounts[5].key = admin;
+    assert!(is_quasar_error(
+        withdraw(&mut fixture, 101).0,
+        QuasarErrorCode::InsufficientFunds
+    ));
+    let (result, run) = withdraw(&mut fixture, 60);
+    result.unwrap();
+    let transfer = spl_token::instruction::transfer(
+        &spl_token::id(),
+        &fixture.accounts[1].key,
+        &fixture.accounts[2].key,
+        &fixture.accounts[3].key,
+        &[],
+        60,
+    )
+    .unwrap();
+    assert_eq!(run.invoked, vec![transfer]);
+    assert_eq!(fixture.quasar_group().total_fees_withdrawn, 60);
+}
+
+#[test]
+fn test_views_reject_foreign_mango_program() {
+    // the blank group's Mango program is the default key, every account a random one
+    for (instruction, num_accounts) in vec![
+        (QuasarInstruction::QuoteRedeem { quantity: 1 }, 6),
+        (QuasarInstruction::EstimateRebalanceCost, 6),
+        (QuasarInstruction::QuoteReferencePrice, 7),
+    ] {
+        assert!(is_quasar_error(
+            process_on_blank_group(instruction, num_accounts, &[]),
+            QuasarErrorCode::InvalidProgramId
+        ));
+    }
+}
+
+#[test]
+fn test_reference_price_rejects_foreign_mango_account() {
+    let token_mint = Pubkey::new_unique();
+    let mango_program_id = Pubkey::new_unique();
+    let mut quasar_group = group_listing(token_mint);
+    quasar_group.mango_program_id = mango_program_id;
+
+    let mut fixture = Fixture::with_group(&quasar_group, 7);
+    fixture.accounts[1].key = token_mint;
+    fixture.accounts[2].key = mango_program_id;
+    assert!(is_quasar_error(
+        fixture.process(QuasarInstruction::QuoteReferencePrice),
+        QuasarErrorCode::InvalidAccount
+    ));
+}
+
+/// Accounts of the removal of a redeemed token whose Mango account holds `quote_deposit` of
+/// quote
+fn remove_token_fixture(quote_deposit: I80F48) -> Fixture {
+    let admin = Pubkey::new_unique();
+    let token_mint = Pubkey::new_unique();
+    let mango_program_id = Pubkey::new_unique();
+    let mango_account_key = Pubkey::new_unique();
+    let mut quasar_group = group_listing(token_mint);
+    quasar_group.admin_key = admin;
+    quasar_group.mango_program_id = mango_program_id;
+    quasar_group.leverage_tokens[0].mango_account = mango_account_key;
+
+    let mut fixture = Fixture::with_group(&quasar_group, 6);
+    let mango_group_key = fixture.accounts[3].key;
+
+    let mint = spl_token::state::Mint {
+        is_initialized: true,
+        ..spl_token::state::Mint::default()
+    };
+    let mut mint_data = vec![0u8; spl_token::state::Mint::LEN];
+    spl_token::state::Mint::pack(mint, &mut mint_data).unwrap();
+    fixture.accounts[1] = TestAccount::owned_by(spl_token::id(), &mint_data);
+    fixture.accounts[1].key = token_mint;
+    fixture.accounts[2].key = mango_program_id;
+
+    let mut mango_account = MangoAccount::zeroed();
+    mango_account.meta_data = MangoMetaData::new(MangoDataType::MangoAccount, 0, true);
+    mango_account.mango_group = mango_group_key;
+    mango_account.deposits[QUOTE_INDEX] = quote_deposit;
+    fixture.accounts[4] =
+        TestAccount::owned_by(mango_program_id, bytemuck::bytes_of(&mango_account));
+    fixture.accounts[4].key = mango_account_key;
+    fixture.accounts[5].key = admin;
+    fixture
+}
+
+/// Removes a redeemed token whose Mango account holds `quote_deposit` of quote
+fn remove_token_with_deposit(quote_deposit: I80F48) -> QuasarResult {
+    let mut fixture = remove_token_fixture(quote_deposit);
+    fixture.process(QuasarInstruction::RemoveLeverageToken)?;
+    assert_eq!(fixture.quasar_group().num_leverage_tokens, 0);
+    Ok(())
+}
+
+#[test]
+fn test_removed_token_leaves_an_empty_mango_account() {
+    // a relisting would hand the deposit to its first minter
+    assert!(is_quasar_error(
+        remove_token_with_deposit(I80F48::from_num(1_000)),
+        QuasarErrorCode::MangoAccountNotEmpty
+    ));
+    assert!(remove_token_with_deposit(I80F48::from_num(0.5)).is_ok());
+}
+
+#[test]
+fn test_last_token_of_another_quote_needs_its_fees_withdrawn() {
+    let remove = |quote_fees: Option<u64>| {
+        let mut fixture = remove_token_fixture(I80F48::ZERO);
+        let mut quasar_group = fixture.quasar_group();
+        let quote_mint = Pubkey::new_unique();
+        quasar_group.signer_key = Pubkey::new_unique();
+        quasar_group.leverage_tokens[0].quote_mint = quote_mint;
+        fixture.accounts[0].set_data(bytemuck::bytes_of(&quasar_group));
+        if let Some(quote_fees) = quote_fees {
+            fixture.accounts.push(TestAccount {
+                key: quasar_group.get_fee_vault(&quote_mint),
+                ..TestAccount::owned_by(
+                    spl_token::id(),
+                    &token_account_data(quote_mint, quasar_group.signer_key, quote_fees),
+                )
+            });
+        }
+        let result = fixture.process(QuasarInstruction::RemoveLeverageToken);
+        (result, fixture.quasar_group().num_leverage_tokens)
+    };
+
+    let (result, num_leverage_tokens) = remove(None);
+    assert!(is_quasar_error(result, QuasarErrorCode::InvalidAccount));
+    assert_eq!(num_leverage_tokens, 1);
+    let (result, num_leverage_tokens) = remove(Some(10));
+    assert!(is_quasar_error(result, QuasarErrorCode::FeesNotWithdrawn));
+    assert_eq!(num_leverage_tokens, 1);
+    let (result, num_leverage_tokens) = remove(Some(0));
+    result.unwrap();
+    assert_eq!(num_leverage_tokens, 0);
+}
+
+/// Instructions sysvar data of a transaction made of `instructions`, executing the one at
+/// `current_index`
+fn instructions_sysvar_data(instructions: &[Instruction], current_index: u16) -> Vec<u8> {
+    let mut data = (instructions.len() as u16).to_le_bytes().to_vec();
+    data.resize(2 + 2 * instructions.len(), 0);
+    for (i, instruction) in instructions.iter().enumerate() {
+        let offset = data.len() as u16;
+        data[2 + 2 * i..4 + 2 * i].copy_from_slice(&offset.to_le_bytes());
+        data.extend_from_slice(&(instruction.accounts.len() as u16).to_le_bytes());
+        for meta in instruction.accounts.iter() {
+            data.push(meta.is_signer as u8 | (meta.is_writable as u8) << 1);
+            data.extend_from_slice(meta.pubkey.as_ref());
+        }
+        data.extend_from_slice(instruction.program_id.as_ref());
+        data.extend_from_slice(&(instruction.data.len() as u16).to_le_bytes());
+        data.extend_from_slice(&instruction.data);
+    }
+    data.extend_from_slice(&current_index.to_le_bytes());
+    data
+}
+
+/// Instruction of the transaction a guarded burn is made in
+#[derive(Clone, Copy)]
+enum TransactionInstruction {
+    Mint,
+    MintOtherToken,
+    Burn,
+    /// An instruction of another program, e.g. one burning through a CPI
+    OtherProgram,
+}
+
+/// Burns a guarded token in a transaction of `instructions` executing the one at
+/// `current_index`. No instructions sysvar is passed for `None`
+fn burn_guarded_token(transaction: Option<(&[TransactionInstruction], u16)>) -> QuasarResult {
+    let token_mint = Pubkey::new_unique();
+    let mango_account = Pubkey::new_unique();
+
+    let mut quasar_group = group_listing(token_mint);
+    quasar_group.leverage_tokens[0].mango_account = mango_account;
+    quasar_group.leverage_tokens[0].same_slot_burn_guard = 1;
+    let mut fixture = Fixture::with_group(&quasar_group, 16);
+    fixture.accounts[1].key = token_mint;
+    fixture.accounts[2].key = get_associated_token_address(&fixture.accounts[6].key, &token_mint);
+    fixture.accounts[5].key = mango_account;
+    fixture.accounts[11].key = spl_token::id();
+
+    if let Some((instructions, current_index)) = transaction {
+        let program_id = fixture.program_id;
+        let instructions: Vec<Instruction> = instructions
+            .iter()
+            .map(|instruction| {
+                let (program_id, token_mint, instruction) = match instruction {
+                    TransactionInstruction::Mint => (
+                        program_id,
+                        token_mint,
+                        QuasarInstruction::MintLeverageToken { quantity: 1 },
+                    ),
+                    TransactionInstruction::MintOtherToken => (
+                        program_id,
+                        Pubkey::new_unique(),
+                        QuasarInstruction::MintLeverageToken { quantity: 1 },
+                    ),
+                    TransactionInstruction::Burn => (
+                        program_id,
+                        token_mint,
+                        QuasarInstruction::BurnLeverageToken { quantity: 1 },
+                    ),
+                    TransactionInstruction::OtherProgram => (
+                        Pubkey::new_unique(),
+                        token_mint,
+                        QuasarInstruction::BurnLeverageToken { quantity: 1 },
+                    ),
+                };
+                Instruction {
+                    program_id,
+                    accounts: vec![
+                        AccountMeta::new(Pubkey::new_unique(), false),
+                        AccountMeta::new(token_mint, false),
+                    ],
+                    data: instruction.pack(),
+                }
+            })
+            .collect();
+
+        let mut instructions_sysvar = TestAccount {
+            is_signer: false,
+            is_writable: false,
+            ..TestAccount::owned_by(
+                sysvar::id(),
+                &instructions_sysvar_data(&instructions, current_index),
+            )
+        };
+        instructions_sysvar.key = sysvar::instructions::id();
+        fixture.accounts.push(instructions_sysvar);
+    }
+    fixture.process(QuasarInstruction::BurnLeverageToken { quantity: 1 })
+}
+
+#[test]
+fn test_burn_guard_rejects_mints_in_the_transaction() {
+    use TransactionInstruction::*;
+
+    // the guarded burn needs the instructions sysvar
+    assert!(is_quasar_error(
+        burn_guarded_token(None),
+        QuasarErrorCode::InvalidAccount
+    ));
+
+    // a mint of the token anywhere in the transaction, whoever makes it, or a burn made
+    // through another program, which could hide such a mint
+    for &(instructions, current_index) in [
+        (&[Mint, Burn][..], 1),
+        (&[Burn, Mint][..], 0),
+        (&[OtherProgram][..], 0),
+    ]
+    .iter()
+    {
+        assert!(is_quasar_error(
+            burn_guarded_token(Some((instructions, current_index))),
+            QuasarErrorCode::SameSlotBurn
+        ));
+    }
+
+    // mints of other tokens don't hold the burn back, which goes on to the blank Mango group
+    for &(instructions, current_index) in
+        [(&[Burn][..], 0), (&[MintOtherToken, Burn][..], 1)].iter()
+    {
+        let code = match burn_guarded_token(Some((instructions, current_index))) {
+            Err(QuasarError::QuasarErrorCode {
+                quasar_error_code, ..
+            }) => Some(quasar_error_code),
+            Err(_) => None,
+            Ok(()) => panic!("the blank Mango group loaded"),
+        };
+        assert_ne!(code, Some(QuasarErrorCode::SameSlotBurn));
+        assert_ne!(code, Some(QuasarErrorCode::InvalidAccount));
+    }
+}
+
+/// Mango state of a token holding `quote` native quote and no position, on a perp market
+/// whose orders are 1000 quote lots per base lot at a native price of 100
+fn quote_only_mango_state(quote: u64) -> (MangoGroup, MangoCache, MangoAccount) {
+    let mut mango_group = MangoGroup::zeroed();
+    mango_group.num_oracles = 1;
+    mango_group.tokens[0].decimals = 6;
+    mango_group.tokens[QUOTE_INDEX].decimals = 6;
+    mango_group.perp_markets[0].base_lot_size = 100;
+    mango_group.perp_markets[0].quote_lot_size = 10;
+
+    let mut mango_cache = MangoCache::zeroed();
+    mango_cache.price_cache[0].price = I80F48::from_num(100);
+    mango_cache.root_bank_cache[QUOTE_INDEX].deposit_index = I80F48::ONE;
+
+    let mut mango_account = MangoAccount::zeroed();
+    mango_account.deposits[QUOTE_INDEX] = I80F48::from_num(quote);
+
+    (mango_group, mango_cache, mango_account)
+}
+
+#[test]
+fn test_volatility_scales_the_rebalance_order() {
+    let (mango_group, mango_cache, mango_account) = quote_only_mango_state(1_000_000);
+    let price = I80F48::from_num(100);
+    let target_leverage = I80F48::from_num(2);
+    let order_quantity = |target, leverage_token: &LeverageToken| {
+        compute_rebalance_order(
+            target,
+            price,
+            &mango_group,
+            &mango_cache,
+            &mango_account,
+            0,
+            leverage_token,
+        )
+        .unwrap()
+        .to_lots()
+        .1
+    };
+
+    // two times the NAV of 1_000_000 native quote, at 1000 quote lots of 10 per base lot
+    let mut leverage_token = LeverageToken::zeroed();
+    leverage_token.target_leverage = target_leverage;
+    leverage_token.volatility_target = I80F48::from_num(0.01);
+    let calm = order_quantity(RebalanceTarget::Leverage(target_leverage), &leverage_token);
+    assert_eq!(calm, 200);
+
+    // twice the volatility target halves the exposure a rebalance targets
+    leverage_token.volatility_ema = I80F48::from_num(0.02);
+    assert_eq!(
+        order_quantity(RebalanceTarget::Leverage(target_leverage), &leverage_token),
+        100
+    );
+
+    // as does a large move sampled by rebalances
+    let mut sampled_token = LeverageToken::zeroed();
+    sampled_token.target_leverage = target_leverage;
+    sampled_token.volatility_target = I80F48::from_num(0.01);
+    sampled_token.update_volatility(price, 0).unwrap();
+    sampled_token
+        .update_volatility(I80F48::from_num(130), VOLATILITY_SAMPLE_SLOTS)
+        .unwrap();
+    let sampled = order_quantity(RebalanceTarget::Leverage(target_leverage), &sampled_token);
+    assert!(sampled > 0 && sampled < calm);
+
+    // an exposure attested by the keeper is taken as is
+    let attested = RebalanceTarget::AttestedExposure {
+        exposure: I80F48::from_num(2_000_000),
+        target_leverage,
+        max_target_leverage: I80F48::from_num(3),
+    };
+    assert_eq!(order_quantity(attested, &leverage_token), calm);
+}
+
+/// Run of the native processor under `TestSyscallStubs`, which gives it a clock and stands
+/// in for the programs it invokes
+struct StubbedRun {
+    clock: Clock,
+    /// Base lots Mango fills of every perp order at its limit price, on the first market
+    perp_fill_lots: i64,
+    logs: Vec<String>,
+    invoked: Vec<Instruction>,
+}
+
+thread_local! {
+    static STUBBED_RUN: RefCell<Option<StubbedRun>> = RefCell::new(None);
+}
+
+/// Syscalls of the native processor tests. They behave as the default ones on threads not
+/// running `process_stubbed`
+struct TestSyscallStubs;
+
+impl SyscallStubs for TestSyscallStubs {
+    fn sol_log(&self, message: &str) {
+        STUBBED_RUN.with(|run| match run.borrow_mut().as_mut() {
+            Some(run) => run.logs.push(message.to_string()),
+            None => println!("{}", message),
+        });
+    }
+
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        STUBBED_RUN.with(|run| match run.borrow().as_ref() {
+            Some(run) => {
+                unsafe { *(var_addr as *mut Clock) = run.clock.clone() };
+                SUCCESS
+            }
+            None => UNSUPPORTED_SYSVAR,
+        })
+    }
+
+    fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+        unsafe { *(var_addr as *mut Rent) = Rent::default() };
+        SUCCESS
+    }
+
+    fn sol_invoke_signed(
+        &self,
+        instruction: &Instruction,
+        account_infos: &[AccountInfo],
+        _signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        let perp_fill_lots = STUBBED_RUN.with(|run| match run.borrow_mut().as_mut() {
+            Some(run) => {
+                run.invoked.push(instruction.clone());
+                run.perp_fill_lots
+            }
+            None => 0,
+        });
+        // Token program instructions don't do anything
+        let mango_instruction = if instruction.program_id == spl_token::id() {
+            None
+        } else {
+            MangoInstruction::unpack(&instruction.data)
+        };
+        match mango_instruction {
+            // Mango credits deposits in full, into the quote the minters pay in
+            Some(MangoInstruction::Deposit { quantity }) => {
+                let mut data = account_infos[2].data.borrow_mut();
+                let mango_account: &mut MangoAccount = bytemuck::from_bytes_mut(&mut data);
+                mango_account.deposits[QUOTE_INDEX] += I80F48::from_num(quantity);
+            }
+            Some(MangoInstruction::PlacePerpOrder {
+                price,
+                quantity,
+                side,
+                ..
+            }) if perp_fill_lots > 0 => {
+                let mango_group: MangoGroup =
+                    *bytemuck::from_bytes(&account_infos[1].data.borrow());
+                let mut data = account_infos[2].data.borrow_mut();
+                let mango_account: &mut MangoAccount = bytemuck::from_bytes_mut(&mut data);
+                let filled = quantity.min(perp_fill_lots);
+                let base_change = match side {
+                    Side::Bid => filled,
+                    Side::Ask => -filled,
+                };
+                let perp_account = &mut mango_account.perp_accounts[0];
+                perp_account.base_position += base_change;
+                perp_account.quote_position -= I80F48::from_num(
+                    base_change * price * mango_group.perp_markets[0].quote_lot_size,
+                );
+            }
+            _ => self.sol_log("SyscallStubs: sol_invoke_signed() not available"),
+        }
+        Ok(())
+    }
+}
+
+/// Processes `instruction` at `clock`, returning its result with the logs and the invoked
+/// instructions of the run
+fn process_stubbed(
+    fixture: &mut Fixture,
+    instruction: QuasarInstruction,
+    clock: Clock,
+    fill_perp_orders: bool,
+) -> (QuasarResult, StubbedRun) {
+    let perp_fill_lots = if fill_perp_orders { i64::MAX } else { 0 };
+    process_partly_filled(fixture, instruction, clock, perp_fill_lots)
+}
+
+/// As `process_stubbed`, Mango filling at most `perp_fill_lots` base lots of a perp order
+fn process_partly_filled(
+    fixture: &mut Fixture,
+    instruction: QuasarInstruction,
+    clock: Clock,
+    perp_fill_lots: i64,
+) -> (QuasarResult, StubbedRun) {
+    static INSTALL_STUBS: Once = Once::new();
+    INSTALL_STUBS.call_once(|| {
+        set_syscall_stubs(Box::new(TestSyscallStubs));
+    });
+
+    STUBBED_RUN.with(|run| {
+        *run.borrow_mut() = Some(StubbedRun {
+            clock,
+            perp_fill_lots,
+            logs: vec![],
+            invoked: vec![],
+        })
+    });
+    let result = fixture.process(instruction);
+    let run = STUBBED_RUN.with(|run| run.borrow_mut().take().unwrap());
+    (result, run)
+}
+
+/// Accounts of an instruction on a token listed on the first Mango perp market, holding
+/// `quote` native quote in its Mango account. Every instruction on a token passes the quasar
+/// group at index 0, the token mint at 1, the Mango program, group and account at 3 to 5
+/// and the Mango cache at 7, so the Mango state is written there
+fn mango_token_fixture(num_accounts: usize, quote: u64) -> Fixture {
+    let token_mint = Pubkey::new_unique();
+    let mango_program = Pubkey::new_unique();
+    let mango_cache_key = Pubkey::new_unique();
+    let perp_market = Pubkey::new_unique();
+    let mut fixture = Fixture::new(num_accounts);
+    let mango_group_key = fixture.accounts[4].key;
+    let mango_account_key = fixture.accounts[5].key;
+
+    let (mut mango_group, mut mango_cache, mut mango_account) = quote_only_mango_state(quote);
+    mango_group.meta_data = MangoMetaData::new(MangoDataType::MangoGroup, 0, true);
+    mango_group.mango_cache = mango_cache_key;
+    mango_group.perp_markets[0].perp_market = perp_market;
+    mango_cache.meta_data = MangoMetaData::new(MangoDataType::MangoCache, 0, true);
+    mango_account.meta_data = MangoMetaData::new(MangoDataType::MangoAccount, 0, true);
+    mango_account.mango_group = mango_group_key;
+
+    let mut quasar_group = group_listing(token_mint);
+    quasar_group.mango_program_id = mango_program;
+    quasar_group.signer_key = Pubkey::new_unique();
+    quasar_group.fee_vault = Pubkey::new_unique();
+    let leverage_token = &mut quasar_group.leverage_tokens[0];
+    leverage_token.mango_account = mango_account_key;
+    leverage_token.mango_perp_market = perp_market;
+    fixture.accounts[0] =
+        TestAccount::owned_by(fixture.program_id, bytemuck::bytes_of(&quasar_group));
+    fixture.accounts[1].key = token_mint;
+    fixture.accounts[3].key = mango_program;
+    fixture.accounts[4] = TestAccount {
+        key: mango_group_key,
+        ..TestAccount::owned_by(mango_program, bytemuck::bytes_of(&mango_group))
+    };
+    fixture.accounts[5] = TestAccount {
+        key: mango_account_key,
+        ..TestAccount::owned_by(mango_program, bytemuck::bytes_of(&mango_account))
+    };
+    fixture.accounts[7] = TestAccount {
+        key: mango_cache_key,
+        ..TestAccount::owned_by(mango_program, bytemuck::bytes_of(&mango_cache))
+    };
+    fixture
+}
+
+/// Accounts of a rebalance of a token targeting 2x from 1_000_000 native quote, on Mango
+/// state written into the fixture. The quasar group is at index 0, the token's Mango account
+/// at index 5
+fn mango_rebalance_fixture() -> Fixture {
+    let mut fixture = mango_token_fixture(12 + MAX_PAIRS, 1_000_000);
+    let mut quasar_group = fixture.quasar_group();
+    let leverage_token = &mut quasar_group.leverage_tokens[0];
+    leverage_token.target_leverage = I80F48::from_num(2);
+    fixture.accounts[8].key = leverage_token.mango_perp_market;
+    fixture.accounts[0].set_data(bytemuck::bytes_of(&quasar_group));
+    fixture
+}
+
+/// Data of an SPL token mint with 6 decimals, `supply` of it minted by `mint_authority`
+fn mint_data(supply: u64, mint_authority: Pubkey) -> Vec<u8> {
+    let mint = spl_token::state::Mint {
+        mint_authority: COption::Some(mint_authority),
+        supply,
+        decimals: 6,
+        is_initialized: true,
+        ..spl_token::state::Mint::default()
+    };
+    let mut data = vec![0u8; spl_token::state::Mint::LEN];
+    spl_token::state::Mint::pack(mint, &mut data).unwrap();
+    data
+}
+
+/// Data of an SPL token account of `owner` holding `amount` of `mint`
+fn token_account_data(mint: Pubkey, owner: Pubkey, amount: u64) -> Vec<u8> {
+    let token_account = spl_token::state::Account {
+        mint,
+        owner,
+        amount,
+        state: spl_token::state::AccountState::Initialized,
+        ..spl_token::state::Account::default()
+    };
+    let mut data = vec![0u8; spl_token::state::Account::LEN];
+    spl_token::state::Account::pack(token_account, &mut data).unwrap();
+    data
+}
+
+/// Accounts of a burn by the owner at index 6, from a supply of `supply` native units
+/// backed by `quote` native quote. No open orders follow
+fn mango_burn_fixture(supply: u64, quote: u64) -> Fixture {
+    let mut fixture = mango_token_fixture(16, quote);
+    let quasar_group = fixture.quasar_group();
+    let token_mint = fixture.accounts[1].key;
+    let owner = fixture.accounts[6].key;
+    fixture.accounts[1].set_data(&mint_data(supply, quasar_group.signer_key));
+    fixture.accounts[2].key = get_associated_token_address(&owner, &token_mint);
+    fixture.accounts[11].key = spl_token::id();
+    fixture.accounts[12].set_data(&token_account_data(quasar_group.quote_mint, owner, 0));
+    fixture.accounts[15].key = quasar_group.fee_vault;
+    fixture
+}
+
+/// Accounts of a mint by the owner at index 6, who holds `owner_quote` native quote, of a
+/// token with a supply of `supply` native units backed by `quote` native quote
+fn mango_mint_fixture(supply: u64, quote: u64, owner_quote: u64) -> Fixture {
+    let mut fixture = mango_token_fixture(15, quote);
+    let quasar_group = fixture.quasar_group();
+    let token_mint = fixture.accounts[1].key;
+    let owner = fixture.accounts[6].key;
+    fixture.accounts[1].set_data(&mint_data(supply, quasar_group.signer_key));
+    fixture.accounts[2].key = get_associated_token_address(&owner, &token_mint);
+    fixture.accounts[11].key = spl_token::id();
+    fixture.accounts[12].set_data(&token_account_data(
+        quasar_group.quote_mint,
+        owner,
+        owner_quote,
+    ));
+    fixture.accounts[14].key = quasar_group.fee_vault;
+    fixture
+}
+
+/// The Mango withdrawal a burn of the token of `fixture` invokes, with the run
+fn burn_withdrawal(fixture: &mut Fixture, quantity: u64) -> (Instruction, StubbedRun) {
+    let mango_program = fixture.accounts[3].key;
+    let (result, run) = process_stubbed(
+        fixture,
+        QuasarInstruction::BurnLeverageToken { quantity },
+        Clock::default(),
+        false,
+    );
+    result.unwrap();
+    let withdrawal = run
+        .invoked
+        .iter()
+        .find(|instruction| {
+            instruction.program_id == mango_program
+                && matches!(
+                    MangoInstruction::unpack(&instruction.data),
+                    Some(MangoInstruction::Withdraw { .. })
+                )
+        })
+        .unwrap()
+        .clone();
+    (withdrawal, run)
+}
+
+/// The result a successful run logged as its return data
+fn logged_return_data(run: &StubbedRun) -> Vec<u8> {
+    let hex = run
+        .logs
+        .iter()
+        .find_map(|log| log.strip_prefix("return data: "))
+        .unwrap();
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+#[test]
+fn test_rebalance_returns_its_result() {
+    // two times the NAV at 1000 quote lots per base lot, as in the volatility test
+    let unfilled = RebalanceResult {
+        traded: false,
+        side: Side::Bid,
+        price: 1000,
+        quantity: 0,
+        effective_leverage_before: I80F48::ZERO,
+        effective_leverage_after: I80F48::ZERO,
+    };
+    let (result, run) = process_stubbed(
+        &mut mango_rebalance_fixture(),
+        QuasarInstruction::Rebalance,
+        Clock::default(),
+        false,
+    );
+    result.unwrap();
+    assert_eq!(logged_return_data(&run), unfilled.pack().to_vec());
+
+    let filled = RebalanceResult {
+        traded: true,
+        quantity: 200,
+        effective_leverage_after: I80F48::from_num(2),
+        ..unfilled
+    };
+    let (result, run) = process_stubbed(
+        &mut mango_rebalance_fixture(),
+        QuasarInstruction::Rebalance,
+        Clock::default(),
+        true,
+    );
+    result.unwrap();
+    assert_eq!(logged_return_data(&run), filled.pack().to_vec());
+}
+
+#[test]
+fn test_rebalances_past_the_epoch_cap_are_rejected() {
+    let mut fixture = mango_rebalance_fixture();
+    let mut quasar_group = fixture.quasar_group();
+    quasar_group.leverage_tokens[0].max_rebalances_per_epoch = 2;
+    fixture.accounts[0].set_data(bytemuck::bytes_of(&quasar_group));
+    let flat_mango_account = fixture.accounts[5].data().to_vec();
+    let mut rebalance = |fill_perp_orders: bool| {
+        let (result, _) = process_stubbed(
+            &mut fixture,
+            QuasarInstruction::Rebalance,
+            Clock::default(),
+            fill_perp_orders,
+        );
+        // back to no position, so that every rebalance has the same order to place
+        fixture.accounts[5].set_data(&flat_mango_account);
+        result
+    };
+
+    // orders that don't fill leave the cap alone
+    for _ in 0..3 {
+        rebalance(false).unwrap();
+    }
+    rebalance(true).unwrap();
+    rebalance(true).unwrap();
+    assert!(is_quasar_error(
+        rebalance(true),
+        QuasarErrorCode::RebalanceLimitReached
+    ));
+}
+
+/// Price and quantity of the perp order a rebalance of the token of `fixture` places
+fn placed_perp_order(fixture: &mut Fixture) -> (i64, i64) {
+    let (result, run) = process_stubbed(
+        fixture,
+        QuasarInstruction::Rebalance,
+        Clock::default(),
+        false,
+    );
+    result.unwrap();
+    run.invoked
+        .iter()
+        .find_map(
+            |instruction| match MangoInstruction::unpack(&instruction.data) {
+                Some(MangoInstruction::PlacePerpOrder {
+                    price, quantity, ..
+                }) => Some((price, quantity)),
+                _ => None,
+            },
+        )
+        .unwrap()
+}
+
+#[test]
+fn test_rebalance_orders_follow_the_price_source() {
+    // the Mango cache prices the base at 100 native quote, 1000 quote lots per base lot
+    let mut fixture = mango_rebalance_fixture();
+    assert_eq!(placed_perp_order(&mut fixture), (1000, 200));
+
+    // the oracle at 150 quote per base prices the order at 1500 quote lots, which buys
+    // fewer base lots for the same exposure
+    let mut fixture = mango_rebalance_fixture();
+    let oracle = StubOracle {
+        magic: STUB_MAGIC,
+        expo: 0,
+        price: I80F48::from_num(150),
+        last_update: 0,
+    };
+    let oracle_account = fixture.program_account(bytemuck::bytes_of(&oracle));
+    let mut quasar_group = fixture.quasar_group();
+    let base_token_mint = Pubkey::new_unique();
+    quasar_group.num_base_tokens = 1;
+    quasar_group.base_tokens[0].mint = base_token_mint;
+    quasar_group.base_tokens[0].decimals = 6;
+    quasar_group.base_tokens[0].oracle = oracle_account.key;
+    quasar_group.base_tokens[0].oracle_type = OracleType::Stub as u8;
+    quasar_group.leverage_tokens[0].base_token_mint = base_token_mint;
+    quasar_group.leverage_tokens[0].rebalance_price_source = RebalancePriceSource::Oracle as u8;
+    fixture.accounts[0].set_data(bytemuck::bytes_of(&quasar_group));
+    fixture.accounts.push(oracle_account);
+    assert_eq!(placed_perp_order(&mut fixture), (1500, 133));
+
+    // and without the oracle the rebalance can't price its order
+    fixture.accounts.pop();
+    let (result, _) = process_stubbed(
+        &mut fixture,
+        QuasarInstruction::Rebalance,
+        Clock::default(),
+        false,
+    );
+    assert!(is_quasar_error(result, QuasarErrorCode::InvalidAccount));
+}
+
+#[test]
+fn test_oracle_price_source_needs_a_base_token_oracle() {
+    let token_mint = Pubkey::new_unique();
+    let base_token_mint = Pubkey::new_unique();
+    let mut quasar_group = group_listing(token_mint);
+    quasar_group.leverage_tokens[0].base_token_mint = base_token_mint;
+    quasar_group.num_base_tokens = 1;
+    quasar_group.base_tokens[0].mint = base_token_mint;
+    let mut fixture = Fixture::with_group(&quasar_group, 3);
+    fixture.accounts[1].key = token_mint;
+    let mut quasar_group = fixture.quasar_group();
+    quasar_group.admin_key = fixture.accounts[2].key;
+    fixture.accounts[0].set_data(bytemuck::bytes_of(&quasar_group));
+    let set_source = |fixture: &mut Fixture, source| {
+        fixture.process(QuasarInstruction::SetRebalancePriceSource { source })
+    };
+
+    assert!(is_quasar_error(
+        set_source(&mut fixture, RebalancePriceSource::Oracle),
+        QuasarErrorCode::InvalidParam
+    ));
+
+    quasar_group.base_tokens[0].oracle = Pubkey::new_unique();
+    fixture.accounts[0].set_data(bytemuck::bytes_of(&quasar_group));
+    set_source(&mut fixture, RebalancePriceSource::Oracle).unwrap();
+    assert!(fixture.quasar_group().leverage_tokens[0].uses_oracle_price());
+    set_source(&mut fixture, RebalancePriceSource::MangoCache).unwrap();
+    assert!(!fixture.quasar_group().leverage_tokens[0].uses_oracle_price());
+
+    fixture.accounts[2].is_signer = false;
+    assert!(is_quasar_error(
+        set_source(&mut fixture, RebalancePriceSource::Oracle),
+        QuasarErrorCode::SignerNecessary
+    ));
+}
+
+#[test]
+fn test_only_the_rebalance_authority_rebalances_a_restricted_group() {
+    let rebalance = |fixture: &mut Fixture| {
+        process_stubbed(
+            fixture,
+            QuasarInstruction::Rebalance,
+            Clock::default(),
+            false,
+        )
+        .0
+    };
+
+    // without an authority anyone rebalances, signing or not
+    let mut fixture = mango_rebalance_fixture();
+    fixture.accounts[6].is_signer = false;
+    rebalance(&mut fixture).unwrap();
+
+    let authority = Pubkey::new_unique();
+    let mut fixture = mango_rebalance_fixture();
+    let mut quasar_group = fixture.quasar_group();
+    quasar_group.rebalance_authority = authority;
+    fixture.accounts[0].set_data(bytemuck::bytes_of(&quasar_group));
+    assert!(is_quasar_error(
+        rebalance(&mut fixture),
+        QuasarErrorCode::InvalidSignerKey
+    ));
+
+    fixture.accounts[6].key = authority;
+    fixture.accounts[6].is_signer = false;
+    assert!(is_quasar_error(
+        rebalance(&mut fixture),
+        QuasarErrorCode::SignerNecessary
+    ));
+
+    fixture.accounts[6].is_signer = true;
+    rebalance(&mut fixture).unwrap();
+}
+
+#[test]
+fn test_burn_takes_only_the_open_orders_of_the_margin_basket() {
+    // the token's Mango account only trades on the spot market of index 2
+    let open_orders = Pubkey::new_unique();
+    let burn_passing = |open_orders_keys: &[Pubkey]| {
+        let mut fixture = mango_burn_fixture(1_000_000, 1_000_000);
+        let mut mango_account: MangoAccount = *bytemuck::from_bytes(fixture.accounts[5].data());
+        mango_account.in_margin_basket[2] = true;
+        mango_account.spot_open_orders[2] = open_orders;
+        fixture.accounts[5].set_data(bytemuck::bytes_of(&mango_account));
+        for key in open_orders_keys.iter() {
+            fixture.accounts.push(TestAccount {
+                key: *key,
+                is_signer: false,
+                ..TestAccount::blank()
+            });
+        }
+        fixture
+    };
+
+    // Mango gets the open orders at the index of their market, the program everywhere else
+    let mut fixture = burn_passing(&[open_orders]);
+    let mango_program = fixture.accounts[3].key;
+    let (withdrawal, _) = burn_withdrawal(&mut fixture, 100_000);
+    let open_orders_keys: Vec<Pubkey> = withdrawal.accounts[10..]
+        .iter()
+        .map(|meta| meta.pubkey)
+        .collect();
+    let mut expected = vec![mango_program; MAX_PAIRS];
+    expected[2] = open_orders;
+    assert_eq!(open_orders_keys, expected);
+
+    // the open orders of the basket are all needed, and nothing more
+    for open_orders_keys in vec![
+        vec![],
+        vec![Pubkey::new_unique()],
+        vec![open_orders, Pubkey::new_unique()],
+    ] {
+        let (result, _) = process_stubbed(
+            &mut burn_passing(&open_orders_keys),
+            QuasarInstruction::BurnLeverageToken { quantity: 100_000 },
+            Clock::default(),
+            false,
+        );
+        assert!(is_quasar_error(result, QuasarErrorCode::InvalidAccount));
+    }
+}
+
+/// Accounts of a view on the token of a burn `fixture`: the group, the mint and the Mango
+/// accounts
+fn view_fixture(fixture: &Fixture) -> Fixture {
+    Fixture {
+        program_id: fixture.program_id,
+        accounts: [0, 1, 3, 4, 5, 7]
+            .iter()
+            .map(|&i| fixture.accounts[i].clone())
+            .collect(),
+    }
+}
+
+#[test]
+fn test_quote_redeem_matches_the_burn_proceeds() {
+    // 3 tokens backed by 2 quote, burned at a fee of 50 bps
+    let mut fixture = mango_burn_fixture(3_000_000, 2_000_000);
+    let mut quasar_group = fixture.quasar_group();
+    quasar_group.leverage_tokens[0].burn_fee_bps = 50;
+    fixture.accounts[0].set_data(bytemuck::bytes_of(&quasar_group));
+
+    let mut quote_fixture = view_fixture(&fixture);
+    let (result, run) = process_stubbed(
+        &mut quote_fixture,
+        QuasarInstruction::QuoteRedeem {
+            quantity: 1_000_000,
+        },
+        Clock::default(),
+        false,
+    );
+    result.unwrap();
+    let quoted = u64::from_le_bytes(logged_return_data(&run)[..8].try_into().unwrap());
+
+    let (withdrawal, run) = burn_withdrawal(&mut fixture, 1_000_000);
+    let withdrawn = match MangoInstruction::unpack(&withdrawal.data) {
+        Some(MangoInstruction::Withdraw { quantity, .. }) => quantity,
+        _ => unreachable!(),
+    };
+    let fee = run
+        .invoked
+        .iter()
+        .filter(|instruction| instruction.program_id == spl_token::id())
+        .find_map(|instruction| {
+            match spl_token::instruction::TokenInstruction::unpack(&instruction.data) {
+                Ok(spl_token::instruction::TokenInstruction::Transfer { amount }) => Some(amount),
+                _ => None,
+            }
+        })
+        .unwrap();
+
+    // 666_666 withdrawn, rounded down, less a fee of 3_334, rounded up
+    assert_eq!(withdrawn, 666_666);
+    assert_eq!(fee, 3_334);
+    assert_eq!(quoted, withdrawn - fee);
+}
+
+#[test]
+fn test_quote_redeem_rejects_what_a_burn_rejects() {
+    // one token, frozen or capped at a tenth per burn when asked
+    let burn_and_quote = |quantity, frozen, max_redemption_per_tx| {
+        let mut fixture = mango_burn_fixture(1_000_000, 1_000_000);
+        let mut quasar_group = fixture.quasar_group();
+        let leverage_token = &mut quasar_group.leverage_tokens[0];
+        if frozen {
+            leverage_token.state = TokenState::Frozen as u8;
+        }
+        leverage_token.max_redemption_per_tx = max_redemption_per_tx;
+        fixture.accounts[0].set_data(bytemuck::bytes_of(&quasar_group));
+
+        let (quoted, _) = process_stubbed(
+            &mut view_fixture(&fixture),
+            QuasarInstruction::QuoteRedeem { quantity },
+            Clock::default(),
+            false,
+        );
+        let (burned, _) = process_stubbed(
+            &mut fixture,
+            QuasarInstruction::BurnLeverageToken { quantity },
+            Clock::default(),
+            false,
+        );
+        (quoted, burned)
+    };
+
+    for &(quantity, frozen, max_redemption_per_tx, error) in [
+        (0, false, 0, QuasarErrorCode::InvalidParam),
+        (100_000, true, 0, QuasarErrorCode::TokenFrozen),
+        (200_000, false, 100_000, QuasarErrorCode::InvalidParam),
+        (2_000_000, false, 0, QuasarErrorCode::InvalidParam),
+    ]
+    .iter()
+    {
+        let (quoted, burned) = burn_and_quote(quantity, frozen, max_redemption_per_tx);
+        assert!(is_quasar_error(quoted, error));
+        assert!(is_quasar_error(burned, error));
+    }
+
+    let (quoted, burned) = burn_and_quote(100_000, false, 100_000);
+    quoted.unwrap();
+    burned.unwrap();
+}
+
+#[test]
+fn test_rebalance_stores_the_nav_and_leverage_it_reports() {
+    // a first rebalance fills up to the 2x target
+    let mut fixture = mango_rebalance_fixture();
+    let (result, _) = process_stubbed(
+        &mut fixture,
+        QuasarInstruction::Rebalance,
+        Clock::default(),
+        true,
+    );
+    result.unwrap();
+
+    // the next one finds the token on target at slot 42, and has nothing to trade
+    let clock = Clock {
+        slot: 42,
+        ..Clock::default()
+    };
+    let (result, run) = process_stubbed(&mut fixture, QuasarInstruction::Rebalance, clock, false);
+    result.unwrap();
+    let reported = RebalanceResult {
+        traded: false,
+        side: Side::Bid,
+        price: 0,
+        quantity: 0,
+        effective_leverage_before: I80F48::from_num(2),
+        effective_leverage_after: I80F48::from_num(2),
+    };
+    assert_eq!(logged_return_data(&run), reported.pack().to_vec());
+
+    let leverage_token = fixture.quasar_group().leverage_tokens[0];
+    assert_eq!(
+        leverage_token.last_effective_leverage,
+        reported.effective_leverage_before
+    );
+    assert_eq!(leverage_token.last_nav, I80F48::from_num(1_000_000));
+    assert_eq!(leverage_token.last_update_slot, 42);
+}
+
+#[test]
+fn test_rebalances_trading_nothing_leave_the_cooldown() {
+    let mut fixture = mango_rebalance_fixture();
+    let mut quasar_group = fixture.quasar_group();
+    quasar_group.leverage_tokens[0].rebalance_cooldown_slots = 10;
+    fixture.accounts[0].set_data(bytemuck::bytes_of(&quasar_group));
+    let mut rebalance_at = |slot, fill| {
+        let clock = Clock {
+            slot,
+            ..Clock::default()
+        };
+        let (result, _) = process_stubbed(&mut fixture, QuasarInstruction::Rebalance, clock, fill);
+        let leverage_token = fixture.quasar_group().leverage_tokens[0];
+        (
+            result,
+            leverage_token.last_rebalance_slot,
+            leverage_token.last_update_slot,
+        )
+    };
+
+    // an order nothing fills doesn't start the cooldown, so the next slot may trade
+    let (result, last_rebalance_slot, last_update_slot) = rebalance_at(100, false);
+    result.unwrap();
+    assert_eq!((last_rebalance_slot, last_update_slot), (0, 100));
+    let (result, last_rebalance_slot, _) = rebalance_at(101, true);
+    result.unwrap();
+    assert_eq!(last_rebalance_slot, 101);
+
+    let (result, _, _) = rebalance_at(105, false);
+    assert!(is_quasar_error(result, QuasarErrorCode::RebalanceCooldown));
+
+    // on target, rebalances trade nothing and keep the cooldown where the trade left it
+    for &slot in [111, 112].iter() {
+        let (result, last_rebalance_slot, last_update_slot) = rebalance_at(slot, false);
+        result.unwrap();
+        assert_eq!((last_rebalance_slot, last_update_slot), (101, slot));
+    }
+}
+
+#[test]
+fn test_partly_filled_rebalance_leaves_the_rest_pending() {
+    let pending_after = |fixture: &mut Fixture, perp_fill_lots: i64| {
+        let (result, run) = process_partly_filled(
+            fixture,
+            QuasarInstruction::Rebalance,
+            Clock::default(),
+            perp_fill_lots,
+        );
+        result.unwrap();
+        let leverage_token = fixture.quasar_group().leverage_tokens[0];
+        (leverage_token.pending_rebalance_quantity, run)
+    };
+
+    // the order for 200 base lots rests on the book
+    let mut fixture = mango_rebalance_fixture();
+    assert_eq!(pending_after(&mut fixture, 0).0, 200);
+
+    // 150 of them fill, the other 50 are left for a follow-up
+    let mut fixture = mango_rebalance_fixture();
+    let (pending, run) = pending_after(&mut fixture, 150);
+    assert_eq!(pending, 50);
+    assert!(fixture.quasar_group().leverage_tokens[0].has_pending_rebalance());
+    let reported = RebalanceResult {
+        traded: true,
+        side: Side::Bid,
+        price: 1000,
+        quantity: 150,
+        effective_leverage_before: I80F48::ZERO,
+        effective_leverage_after: I80F48::from_num(1.5),
+    };
+    assert_eq!(logged_return_data(&run), reported.pack().to_vec());
+
+    // the follow-up orders the 50 left, and fills them
+    let (pending, run) = pending_after(&mut fixture, i64::MAX);
+    assert_eq!(pending, 0);
+    assert!(!fixture.quasar_group().leverage_tokens[0].has_pending_rebalance());
+    let reported = RebalanceResult {
+        quantity: 50,
+        effective_leverage_before: I80F48::from_num(1.5),
+        effective_leverage_after: I80F48::from_num(2),
+        ..reported
+    };
+    assert_eq!(logged_return_data(&run), reported.pack().to_vec());
+}
+
+/// Adds a base token whose mint, owned by `mint_owner`, Mango lists with `perp_market`
+/// as its perp market, returning the result with the group after it
+fn add_base_token(mint_owner: Pubkey, perp_market: Pubkey) -> (QuasarResult, QuasarGroup) {
+    let mango_program = Pubkey::new_unique();
+    let mut fixture = Fixture::new(5);
+    let base_token_mint = fixture.accounts[1].key;
+
+    let mut quasar_group = new_quasar_group();
+    quasar_group.admin_key = fixture.accounts[3].key;
+    quasar_group.mango_program_id = mango_program;
+    fixture.accounts[0] =
+        TestAccount::owned_by(fixture.program_id, bytemuck::bytes_of(&quasar_group));
+    fixture.accounts[1] = TestAccount {
+        key: base_token_mint,
+        ..TestAccount::owned_by(mint_owner, &mint_data(0, Pubkey::new_unique()))
+    };
+    // a pyth price account is only recognized by its magic when added
+    fixture.accounts[2].set_data(&MAGIC.to_le_bytes());
+
+    let mut mango_group = MangoGroup::zeroed();
+    mango_group.meta_data = MangoMetaData::new(MangoDataType::MangoGroup, 0, true);
+    mango_group.num_oracles = 1;
+    mango_group.tokens[0].mint = base_token_mint;
+    mango_group.perp_markets[0].perp_market = perp_market;
+    fixture.accounts[4] = TestAccount::owned_by(mango_program, bytemuck::bytes_of(&mango_group));
+
+    let result = fixture.process(QuasarInstruction::AddBaseToken);
+    (result, fixture.quasar_group())
+}
+
+#[test]
+fn test_base_tokens_need_a_mango_perp_market() {
+    let (result, quasar_group) = add_base_token(spl_token::id(), Pubkey::default());
+    assert!(is_quasar_error(result, QuasarErrorCode::InvalidToken));
+    assert_eq!(quasar_group.num_base_tokens, 0);
+
+    let (result, quasar_group) = add_base_token(spl_token::id(), Pubkey::new_unique());
+    result.unwrap();
+    assert_eq!(quasar_group.num_base_tokens, 1);
+    assert_eq!(
+        quasar_group.base_tokens[0].oracle_type,
+        OracleType::Pyth as u8
+    );
+}
+
+#[test]
+fn test_mint_and_burn_need_the_owner_to_sign() {
+    for (mut fixture, instruction) in vec![
+        (
+            mango_mint_fixture(1_000_000, 1_000_000, 1_000_000),
+            QuasarInstruction::MintLeverageToken { quantity: 100_000 },
+        ),
+        (
+            mango_burn_fixture(1_000_000, 1_000_000),
+            QuasarInstruction::BurnLeverageToken { quantity: 100_000 },
+        ),
+    ] {
+        fixture.accounts[6].is_signer = false;
+        let (result, run) =
+            process_stubbed(&mut fixture, instruction.clone(), Clock::default(), false);
+        assert!(is_quasar_error(result, QuasarErrorCode::SignerNecessary));
+        assert!(run.invoked.is_empty());
+
+        fixture.accounts[6].is_signer = true;
+        process_stubbed(&mut fixture, instruction, Clock::default(), false)
+            .0
+            .unwrap();
+    }
+}
+
+/// Lists a token on a Mango account that is already initialized, owned by the group signer
+/// or by someone else
+fn add_leverage_token_on_mango_account(owner_is_group_signer: bool) -> (QuasarResult, QuasarGroup) {
+    // the Mango accounts are where `AddLeverageToken` takes them
+    let mut fixture = mango_token_fixture(12, 0);
+    let mut quasar_group = new_quasar_group();
+    quasar_group.admin_key = fixture.accounts[10].key;
+    quasar_group.signer_key = fixture.accounts[11].key;
+    quasar_group.mango_program_id = fixture.accounts[3].key;
+    quasar_group.num_base_tokens = 1;
+    quasar_group.base_tokens[0].mint = fixture.accounts[2].key;
+    fixture.accounts[0].set_data(bytemuck::bytes_of(&quasar_group));
+    fixture.accounts[7].key = system_program::id();
+    fixture.accounts[8].key = spl_token::id();
+    fixture.accounts[9].key = sysvar::rent::id();
+
+    let mut mango_account: MangoAccount = *bytemuck::from_bytes(fixture.accounts[5].data());
+    mango_account.owner = if owner_is_group_signer {
+        quasar_group.signer_key
+    } else {
+        Pubkey::new_unique()
+    };
+    fixture.accounts[5].set_data(bytemuck::bytes_of(&mango_account));
+
+    let (result, _) = process_stubbed(
+        &mut fixture,
+        QuasarInstruction::AddLeverageToken {
+            target_leverage_bps: 20_000,
+            seed_quantity: 0,
+        },
+        Clock::default(),
+        false,
+    );
+    (result, fixture.quasar_group())
+}
+
+#[test]
+fn test_leverage_tokens_reject_a_foreign_mango_account() {
+    let (result, quasar_group) = add_leverage_token_on_mango_account(false);
+    assert!(is_quasar_error(result, QuasarErrorCode::InvalidOwner));
+    assert_eq!(quasar_group.num_leverage_tokens, 0);
+
+    let (result, quasar_group) = add_leverage_token_on_mango_account(true);
+    result.unwrap();
+    assert_eq!(quasar_group.num_leverage_tokens, 1);
+}
+
+#[test]
+fn test_stale_mango_cache_entries_are_rejected() {
+    let clock = Clock {
+        unix_timestamp: 105,
+        ..Clock::default()
+    };
+    for (mut fixture, instruction) in vec![
+        (mango_rebalance_fixture(), QuasarInstruction::Rebalance),
+        (
+            mango_burn_fixture(1_000_000, 1_000_000),
+            QuasarInstruction::BurnLeverageToken { quantity: 100_000 },
+        ),
+    ] {
+        // Mango takes cache entries up to 10 seconds old, these were updated 5 seconds ago
+        let mut mango_group: MangoGroup = *bytemuck::from_bytes(fixture.accounts[4].data());
+        mango_group.valid_interval = 10;
+        fixture.accounts[4].set_data(bytemuck::bytes_of(&mango_group));
+        let mut mango_cache: MangoCache = *bytemuck::from_bytes(fixture.accounts[7].data());
+        mango_cache.price_cache[0].last_update = 100;
+        mango_cache.perp_market_cache[0].last_update = 100;
+        fixture.accounts[7].set_data(bytemuck::bytes_of(&mango_cache));
+        process_stubbed(&mut fixture, instruction.clone(), clock.clone(), false)
+            .0
+            .unwrap();
+
+        // the base price alone falling behind is enough to stop it
+        mango_cache.price_cache[0].last_update = 94;
+        fixture.accounts[7].set_data(bytemuck::bytes_of(&mango_cache));
+        let (result, _) = process_stubbed(&mut fixture, instruction, clock.clone(), false);
+        assert!(is_quasar_error(result, QuasarErrorCode::StaleOracle));
+    }
+}
+
+#[test]
+fn test_near_zero_nav_rounds_against_minters_and_burners() {
+    // a whole token is backed by a single native quote, a native unit by a millionth of one
+    let mint = |min_native_price: I80F48, quantity| {
+        let mut fixture = mango_mint_fixture(1_000_000, 1, 1_000_000);
+        let mut quasar_group = fixture.quasar_group();
+        quasar_group.leverage_tokens[0].min_native_price = min_native_price;
+        fixture.accounts[0].set_data(bytemuck::bytes_of(&quasar_group));
+        process_stubbed(
+            &mut fixture,
+            QuasarInstruction::MintLeverageToken { quantity },
+            Clock::default(),
+            false,
+        )
+    };
+
+    // below the floor nothing is minted
+    let (result, run) = mint(I80F48::from_num(0.001), 1);
+    assert!(is_quasar_error(result, QuasarErrorCode::NavBelowMinimum));
+    assert!(run.invoked.is_empty());
+
+    // without one, a native unit still costs a whole native quote
+    let (result, run) = mint(I80F48::ZERO, 1);
+    result.unwrap();
+    assert!(run.invoked.iter().any(|instruction| matches!(
+        MangoInstruction::unpack(&instruction.data),
+        Some(MangoInstruction::Deposit { quantity: 1 })
+    )));
+
+    // and burning all but one native unit of the supply redeems nothing
+    let (withdrawal, _) = burn_withdrawal(&mut mango_burn_fixture(1_000_000, 1), 999_999);
+    assert!(matches!(
+        MangoInstruction::unpack(&withdrawal.data),
+        Some(MangoInstruction::Withdraw { quantity: 0, .. })
+    ));
+}
+
+#[test]
+fn test_base_token_mints_of_another_token_program_are_rejected() {
+    let token_2022 = Pubkey::from_str("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb").unwrap();
+    for mint_owner in vec![token_2022, Pubkey::new_unique()] {
+        let (result, quasar_group) = add_base_token(mint_owner, Pubkey::new_unique());
+        assert!(is_quasar_error(result, QuasarErrorCode::InvalidToken));
+        assert_eq!(quasar_group.num_base_tokens, 0);
+    }
+}
+
+#[test]
+fn test_closing_a_group_returns_its_rent_to_the_recipient() {
+    // the fee vault holds `fees` when given, the group has none otherwise
+    let close = |mut quasar_group: QuasarGroup, fees: Option<u64>| {
+        let mut fixture = Fixture::new(4);
+        quasar_group.admin_key = fixture.accounts[2].key;
+        if let Some(fees) = fees {
+            quasar_group.fee_vault = fixture.accounts[3].key;
+            fixture.accounts[3] = TestAccount {
+                key: quasar_group.fee_vault,
+                ..TestAccount::owned_by(
+                    spl_token::id(),
+                    &token_account_data(quasar_group.quote_mint, quasar_group.signer_key, fees),
+                )
+            };
+        }
+        fixture.accounts[0] =
+            TestAccount::owned_by(fixture.program_id, bytemuck::bytes_of(&quasar_group));
+        fixture.accounts[0].lamports = 150_000_000;
+        fixture.accounts[1].lamports = 5;
+        let result = fixture.process(QuasarInstruction::CloseQuasarGroup);
+        (result, fixture)
+    };
+
+    // a group still listing a token or holding fees keeps its rent
+    for ((result, fixture), error) in vec![
+        (
+            close(group_listing(Pubkey::new_unique()), None),
+            QuasarErrorCode::InvalidAccount,
+        ),
+        (
+            close(new_quasar_group(), Some(1)),
+            QuasarErrorCode::FeesNotWithdrawn,
+        ),
+    ] {
+        assert!(is_quasar_error(result, error));
+        assert_eq!(fixture.accounts[0].lamports, 150_000_000);
+        assert_eq!(fixture.accounts[1].lamports, 5);
+    }
+
+    for fees in vec![None, Some(0)] {
+        let (result, fixture) = close(new_quasar_group(), fees);
+        result.unwrap();
+        assert_eq!(fixture.accounts[0].lamports, 0);
+        assert_eq!(fixture.accounts[1].lamports, 150_000_005);
+        assert!(!fixture.quasar_group().meta_data.is_initialized);
+    }
+}
+
+/// Sets a stub quote oracle pricing the quote at `quote_price` reference units on the group
+/// of `fixture`, returning the oracle account
+fn set_stub_quote_oracle(fixture: &mut Fixture, quote_price: f64) -> TestAccount {
+    let oracle = StubOracle {
+        magic: STUB_MAGIC,
+        expo: 0,
+        price: I80F48::from_num(quote_price),
+        last_update: 0,
+    };
+    let oracle_account = fixture.program_account(bytemuck::bytes_of(&oracle));
+    let mut quasar_group = fixture.quasar_group();
+    quasar_group.quote_oracle = oracle_account.key;
+    fixture.accounts[0].set_data(bytemuck::bytes_of(&quasar_group));
+    oracle_account
+}
+
+#[test]
+fn test_depegged_quote_prices_the_token_in_the_reference_unit() {
+    // the view takes the quote oracle after the accounts of every view
+    let reference_price = |supply, quote, quote_price: Option<f64>| {
+        let mut fixture = mango_burn_fixture(supply, quote);
+        let oracle_account = match quote_price {
+            Some(quote_price) => set_stub_quote_oracle(&mut fixture, quote_price),
+            None => TestAccount::blank(),
+        };
+        let mut quote_fixture = view_fixture(&fixture);
+        quote_fixture.accounts.push(oracle_account);
+        let (result, run) = process_stubbed(
+            &mut quote_fixture,
+            QuasarInstruction::QuoteReferencePrice,
+            Clock::default(),
+            false,
+        );
+        result.unwrap();
+        I80F48::from_le_bytes(logged_return_data(&run)[..16].try_into().unwrap())
+    };
+
+    // 2 tokens backed by 2 quote are worth a quote each, half a reference unit at a depeg
+    // to 0.5, while a pegged quote takes any account
+    assert_eq!(reference_price(2_000_000, 2_000_000, None), I80F48::ONE);
+    assert_eq!(
+        reference_price(2_000_000, 2_000_000, Some(0.5)),
+        I80F48::from_num(0.5)
+    );
+    // before the first mint the token is worth one reference unit whatever the quote trades at
+    assert_eq!(reference_price(0, 0, Some(0.5)), I80F48::ONE);
+}
+
+#[test]
+fn test_reference_price_rejects_a_cache_of_another_mango_group() {
+    let fixture = mango_burn_fixture(2_000_000, 2_000_000);
+    let mut quote_fixture = view_fixture(&fixture);
+    quote_fixture.accounts[5].key = Pubkey::new_unique();
+    quote_fixture.accounts.push(TestAccount::blank());
+
+    let (result, _) = process_stubbed(
+        &mut quote_fixture,
+        QuasarInstruction::QuoteReferencePrice,
+        Clock::default(),
+        false,
+    );
+    assert!(is_quasar_error(result, QuasarErrorCode::InvalidAccount));
+}
+
+#[test]
+fn test_token_ledger_counts_the_outstanding_supply_in_the_reference_unit() {
+    // returns the net deposits of the ledger and of the capped group
+    let init_ledger = |quote_price: Option<f64>| {
+        // 2 tokens backed by 2 quote
+        let mut fixture = mango_burn_fixture(2_000_000, 2_000_000);
+        let oracle_account = quote_price.map(|price| set_stub_quote_oracle(&mut fixture, price));
+        let admin = Pubkey::new_unique();
+        let mut quasar_group = fixture.quasar_group();
+        quasar_group.admin_key = admin;
+        quasar_group.max_net_deposits = u64::MAX;
+        fixture.accounts[0].set_data(bytemuck::bytes_of(&quasar_group));
+        let mut token_ledger = fixture.program_account(&[0u8; size_of::<TokenLedger>()]);
+        token_ledger.is_writable = true;
+        token_ledger.lamports = Rent::default().minimum_balance(size_of::<TokenLedger>());
+
+        let mut ledger_fixture = Fixture {
+            program_id: fixture.program_id,
+            accounts: vec![
+                fixture.accounts[0].clone(),
+                fixture.accounts[1].clone(),
+                token_ledger,
+                TestAccount {
+                    key: admin,
+                    ..TestAccount::blank()
+                },
+                fixture.accounts[3].clone(),
+                fixture.accounts[4].clone(),
+                fixture.accounts[5].clone(),
+                fixture.accounts[7].clone(),
+            ],
+        };
+        ledger_fixture.accounts.extend(oracle_account);
+        let (result, _) = process_stubbed(
+            &mut ledger_fixture,
+            QuasarInstruction::InitTokenLedger,
+            Clock::default(),
+            false,
+        );
+        result.unwrap();
+        let token_ledger: TokenLedger = *bytemuck::from_bytes(ledger_fixture.accounts[2].data());
+        (
+            token_ledger.net_deposits,
+            ledger_fixture.quasar_group().total_net_deposits,
+        )
+    };
+
+    // the supply counts at its NAV, worth half as much in the reference unit at a depeg to 0.5
+    assert_eq!(init_ledger(None), (2_000_000, 2_000_000));
+    assert_eq!(init_ledger(Some(0.5)), (1_000_000, 1_000_000));
+}
+
+#[test]
+fn test_depegged_quote_only_moves_the_first_mint_price() {
+    let deposit = |supply, quote| {
+        let mut fixture = mango_mint_fixture(supply, quote, 10_000_000);
+        let oracle_account = set_stub_quote_oracle(&mut fixture, 0.5);
+        fixture.accounts.push(oracle_account);
+        let (result, run) = process_stubbed(
+            &mut fixture,
+            QuasarInstruction::MintLeverageToken {
+                quantity: 1_000_000,
+            },
+            Clock::default(),
+            false,
+        );
+        result.unwrap();
+        run.invoked
+            .iter()
+            .find_map(
+                |instruction| match MangoInstruction::unpack(&instruction.data) {
+                    Some(MangoInstruction::Deposit { quantity }) => Some(quantity),
+                    _ => None,
+                },
+            )
+            .unwrap()
+    };
+
+    // the first token costs one reference unit, two quote at a depeg to 0.5
+    assert_eq!(deposit(0, 0), 2_000_000);
+    // later ones settle at the NAV in quote, a quote each here
+    assert_eq!(deposit(1_000_000, 1_000_000), 1_000_000);
+}
@@ -0,0 +1,1204 @@
+#![allow(dead_code)]
+
+use std::mem::size_of;
+
+use bytemuck::{bytes_of, bytes_of_mut, Zeroable};
+use fixed::types::I80F48;
+use mango::{
+    matching::BookSide,
+    queue::{AnyEvent, EventQueueHeader},
+    state::{
+        DataType, MangoAccount, MangoCache, MangoGroup, MetaData, NodeBank, PerpMarket, RootBank,
+        MAX_PAIRS, QUOTE_INDEX,
+    },
+};
+use solana_program::{
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
+    program_pack::Pack,
+    pubkey::Pubkey,
+    rent::Rent,
+    system_instruction,
+};
+use solana_program_test::{processor, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    account::Account,
+    signature::{Keypair, Signer},
+    transaction::Transaction,
+    transport::TransportError,
+};
+use spl_associated_token_account::{create_associated_token_account, get_associated_token_address};
+use spl_token::state::{Account as TokenAccount, AccountState, Mint};
+
+use quasar::{
+    instruction::{
+        add_base_token, add_base_tokens, add_leverage_token, burn_leverage_token,
+        cancel_rebalance_orders, init_quasar_group, mint_leverage_token, rebalance,
+        remove_base_token, remove_leverage_token, set_fees, set_leverage_token_params,
+        set_token_state, set_treasury, AddLeverageTokenKeys, LeverageTokenParams,
+        LeverageTokenSeedKeys, MintBurnKeys, PerpBookKeys, RebalanceKeys, TokenKeys,
+    },
+    oracle::StubOracle,
+    processor::Processor,
+    state::{LeverageToken, QuasarGroup, TokenState},
+    utils::{governance_program, group_signer, leverage_to_bps, simulate_init},
+};
+
+pub const BASE_DECIMALS: u8 = 9;
+pub const QUOTE_DECIMALS: u8 = 6;
+/// Native quote per native base the Mango cache prices every base at, 100 quote per base
+pub const BASE_NATIVE_PRICE: f64 = 0.1;
+/// Timestamp the Mango cache entries are stamped with, far enough in the future that Mango
+/// and quasar take them as fresh for the whole test without refreshing the cache
+const CACHE_LAST_UPDATE: u64 = 4_000_000_000;
+
+fn process_quasar(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    Processor::process(program_id, accounts, data).map_err(|e| e.into())
+}
+
+/// Stands in for the SPL governance program, which only has to own the admin account
+fn process_governance(
+    _program_id: &Pubkey,
+    _accounts: &[AccountInfo],
+    _data: &[u8],
+) -> ProgramResult {
+    Ok(())
+}
+
+fn process_mango(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    mango::processor::Processor::process(program_id, accounts, data).map_err(|e| e.into())
+}
+
+/// A Mango group written straight into the genesis accounts, listing a perp market for
+/// every base mint and banking quote in `quote_root_bank`. This is all InitMangoAccount,
+/// deposits, withdrawals, perp orders and the quasar checks need
+///
+/// Mango state a test needs, such as the positions of a Mango account, has to be written
+/// into the genesis accounts as well: the runtime only lets Mango write its accounts, so no
+/// quasar instruction, test-only or not, can set them. See `MangoPositions`
+fn mango_group_account(
+    mango_program_id: &Pubkey,
+    mango_group: &Pubkey,
+    mango_cache: &Pubkey,
+    quote_mint: &Pubkey,
+    quote_root_bank: &Pubkey,
+    base_mints: &[Pubkey],
+    perp_markets: &[Pubkey],
+) -> Account {
+    let (signer_key, signer_nonce) = simulate_init(mango_group, mango_program_id);
+    let mut mango_group = MangoGroup::zeroed();
+    mango_group.meta_data = MetaData::new(DataType::MangoGroup, 0, true);
+    mango_group.mango_cache = *mango_cache;
+    mango_group.num_oracles = base_mints.len();
+    mango_group.valid_interval = 10;
+    mango_group.signer_key = signer_key;
+    mango_group.signer_nonce = signer_nonce;
+
+    for (i, mint) in base_mints.iter().enumerate() {
+        mango_group.tokens[i].mint = *mint;
+        mango_group.tokens[i].decimals = BASE_DECIMALS;
+        let spot_market_info = &mut mango_group.spot_markets[i];
+        spot_market_info.maint_asset_weight = I80F48::from_num(0.9);
+        spot_market_info.init_asset_weight = I80F48::from_num(0.8);
+        spot_market_info.maint_liab_weight = I80F48::from_num(1.1);
+        spot_market_info.init_liab_weight = I80F48::from_num(1.2);
+        let perp_market_info = &mut mango_group.perp_markets[i];
+        perp_market_info.perp_market = perp_markets[i];
+        perp_market_info.base_lot_size = 100;
+        perp_market_info.quote_lot_size = 10;
+        perp_market_info.maint_asset_weight = I80F48::from_num(0.95);
+        perp_market_info.init_asset_weight = I80F48::from_num(0.9);
+        perp_market_info.maint_liab_weight = I80F48::from_num(1.05);
+        perp_market_info.init_liab_weight = I80F48::from_num(1.1);
+    }
+    mango_group.tokens[QUOTE_INDEX].mint = *quote_mint;
+    mango_group.tokens[QUOTE_INDEX].decimals = QUOTE_DECIMALS;
+    mango_group.tokens[QUOTE_INDEX].root_bank = *quote_root_bank;
+
+    program_account(mango_program_id, bytes_of(&mango_group).to_vec())
+}
+
+/// A Mango cache of the group pricing every base at `BASE_NATIVE_PRICE`, with unit bank
+/// indexes and no funding. Every entry is stamped `CACHE_LAST_UPDATE`, so it never needs
+/// a refresh
+fn mango_cache_account(mango_program_id: &Pubkey, num_base_mints: usize) -> Account {
+    let mut mango_cache = MangoCache::zeroed();
+    mango_cache.meta_data = MetaData::new(DataType::MangoCache, 0, true);
+    for i in 0..num_base_mints {
+        mango_cache.price_cache[i].price = I80F48::from_num(BASE_NATIVE_PRICE);
+        mango_cache.price_cache[i].last_update = CACHE_LAST_UPDATE;
+        mango_cache.perp_market_cache[i].last_update = CACHE_LAST_UPDATE;
+    }
+    for root_bank_cache in mango_cache.root_bank_cache.iter_mut() {
+        root_bank_cache.deposit_index = I80F48::ONE;
+        root_bank_cache.borrow_index = I80F48::ONE;
+        root_bank_cache.last_update = CACHE_LAST_UPDATE;
+    }
+
+    program_account(mango_program_id, bytes_of(&mango_cache).to_vec())
+}
+
+/// Root bank with the single node bank `node_bank`, at unit indexes
+fn root_bank_account(mango_program_id: &Pubkey, node_bank: &Pubkey) -> Account {
+    let mut root_bank = RootBank::zeroed();
+    root_bank.meta_data = MetaData::new(DataType::RootBank, 0, true);
+    root_bank.num_node_banks = 1;
+    root_bank.node_banks[0] = *node_bank;
+    root_bank.deposit_index = I80F48::ONE;
+    root_bank.borrow_index = I80F48::ONE;
+
+    program_account(mango_program_id, bytes_of(&root_bank).to_vec())
+}
+
+/// Node bank keeping its tokens in `vault`, of which `deposits` are deposited
+fn node_bank_account(mango_program_id: &Pubkey, vault: &Pubkey, deposits: u64) -> Account {
+    let mut node_bank = NodeBank::zeroed();
+    node_bank.meta_data = MetaData::new(DataType::NodeBank, 0, true);
+    node_bank.deposits = I80F48::from_num(deposits);
+    node_bank.vault = *vault;
+
+    program_account(mango_program_id, bytes_of(&node_bank).to_vec())
+}
+
+/// Perp market of the group with the lot sizes `mango_group_account` lists it with
+fn perp_market_account(
+    mango_program_id: &Pubkey,
+    mango_group: &Pubkey,
+    book: &PerpBookKeys,
+    event_queue: &Pubkey,
+) -> Account {
+    let mut perp_market = PerpMarket::zeroed();
+    perp_market.meta_data = MetaData::new(DataType::PerpMarket, 0, true);
+    perp_market.mango_group = *mango_group;
+    perp_market.bids = book.bids;
+    perp_market.asks = book.asks;
+    perp_market.event_queue = *event_queue;
+    perp_market.base_lot_size = 100;
+    perp_market.quote_lot_size = 10;
+    perp_market.long_funding = I80F48::ZERO;
+    perp_market.short_funding = I80F48::ZERO;
+
+    program_account(mango_program_id, bytes_of(&perp_market).to_vec())
+}
+
+/// Empty side of a perp order book
+fn book_side_account(mango_program_id: &Pubkey, data_type: DataType) -> Account {
+    let mut book_side = BookSide::zeroed();
+    book_side.meta_data = MetaData::new(data_type, 0, true);
+
+    program_account(mango_program_id, bytes_of(&book_side).to_vec())
+}
+
+/// Empty event queue with room for `EVENT_QUEUE_LEN` events
+fn event_queue_account(mango_program_id: &Pubkey) -> Account {
+    const EVENT_QUEUE_LEN: usize = 32;
+    let mut header = EventQueueHeader::zeroed();
+    header.meta_data = MetaData::new(DataType::EventQueue, 0, true);
+    let mut data = bytes_of(&header).to_vec();
+    data.resize(
+        size_of::<EventQueueHeader>() + EVENT_QUEUE_LEN * size_of::<AnyEvent>(),
+        0,
+    );
+
+    program_account(mango_program_id, data)
+}
+
+/// Positions a Mango account starts with, on the market of the first base mint
+///
+/// Rebalances only trade off what the Mango account holds, which a test can't change through
+/// Mango without a counterparty filling its orders. Seeding the account instead lets a test
+/// put a token at any leverage before its first rebalance
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MangoPositions {
+    /// Native quote deposited
+    pub quote_deposit: u64,
+    /// Native base deposited as spot collateral
+    pub base_deposit: u64,
+    /// Perp position in base lots
+    pub perp_base_position: i64,
+    /// Native quote paid (negative) or received for the perp position
+    pub perp_quote_position: i64,
+}
+
+impl MangoPositions {
+    /// Positions worth `nav` native quote at `leverage` times exposure, all held on the perp
+    /// market at `BASE_NATIVE_PRICE`
+    pub fn perp_at_leverage(nav: u64, leverage: i64) -> Self {
+        // a base lot of 100 native base is worth 10 native quote
+        let exposure = nav as i64 * leverage;
+        Self {
+            quote_deposit: nav,
+            perp_base_position: exposure / 10,
+            perp_quote_position: -exposure,
+            ..Self::default()
+        }
+    }
+
+    /// Positions worth `nav` native quote, of which `spot_value` is held as base deposited
+    /// at `BASE_NATIVE_PRICE`
+    pub fn spot(nav: u64, spot_value: u64) -> Self {
+        Self {
+            quote_deposit: nav - spot_value,
+            base_deposit: spot_value * 10,
+            ..Self::default()
+        }
+    }
+}
+
+/// Mango account of the group owned by `owner`, holding `positions`
+fn mango_account_account(
+    mango_program_id: &Pubkey,
+    mango_group: &Pubkey,
+    owner: &Pubkey,
+    positions: &MangoPositions,
+) -> Account {
+    let mut mango_account = MangoAccount::zeroed();
+    mango_account.meta_data = MetaData::new(DataType::MangoAccount, 0, true);
+    mango_account.mango_group = *mango_group;
+    mango_account.owner = *owner;
+    mango_account.deposits[QUOTE_INDEX] = I80F48::from_num(positions.quote_deposit);
+    mango_account.deposits[0] = I80F48::from_num(positions.base_deposit);
+    let perp_account = &mut mango_account.perp_accounts[0];
+    perp_account.base_position = positions.perp_base_position;
+    perp_account.quote_position = I80F48::from_num(positions.perp_quote_position);
+
+    program_account(mango_program_id, bytes_of(&mango_account).to_vec())
+}
+
+/// Initialized token account of `mint` held by `owner`
+fn token_account(mint: &Pubkey, owner: &Pubkey, amount: u64) -> Account {
+    let mut data = vec![0u8; TokenAccount::LEN];
+    TokenAccount {
+        mint: *mint,
+        owner: *owner,
+        amount,
+        state: AccountState::Initialized,
+        ..TokenAccount::default()
+    }
+    .pack_into_slice(&mut data);
+
+    program_account(&spl_token::id(), data)
+}
+
+fn program_account(owner: &Pubkey, data: Vec<u8>) -> Account {
+    Account {
+        lamports: Rent::default().minimum_balance(data.len()),
+        data,
+        owner: *owner,
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+pub struct QuasarProgramTest {
+    pub context: ProgramTestContext,
+    pub quasar_program_id: Pubkey,
+    pub mango_program_id: Pubkey,
+    pub mango_group: Pubkey,
+    pub mango_cache: Pubkey,
+    pub quote_mint: Keypair,
+    pub base_mints: Vec<Keypair>,
+    /// Perp markets the Mango group lists for the base mints
+    pub perp_markets: Vec<Pubkey>,
+    /// Order books and event queues of `perp_markets`
+    pub perp_books: Vec<PerpBookKeys>,
+    pub event_queues: Vec<Pubkey>,
+    /// Mango accounts of the group seeded with positions, owned by the signer of the quasar
+    /// group `init_quasar_group` creates
+    pub seeded_mango_accounts: Vec<Pubkey>,
+    /// Signer of the Mango group, owning its vaults
+    pub mango_signer: Pubkey,
+    /// Quote bank of the Mango group, which mints deposit to and burns withdraw from
+    pub quote_root_bank: Pubkey,
+    pub quote_node_bank: Pubkey,
+    pub quote_vault: Pubkey,
+    /// Another Mango group listing the same base mints, quoted in `second_quote_mint`
+    pub second_mango_group: Pubkey,
+    pub second_mango_cache: Pubkey,
+    pub second_quote_mint: Keypair,
+    pub quasar_group: Pubkey,
+    pub signer_nonce: u64,
+    pub signer_key: Pubkey,
+    /// Account `init_quasar_group` creates the group in, chosen up front so that seeded
+    /// Mango accounts can be owned by its signer
+    quasar_group_keypair: Option<Keypair>,
+}
+
+impl QuasarProgramTest {
+    pub async fn start_new(num_base_mints: usize) -> Self {
+        Self::start_with_mango_accounts(num_base_mints, &[]).await
+    }
+
+    /// Starts with a Mango account of the primary group holding each of `positions`, in
+    /// `seeded_mango_accounts`. The quote deposits are banked in the quote bank
+    pub async fn start_with_mango_accounts(
+        num_base_mints: usize,
+        positions: &[MangoPositions],
+    ) -> Self {
+        let quasar_program_id = Pubkey::new_unique();
+        let mango_program_id = Pubkey::new_unique();
+        let mango_group = Pubkey::new_unique();
+        let mango_cache = Pubkey::new_unique();
+        let quote_mint = Keypair::new();
+        let base_mints: Vec<Keypair> = (0..num_base_mints).map(|_| Keypair::new()).collect();
+        let perp_markets: Vec<Pubkey> = (0..num_base_mints).map(|_| Pubkey::new_unique()).collect();
+        let second_mango_group = Pubkey::new_unique();
+        let second_mango_cache = Pubkey::new_unique();
+        let second_quote_mint = Keypair::new();
+        let second_perp_markets: Vec<Pubkey> =
+            (0..num_base_mints).map(|_| Pubkey::new_unique()).collect();
+        let (mango_signer, _) = simulate_init(&mango_group, &mango_program_id);
+        let quote_root_bank = Pubkey::new_unique();
+        let quote_node_bank = Pubkey::new_unique();
+        let quote_vault = Pubkey::new_unique();
+        let base_mint_keys: Vec<Pubkey> = base_mints.iter().map(|kp| kp.pubkey()).collect();
+        let perp_books: Vec<PerpBookKeys> = perp_markets
+            .iter()
+            .map(|&perp_market| PerpBookKeys {
+                perp_market,
+                bids: Pubkey::new_unique(),
+                asks: Pubkey::new_unique(),
+            })
+            .collect();
+        let event_queues: Vec<Pubkey> = (0..num_base_mints).map(|_| Pubkey::new_unique()).collect();
+        let quasar_group_keypair = Keypair::new();
+        let (quasar_signer, _) = simulate_init(&quasar_group_keypair.pubkey(), &quasar_program_id);
+        let seeded_mango_accounts: Vec<Pubkey> =
+            positions.iter().map(|_| Pubkey::new_unique()).collect();
+        let quote_deposits: u64 = positions.iter().map(|p| p.quote_deposit).sum();
+
+        let mut test = ProgramTest::new("quasar", quasar_program_id, processor!(process_quasar));
+        test.add_program("mango", mango_program_id, processor!(process_mango));
+        test.add_program(
+            "governance",
+            governance_program::id(),
+            processor!(process_governance),
+        );
+        test.add_account(
+            mango_group,
+            mango_group_account(
+                &mango_program_id,
+                &mango_group,
+                &mango_cache,
+                &quote_mint.pubkey(),
+                &quote_root_bank,
+                &base_mint_keys,
+                &perp_markets,
+            ),
+        );
+        test.add_account(
+            mango_cache,
+            mango_cache_account(&mango_program_id, num_base_mints),
+        );
+        test.add_account(
+            quote_root_bank,
+            root_bank_account(&mango_program_id, &quote_node_bank),
+        );
+        test.add_account(
+            quote_node_bank,
+            node_bank_account(&mango_program_id, &quote_vault, quote_deposits),
+        );
+        test.add_account(
+            quote_vault,
+            token_account(&quote_mint.pubkey(), &mango_signer, quote_deposits),
+        );
+        for (book, event_queue) in perp_books.iter().zip(event_queues.iter()) {
+            test.add_account(
+                book.perp_market,
+                perp_market_account(&mango_program_id, &mango_group, book, event_queue),
+            );
+            test.add_account(
+                book.bids,
+                book_side_account(&mango_program_id, DataType::Bids),
+            );
+            test.add_account(
+                book.asks,
+                book_side_account(&mango_program_id, DataType::Asks),
+            );
+            test.add_account(*event_queue, event_queue_account(&mango_program_id));
+        }
+        for (mango_account, positions) in seeded_mango_accounts.iter().zip(positions.iter()) {
+            test.add_account(
+                *mango_account,
+                mango_account_account(&mango_program_id, &mango_group, &quasar_signer, positions),
+            );
+        }
+        // Only lists the tokens, it banks nothing
+        test.add_account(
+            second_mango_group,
+            mango_group_account(
+                &mango_program_id,
+                &second_mango_group,
+                &second_mango_cache,
+                &second_quote_mint.pubkey(),
+                &Pubkey::new_unique(),
+                &base_mint_keys,
+                &second_perp_markets,
+            ),
+        );
+        test.add_account(
+            second_mango_cache,
+            mango_cache_account(&mango_program_id, num_base_mints),
+        );
+
+        let context = test.start_with_context().await;
+        let mut program_test = Self {
+            context,
+            quasar_program_id,
+            mango_program_id,
+            mango_group,
+            mango_cache,
+            quote_mint,
+            base_mints,
+            perp_markets,
+            perp_books,
+            event_queues,
+            seeded_mango_accounts,
+            mango_signer,
+            quote_root_bank,
+            quote_node_bank,
+            quote_vault,
+            second_mango_group,
+            second_mango_cache,
+            second_quote_mint,
+            quasar_group: Pubkey::default(),
+            signer_nonce: 0,
+            signer_key: Pubkey::default(),
+            quasar_group_keypair: Some(quasar_group_keypair),
+        };
+
+        let quote_mint = clone_keypair(&program_test.quote_mint);
+        program_test.create_mint(&quote_mint, QUOTE_DECIMALS).await;
+        let second_quote_mint = clone_keypair(&program_test.second_quote_mint);
+        program_test
+            .create_mint(&second_quote_mint, QUOTE_DECIMALS)
+            .await;
+        for i in 0..num_base_mints {
+            let base_mint = clone_keypair(&program_test.base_mints[i]);
+            program_test.create_mint(&base_mint, BASE_DECIMALS).await;
+        }
+
+        program_test
+    }
+
+    pub fn admin(&self) -> Pubkey {
+        self.context.payer.pubkey()
+    }
+
+    pub async fn process_transaction(
+        &mut self,
+        instructions: &[Instruction],
+        signers: &[&Keypair],
+    ) -> Result<(), TransportError> {
+        let mut transaction =
+            Transaction::new_with_payer(instructions, Some(&self.context.payer.pubkey()));
+
+        let mut all_signers = vec![&self.context.payer];
+        all_signers.extend_from_slice(signers);
+        transaction.sign(&all_signers, self.context.last_blockhash);
+
+        self.context
+            .banks_client
+            .process_transaction(transaction)
+            .await
+    }
+
+    /// Moves on to a new blockhash, so that a transaction sent before can be sent again
+    pub async fn refresh_blockhash(&mut self) {
+        let (blockhash, _) = self
+            .context
+            .banks_client
+            .get_new_blockhash(&self.context.last_blockhash)
+            .await
+            .unwrap();
+        self.context.last_blockhash = blockhash;
+    }
+
+    pub async fn create_account(&mut self, space: usize, owner: &Pubkey) -> Pubkey {
+        self.create_account_with_keypair(Keypair::new(), space, owner)
+            .await
+    }
+
+    pub async fn create_account_with_keypair(
+        &mut self,
+        keypair: Keypair,
+        space: usize,
+        owner: &Pubkey,
+    ) -> Pubkey {
+        let instruction = system_instruction::create_account(
+            &self.context.payer.pubkey(),
+            &keypair.pubkey(),
+            Rent::default().minimum_balance(space),
+            space as u64,
+            owner,
+        );
+
+        self.process_transaction(&[instruction], &[&keypair])
+            .await
+            .unwrap();
+        keypair.pubkey()
+    }
+
+    pub async fn create_mint(&mut self, mint: &Keypair, decimals: u8) {
+        let payer = self.context.payer.pubkey();
+        let instructions = [
+            system_instruction::create_account(
+                &payer,
+                &mint.pubkey(),
+                Rent::default().minimum_balance(Mint::LEN),
+                Mint::LEN as u64,
+                &spl_token::id(),
+            ),
+            spl_token::instruction::initialize_mint(
+                &spl_token::id(),
+                &mint.pubkey(),
+                &payer,
+                None,
+                decimals,
+            )
+            .unwrap(),
+        ];
+
+        self.process_transaction(&instructions, &[mint])
+            .await
+            .unwrap();
+    }
+
+    pub async fn create_token_account(&mut self, mint: &Pubkey, owner: &Pubkey) -> Pubkey {
+        let payer = self.context.payer.pubkey();
+        let account = Keypair::new();
+        let instructions = [
+            system_instruction::create_account(
+                &payer,
+                &account.pubkey(),
+                Rent::default().minimum_balance(TokenAccount::LEN),
+                TokenAccount::LEN as u64,
+                &spl_token::id(),
+            ),
+            spl_token::instruction::initialize_account(
+                &spl_token::id(),
+                &account.pubkey(),
+                mint,
+                owner,
+            )
+            .unwrap(),
+        ];
+
+        self.process_transaction(&instructions, &[&account])
+            .await
+            .unwrap();
+        account.pubkey()
+    }
+
+    pub async fn get_account(&mut self, key: Pubkey) -> Account {
+        self.context
+            .banks_client
+            .get_account(key)
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    pub async fn get_rent(&mut self) -> Rent {
+        self.context.banks_client.get_rent().await.unwrap()
+    }
+
+    pub async fn load_quasar_group(&mut self) -> QuasarGroup {
+        let account = self
+            .context
+            .banks_client
+            .get_account(self.quasar_group)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let mut quasar_group = QuasarGroup::zeroed();
+        bytes_of_mut(&mut quasar_group).copy_from_slice(&account.data);
+        quasar_group
+    }
+
+    /// Inits a fresh group, in the account whose signer owns `seeded_mango_accounts` the
+    /// first time
+    pub async fn init_quasar_group(&mut self) -> Result<(), TransportError> {
+        let quasar_program_id = self.quasar_program_id;
+        let keypair = self
+            .quasar_group_keypair
+            .take()
+            .unwrap_or_else(Keypair::new);
+        self.quasar_group = self
+            .create_account_with_keypair(keypair, size_of::<QuasarGroup>(), &quasar_program_id)
+            .await;
+
+        let (signer_key, signer_nonce) = simulate_init(&self.quasar_group, &quasar_program_id);
+        self.signer_key = signer_key;
+        self.signer_nonce = signer_nonce;
+        self.send_init_quasar_group(signer_nonce).await
+    }
+
+    /// Inits a fresh group with the first nonce that doesn't derive a PDA, next to the
+    /// signer key of the valid one
+    pub async fn init_quasar_group_with_invalid_nonce(&mut self) -> Result<(), TransportError> {
+        let quasar_program_id = self.quasar_program_id;
+        self.quasar_group = self
+            .create_account(size_of::<QuasarGroup>(), &quasar_program_id)
+            .await;
+
+        let (signer_key, _) = simulate_init(&self.quasar_group, &quasar_program_id);
+        self.signer_key = signer_key;
+        let invalid_nonce = (0..u64::MAX)
+            .find(|&nonce| group_signer(&self.quasar_group, nonce, &quasar_program_id).is_err())
+            .unwrap();
+        self.send_init_quasar_group(invalid_nonce).await
+    }
+
+    pub async fn send_init_quasar_group(
+        &mut self,
+        signer_nonce: u64,
+    ) -> Result<(), TransportError> {
+        let quasar_program_id = self.quasar_program_id;
+        let signer_key = self.signer_key;
+        let instruction = init_quasar_group(
+            &quasar_program_id,
+            &self.quasar_group,
+            &signer_key,
+            &self.admin(),
+            &self.mango_program_id,
+            signer_nonce,
+        );
+        self.process_transaction(&[instruction], &[]).await
+    }
+
+    /// Inits a fresh group whose admin is an account owned by `owner`, which doesn't sign
+    /// and passes `governance_program` as the governance program, returning the admin
+    pub async fn init_quasar_group_with_governed_admin(
+        &mut self,
+        owner: Pubkey,
+        governance_program: Pubkey,
+    ) -> Result<Pubkey, TransportError> {
+        let quasar_program_id = self.quasar_program_id;
+        self.quasar_group = self
+            .create_account(size_of::<QuasarGroup>(), &quasar_program_id)
+            .await;
+        let admin = self.create_account(0, &owner).await;
+
+        let (signer_key, signer_nonce) = simulate_init(&self.quasar_group, &quasar_program_id);
+        self.signer_key = signer_key;
+        let mut instruction = init_quasar_group(
+            &quasar_program_id,
+            &self.quasar_group,
+            &signer_key,
+            &admin,
+            &self.mango_program_id,
+            signer_nonce,
+        );
+        instruction.accounts[2].is_signer = false;
+        instruction
+            .accounts
+            .push(AccountMeta::new_readonly(governance_program, false));
+        self.process_transaction(&[instruction], &[]).await?;
+
+        Ok(admin)
+    }
+
+    /// Adds the base mint at `index` with a fresh stub oracle, returning the oracle key
+    pub async fn add_base_token(&mut self, index: usize) -> Result<Pubkey, TransportError> {
+        let quasar_program_id = self.quasar_program_id;
+        let oracle = self
+            .create_account(size_of::<StubOracle>(), &quasar_program_id)
+            .await;
+
+        let instruction = add_base_token(
+            &quasar_program_id,
+            &self.quasar_group,
+            &self.base_mints[index].pubkey(),
+            &oracle,
+            &self.admin(),
+            &self.mango_group,
+        );
+        self.process_transaction(&[instruction], &[]).await?;
+
+        Ok(oracle)
+    }
+
+    /// Adds the base mints at `indices` in one instruction, each with a fresh stub oracle,
+    /// returning the oracle keys
+    pub async fn add_base_tokens(
+        &mut self,
+        indices: &[usize],
+    ) -> Result<Vec<Pubkey>, TransportError> {
+        let quasar_program_id = self.quasar_program_id;
+        let mut tokens = vec![];
+        for &index in indices {
+            let oracle = self
+                .create_account(size_of::<StubOracle>(), &quasar_program_id)
+                .await;
+            tokens.push((self.base_mints[index].pubkey(), oracle));
+        }
+
+        let instruction = add_base_tokens(
+            &quasar_program_id,
+            &self.quasar_group,
+            &self.admin(),
+            &self.mango_group,
+            &tokens,
+        );
+        self.process_transaction(&[instruction], &[]).await?;
+
+        Ok(tokens.into_iter().map(|(_, oracle)| oracle).collect())
+    }
+
+    /// Adds a leverage token on the base mint at `index`, returning its mint and Mango account.
+    /// Its perp market isn't listed by the Mango group
+    pub async fn add_leverage_token(
+        &mut self,
+        index: usize,
+        target_leverage: I80F48,
+    ) -> Result<(Pubkey, Pubkey), TransportError> {
+        let mango_program_id = self.mango_program_id;
+        let mango_account = self
+            .create_account(size_of::<MangoAccount>(), &mango_program_id)
+            .await;
+        let mint = self
+            .add_leverage_token_with_mango_account(
+                index,
+                target_leverage,
+                mango_account,
+                Pubkey::new_unique(),
+            )
+            .await?;
+
+        Ok((mint, mango_account))
+    }
+
+    /// Adds a leverage token on the base mint at `index` trading its listed perp market from
+    /// the seeded Mango account `mango_account`, returning its mint
+    pub async fn add_leverage_token_with_seeded_account(
+        &mut self,
+        index: usize,
+        target_leverage: I80F48,
+        mango_account: Pubkey,
+    ) -> Result<Pubkey, TransportError> {
+        let perp_market = self.perp_markets[index];
+        self.add_leverage_token_with_mango_account(
+            index,
+            target_leverage,
+            mango_account,
+            perp_market,
+        )
+        .await
+    }
+
+    /// Adds a leverage token on the base mint at `index` trading `perp_market` from
+    /// `mango_account`, returning its mint
+    pub async fn add_leverage_token_with_mango_account(
+        &mut self,
+        index: usize,
+        target_leverage: I80F48,
+        mango_account: Pubkey,
+        perp_market: Pubkey,
+    ) -> Result<Pubkey, TransportError> {
+        let mango_group = self.mango_group;
+        let quote_mint = self.quote_mint.pubkey();
+        self.add_leverage_token_in_mango_group(
+            index,
+            target_leverage,
+            mango_group,
+            quote_mint,
+            mango_account,
+            perp_market,
+        )
+        .await
+    }
+
+    /// Adds a leverage token on the base mint at `index` trading `perp_market` from
+    /// `mango_account` of `mango_group`, quoted in `quote_mint`, returning its mint
+    pub async fn add_leverage_token_in_mango_group(
+        &mut self,
+        index: usize,
+        target_leverage: I80F48,
+        mango_group: Pubkey,
+        quote_mint: Pubkey,
+        mango_account: Pubkey,
+        perp_market: Pubkey,
+    ) -> Result<Pubkey, TransportError> {
+        self.send_add_leverage_token(
+            index,
+            target_leverage,
+            mango_group,
+            quote_mint,
+            mango_account,
+            perp_market,
+            None,
+        )
+        .await
+    }
+
+    /// Adds a leverage token on the base mint at `index` which the admin seeds with
+    /// `seed_quantity` tokens paid from `admin_quote`, returning its mint and Mango account
+    pub async fn add_seeded_leverage_token(
+        &mut self,
+        index: usize,
+        target_leverage: I80F48,
+        seed_quantity: u64,
+        admin_quote: Pubkey,
+    ) -> Result<(Pubkey, Pubkey), TransportError> {
+        let mango_program_id = self.mango_program_id;
+        let mango_account = self
+            .create_account(size_of::<MangoAccount>(), &mango_program_id)
+            .await;
+        let seed = LeverageTokenSeedKeys {
+            quantity: seed_quantity,
+            mango_cache: self.mango_cache,
+            quote_root_bank: self.quote_root_bank,
+            quote_node_bank: self.quote_node_bank,
+            quote_vault: self.quote_vault,
+            admin_quote_token_account: admin_quote,
+        };
+        let mango_group = self.mango_group;
+        let quote_mint = self.quote_mint.pubkey();
+        let mint = self
+            .send_add_leverage_token(
+                index,
+                target_leverage,
+                mango_group,
+                quote_mint,
+                mango_account,
+                Pubkey::new_unique(),
+                Some(&seed),
+            )
+            .await?;
+
+        Ok((mint, mango_account))
+    }
+
+    async fn send_add_leverage_token(
+        &mut self,
+        index: usize,
+        target_leverage: I80F48,
+        mango_group: Pubkey,
+        quote_mint: Pubkey,
+        mango_account: Pubkey,
+        perp_market: Pubkey,
+        seed: Option<&LeverageTokenSeedKeys>,
+    ) -> Result<Pubkey, TransportError> {
+        let mint = Keypair::new();
+        let quasar_group = self.load_quasar_group().await;
+        let keys = AddLeverageTokenKeys {
+            quasar_group: self.quasar_group,
+            mint: mint.pubkey(),
+            base_token_mint: self.base_mints[index].pubkey(),
+            mango_program: self.mango_program_id,
+            mango_group,
+            mango_account,
+            mango_perp_market: perp_market,
+            admin: self.admin(),
+            signer: self.signer_key,
+            quote_mint: if quote_mint == quasar_group.quote_mint {
+                None
+            } else {
+                Some(quote_mint)
+            },
+        };
+
+        let instruction = add_leverage_token(
+            &self.quasar_program_id,
+            &keys,
+            leverage_to_bps(target_leverage).unwrap(),
+            seed,
+        );
+        self.process_transaction(&[instruction], &[&mint]).await?;
+
+        Ok(mint.pubkey())
+    }
+
+    /// Removes the leverage token of `mint`, which must have no supply
+    pub async fn remove_leverage_token(
+        &mut self,
+        mint: Pubkey,
+        mango_account: Pubkey,
+    ) -> Result<(), TransportError> {
+        let instruction = remove_leverage_token(
+            &self.quasar_program_id,
+            &self.token_keys(mint, mango_account),
+            &self.admin(),
+            None,
+        );
+        self.process_transaction(&[instruction], &[]).await
+    }
+
+    /// Makes the group collect fees in a fresh signer-owned vault of the primary quote mint
+    /// and withdraw them to a fresh treasury of the admin
+    pub async fn set_treasury(&mut self) -> Result<(), TransportError> {
+        let quote_mint = self.quote_mint.pubkey();
+        let signer_key = self.signer_key;
+        let admin = self.admin();
+        let fee_vault = self.create_token_account(&quote_mint, &signer_key).await;
+        let treasury = self.create_token_account(&quote_mint, &admin).await;
+
+        let instruction = set_treasury(
+            &self.quasar_program_id,
+            &self.quasar_group,
+            &fee_vault,
+            &treasury,
+            &admin,
+        );
+        self.process_transaction(&[instruction], &[]).await
+    }
+
+    pub async fn set_fees(
+        &mut self,
+        mint_fee_bps: u16,
+        burn_fee_bps: u16,
+    ) -> Result<(), TransportError> {
+        let instruction = set_fees(
+            &self.quasar_program_id,
+            &self.quasar_group,
+            &self.admin(),
+            mint_fee_bps,
+            burn_fee_bps,
+        );
+        self.process_transaction(&[instruction], &[]).await
+    }
+
+    /// A fresh primary quote account of the admin holding `amount` native quote
+    pub async fn create_funded_quote_account(&mut self, amount: u64) -> Pubkey {
+        let quote_mint = self.quote_mint.pubkey();
+        let admin = self.admin();
+        let account = self.create_token_account(&quote_mint, &admin).await;
+        let instruction = spl_token::instruction::mint_to(
+            &spl_token::id(),
+            &quote_mint,
+            &account,
+            &admin,
+            &[],
+            amount,
+        )
+        .unwrap();
+        self.process_transaction(&[instruction], &[]).await.unwrap();
+        account
+    }
+
+    pub async fn get_token_balance(&mut self, account: Pubkey) -> u64 {
+        TokenAccount::unpack(&self.get_account(account).await.data)
+            .unwrap()
+            .amount
+    }
+
+    pub async fn load_mango_account(&mut self, mango_account: Pubkey) -> MangoAccount {
+        let account = self.get_account(mango_account).await;
+        let mut loaded = MangoAccount::zeroed();
+        bytes_of_mut(&mut loaded).copy_from_slice(&account.data);
+        loaded
+    }
+
+    /// Keys of mints and burns by the admin of the leverage token of `mint`, paying from
+    /// and to `owner_quote` through the quote bank of the primary Mango group
+    pub async fn mint_burn_keys(
+        &mut self,
+        mint: Pubkey,
+        mango_account: Pubkey,
+        owner_quote: Pubkey,
+    ) -> MintBurnKeys {
+        let quasar_group = self.load_quasar_group().await;
+        MintBurnKeys {
+            token: self.token_keys(mint, mango_account),
+            owner: self.admin(),
+            mango_cache: self.mango_cache,
+            root_bank: self.quote_root_bank,
+            node_bank: self.quote_node_bank,
+            vault: self.quote_vault,
+            owner_quote_token_account: owner_quote,
+            signer: self.signer_key,
+            fee_vault: quasar_group.fee_vault,
+            token_ledger: None,
+            group_capped: false,
+        }
+    }
+
+    /// Mints `quantity` of the leverage token of `mint` to the admin's associated token
+    /// account, creating it first if needed
+    pub async fn mint_leverage_token(
+        &mut self,
+        mint: Pubkey,
+        mango_account: Pubkey,
+        owner_quote: Pubkey,
+        quantity: u64,
+    ) -> Result<(), TransportError> {
+        let admin = self.admin();
+        let keys = self.mint_burn_keys(mint, mango_account, owner_quote).await;
+        let mut instructions = vec![];
+        let leverage_token_account = get_associated_token_address(&admin, &mint);
+        if self
+            .context
+            .banks_client
+            .get_account(leverage_token_account)
+            .await
+            .unwrap()
+            .is_none()
+        {
+            instructions.push(create_associated_token_account(&admin, &admin, &mint));
+        }
+        instructions.push(mint_leverage_token(
+            &self.quasar_program_id,
+            &keys,
+            None,
+            None,
+            quantity,
+        ));
+        self.process_transaction(&instructions, &[]).await
+    }
+
+    /// Burns `quantity` of the leverage token of `mint` from the admin's associated token
+    /// account, whose Mango account has no open orders
+    pub async fn burn_leverage_token(
+        &mut self,
+        mint: Pubkey,
+        mango_account: Pubkey,
+        owner_quote: Pubkey,
+        quantity: u64,
+    ) -> Result<(), TransportError> {
+        let keys = self.mint_burn_keys(mint, mango_account, owner_quote).await;
+        let instruction = burn_leverage_token(
+            &self.quasar_program_id,
+            &keys,
+            &self.mango_signer,
+            &[],
+            quantity,
+        );
+        self.process_transaction(&[instruction], &[]).await
+    }
+
+    /// Removes the base token of `base_mint`, which no leverage token may reference
+    pub async fn remove_base_token(&mut self, base_mint: Pubkey) -> Result<(), TransportError> {
+        let instruction = remove_base_token(
+            &self.quasar_program_id,
+            &self.quasar_group,
+            &base_mint,
+            &self.admin(),
+        );
+        self.process_transaction(&[instruction], &[]).await
+    }
+
+    pub async fn set_leverage_token_params(
+        &mut self,
+        mint: Pubkey,
+        params: &LeverageTokenParams,
+    ) -> Result<(), TransportError> {
+        let instruction = set_leverage_token_params(
+            &self.quasar_program_id,
+            &self.quasar_group,
+            &mint,
+            &self.admin(),
+            params,
+        );
+        self.process_transaction(&[instruction], &[]).await
+    }
+
+    pub async fn load_leverage_token(&mut self, mint: Pubkey) -> LeverageToken {
+        let quasar_group = self.load_quasar_group().await;
+        quasar_group
+            .leverage_tokens
+            .iter()
+            .find(|leverage_token| leverage_token.mint == mint)
+            .copied()
+            .unwrap()
+    }
+
+    /// Order book and event queue of `perp_market`, seeded when the Mango group lists it and
+    /// random accounts otherwise
+    fn perp_book(&self, perp_market: Pubkey) -> (PerpBookKeys, Pubkey) {
+        match self
+            .perp_markets
+            .iter()
+            .position(|&listed| listed == perp_market)
+        {
+            Some(index) => (self.perp_books[index], self.event_queues[index]),
+            None => (
+                PerpBookKeys {
+                    perp_market,
+                    bids: Pubkey::new_unique(),
+                    asks: Pubkey::new_unique(),
+                },
+                Pubkey::new_unique(),
+            ),
+        }
+    }
+
+    /// Rebalances the leverage token of `mint` as a permissionless keeper, on the seeded book
+    /// of its perp market when the Mango group lists it
+    pub async fn rebalance(&mut self, mint: Pubkey) -> Result<(), TransportError> {
+        let leverage_token = self.load_leverage_token(mint).await;
+        let (book, event_queue) = self.perp_book(leverage_token.mango_perp_market);
+        let keys = RebalanceKeys {
+            token: self.token_keys(mint, leverage_token.mango_account),
+            signer: self.signer_key,
+            caller: self.admin(),
+            caller_is_signer: false,
+            mango_cache: self.mango_cache,
+            book,
+            event_queue,
+        };
+
+        let instruction = rebalance(
+            &self.quasar_program_id,
+            &keys,
+            &[Pubkey::default(); MAX_PAIRS],
+            None,
+            None,
+            None,
+            None,
+        );
+        self.process_transaction(&[instruction], &[]).await
+    }
+
+    /// Cancels the resting rebalance orders of the leverage token of `mint` as the admin,
+    /// the keeper of a permissionless group
+    pub async fn cancel_rebalance_orders(&mut self, mint: Pubkey) -> Result<(), TransportError> {
+        let leverage_token = self.load_leverage_token(mint).await;
+        let (book, _) = self.perp_book(leverage_token.mango_perp_market);
+        let instruction = cancel_rebalance_orders(
+            &self.quasar_program_id,
+            &self.token_keys(mint, leverage_token.mango_account),
+            &self.signer_key,
+            &book,
+            &self.admin(),
+        );
+        self.process_transaction(&[instruction], &[]).await
+    }
+
+    /// Freezes or unfreezes the leverage token of `mint`, freezing cancels its resting
+    /// rebalance orders
+    pub async fn set_token_state(
+        &mut self,
+        mint: Pubkey,
+        state: TokenState,
+    ) -> Result<(), TransportError> {
+        let leverage_token = self.load_leverage_token(mint).await;
+        let (book, _) = self.perp_book(leverage_token.mango_perp_market);
+        let instruction = set_token_state(
+            &self.quasar_program_id,
+            &self.token_keys(mint, leverage_token.mango_account),
+            &self.signer_key,
+            &book,
+            &self.admin(),
+            state,
+        );
+        self.process_transaction(&[instruction], &[]).await
+    }
+
+    /// Keys of the leverage token of `mint` held in `mango_account` of the primary Mango group
+    pub fn token_keys(&self, mint: Pubkey, mango_account: Pubkey) -> TokenKeys {
+        TokenKeys {
+            quasar_group: self.quasar_group,
+            token_mint: mint,
+            mango_program: self.mango_program_id,
+            mango_group: self.mango_group,
+            mango_account,
+        }
+    }
+}
+
+pub fn clone_keypair(keypair: &Keypair) -> Keypair {
+    Keypair::from_bytes(&keypair.to_bytes()).unwrap()
+}
@@ -0,0 +1,849 @@
+use bytemuck::Zeroable;
+use fixed::types::I80F48;
+use quasar::{
+    error::{QuasarError, QuasarErrorCode},
+    oracle::{read_oracle_price, OracleType, StubOracle, STUB_MAGIC},
+    state::{
+        check_mint_price_band, initial_quote_amount, native_price_from_nav, net_deposit_amount,
+        BaseToken, DataType, LeverageToken, MetaData, QuasarGroup, RebalanceConfig,
+        RebalancePriceSource, RebalanceRecord, TokenLedger, TokenState,
+        LEGACY_REBALANCE_CONFIG_SIZE, MAX_BASE_TOKENS, MAX_BASE_TOKEN_DECIMALS,
+        MAX_LEVERAGE_TOKENS, MAX_PRICE_MULTIPLE, NET_DEPOSITS_DECIMALS, QUASAR_GROUP_VERSION,
+        REBALANCE_HISTORY_LEN, USE_GROUP_FEE, VOLATILITY_SAMPLE_SLOTS,
+    },
+};
+use solana_program::{account_info::AccountInfo, pubkey::Pubkey};
+use spl_associated_token_account::get_associated_token_address;
+use std::convert::TryFrom;
+use std::mem::size_of;
+
+#[test]
+fn test_find_default_pubkey_skips_empty_slots() {
+    let quasar_group = QuasarGroup::zeroed();
+
+    assert_eq!(quasar_group.find_base_token_index(&Pubkey::default()), None);
+    assert_eq!(
+        quasar_group.find_leverage_token_index_by_mint(&Pubkey::default()),
+        None
+    );
+    assert_eq!(
+        quasar_group.find_leverage_token_index(&Pubkey::default(), I80F48::from_num(0)),
+        None
+    );
+}
+
+#[test]
+fn test_account_layout_sizes() {
+    // Changing any of these breaks existing accounts and needs a migration
+    assert_eq!(size_of::<BaseToken>(), 72);
+    assert_eq!(size_of::<LeverageToken>(), 624);
+    assert_eq!(size_of::<QuasarGroup>(), 21744);
+    assert_eq!(size_of::<RebalanceConfig>(), 176);
+    assert_eq!(size_of::<TokenLedger>(), 96);
+}
+
+#[test]
+fn test_legacy_rebalance_config_reads_without_keeper() {
+    let mut rebalance_config = RebalanceConfig::zeroed();
+    rebalance_config.meta_data = MetaData::new(DataType::RebalanceConfig, 0, true);
+    rebalance_config.leverage_token_mint = Pubkey::new_unique();
+    rebalance_config.update_authority = Pubkey::new_unique();
+    rebalance_config.max_target_leverage = I80F48::from_num(3);
+    rebalance_config.target_leverage = I80F48::from_num(-2);
+    rebalance_config.keeper = Pubkey::new_unique();
+
+    // the legacy layout stops right before the keeper
+    let legacy_data = &bytemuck::bytes_of(&rebalance_config)[..LEGACY_REBALANCE_CONFIG_SIZE];
+    let migrated = RebalanceConfig::from_legacy(legacy_data).unwrap();
+    assert_eq!(
+        migrated.leverage_token_mint,
+        rebalance_config.leverage_token_mint
+    );
+    assert_eq!(migrated.update_authority, rebalance_config.update_authority);
+    assert_eq!(migrated.max_target_leverage, I80F48::from_num(3));
+    assert_eq!(migrated.target_leverage, I80F48::from_num(-2));
+    assert_eq!(migrated.keeper, Pubkey::default());
+
+    // a current config, and a legacy sized account never initialized
+    for data in [
+        bytemuck::bytes_of(&rebalance_config),
+        &[0u8; LEGACY_REBALANCE_CONFIG_SIZE][..],
+    ]
+    .iter()
+    {
+        assert!(matches!(
+            RebalanceConfig::from_legacy(data),
+            Err(QuasarError::QuasarErrorCode { quasar_error_code, .. })
+                if quasar_error_code == QuasarErrorCode::InvalidAccount
+        ));
+    }
+}
+
+#[test]
+fn test_load_rejects_inflated_token_counts() {
+    let program_id = Pubkey::new_unique();
+    let key = Pubkey::new_unique();
+
+    for &(num_base_tokens, num_leverage_tokens) in
+        [(MAX_BASE_TOKENS + 1, 0), (0, MAX_LEVERAGE_TOKENS + 1)].iter()
+    {
+        let mut quasar_group = QuasarGroup::zeroed();
+        quasar_group.meta_data = MetaData::new(DataType::QuasarGroup, QUASAR_GROUP_VERSION, true);
+        quasar_group.num_base_tokens = num_base_tokens;
+        quasar_group.num_leverage_tokens = num_leverage_tokens;
+
+        let mut lamports = 0;
+        let account = AccountInfo::new(
+            &key,
+            false,
+            true,
+            &mut lamports,
+            bytemuck::bytes_of_mut(&mut quasar_group),
+            &program_id,
+            false,
+            0,
+        );
+
+        assert!(matches!(
+            QuasarGroup::load_checked(&account, &program_id),
+            Err(QuasarError::QuasarErrorCode { quasar_error_code, .. })
+                if quasar_error_code == QuasarErrorCode::InvalidAccount
+        ));
+        assert!(matches!(
+            QuasarGroup::load_mut_checked(&account, &program_id),
+            Err(QuasarError::QuasarErrorCode { quasar_error_code, .. })
+                if quasar_error_code == QuasarErrorCode::InvalidAccount
+        ));
+    }
+}
+
+#[test]
+fn test_load_rejects_other_layout_versions() {
+    let program_id = Pubkey::new_unique();
+    let key = Pubkey::new_unique();
+
+    let mut quasar_group = QuasarGroup::zeroed();
+    quasar_group.meta_data = MetaData::new(DataType::QuasarGroup, 0, true);
+    let mut lamports = 0;
+    let account = AccountInfo::new(
+        &key,
+        false,
+        true,
+        &mut lamports,
+        bytemuck::bytes_of_mut(&mut quasar_group),
+        &program_id,
+        false,
+        0,
+    );
+
+    assert!(matches!(
+        QuasarGroup::load_checked(&account, &program_id),
+        Err(QuasarError::QuasarErrorCode { quasar_error_code, .. })
+            if quasar_error_code == QuasarErrorCode::UnsupportedVersion
+    ));
+    assert!(matches!(
+        QuasarGroup::load_mut_checked(&account, &program_id),
+        Err(QuasarError::QuasarErrorCode { quasar_error_code, .. })
+            if quasar_error_code == QuasarErrorCode::UnsupportedVersion
+    ));
+}
+
+#[test]
+fn test_leverage_token_fee_overrides_group_default() {
+    let mut quasar_group = QuasarGroup::zeroed();
+    quasar_group.mint_fee_bps = 10;
+    quasar_group.burn_fee_bps = 20;
+
+    let mut leverage_token = LeverageToken::zeroed();
+    leverage_token.mint_fee_bps = USE_GROUP_FEE;
+    leverage_token.burn_fee_bps = USE_GROUP_FEE;
+    assert_eq!(leverage_token.get_mint_fee_bps(&quasar_group), 10);
+    assert_eq!(leverage_token.get_burn_fee_bps(&quasar_group), 20);
+
+    leverage_token.mint_fee_bps = 50;
+    assert_eq!(leverage_token.get_mint_fee_bps(&quasar_group), 50);
+    assert_eq!(leverage_token.get_burn_fee_bps(&quasar_group), 20);
+
+    // zero is a real override, not the sentinel
+    leverage_token.burn_fee_bps = 0;
+    assert_eq!(leverage_token.get_burn_fee_bps(&quasar_group), 0);
+}
+
+#[test]
+fn test_base_token_decimals_bound() {
+    assert!(BaseToken::check_decimals(6).is_ok());
+    assert!(BaseToken::check_decimals(MAX_BASE_TOKEN_DECIMALS).is_ok());
+    // the native unit of the largest allowed decimals still fits
+    assert!(10u64.checked_pow(MAX_BASE_TOKEN_DECIMALS.into()).is_some());
+
+    for &decimals in [MAX_BASE_TOKEN_DECIMALS + 1, u8::MAX].iter() {
+        assert!(matches!(
+            BaseToken::check_decimals(decimals),
+            Err(QuasarError::QuasarErrorCode { quasar_error_code, .. })
+                if quasar_error_code == QuasarErrorCode::InvalidParam
+        ));
+    }
+}
+
+#[test]
+fn test_rebalance_reads_config_target() {
+    let mut leverage_token = LeverageToken::zeroed();
+    leverage_token.mint = Pubkey::new_unique();
+    leverage_token.target_leverage = I80F48::from_num(3);
+
+    // without a config the scalar target applies
+    assert_eq!(
+        leverage_token.get_target_leverage(None).unwrap(),
+        I80F48::from_num(3)
+    );
+
+    let mut rebalance_config = RebalanceConfig::zeroed();
+    rebalance_config.leverage_token_mint = leverage_token.mint;
+    rebalance_config.max_target_leverage = I80F48::from_num(5);
+    rebalance_config.target_leverage = I80F48::from_num(2.5);
+    leverage_token.rebalance_config = Pubkey::new_unique();
+
+    assert_eq!(
+        leverage_token
+            .get_target_leverage(Some(&rebalance_config))
+            .unwrap(),
+        I80F48::from_num(2.5)
+    );
+    // a token following a config can't fall back to the scalar target
+    assert!(leverage_token.get_target_leverage(None).is_err());
+
+    rebalance_config.leverage_token_mint = Pubkey::new_unique();
+    assert!(leverage_token
+        .get_target_leverage(Some(&rebalance_config))
+        .is_err());
+
+    assert!(rebalance_config
+        .check_target_leverage(I80F48::from_num(-5))
+        .is_ok());
+    assert!(rebalance_config
+        .check_target_leverage(I80F48::from_num(5.5))
+        .is_err());
+}
+
+#[test]
+fn test_frozen_token_is_not_active() {
+    let mut leverage_token = LeverageToken::zeroed();
+    assert!(leverage_token.check_active().is_ok());
+
+    leverage_token.state = TokenState::Frozen as u8;
+    assert!(matches!(
+        leverage_token.check_active(),
+        Err(QuasarError::QuasarErrorCode {
+            quasar_error_code: QuasarErrorCode::TokenFrozen,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_native_price_follows_token_decimals() {
+    let quote_decimals = 6;
+
+    // one whole token initially costs one whole quote token whatever its decimals
+    for &decimals in [0u8, 3, 6].iter() {
+        let native_price =
+            native_price_from_nav(I80F48::from_num(0), 0, decimals, quote_decimals).unwrap();
+        assert_eq!(
+            native_price * I80F48::from_num(10u64.pow(decimals.into())),
+            I80F48::from_num(1_000_000)
+        );
+    }
+
+    // 2 whole tokens of 6 decimals backed by 200 USDC are worth 100 USDC each
+    let native_price =
+        native_price_from_nav(I80F48::from_num(200_000_000), 2_000_000, 6, quote_decimals).unwrap();
+    assert_eq!(native_price, I80F48::from_num(100));
+    assert_eq!(
+        native_price * I80F48::from_num(1_000_000),
+        I80F48::from_num(100_000_000)
+    );
+
+    assert!(native_price_from_nav(I80F48::from_num(0), 0, 20, quote_decimals).is_err());
+}
+
+#[test]
+fn test_seed_mint_is_priced_at_the_initial_price() {
+    let quote_decimals = 6;
+    let initial_price = native_price_from_nav(I80F48::from_num(0), 0, 6, quote_decimals).unwrap();
+
+    // seeding 5 whole tokens takes 5 USDC and leaves the price where it started
+    let seed_quantity = 5_000_000;
+    let quote_amount = initial_quote_amount(seed_quantity, 6, quote_decimals).unwrap();
+    assert_eq!(quote_amount, 5_000_000);
+    assert_eq!(
+        native_price_from_nav(
+            I80F48::from_num(quote_amount),
+            seed_quantity,
+            6,
+            quote_decimals
+        )
+        .unwrap(),
+        initial_price
+    );
+
+    // a fraction of a native quote unit is rounded up in favour of the token
+    assert_eq!(initial_quote_amount(1, 9, quote_decimals).unwrap(), 1);
+    assert_eq!(initial_quote_amount(0, 9, quote_decimals).unwrap(), 0);
+}
+
+#[test]
+fn test_redemption_is_capped_per_burn() {
+    let mut leverage_token = LeverageToken::zeroed();
+    assert!(leverage_token.check_redemption_size(u64::MAX).is_ok());
+
+    leverage_token.max_redemption_per_tx = 1_000;
+    assert!(leverage_token.check_redemption_size(1_000).is_ok());
+    assert!(matches!(
+        leverage_token.check_redemption_size(1_001),
+        Err(QuasarError::QuasarErrorCode {
+            quasar_error_code: QuasarErrorCode::InvalidParam,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_mints_wait_for_activation_slot() {
+    let mut leverage_token = LeverageToken::zeroed();
+    leverage_token.active_from_slot = 1_000;
+
+    assert!(matches!(
+        leverage_token.check_mint_active(999),
+        Err(QuasarError::QuasarErrorCode {
+            quasar_error_code: QuasarErrorCode::TokenNotActive,
+            ..
+        })
+    ));
+    assert!(leverage_token.check_mint_active(1_000).is_ok());
+    assert!(leverage_token.check_mint_active(5_000).is_ok());
+}
+
+#[test]
+fn test_fees_route_by_quote_mint() {
+    let usdc = Pubkey::new_unique();
+    let usdt = Pubkey::new_unique();
+
+    let mut quasar_group = QuasarGroup::zeroed();
+    quasar_group.signer_key = Pubkey::new_unique();
+    quasar_group.quote_mint = usdc;
+    quasar_group.fee_vault = Pubkey::new_unique();
+    quasar_group.treasury = Pubkey::new_unique();
+    quasar_group.treasury_owner = Pubkey::new_unique();
+
+    let mut usdc_token = LeverageToken::zeroed();
+    usdc_token.quote_mint = usdc;
+    let mut usdt_token = LeverageToken::zeroed();
+    usdt_token.quote_mint = usdt;
+
+    assert_eq!(
+        quasar_group.get_fee_vault(&usdc_token.quote_mint),
+        quasar_group.fee_vault
+    );
+    assert_eq!(
+        quasar_group.get_treasury(&usdc_token.quote_mint),
+        quasar_group.treasury
+    );
+
+    assert_eq!(
+        quasar_group.get_fee_vault(&usdt_token.quote_mint),
+        get_associated_token_address(&quasar_group.signer_key, &usdt)
+    );
+    assert_eq!(
+        quasar_group.get_treasury(&usdt_token.quote_mint),
+        get_associated_token_address(&quasar_group.treasury_owner, &usdt)
+    );
+}
+
+#[test]
+fn test_desynced_supply_is_detected() {
+    let mut token_ledger = TokenLedger::zeroed();
+    token_ledger.record_mint(1_000, 0).unwrap();
+    token_ledger.record_burn(400, 1_000).unwrap();
+
+    assert_eq!(token_ledger.get_expected_supply().unwrap(), 600);
+    assert!(token_ledger.check_supply(600).is_ok());
+
+    for &supply in [0, 599, 601, 1_000].iter() {
+        assert!(matches!(
+            token_ledger.check_supply(supply),
+            Err(QuasarError::QuasarErrorCode {
+                quasar_error_code: QuasarErrorCode::SupplyMismatch,
+                ..
+            })
+        ));
+    }
+}
+
+#[test]
+fn test_leverage_cap_overrides_rebalance_cooldown() {
+    let mut leverage_token = LeverageToken::zeroed();
+    leverage_token.target_leverage = I80F48::from_num(3);
+    leverage_token.max_effective_leverage = I80F48::from_num(4);
+    leverage_token.rebalance_cooldown_slots = 100;
+    leverage_token.last_rebalance_slot = 1_000;
+
+    // under the cap the cooldown holds
+    assert!(matches!(
+        leverage_token.check_rebalance_due(I80F48::from_num(3.5), 1_050),
+        Err(QuasarError::QuasarErrorCode {
+            quasar_error_code: QuasarErrorCode::RebalanceCooldown,
+            ..
+        })
+    ));
+    assert!(leverage_token
+        .check_rebalance_due(I80F48::from_num(3.5), 1_100)
+        .is_ok());
+
+    // past the cap the token deleverages right away, shorts included
+    assert!(leverage_token
+        .check_rebalance_due(I80F48::from_num(4.5), 1_050)
+        .is_ok());
+    assert!(leverage_token
+        .check_rebalance_due(I80F48::from_num(-4.5), 1_050)
+        .is_ok());
+
+    // a zero cap never overrides the cooldown
+    leverage_token.max_effective_leverage = I80F48::from_num(0);
+    assert!(leverage_token
+        .check_rebalance_due(I80F48::from_num(10), 1_050)
+        .is_err());
+}
+
+#[test]
+fn test_burn_borrow_policy() {
+    let nav = I80F48::from_num(10_000);
+    let mut leverage_token = LeverageToken::zeroed();
+
+    // deposits covering the burn never borrow, whatever the policy
+    assert!(!leverage_token
+        .check_burn_borrow(1_000, I80F48::from_num(1_000), nav)
+        .unwrap());
+
+    // by default a burn short of deposits fails rather than borrowing
+    assert!(matches!(
+        leverage_token.check_burn_borrow(1_500, I80F48::from_num(1_000), nav),
+        Err(QuasarError::QuasarErrorCode {
+            quasar_error_code: QuasarErrorCode::InsufficientFunds,
+            ..
+        })
+    ));
+
+    // allowed borrows stay within 10% of the NAV, counting what is already borrowed
+    leverage_token.allow_borrow = 1;
+    leverage_token.max_borrow_bps = 1_000;
+    assert!(leverage_token
+        .check_burn_borrow(1_500, I80F48::from_num(500), nav)
+        .unwrap());
+    assert!(leverage_token
+        .check_burn_borrow(1_500, I80F48::from_num(499), nav)
+        .is_err());
+    assert!(leverage_token
+        .check_burn_borrow(500, I80F48::from_num(-600), nav)
+        .is_err());
+}
+
+#[test]
+fn test_mints_respect_increment() {
+    let mut leverage_token = LeverageToken::zeroed();
+    assert!(leverage_token.check_mint_increment(7).is_ok());
+
+    leverage_token.min_mint_increment = 1_000;
+    assert!(leverage_token.check_mint_increment(1_000).is_ok());
+    assert!(leverage_token.check_mint_increment(25_000).is_ok());
+    for &quantity in [1u64, 999, 1_001, 25_500].iter() {
+        assert!(matches!(
+            leverage_token.check_mint_increment(quantity),
+            Err(QuasarError::QuasarErrorCode {
+                quasar_error_code: QuasarErrorCode::InvalidParam,
+                ..
+            })
+        ));
+    }
+}
+
+#[test]
+fn test_rebalance_price_source_defaults_to_mango_cache() {
+    let mut leverage_token = LeverageToken::zeroed();
+    assert_eq!(
+        RebalancePriceSource::try_from(leverage_token.rebalance_price_source),
+        Ok(RebalancePriceSource::MangoCache)
+    );
+    assert!(!leverage_token.uses_oracle_price());
+
+    leverage_token.rebalance_price_source = RebalancePriceSource::Oracle as u8;
+    assert!(leverage_token.uses_oracle_price());
+}
+
+#[test]
+fn test_group_cap_bounds_net_deposits() {
+    let mut quasar_group = QuasarGroup::zeroed();
+    // uncapped by default
+    assert!(quasar_group.check_group_cap(u64::MAX).is_ok());
+
+    quasar_group.max_net_deposits = 1_000;
+    quasar_group.add_net_deposits(900).unwrap();
+    assert!(quasar_group.check_group_cap(100).is_ok());
+    assert!(matches!(
+        quasar_group.check_group_cap(101),
+        Err(QuasarError::QuasarErrorCode {
+            quasar_error_code: QuasarErrorCode::GroupCapExceeded,
+            ..
+        })
+    ));
+
+    quasar_group.remove_net_deposits(400).unwrap();
+    assert!(quasar_group.check_group_cap(500).is_ok());
+    // accounting that doesn't add up is an error rather than floored
+    assert!(quasar_group.remove_net_deposits(501).is_err());
+}
+
+#[test]
+fn test_burns_take_their_share_of_net_deposits() {
+    let mut token_ledger = TokenLedger::zeroed();
+    token_ledger.record_mint(3, 1_000).unwrap();
+
+    // a burn takes out its share of the supply, whatever it pays out, rounded down
+    assert_eq!(token_ledger.record_burn(1, 3).unwrap(), 333);
+    assert_eq!(token_ledger.record_burn(1, 2).unwrap(), 333);
+    // with the last burn taking the rest
+    assert_eq!(token_ledger.record_burn(1, 1).unwrap(), 334);
+    assert_eq!(token_ledger.net_deposits, 0);
+    assert_eq!(token_ledger.get_expected_supply().unwrap(), 0);
+
+    assert!(token_ledger.record_burn(2, 1).is_err());
+}
+
+#[test]
+fn test_net_deposits_are_counted_in_the_reference_unit() {
+    let par = I80F48::from_num(1);
+    // a dollar of six and of nine decimals quote counts the same
+    assert_eq!(
+        net_deposit_amount(1_000_000, 6, par).unwrap(),
+        10u64.pow(NET_DEPOSITS_DECIMALS.into())
+    );
+    assert_eq!(
+        net_deposit_amount(1_000_000_000, 9, par).unwrap(),
+        10u64.pow(NET_DEPOSITS_DECIMALS.into())
+    );
+    // and a quote below its peg counts for less, rounded down
+    assert_eq!(
+        net_deposit_amount(1_000_000, 6, I80F48::from_num(0.5)).unwrap(),
+        500_000
+    );
+    assert_eq!(net_deposit_amount(1_999, 9, par).unwrap(), 1);
+}
+
+#[test]
+fn test_dust_supply_price_is_bounded() {
+    let quote_decimals = 6;
+    let initial_price = native_price_from_nav(I80F48::from_num(0), 0, 0, quote_decimals).unwrap();
+
+    // a single token left outstanding, with 1_000 USDC donated to the Mango account
+    let donated_nav = I80F48::from_num(1_000_000_000);
+    assert_eq!(
+        native_price_from_nav(donated_nav, 1, 0, quote_decimals).unwrap(),
+        initial_price * I80F48::from_num(1_000)
+    );
+
+    // the same donation on a dust supply of a 6 decimals token can't be minted at, but the
+    // holder still redeems the donation at it
+    let dust_price = native_price_from_nav(donated_nav, 1, 6, quote_decimals).unwrap();
+    assert_eq!(dust_price, donated_nav);
+    assert!(matches!(
+        check_mint_price_band(dust_price, 6, quote_decimals),
+        Err(QuasarError::QuasarErrorCode {
+            quasar_error_code: QuasarErrorCode::PriceOutOfBand,
+            ..
+        })
+    ));
+    let max_price = initial_price * I80F48::from_num(MAX_PRICE_MULTIPLE);
+    assert!(check_mint_price_band(max_price, 0, quote_decimals).is_ok());
+    assert!(check_mint_price_band(max_price + I80F48::from_num(1), 0, quote_decimals).is_err());
+}
+
+#[test]
+fn test_base_token_prices_match_their_oracles() {
+    let mut quasar_group = QuasarGroup::zeroed();
+    let keys: Vec<Pubkey> = (0..2).map(|_| Pubkey::new_unique()).collect();
+    let mut oracles: Vec<StubOracle> = [4_000i64, 25]
+        .iter()
+        .map(|&price| {
+            let mut oracle = StubOracle::zeroed();
+            oracle.magic = 0x6F676E4D;
+            oracle.expo = -2;
+            oracle.price = I80F48::from_num(price);
+            oracle
+        })
+        .collect();
+    for (i, key) in keys.iter().enumerate() {
+        quasar_group.base_tokens[i].mint = Pubkey::new_unique();
+        quasar_group.base_tokens[i].oracle = *key;
+    }
+    quasar_group.num_base_tokens = 2;
+
+    let owner = Pubkey::default();
+    let mut lamports = vec![0u64; 2];
+    let oracle_ais: Vec<AccountInfo> = keys
+        .iter()
+        .zip(lamports.iter_mut())
+        .zip(oracles.iter_mut())
+        .map(|((key, lamports), oracle)| {
+            AccountInfo::new(
+                key,
+                false,
+                false,
+                lamports,
+                bytemuck::bytes_of_mut(oracle),
+                &owner,
+                false,
+                0,
+            )
+        })
+        .collect();
+
+    let data = quasar_group.pack_base_token_prices(&oracle_ais).unwrap();
+    assert_eq!(data.len(), 2 * 80);
+    for (i, entry) in data.chunks(80).enumerate() {
+        let base_token = &quasar_group.base_tokens[i];
+        assert_eq!(entry[..32], base_token.mint.to_bytes());
+        assert_eq!(entry[32..64], base_token.oracle.to_bytes());
+        let price = read_oracle_price(&oracle_ais[i]).unwrap();
+        assert_eq!(entry[64..], price.to_le_bytes());
+    }
+
+    // every base token's oracle must be passed, in order
+    assert!(quasar_group
+        .pack_base_token_prices(&oracle_ais[..1])
+        .is_err());
+    let swapped = vec![oracle_ais[1].clone(), oracle_ais[0].clone()];
+    assert!(quasar_group.pack_base_token_prices(&swapped).is_err());
+}
+
+#[test]
+fn test_base_token_reads_its_recorded_oracle_type() {
+    let mut oracle = StubOracle::zeroed();
+    oracle.magic = STUB_MAGIC;
+    oracle.expo = -2;
+    oracle.price = I80F48::from_num(4_000);
+    let key = Pubkey::new_unique();
+    let owner = Pubkey::default();
+    let mut lamports = 0u64;
+    let mut base_token = BaseToken::zeroed();
+    base_token.oracle = key;
+
+    {
+        let oracle_ai = AccountInfo::new(
+            &key,
+            false,
+            false,
+            &mut lamports,
+            bytemuck::bytes_of_mut(&mut oracle),
+            &owner,
+            false,
+            0,
+        );
+        let price = read_oracle_price(&oracle_ai).unwrap();
+
+        // tokens added before the type was recorded still detect it
+        assert_eq!(base_token.oracle_type(), OracleType::Unknown);
+        assert_eq!(base_token.read_oracle_price(&oracle_ai).unwrap(), price);
+
+        base_token.oracle_type = OracleType::Stub as u8;
+        assert_eq!(base_token.read_oracle_price(&oracle_ai).unwrap(), price);
+
+        // the recorded type is trusted, so a stub read as pyth is rejected
+        base_token.oracle_type = OracleType::Pyth as u8;
+        assert!(is_invalid_account(base_token.read_oracle_price(&oracle_ai)));
+    }
+
+    // an account whose magic no longer matches the recorded type
+    oracle.magic = 0;
+    let oracle_ai = AccountInfo::new(
+        &key,
+        false,
+        false,
+        &mut lamports,
+        bytemuck::bytes_of_mut(&mut oracle),
+        &owner,
+        false,
+        0,
+    );
+    base_token.oracle_type = OracleType::Stub as u8;
+    assert!(is_invalid_account(base_token.read_oracle_price(&oracle_ai)));
+}
+
+fn is_invalid_account<T>(result: Result<T, QuasarError>) -> bool {
+    matches!(
+        result,
+        Err(QuasarError::QuasarErrorCode {
+            quasar_error_code: QuasarErrorCode::InvalidAccount,
+            ..
+        })
+    )
+}
+
+#[test]
+fn test_rebalances_are_capped_per_epoch() {
+    let mut leverage_token = LeverageToken::zeroed();
+    leverage_token.max_effective_leverage = I80F48::from_num(4);
+    leverage_token.max_rebalances_per_epoch = 2;
+    let on_target = I80F48::from_num(3.5);
+
+    // rebalances that didn't trade aren't counted
+    for _ in 0..10 {
+        leverage_token.check_epoch_rebalance(on_target, 7).unwrap();
+    }
+    for _ in 0..2 {
+        leverage_token.check_epoch_rebalance(on_target, 7).unwrap();
+        leverage_token.record_epoch_rebalance(7);
+    }
+    // the third rebalance of the epoch is rejected
+    assert!(matches!(
+        leverage_token.check_epoch_rebalance(on_target, 7),
+        Err(QuasarError::QuasarErrorCode {
+            quasar_error_code: QuasarErrorCode::RebalanceLimitReached,
+            ..
+        })
+    ));
+
+    // an emergency deleverage still goes through
+    leverage_token
+        .check_epoch_rebalance(I80F48::from_num(4.5), 7)
+        .unwrap();
+
+    // the count starts over in the next epoch
+    leverage_token.check_epoch_rebalance(on_target, 8).unwrap();
+    leverage_token.record_epoch_rebalance(8);
+    assert_eq!(leverage_token.epoch_rebalances, 1);
+    assert_eq!(leverage_token.rebalance_epoch, 8);
+
+    // a zero cap never rejects
+    leverage_token.max_rebalances_per_epoch = 0;
+    for _ in 0..10 {
+        leverage_token.check_epoch_rebalance(on_target, 8).unwrap();
+        leverage_token.record_epoch_rebalance(8);
+    }
+}
+
+#[test]
+fn test_rising_volatility_lowers_the_target_leverage() {
+    let mut leverage_token = LeverageToken::zeroed();
+    leverage_token.target_leverage = I80F48::from_num(3);
+    leverage_token.volatility_target = I80F48::from_num(0.01);
+    let target = leverage_token.target_leverage;
+
+    // the first rebalance only samples its price
+    let mut slot = 1_000;
+    leverage_token
+        .update_volatility(I80F48::from_num(100), slot)
+        .unwrap();
+    assert_eq!(leverage_token.volatility_ema, I80F48::ZERO);
+    assert_eq!(leverage_token.volatility_slot, slot);
+    assert_eq!(
+        leverage_token.adapt_target_leverage(target).unwrap(),
+        target
+    );
+
+    // calm markets keep the base target
+    slot += VOLATILITY_SAMPLE_SLOTS;
+    leverage_token
+        .update_volatility(I80F48::from_num(101), slot)
+        .unwrap();
+    assert!(leverage_token.volatility_ema <= leverage_token.volatility_target);
+    assert_eq!(
+        leverage_token.adapt_target_leverage(target).unwrap(),
+        target
+    );
+
+    // every large move lowers the target further, leaving the base target untouched
+    let mut last_target = target;
+    for &price in [120, 95, 125, 90].iter() {
+        slot += VOLATILITY_SAMPLE_SLOTS;
+        leverage_token
+            .update_volatility(I80F48::from_num(price), slot)
+            .unwrap();
+        let adapted = leverage_token.adapt_target_leverage(target).unwrap();
+        assert!(adapted < last_target);
+        last_target = adapted;
+    }
+    assert_eq!(leverage_token.target_leverage, target);
+
+    // short tokens are scaled towards zero too
+    let short_target = I80F48::from_num(-2);
+    let adapted = leverage_token.adapt_target_leverage(short_target).unwrap();
+    assert!(adapted > short_target && adapted < I80F48::ZERO);
+
+    // without a volatility target the target is fixed
+    leverage_token.volatility_target = I80F48::ZERO;
+    assert_eq!(
+        leverage_token.adapt_target_leverage(target).unwrap(),
+        target
+    );
+}
+
+#[test]
+fn test_volatility_is_sampled_on_a_fixed_schedule() {
+    let sampled = |prices: &[(u64, u64)]| {
+        let mut leverage_token = LeverageToken::zeroed();
+        for &(slot, price) in prices.iter() {
+            leverage_token
+                .update_volatility(I80F48::from_num(price), slot)
+                .unwrap();
+        }
+        leverage_token
+    };
+
+    // rebalances before the next sample is due leave the EMA and the sampled price alone
+    let leverage_token = sampled(&[(0, 100), (VOLATILITY_SAMPLE_SLOTS - 1, 150)]);
+    assert_eq!(leverage_token.volatility_ema, I80F48::ZERO);
+    assert_eq!(leverage_token.volatility_price, I80F48::from_num(100));
+    assert_eq!(leverage_token.volatility_slot, 0);
+
+    // so calling rebalance more often doesn't add samples
+    let frequent = sampled(&[
+        (0, 100),
+        (VOLATILITY_SAMPLE_SLOTS / 2, 90),
+        (VOLATILITY_SAMPLE_SLOTS, 110),
+    ]);
+    let scheduled = sampled(&[(0, 100), (VOLATILITY_SAMPLE_SLOTS, 110)]);
+    assert_eq!(frequent.volatility_ema, scheduled.volatility_ema);
+
+    // a return over four intervals counts as one over a single interval half its size
+    let rare = sampled(&[(0, 100), (4 * VOLATILITY_SAMPLE_SLOTS, 120)]);
+    assert_eq!(rare.volatility_ema, scheduled.volatility_ema);
+    assert_eq!(rare.volatility_slot, 4 * VOLATILITY_SAMPLE_SLOTS);
+}
+
+#[test]
+fn test_rebalance_history_keeps_the_latest() {
+    let mut leverage_token = LeverageToken::zeroed();
+    assert!(leverage_token.recent_rebalances().is_empty());
+
+    let record = |slot: u64| RebalanceRecord {
+        slot,
+        price: 100 + slot as i64,
+        quantity: 10,
+        side: (slot % 2) as u8,
+        padding: [0u8; 7],
+    };
+    for slot in 0..2 {
+        leverage_token.record_rebalance(record(slot));
+    }
+    assert_eq!(
+        leverage_token.recent_rebalances(),
+        vec![record(0), record(1)]
+    );
+
+    let num_rebalances = REBALANCE_HISTORY_LEN as u64 + 2;
+    for slot in 2..num_rebalances {
+        leverage_token.record_rebalance(record(slot));
+    }
+    let expected: Vec<RebalanceRecord> = (2..num_rebalances).map(record).collect();
+    assert_eq!(leverage_token.recent_rebalances(), expected);
+    assert_eq!(leverage_token.num_rebalances, num_rebalances);
+}
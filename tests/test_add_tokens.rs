@@ -0,0 +1,337 @@
+#![cfg(feature = "test-bpf")]
+
+mod program_test;
+
+use std::mem::size_of;
+
+use fixed::types::I80F48;
+use mango::state::{MangoAccount, QUOTE_INDEX};
+use solana_program::{instruction::InstructionError, program_pack::Pack, pubkey::Pubkey};
+use solana_sdk::{signature::Signer, transaction::TransactionError, transport::TransportError};
+use spl_associated_token_account::get_associated_token_address;
+use spl_token::state::{Account as TokenAccount, Mint};
+
+use program_test::QuasarProgramTest;
+use quasar::{
+    error::QuasarErrorCode,
+    utils::{governance_program, group_signer},
+};
+
+fn is_quasar_error(result: Result<(), TransportError>, code: QuasarErrorCode) -> bool {
+    let code: u32 = code.into();
+    matches!(
+        result,
+        Err(TransportError::TransactionError(TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(custom)
+        ))) if custom == code
+    )
+}
+
+#[tokio::test]
+async fn test_init_and_add_tokens() {
+    let mut test = QuasarProgramTest::start_new(1).await;
+
+    test.init_quasar_group().await.unwrap();
+    let quasar_group = test.load_quasar_group().await;
+    assert!(quasar_group.meta_data.is_initialized);
+    assert_eq!(quasar_group.admin_key, test.admin());
+    assert_eq!(quasar_group.signer_key, test.signer_key);
+    assert_eq!(
+        group_signer(
+            &test.quasar_group,
+            quasar_group.signer_nonce,
+            &test.quasar_program_id
+        )
+        .unwrap(),
+        quasar_group.signer_key
+    );
+    assert_eq!(quasar_group.mango_program_id, test.mango_program_id);
+
+    let oracle = test.add_base_token(0).await.unwrap();
+    let quasar_group = test.load_quasar_group().await;
+    assert_eq!(quasar_group.num_base_tokens, 1);
+    assert_eq!(
+        quasar_group.base_tokens[0].mint,
+        test.base_mints[0].pubkey()
+    );
+    assert_eq!(quasar_group.base_tokens[0].oracle, oracle);
+
+    let target_leverage = I80F48::from_num(3);
+    let (mint, mango_account) = test.add_leverage_token(0, target_leverage).await.unwrap();
+    let quasar_group = test.load_quasar_group().await;
+    assert_eq!(quasar_group.num_leverage_tokens, 1);
+
+    let leverage_token = quasar_group.leverage_tokens[0];
+    assert_eq!(leverage_token.mint, mint);
+    assert_eq!(leverage_token.base_token_mint, test.base_mints[0].pubkey());
+    assert_eq!(leverage_token.target_leverage, target_leverage);
+    assert_eq!(leverage_token.mango_account, mango_account);
+
+    // the mint created by the program is funded for the cluster's actual rent
+    let mint_account = test.get_account(mint).await;
+    let rent = test.get_rent().await;
+    assert!(rent.is_exempt(mint_account.lamports, mint_account.data.len()));
+
+    // the same base token and target leverage can't be listed twice
+    assert!(is_quasar_error(
+        test.add_leverage_token(0, target_leverage)
+            .await
+            .map(|_| ()),
+        QuasarErrorCode::DuplicateToken
+    ));
+}
+
+#[tokio::test]
+async fn test_repeated_setup_reports_specific_errors() {
+    let mut test = QuasarProgramTest::start_new(1).await;
+    test.init_quasar_group().await.unwrap();
+
+    let signer_nonce = test.signer_nonce;
+    assert!(is_quasar_error(
+        test.send_init_quasar_group(signer_nonce).await,
+        QuasarErrorCode::AlreadyInitialized
+    ));
+
+    test.add_base_token(0).await.unwrap();
+    assert!(is_quasar_error(
+        test.add_base_token(0).await.map(|_| ()),
+        QuasarErrorCode::DuplicateToken
+    ));
+}
+
+#[tokio::test]
+async fn test_relisting_reuses_initialized_mango_account() {
+    let mut test = QuasarProgramTest::start_new(1).await;
+    test.init_quasar_group().await.unwrap();
+    test.add_base_token(0).await.unwrap();
+
+    let mango_program_id = test.mango_program_id;
+    let mango_account = test
+        .create_account(size_of::<MangoAccount>(), &mango_program_id)
+        .await;
+    let perp_market = test.perp_markets[0];
+    let target_leverage = I80F48::from_num(3);
+    let mint = test
+        .add_leverage_token_with_mango_account(0, target_leverage, mango_account, perp_market)
+        .await
+        .unwrap();
+
+    // the Mango account stays initialized once the token is gone
+    test.remove_leverage_token(mint, mango_account)
+        .await
+        .unwrap();
+    assert_eq!(test.load_quasar_group().await.num_leverage_tokens, 0);
+
+    let mint = test
+        .add_leverage_token_with_mango_account(0, target_leverage, mango_account, perp_market)
+        .await
+        .unwrap();
+    let quasar_group = test.load_quasar_group().await;
+    assert_eq!(quasar_group.num_leverage_tokens, 1);
+    assert_eq!(quasar_group.leverage_tokens[0].mint, mint);
+    assert_eq!(quasar_group.leverage_tokens[0].mango_account, mango_account);
+
+    // but it can't back two tokens at once
+    assert!(test
+        .add_leverage_token_with_mango_account(0, I80F48::from_num(2), mango_account, perp_market)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_removed_base_token_cant_back_a_leverage_token() {
+    let mut test = QuasarProgramTest::start_new(2).await;
+    test.init_quasar_group().await.unwrap();
+    test.add_base_tokens(&[0, 1]).await.unwrap();
+    let base_mint = test.base_mints[0].pubkey();
+    let target_leverage = I80F48::from_num(3);
+
+    // a base token outlives the leverage tokens referencing it
+    let (mint, mango_account) = test.add_leverage_token(0, target_leverage).await.unwrap();
+    assert!(is_quasar_error(
+        test.remove_base_token(base_mint).await,
+        QuasarErrorCode::BaseTokenInUse
+    ));
+    test.remove_leverage_token(mint, mango_account)
+        .await
+        .unwrap();
+
+    test.remove_base_token(base_mint).await.unwrap();
+    let quasar_group = test.load_quasar_group().await;
+    assert_eq!(quasar_group.num_base_tokens, 1);
+    assert_eq!(
+        quasar_group.base_tokens[0].mint,
+        test.base_mints[1].pubkey()
+    );
+    assert!(quasar_group.find_base_token_index(&base_mint).is_none());
+
+    assert!(is_quasar_error(
+        test.add_leverage_token(0, target_leverage)
+            .await
+            .map(|_| ()),
+        QuasarErrorCode::InvalidAccount
+    ));
+}
+
+#[tokio::test]
+async fn test_init_rejects_nonce_without_pda() {
+    let mut test = QuasarProgramTest::start_new(1).await;
+
+    assert!(is_quasar_error(
+        test.init_quasar_group_with_invalid_nonce().await,
+        QuasarErrorCode::InvalidSignerKey
+    ));
+    assert!(!test.load_quasar_group().await.meta_data.is_initialized);
+}
+
+#[tokio::test]
+async fn test_unsigned_admin_must_be_owned_by_governance() {
+    let mut test = QuasarProgramTest::start_new(1).await;
+
+    // any other executable program can't vouch for an unsigned admin
+    let mango_program_id = test.mango_program_id;
+    assert!(is_quasar_error(
+        test.init_quasar_group_with_governed_admin(mango_program_id, mango_program_id)
+            .await
+            .map(|_| ()),
+        QuasarErrorCode::InvalidProgramId
+    ));
+    assert!(!test.load_quasar_group().await.meta_data.is_initialized);
+
+    let admin = test
+        .init_quasar_group_with_governed_admin(governance_program::id(), governance_program::id())
+        .await
+        .unwrap();
+    assert_eq!(test.load_quasar_group().await.admin_key, admin);
+}
+
+#[tokio::test]
+async fn test_add_base_tokens_in_one_call() {
+    let mut test = QuasarProgramTest::start_new(3).await;
+    test.init_quasar_group().await.unwrap();
+
+    // a mint repeated within the batch fails the whole batch
+    assert!(test.add_base_tokens(&[0, 1, 0]).await.is_err());
+    assert_eq!(test.load_quasar_group().await.num_base_tokens, 0);
+
+    let oracles = test.add_base_tokens(&[0, 1, 2]).await.unwrap();
+    let quasar_group = test.load_quasar_group().await;
+    assert_eq!(quasar_group.num_base_tokens, 3);
+    for (i, oracle) in oracles.into_iter().enumerate() {
+        assert_eq!(
+            quasar_group.base_tokens[i].mint,
+            test.base_mints[i].pubkey()
+        );
+        assert_eq!(quasar_group.base_tokens[i].oracle, oracle);
+    }
+}
+
+#[tokio::test]
+async fn test_tokens_in_two_quote_currencies() {
+    let mut test = QuasarProgramTest::start_new(1).await;
+    test.init_quasar_group().await.unwrap();
+    test.set_treasury().await.unwrap();
+    test.add_base_token(0).await.unwrap();
+    let quote_mint = test.quote_mint.pubkey();
+    let second_quote_mint = test.second_quote_mint.pubkey();
+    let second_mango_group = test.second_mango_group;
+    let mango_program_id = test.mango_program_id;
+
+    // the primary quote pays its fees into the group's fee vault
+    test.add_leverage_token(0, I80F48::from_num(3))
+        .await
+        .unwrap();
+
+    // the other quote's fee vault is created with its first token
+    let second_fee_vault = test
+        .load_quasar_group()
+        .await
+        .get_fee_vault(&second_quote_mint);
+    assert!(test
+        .context
+        .banks_client
+        .get_account(second_fee_vault)
+        .await
+        .unwrap()
+        .is_none());
+    let mango_account = test
+        .create_account(size_of::<MangoAccount>(), &mango_program_id)
+        .await;
+    test.add_leverage_token_in_mango_group(
+        0,
+        I80F48::from_num(2),
+        second_mango_group,
+        second_quote_mint,
+        mango_account,
+        Pubkey::new_unique(),
+    )
+    .await
+    .unwrap();
+    let fee_vault = TokenAccount::unpack(&test.get_account(second_fee_vault).await.data).unwrap();
+    assert_eq!(fee_vault.mint, second_quote_mint);
+    assert_eq!(fee_vault.owner, test.signer_key);
+
+    // and reused by the next one
+    let mango_account = test
+        .create_account(size_of::<MangoAccount>(), &mango_program_id)
+        .await;
+    test.add_leverage_token_in_mango_group(
+        0,
+        I80F48::from_num(4),
+        second_mango_group,
+        second_quote_mint,
+        mango_account,
+        Pubkey::new_unique(),
+    )
+    .await
+    .unwrap();
+
+    let quasar_group = test.load_quasar_group().await;
+    assert_eq!(quasar_group.num_leverage_tokens, 3);
+    assert_eq!(quasar_group.leverage_tokens[0].quote_mint, quote_mint);
+    assert_eq!(
+        quasar_group.leverage_tokens[1].quote_mint,
+        second_quote_mint
+    );
+    assert_eq!(
+        quasar_group.leverage_tokens[2].quote_mint,
+        second_quote_mint
+    );
+    assert_ne!(
+        quasar_group.get_fee_vault(&quote_mint),
+        quasar_group.get_fee_vault(&second_quote_mint)
+    );
+}
+
+#[tokio::test]
+async fn test_seeded_token_starts_at_the_initial_price() {
+    let mut test = QuasarProgramTest::start_new(1).await;
+    test.init_quasar_group().await.unwrap();
+    test.add_base_token(0).await.unwrap();
+    let admin_quote = test.create_funded_quote_account(50_000_000).await;
+
+    let (mint, mango_account) = test
+        .add_seeded_leverage_token(0, I80F48::from_num(3), 10, admin_quote)
+        .await
+        .unwrap();
+
+    // the admin paid one whole quote for each of the 10 seeded tokens
+    let admin_leverage_token = get_associated_token_address(&test.admin(), &mint);
+    let supply = Mint::unpack(&test.get_account(mint).await.data)
+        .unwrap()
+        .supply;
+    assert_eq!(supply, 10);
+    assert_eq!(test.get_token_balance(admin_leverage_token).await, 10);
+    assert_eq!(test.get_token_balance(admin_quote).await, 40_000_000);
+    assert_eq!(test.get_token_balance(test.quote_vault).await, 10_000_000);
+    let loaded = test.load_mango_account(mango_account).await;
+    assert_eq!(loaded.deposits[QUOTE_INDEX], I80F48::from_num(10_000_000));
+
+    // so the next mint is priced off a NAV of one quote per token
+    test.mint_leverage_token(mint, mango_account, admin_quote, 1)
+        .await
+        .unwrap();
+    assert_eq!(test.get_token_balance(admin_quote).await, 39_000_000);
+    assert_eq!(test.get_token_balance(admin_leverage_token).await, 11);
+}
@@ -0,0 +1,80 @@
+#![cfg(feature = "test-bpf")]
+
+mod program_test;
+
+use fixed::types::I80F48;
+use mango::state::QUOTE_INDEX;
+use spl_associated_token_account::get_associated_token_address;
+
+use program_test::QuasarProgramTest;
+use quasar::utils::get_fee_amount;
+
+const MINT_FEE_BPS: u16 = 30;
+const BURN_FEE_BPS: u16 = 50;
+/// The first mint prices the token at one whole quote
+const INITIAL_NATIVE_PRICE: u64 = 1_000_000;
+
+#[tokio::test]
+async fn test_mint_and_burn() {
+    let mut test = QuasarProgramTest::start_new(1).await;
+    test.init_quasar_group().await.unwrap();
+    test.add_base_token(0).await.unwrap();
+    let (mint, mango_account) = test
+        .add_leverage_token(0, I80F48::from_num(3))
+        .await
+        .unwrap();
+    test.set_treasury().await.unwrap();
+    test.set_fees(MINT_FEE_BPS, BURN_FEE_BPS).await.unwrap();
+
+    let fee_vault = test.load_quasar_group().await.fee_vault;
+    let owner_quote = test.create_funded_quote_account(200_000_000).await;
+    let owner_leverage_token = get_associated_token_address(&test.admin(), &mint);
+
+    // The deposit lands in the Mango account, the fee on top of it in the fee vault
+    test.mint_leverage_token(mint, mango_account, owner_quote, 100)
+        .await
+        .unwrap();
+    let quote_amount = 100 * INITIAL_NATIVE_PRICE;
+    let mint_fee = get_fee_amount(quote_amount, MINT_FEE_BPS).unwrap();
+    assert_eq!(mint_fee, 300_000);
+    assert_eq!(test.get_token_balance(owner_leverage_token).await, 100);
+    assert_eq!(
+        test.get_token_balance(owner_quote).await,
+        200_000_000 - quote_amount - mint_fee
+    );
+    assert_eq!(test.get_token_balance(test.quote_vault).await, quote_amount);
+    assert_eq!(test.get_token_balance(fee_vault).await, mint_fee);
+    let loaded = test.load_mango_account(mango_account).await;
+    assert_eq!(loaded.deposits[QUOTE_INDEX], I80F48::from_num(quote_amount));
+
+    // Burns withdraw at the same NAV and pay their fee out of the withdrawal
+    test.burn_leverage_token(mint, mango_account, owner_quote, 40)
+        .await
+        .unwrap();
+    let first_burn_fee = get_fee_amount(40 * INITIAL_NATIVE_PRICE, BURN_FEE_BPS).unwrap();
+    assert_eq!(test.get_token_balance(owner_leverage_token).await, 60);
+    assert_eq!(
+        test.get_token_balance(owner_quote).await,
+        200_000_000 - 60 * INITIAL_NATIVE_PRICE - mint_fee - first_burn_fee
+    );
+    assert_eq!(
+        test.get_token_balance(test.quote_vault).await,
+        60 * INITIAL_NATIVE_PRICE
+    );
+
+    // Burning the rest of the supply empties the Mango account
+    test.burn_leverage_token(mint, mango_account, owner_quote, 60)
+        .await
+        .unwrap();
+    let second_burn_fee = get_fee_amount(60 * INITIAL_NATIVE_PRICE, BURN_FEE_BPS).unwrap();
+    let fees = mint_fee + first_burn_fee + second_burn_fee;
+    assert_eq!(test.get_token_balance(owner_leverage_token).await, 0);
+    assert_eq!(
+        test.get_token_balance(owner_quote).await,
+        200_000_000 - fees
+    );
+    assert_eq!(test.get_token_balance(test.quote_vault).await, 0);
+    assert_eq!(test.get_token_balance(fee_vault).await, fees);
+    let loaded = test.load_mango_account(mango_account).await;
+    assert_eq!(loaded.deposits[QUOTE_INDEX], I80F48::from_num(0));
+}
@@ -0,0 +1,242 @@
+#![cfg(feature = "test-bpf")]
+
+mod program_test;
+
+use fixed::types::I80F48;
+use solana_program::instruction::InstructionError;
+use solana_sdk::{transaction::TransactionError, transport::TransportError};
+
+use program_test::{MangoPositions, QuasarProgramTest};
+use quasar::{error::QuasarErrorCode, instruction::LeverageTokenParams, state::TokenState};
+
+fn is_quasar_error(result: Result<(), TransportError>, code: QuasarErrorCode) -> bool {
+    let code: u32 = code.into();
+    matches!(
+        result,
+        Err(TransportError::TransactionError(TransactionError::InstructionError(
+            0,
+            InstructionError::Custom(custom)
+        ))) if custom == code
+    )
+}
+
+/// NAV of every seeded Mango account, 1000 quote
+const NAV: u64 = 1_000_000_000;
+
+#[tokio::test]
+async fn test_rebalance_rejects_unlisted_perp_market() {
+    let mut test = QuasarProgramTest::start_new(1).await;
+    test.init_quasar_group().await.unwrap();
+    test.add_base_token(0).await.unwrap();
+
+    // the harness lists the token on a perp market the Mango group doesn't have,
+    // as if Mango had delisted it
+    let (mint, _) = test
+        .add_leverage_token(0, I80F48::from_num(3))
+        .await
+        .unwrap();
+
+    assert!(is_quasar_error(
+        test.rebalance(mint).await,
+        QuasarErrorCode::InvalidAccount
+    ));
+}
+
+#[tokio::test]
+async fn test_rebalance_at_target_places_no_order() {
+    let mut test = QuasarProgramTest::start_with_mango_accounts(
+        1,
+        &[MangoPositions::perp_at_leverage(NAV, 3)],
+    )
+    .await;
+    test.init_quasar_group().await.unwrap();
+    test.add_base_token(0).await.unwrap();
+    let mango_account = test.seeded_mango_accounts[0];
+    let mint = test
+        .add_leverage_token_with_seeded_account(0, I80F48::from_num(3), mango_account)
+        .await
+        .unwrap();
+
+    test.rebalance(mint).await.unwrap();
+    let leverage_token = test.load_leverage_token(mint).await;
+    assert_eq!(leverage_token.last_effective_leverage, I80F48::from_num(3));
+    assert_eq!(leverage_token.last_nav, I80F48::from_num(NAV));
+    assert_eq!(leverage_token.pending_rebalance_quantity, 0);
+    // a rebalance that trades nothing isn't recorded
+    assert_eq!(leverage_token.num_rebalances, 0);
+    assert_eq!(leverage_token.epoch_rebalances, 0);
+    let perp_account = test.load_mango_account(mango_account).await.perp_accounts[0];
+    assert_eq!(perp_account.bids_quantity, 0);
+    assert_eq!(perp_account.asks_quantity, 0);
+}
+
+#[tokio::test]
+async fn test_rebalance_under_target_bids_on_the_perp_market() {
+    let mut test = QuasarProgramTest::start_with_mango_accounts(
+        1,
+        &[MangoPositions::perp_at_leverage(NAV, 1)],
+    )
+    .await;
+    test.init_quasar_group().await.unwrap();
+    test.add_base_token(0).await.unwrap();
+    let mango_account = test.seeded_mango_accounts[0];
+    let mint = test
+        .add_leverage_token_with_seeded_account(0, I80F48::from_num(3), mango_account)
+        .await
+        .unwrap();
+
+    // two NAVs of exposure short, at 10 native quote per base lot. Nothing on the book fills
+    // the bid, so it rests in full
+    test.rebalance(mint).await.unwrap();
+    let leverage_token = test.load_leverage_token(mint).await;
+    assert_eq!(leverage_token.last_effective_leverage, I80F48::from_num(1));
+    assert_eq!(leverage_token.pending_rebalance_quantity, 200_000_000);
+    assert_eq!(leverage_token.num_rebalances, 0);
+    let perp_account = test.load_mango_account(mango_account).await.perp_accounts[0];
+    assert_eq!(perp_account.bids_quantity, 200_000_000);
+    assert_eq!(perp_account.asks_quantity, 0);
+    assert_eq!(perp_account.base_position, 100_000_000);
+}
+
+#[tokio::test]
+async fn test_rebalance_selling_spot_needs_the_spot_accounts() {
+    // half the NAV held as base deposits
+    let mut test =
+        QuasarProgramTest::start_with_mango_accounts(1, &[MangoPositions::spot(NAV, NAV / 2)])
+            .await;
+    test.init_quasar_group().await.unwrap();
+    test.add_base_token(0).await.unwrap();
+    let mango_account = test.seeded_mango_accounts[0];
+    let mint = test
+        .add_leverage_token_with_seeded_account(0, I80F48::from_num(0.25), mango_account)
+        .await
+        .unwrap();
+
+    // the correction sells a quarter of the NAV of spot, which the perp leg can't take over
+    assert!(is_quasar_error(
+        test.rebalance(mint).await,
+        QuasarErrorCode::InvalidAccount
+    ));
+    let perp_account = test.load_mango_account(mango_account).await.perp_accounts[0];
+    assert_eq!(perp_account.asks_quantity, 0);
+}
+
+#[tokio::test]
+async fn test_rebalance_over_the_cap_deleverages_during_the_cooldown() {
+    let mut test = QuasarProgramTest::start_with_mango_accounts(
+        1,
+        &[
+            MangoPositions::perp_at_leverage(NAV, 5),
+            MangoPositions::perp_at_leverage(NAV, 3),
+        ],
+    )
+    .await;
+    test.init_quasar_group().await.unwrap();
+    test.add_base_token(0).await.unwrap();
+    let params = LeverageTokenParams {
+        max_effective_leverage: Some(I80F48::from_num(4)),
+        rebalance_cooldown_slots: Some(1_000_000),
+        ..LeverageTokenParams::default()
+    };
+    let over_cap_account = test.seeded_mango_accounts[0];
+    let over_cap = test
+        .add_leverage_token_with_seeded_account(0, I80F48::from_num(3), over_cap_account)
+        .await
+        .unwrap();
+    test.set_leverage_token_params(over_cap, &params)
+        .await
+        .unwrap();
+    let under_cap_account = test.seeded_mango_accounts[1];
+    let under_cap = test
+        .add_leverage_token_with_seeded_account(0, I80F48::from_num(2), under_cap_account)
+        .await
+        .unwrap();
+    test.set_leverage_token_params(under_cap, &params)
+        .await
+        .unwrap();
+
+    // off target but under the cap, the token waits out the cooldown
+    assert!(is_quasar_error(
+        test.rebalance(under_cap).await,
+        QuasarErrorCode::RebalanceCooldown
+    ));
+
+    // over the cap it asks down to the target right away
+    test.rebalance(over_cap).await.unwrap();
+    let leverage_token = test.load_leverage_token(over_cap).await;
+    assert_eq!(leverage_token.last_effective_leverage, I80F48::from_num(5));
+    assert_eq!(leverage_token.pending_rebalance_quantity, -200_000_000);
+    let perp_account = test
+        .load_mango_account(over_cap_account)
+        .await
+        .perp_accounts[0];
+    assert_eq!(perp_account.asks_quantity, 200_000_000);
+    assert_eq!(perp_account.bids_quantity, 0);
+}
+
+#[tokio::test]
+async fn test_cancel_pulls_the_resting_rebalance_order() {
+    let mut test = QuasarProgramTest::start_with_mango_accounts(
+        1,
+        &[MangoPositions::perp_at_leverage(NAV, 1)],
+    )
+    .await;
+    test.init_quasar_group().await.unwrap();
+    test.add_base_token(0).await.unwrap();
+    let mango_account = test.seeded_mango_accounts[0];
+    let mint = test
+        .add_leverage_token_with_seeded_account(0, I80F48::from_num(3), mango_account)
+        .await
+        .unwrap();
+
+    test.rebalance(mint).await.unwrap();
+    let perp_account = test.load_mango_account(mango_account).await.perp_accounts[0];
+    assert_eq!(perp_account.bids_quantity, 200_000_000);
+
+    test.cancel_rebalance_orders(mint).await.unwrap();
+    let perp_account = test.load_mango_account(mango_account).await.perp_accounts[0];
+    assert_eq!(perp_account.bids_quantity, 0);
+    assert_eq!(perp_account.base_position, 100_000_000);
+    let leverage_token = test.load_leverage_token(mint).await;
+    assert_eq!(leverage_token.pending_rebalance_quantity, 0);
+}
+
+#[tokio::test]
+async fn test_freeze_cancels_resting_orders() {
+    let mut test = QuasarProgramTest::start_with_mango_accounts(
+        1,
+        &[MangoPositions::perp_at_leverage(NAV, 1)],
+    )
+    .await;
+    test.init_quasar_group().await.unwrap();
+    test.add_base_token(0).await.unwrap();
+    let mango_account = test.seeded_mango_accounts[0];
+    let mint = test
+        .add_leverage_token_with_seeded_account(0, I80F48::from_num(3), mango_account)
+        .await
+        .unwrap();
+    test.rebalance(mint).await.unwrap();
+
+    test.set_token_state(mint, TokenState::Frozen)
+        .await
+        .unwrap();
+    let perp_account = test.load_mango_account(mango_account).await.perp_accounts[0];
+    assert_eq!(perp_account.bids_quantity, 0);
+    let leverage_token = test.load_leverage_token(mint).await;
+    assert_eq!(leverage_token.state, TokenState::Frozen as u8);
+    assert_eq!(leverage_token.pending_rebalance_quantity, 0);
+
+    // nothing trades again until the token is unfrozen
+    test.refresh_blockhash().await;
+    assert!(is_quasar_error(
+        test.rebalance(mint).await,
+        QuasarErrorCode::TokenFrozen
+    ));
+    test.set_token_state(mint, TokenState::Active)
+        .await
+        .unwrap();
+    test.refresh_blockhash().await;
+    test.rebalance(mint).await.unwrap();
+    let perp_account = test.load_mango_account(mango_account).await.perp_accounts[0];
+    assert_eq!(perp_account.bids_quantity, 200_000_000);
+}
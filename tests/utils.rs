@@ -0,0 +1,1224 @@
+use bytemuck::Zeroable;
+use fixed::types::I80F48;
+use mango::{
+    instruction::MangoInstruction,
+    matching::{OrderType, Side},
+    state::{
+        DataType as MangoDataType, MangoAccount, MangoCache, MangoGroup, MetaData as MangoMetaData,
+        RootBankCache, MAX_PAIRS, QUOTE_INDEX,
+    },
+};
+use quasar::{
+    error::{QuasarError, QuasarErrorCode},
+    state::{native_price_from_nav, LeverageToken, MAX_LEVERAGE_TOKENS},
+    utils::{
+        add_market_value, apply_price_offset, bps_to_leverage, check_attested_exposure,
+        check_batch_size, check_deposit_credited, check_exposure_sign, check_mango_account_empty,
+        check_mango_cache_fresh, check_mint_funds, check_nav_move, check_nav_positive,
+        check_perp_flat, estimate_taker_fee, format_leverage, get_fee_amount,
+        get_maint_weighted_exposure, get_mango_quote_value, get_mango_spot_value, get_quote_amount,
+        get_size_adjusted_price, get_spot_ask_limits, get_spot_bid_limits, group_signer,
+        leverage_to_bps, liquidation_distance, load_mango_context, mango_net_asset_value,
+        native_to_ui_price, oracle_to_native_price, quote_to_reference, rebalance_client_order_id,
+        reference_to_quote, safe_div, simulate_init, split_mint_deposit, split_rebalance_exposure,
+        sqrt_ratio, BaseLots, NavBreakdown, PerpMarketUnits, QuoteLots, QuoteNative,
+        RebalanceResult, MAX_BATCH_SIZE, MAX_REBALANCE_PRICE_OFFSET_BPS,
+    },
+};
+use solana_program::{account_info::AccountInfo, pubkey::Pubkey};
+use spl_token::state::Account as TokenAccount;
+use std::collections::HashSet;
+
+fn is_quasar_error<T>(result: Result<T, QuasarError>, code: QuasarErrorCode) -> bool {
+    matches!(
+        result,
+        Err(QuasarError::QuasarErrorCode { quasar_error_code, .. }) if quasar_error_code == code
+    )
+}
+
+#[test]
+fn test_safe_div() {
+    assert_eq!(
+        safe_div(I80F48::from_num(10), I80F48::from_num(4)).unwrap(),
+        I80F48::from_num(2.5)
+    );
+    assert_eq!(
+        safe_div(I80F48::from_num(-9), I80F48::from_num(3)).unwrap(),
+        I80F48::from_num(-3)
+    );
+    assert!(is_quasar_error(
+        safe_div(I80F48::from_num(1), I80F48::from_num(0)),
+        QuasarErrorCode::MathError
+    ));
+    assert!(is_quasar_error(
+        safe_div(I80F48::MAX, I80F48::from_num(0.5)),
+        QuasarErrorCode::MathError
+    ));
+}
+
+#[test]
+fn test_sqrt_ratio() {
+    assert_eq!(sqrt_ratio(4, 1).unwrap(), I80F48::from_num(2));
+    assert_eq!(sqrt_ratio(9, 4).unwrap(), I80F48::from_num(1.5));
+    assert_eq!(sqrt_ratio(0, 7).unwrap(), I80F48::ZERO);
+    assert_eq!(sqrt_ratio(u64::MAX, u64::MAX).unwrap(), I80F48::ONE);
+
+    // rounded down to six decimals
+    assert_eq!(
+        sqrt_ratio(2, 1).unwrap(),
+        I80F48::from_num(1_414_213) / I80F48::from_num(1_000_000)
+    );
+
+    assert!(is_quasar_error(
+        sqrt_ratio(1, 0),
+        QuasarErrorCode::MathError
+    ));
+}
+
+#[test]
+fn test_perp_market_units_price_conversion() {
+    let price = I80F48::from_num(0.04512);
+    let (base_decimals, quote_decimals, base_lot_size, quote_lot_size) = (9u8, 6u8, 100i64, 10i64);
+
+    let inline = price
+        .checked_mul(I80F48::from_num(10u64.pow(quote_decimals.into())))
+        .unwrap()
+        .checked_mul(I80F48::from_num(base_lot_size))
+        .unwrap()
+        .checked_div(I80F48::from_num(quote_lot_size))
+        .unwrap()
+        .checked_div(I80F48::from_num(10u64.pow(base_decimals.into())))
+        .unwrap();
+
+    let units =
+        PerpMarketUnits::new(base_decimals, quote_decimals, base_lot_size, quote_lot_size).unwrap();
+    // the cached conversion rounds once where the inline chain rounds twice
+    let diff = (units.price_to_quote_lots(price).unwrap() - inline).abs();
+    assert!(diff <= I80F48::from_num(1e-12));
+    assert_eq!(
+        units
+            .native_quote_to_quote_lots(QuoteNative(I80F48::from_num(1_000)))
+            .unwrap(),
+        QuoteLots(I80F48::from_num(100))
+    );
+}
+
+#[test]
+fn test_perp_market_units_extreme_lot_sizes() {
+    // (price, base_decimals, quote_decimals, base_lot_size, quote_lot_size)
+    let cases = [
+        (1e8, 9u8, 6u8, 1_000_000_000_000i64, 1_000_000i64),
+        (0.000_000_5, 0, 6, 10_000_000, 1),
+        (123_456.789, 6, 6, 1_000_000_000, 1_000_000_000),
+        (0.000_001, 9, 6, 1, 1),
+    ];
+
+    for &(price, base_decimals, quote_decimals, base_lot_size, quote_lot_size) in cases.iter() {
+        let units =
+            PerpMarketUnits::new(base_decimals, quote_decimals, base_lot_size, quote_lot_size)
+                .unwrap();
+        let expected = price * 10f64.powi(quote_decimals.into()) * base_lot_size as f64
+            / quote_lot_size as f64
+            / 10f64.powi(base_decimals.into());
+
+        let actual: f64 = units
+            .price_to_quote_lots(I80F48::from_num(price))
+            .unwrap()
+            .to_num();
+        assert!(
+            (actual - expected).abs() < 1.0,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+}
+
+#[test]
+fn test_price_to_quote_lots_vectors() {
+    // (price, base_decimals, quote_decimals, base_lot_size, quote_lot_size, expected),
+    // expected = price * quote_unit * base_lot_size / quote_lot_size / base_unit worked out
+    // by hand
+    let vectors: [(f64, u8, u8, i64, i64, f64); 8] = [
+        (45_000.0, 6, 6, 100, 10, 450_000.0),
+        (3_000.0, 9, 6, 10_000, 100, 300.0),
+        (150.0, 9, 6, 10_000_000, 100, 15_000.0),
+        (1.5, 6, 6, 1_000_000, 1_000, 1_500.0),
+        (0.25, 8, 6, 100_000_000, 1, 250_000.0),
+        (20_000.0, 0, 6, 1, 1_000_000, 20_000.0),
+        (12.5, 9, 9, 1_000, 1_000, 12.5),
+        (64.0, 6, 2, 10_000, 1, 64.0),
+    ];
+
+    for &(price, base_decimals, quote_decimals, base_lot_size, quote_lot_size, expected) in
+        vectors.iter()
+    {
+        let units =
+            PerpMarketUnits::new(base_decimals, quote_decimals, base_lot_size, quote_lot_size)
+                .unwrap();
+        assert_eq!(
+            units.price_to_quote_lots(I80F48::from_num(price)).unwrap(),
+            I80F48::from_num(expected),
+            "price {} with decimals {}/{} and lot sizes {}/{}",
+            price,
+            base_decimals,
+            quote_decimals,
+            base_lot_size,
+            quote_lot_size
+        );
+    }
+}
+
+#[test]
+fn test_perp_market_units_conversions_compose() {
+    let units = PerpMarketUnits::new(9, 6, 100, 10).unwrap();
+    // 45.12 quote per base is 0.4512 quote lots per base lot
+    let price = units.price_to_quote_lots(I80F48::from_num(45.12)).unwrap();
+
+    let amount = QuoteNative(I80F48::from_num(4_512_000));
+    let quote_lots = units.native_quote_to_quote_lots(amount).unwrap();
+    assert_eq!(quote_lots, QuoteLots(I80F48::from_num(451_200)));
+
+    let quantity = units.quote_lots_to_base_lots(quote_lots, price).unwrap();
+    let diff = (quantity.0 - I80F48::from_num(1_000_000)).abs();
+    assert!(diff <= I80F48::from_num(1e-6));
+
+    let round_trip = units
+        .quote_lots_to_native_quote(units.base_lots_to_quote_lots(quantity, price).unwrap())
+        .unwrap();
+    assert!((round_trip.0 - amount.0).abs() <= I80F48::from_num(1e-6));
+
+    assert!(is_quasar_error(
+        units.quote_lots_to_base_lots(quote_lots, I80F48::from_num(0)),
+        QuasarErrorCode::MathError
+    ));
+    assert_eq!(
+        units
+            .base_lots_to_quote_lots(BaseLots(I80F48::from_num(-2)), I80F48::from_num(3))
+            .unwrap(),
+        QuoteLots(I80F48::from_num(-6))
+    );
+}
+
+#[test]
+fn test_perp_market_units_reject_empty_lot_sizes() {
+    assert!(is_quasar_error(
+        PerpMarketUnits::new(9, 6, 0, 10),
+        QuasarErrorCode::InvalidParam
+    ));
+    assert!(is_quasar_error(
+        PerpMarketUnits::new(9, 6, 100, 0),
+        QuasarErrorCode::InvalidParam
+    ));
+
+    // a market Mango reports without lot sizes fails cleanly instead of dividing by zero
+    let mut mango_group = MangoGroup::zeroed();
+    mango_group.perp_markets[0].quote_lot_size = 10;
+    assert!(is_quasar_error(
+        PerpMarketUnits::load(&mango_group, 0),
+        QuasarErrorCode::InvalidAccount
+    ));
+    mango_group.perp_markets[0].base_lot_size = 100;
+    mango_group.perp_markets[0].quote_lot_size = 0;
+    assert!(is_quasar_error(
+        PerpMarketUnits::load(&mango_group, 0),
+        QuasarErrorCode::InvalidAccount
+    ));
+    mango_group.perp_markets[0].quote_lot_size = 10;
+    assert!(PerpMarketUnits::load(&mango_group, 0).is_ok());
+}
+
+#[test]
+fn test_quote_decimals_overflowing_u64_fail_cleanly() {
+    // 10^20 native units don't fit a u64
+    assert!(is_quasar_error(
+        PerpMarketUnits::new(9, 20, 100, 10),
+        QuasarErrorCode::MathError
+    ));
+    assert!(is_quasar_error(
+        PerpMarketUnits::new(20, 6, 100, 10),
+        QuasarErrorCode::MathError
+    ));
+    assert!(PerpMarketUnits::new(9, 19, 100, 10).is_ok());
+
+    assert!(LeverageToken::check_quote_decimals(19).is_ok());
+    assert!(is_quasar_error(
+        LeverageToken::check_quote_decimals(20),
+        QuasarErrorCode::InvalidParam
+    ));
+}
+
+#[test]
+fn test_check_batch_size() {
+    assert!(check_batch_size(1).is_ok());
+    assert!(check_batch_size(MAX_BATCH_SIZE).is_ok());
+    assert!(is_quasar_error(
+        check_batch_size(0),
+        QuasarErrorCode::InvalidParam
+    ));
+    assert!(is_quasar_error(
+        check_batch_size(MAX_BATCH_SIZE + 1),
+        QuasarErrorCode::InvalidParam
+    ));
+}
+
+#[test]
+fn test_format_leverage() {
+    assert_eq!(format_leverage(I80F48::from_num(3)), "3.00x");
+    assert_eq!(format_leverage(I80F48::from_num(0.5)), "0.50x");
+    assert_eq!(format_leverage(I80F48::from_num(2.346)), "2.35x");
+    assert_eq!(format_leverage(I80F48::from_num(10.001)), "10.00x");
+    assert_eq!(format_leverage(I80F48::from_num(-1.5)), "-1.50x");
+    assert_eq!(format_leverage(I80F48::from_num(-0.25)), "-0.25x");
+    assert_eq!(format_leverage(I80F48::from_num(0)), "0.00x");
+}
+
+#[test]
+fn test_split_mint_deposit() {
+    assert_eq!(split_mint_deposit(1_000_000, 0).unwrap(), (1_000_000, 0));
+    assert_eq!(
+        split_mint_deposit(1_000_000, 3_000).unwrap(),
+        (700_000, 300_000)
+    );
+    assert_eq!(
+        split_mint_deposit(1_000_000, 10_000).unwrap(),
+        (0, 1_000_000)
+    );
+    // the swapped part rounds down, the rest stays quote
+    assert_eq!(split_mint_deposit(999, 5_000).unwrap(), (500, 499));
+    assert!(is_quasar_error(
+        split_mint_deposit(1_000_000, 10_001),
+        QuasarErrorCode::InvalidParam
+    ));
+}
+
+#[test]
+fn test_mint_deposit_collateral_composition() {
+    // 1000 USDC minting a token swapping 30% into SOL at 45.12 USDC, on a market with
+    // 0.1 SOL coin lots and 100 native USDC pc lots
+    let price = I80F48::from_num(0.04512);
+    let (coin_lot_size, pc_lot_size) = (100_000_000u64, 100u64);
+
+    let (kept, swapped) = split_mint_deposit(1_000_000_000, 3_000).unwrap();
+    let (limit_price, max_coin_qty) =
+        get_spot_bid_limits(swapped, price, coin_lot_size, pc_lot_size, 100).unwrap();
+    assert_eq!(limit_price, 45_571);
+    assert_eq!(max_coin_qty, 66);
+
+    // filled at the oracle price of 45_120 pc lots per coin lot, the spend never exceeds
+    // the swapped amount
+    let spent = max_coin_qty * 45_120 * pc_lot_size;
+    assert!(spent <= swapped);
+
+    let quote_collateral = kept + swapped - spent;
+    let base_collateral = max_coin_qty * coin_lot_size;
+    assert_eq!(quote_collateral, 702_208_000);
+    assert_eq!(base_collateral, 6_600_000_000);
+}
+
+#[test]
+fn test_spot_bid_limits_below_one_lot() {
+    let (_, max_coin_qty) =
+        get_spot_bid_limits(1_000, I80F48::from_num(0.04512), 100_000_000, 100, 100).unwrap();
+    assert_eq!(max_coin_qty, 0);
+    assert!(is_quasar_error(
+        get_spot_bid_limits(1_000, I80F48::from_num(1), 0, 100, 100),
+        QuasarErrorCode::InvalidParam
+    ));
+}
+
+#[test]
+fn test_under_funded_mint_is_rejected_early() {
+    let mut quote_account = TokenAccount::default();
+    quote_account.amount = 1_000_000;
+    let fee = get_fee_amount(1_000_000, 30).unwrap();
+
+    // the fee is paid on top of the deposit
+    assert!(check_mint_funds(quote_account.amount, 1_000_000 - fee, fee).is_ok());
+    assert!(is_quasar_error(
+        check_mint_funds(quote_account.amount, 1_000_000, fee),
+        QuasarErrorCode::InsufficientFunds
+    ));
+    assert!(check_mint_funds(u64::MAX, u64::MAX, 1).is_err());
+}
+
+#[test]
+fn test_mint_and_burn_are_blocked_at_negative_nav() {
+    let quote_decimals = 6;
+    let price_at =
+        |nav: i64| native_price_from_nav(I80F48::from_num(nav), 1_000, 6, quote_decimals);
+
+    // an underwater position prices the token below zero, which would round redemptions to
+    // nothing while the tokens are burned
+    let negative_price = price_at(-5_000_000).unwrap();
+    assert!(negative_price < I80F48::ZERO);
+    assert!(is_quasar_error(
+        check_nav_positive(negative_price),
+        QuasarErrorCode::NonPositiveNav
+    ));
+    assert!(is_quasar_error(
+        check_nav_positive(price_at(0).unwrap()),
+        QuasarErrorCode::NonPositiveNav
+    ));
+
+    assert!(check_nav_positive(price_at(5_000_000).unwrap()).is_ok());
+}
+
+#[test]
+fn test_price_offset_moves_rebalance_orders_away_from_the_market() {
+    let price = I80F48::from_num(1_000);
+    assert_eq!(apply_price_offset(price, true, 0).unwrap(), price);
+    assert_eq!(apply_price_offset(price, false, 0).unwrap(), price);
+
+    // bids rest below the market, asks above
+    assert_eq!(
+        apply_price_offset(price, true, 20).unwrap(),
+        I80F48::from_num(998)
+    );
+    assert_eq!(
+        apply_price_offset(price, false, 20).unwrap(),
+        I80F48::from_num(1_002)
+    );
+
+    assert!(apply_price_offset(price, true, MAX_REBALANCE_PRICE_OFFSET_BPS).is_ok());
+}
+
+#[test]
+fn test_nav_of_many_large_positions_fails_cleanly() {
+    let large = I80F48::MAX / I80F48::from_num(4);
+
+    // offsetting perp values are netted before they reach the total
+    let mut total = I80F48::ZERO;
+    for _ in 0..MAX_PAIRS {
+        total = add_market_value(total, I80F48::ZERO, large + large, -large - large).unwrap();
+    }
+    assert_eq!(total, I80F48::ZERO);
+
+    // large spot values across many markets overflow with a math error instead of panicking
+    let mut result = Ok(I80F48::ZERO);
+    for _ in 0..MAX_PAIRS {
+        result =
+            result.and_then(|total| add_market_value(total, large, I80F48::ZERO, I80F48::ZERO));
+    }
+    assert!(is_quasar_error(result, QuasarErrorCode::MathError));
+}
+
+#[test]
+fn test_leverage_bps_round_trip() {
+    assert_eq!(leverage_to_bps(I80F48::from_num(3)).unwrap(), 30_000);
+    assert_eq!(leverage_to_bps(I80F48::from_num(-1.5)).unwrap(), -15_000);
+    assert_eq!(bps_to_leverage(30_000), I80F48::from_num(3));
+    assert_eq!(bps_to_leverage(-15_000), I80F48::from_num(-1.5));
+
+    // every bps survives the trip through the stored I80F48, shorts included
+    for bps in (-200_000..=200_000).step_by(7) {
+        assert_eq!(leverage_to_bps(bps_to_leverage(bps)).unwrap(), bps);
+    }
+    assert_eq!(
+        leverage_to_bps(bps_to_leverage(i32::MAX)).unwrap(),
+        i32::MAX
+    );
+    assert_eq!(
+        leverage_to_bps(bps_to_leverage(i32::MIN)).unwrap(),
+        i32::MIN
+    );
+
+    assert!(is_quasar_error(
+        leverage_to_bps(I80F48::from_num(i32::MAX)),
+        QuasarErrorCode::MathError
+    ));
+}
+
+#[test]
+fn test_get_fee_amount() {
+    assert_eq!(get_fee_amount(1_000_000, 0).unwrap(), 0);
+    assert_eq!(get_fee_amount(1_000_000, 30).unwrap(), 3_000);
+    // rounds up
+    assert_eq!(get_fee_amount(1_001, 10).unwrap(), 2);
+    assert_eq!(get_fee_amount(u64::MAX, 10_000).unwrap(), u64::MAX);
+    assert!(is_quasar_error(
+        get_fee_amount(1_000, 10_001),
+        QuasarErrorCode::InvalidParam
+    ));
+}
+
+#[test]
+fn test_check_nav_move() {
+    let before = I80F48::from_num(100);
+
+    // 1% threshold
+    assert!(check_nav_move(before, I80F48::from_num(101), 100).is_ok());
+    assert!(check_nav_move(before, I80F48::from_num(99), 100).is_ok());
+    assert!(is_quasar_error(
+        check_nav_move(before, I80F48::from_num(101.01), 100),
+        QuasarErrorCode::NavMoveTooLarge
+    ));
+    assert!(is_quasar_error(
+        check_nav_move(before, I80F48::from_num(98.99), 100),
+        QuasarErrorCode::NavMoveTooLarge
+    ));
+
+    // disabled
+    assert!(check_nav_move(before, I80F48::from_num(1_000), 0).is_ok());
+}
+
+#[test]
+fn test_simulate_init_matches_group_signer() {
+    let program_id = Pubkey::new_unique();
+    let group_key = Pubkey::new_unique();
+
+    let (signer_key, nonce) = simulate_init(&group_key, &program_id);
+    assert_eq!(
+        group_signer(&group_key, nonce, &program_id).unwrap(),
+        signer_key
+    );
+    assert!((0..nonce).all(|n| group_signer(&group_key, n, &program_id).is_err()));
+}
+
+#[test]
+fn test_rebalance_client_order_id_is_forwarded() {
+    // the slot in the high bits, the token index in the low byte
+    let client_order_id = rebalance_client_order_id(123_456, 3);
+    assert_eq!(client_order_id, 31_604_739);
+    assert_eq!(rebalance_client_order_id(0, 0), 0);
+    assert_eq!(rebalance_client_order_id(1, 0), 256);
+    assert_eq!(rebalance_client_order_id(1, MAX_LEVERAGE_TOKENS - 1), 287);
+
+    let mut ids = HashSet::new();
+    for slot in 123_456..123_460 {
+        for index in 0..MAX_LEVERAGE_TOKENS {
+            assert!(ids.insert(rebalance_client_order_id(slot, index)));
+        }
+    }
+
+    let data = MangoInstruction::PlacePerpOrder {
+        price: 100,
+        quantity: 10,
+        client_order_id,
+        side: Side::Bid,
+        order_type: OrderType::Limit,
+    }
+    .pack();
+    match MangoInstruction::unpack(&data) {
+        Some(MangoInstruction::PlacePerpOrder {
+            client_order_id: packed,
+            ..
+        }) => assert_eq!(packed, client_order_id),
+        _ => panic!("not a PlacePerpOrder"),
+    }
+}
+
+#[test]
+fn test_estimate_taker_fee() {
+    let units = PerpMarketUnits::new(9, 6, 100, 10).unwrap();
+    let taker_fee = I80F48::from_num(0.0005);
+    let price = I80F48::from_num(45);
+
+    // 1000 base lots at 45 quote lots each, 10 native quote per quote lot
+    let notional = I80F48::from_num(1_000 * 45 * 10);
+    for &quantity in [1_000, -1_000].iter() {
+        let fee = estimate_taker_fee(
+            &units,
+            BaseLots(I80F48::from_num(quantity)),
+            price,
+            taker_fee,
+        )
+        .unwrap();
+        let diff = (fee.0 - notional * taker_fee).abs();
+        assert!(diff <= I80F48::from_num(1e-9));
+        assert!((fee.0 - I80F48::from_num(225)).abs() <= I80F48::from_num(1e-9));
+    }
+
+    assert_eq!(
+        estimate_taker_fee(&units, BaseLots(I80F48::from_num(0)), price, taker_fee).unwrap(),
+        QuoteNative(I80F48::from_num(0))
+    );
+}
+
+#[test]
+fn test_size_adjusted_price() {
+    let price = I80F48::from_num(10_000);
+    let supply = 1_000_000;
+
+    // a mint of a tenth of the supply pays a tenth of the 50 bps premium
+    assert_eq!(
+        get_size_adjusted_price(price, 100_000, supply, 50, true).unwrap(),
+        I80F48::from_num(10_005)
+    );
+    assert_eq!(
+        get_size_adjusted_price(price, 100_000, supply, 50, false).unwrap(),
+        I80F48::from_num(9_995)
+    );
+    // the premium is capped at the full rate
+    assert_eq!(
+        get_size_adjusted_price(price, 10 * supply, supply, 50, true).unwrap(),
+        I80F48::from_num(10_050)
+    );
+
+    // small mints and burns settle at the plain price
+    assert_eq!(
+        get_size_adjusted_price(price, 100, supply, 50, true).unwrap(),
+        price
+    );
+    assert_eq!(
+        get_size_adjusted_price(price, 100, supply, 50, false).unwrap(),
+        price
+    );
+    // so does the first mint, and everything while the premium is disabled
+    assert_eq!(
+        get_size_adjusted_price(price, 100_000, 0, 50, true).unwrap(),
+        price
+    );
+    assert_eq!(
+        get_size_adjusted_price(price, supply, supply, 0, true).unwrap(),
+        price
+    );
+
+    assert!(is_quasar_error(
+        get_size_adjusted_price(price, 1, supply, 10_001, true),
+        QuasarErrorCode::InvalidParam
+    ));
+}
+
+#[test]
+fn test_spot_ask_limits() {
+    // 300 USDC worth of BTC at 45_120 pc lots per coin lot, down to 1% below
+    let (limit_price, max_coin_qty) = get_spot_ask_limits(
+        300_000_000,
+        I80F48::from_num(0.04512),
+        100_000_000,
+        100,
+        100,
+    )
+    .unwrap();
+    assert_eq!(limit_price, 44_668);
+    assert_eq!(max_coin_qty, 66);
+
+    assert!(is_quasar_error(
+        get_spot_ask_limits(1_000, I80F48::from_num(1), 100, 100, 10_001),
+        QuasarErrorCode::InvalidParam
+    ));
+}
+
+#[test]
+fn test_rebalance_split_minimizes_traded_notional() {
+    let quote = |value: f64| QuoteNative(I80F48::from_num(value));
+
+    // (spot exposure, perp exposure, target exposure, spot delta, perp delta)
+    let cases = [
+        // 3x long at a NAV of 1_000 going to 2x: the 1_000 reduction sells all 300 of spot
+        // and takes the other 700 off the perp leg
+        (300.0, 2_700.0, 2_000.0, -300.0, -700.0),
+        // a 100 reduction is covered by the spot collateral alone
+        (300.0, 2_700.0, 2_900.0, -100.0, 0.0),
+        // a fractional reduction keeps its fraction on the spot leg
+        (300.0, 2_700.0, 2_999.5, -0.5, 0.0),
+        // a short token sells its 300 of spot collateral and shorts the other 1_000
+        (300.0, 0.0, -1_000.0, -300.0, -1_000.0),
+        // increasing exposure goes through the perp leg
+        (300.0, 2_700.0, 3_500.0, 0.0, 500.0),
+        (0.0, -1_000.0, -500.0, 0.0, 500.0),
+        // borrowed base isn't sold further
+        (-200.0, 1_000.0, 500.0, 0.0, -300.0),
+        (0.0, 0.0, 0.0, 0.0, 0.0),
+    ];
+    for &(spot, perp, target, spot_delta, perp_delta) in cases.iter() {
+        assert_eq!(
+            split_rebalance_exposure(quote(spot), quote(perp), quote(target)).unwrap(),
+            (quote(spot_delta), quote(perp_delta))
+        );
+    }
+}
+
+#[test]
+fn test_depegged_quote_adjusts_nav() {
+    let nav = I80F48::from_num(1_000_000);
+
+    // a pegged quote leaves the NAV as is
+    assert_eq!(quote_to_reference(nav, I80F48::ONE).unwrap(), nav);
+
+    // with the quote at 0.5 the same quote NAV is worth half in the reference unit, and one
+    // reference unit costs twice the quote
+    let quote_price = I80F48::from_num(0.5);
+    assert_eq!(
+        quote_to_reference(nav, quote_price).unwrap(),
+        I80F48::from_num(500_000)
+    );
+    assert_eq!(
+        reference_to_quote(nav, quote_price).unwrap(),
+        I80F48::from_num(2_000_000)
+    );
+
+    assert!(is_quasar_error(
+        quote_to_reference(nav, I80F48::from_num(0)),
+        QuasarErrorCode::UnreliableOracle
+    ));
+    assert!(is_quasar_error(
+        reference_to_quote(nav, I80F48::from_num(-1)),
+        QuasarErrorCode::UnreliableOracle
+    ));
+}
+
+#[test]
+fn test_mango_spot_value_overflow_is_an_error() {
+    let mut mango_account = MangoAccount::zeroed();
+    let mut bank_cache = RootBankCache::zeroed();
+    bank_cache.deposit_index = I80F48::from_num(2);
+    bank_cache.borrow_index = I80F48::from_num(2);
+
+    mango_account.deposits[0] = I80F48::from_num(1_000);
+    assert_eq!(
+        get_mango_spot_value(&mango_account, &bank_cache, I80F48::from_num(3), 0).unwrap(),
+        I80F48::from_num(6_000)
+    );
+
+    mango_account.deposits[0] = I80F48::MAX;
+    assert!(is_quasar_error(
+        get_mango_spot_value(&mango_account, &bank_cache, I80F48::from_num(1), 0),
+        QuasarErrorCode::MathError
+    ));
+
+    mango_account.deposits[0] = I80F48::from_num(0);
+    mango_account.borrows[0] = I80F48::MAX;
+    assert!(is_quasar_error(
+        get_mango_spot_value(&mango_account, &bank_cache, I80F48::from_num(1), 0),
+        QuasarErrorCode::MathError
+    ));
+
+    // the index fits but the value doesn't
+    mango_account.borrows[0] = I80F48::from_num(0);
+    mango_account.deposits[0] = I80F48::MAX / 4;
+    assert!(is_quasar_error(
+        get_mango_spot_value(&mango_account, &bank_cache, I80F48::from_num(4), 0),
+        QuasarErrorCode::MathError
+    ));
+}
+
+#[test]
+fn test_short_mango_deposit_is_detected() {
+    let mut mango_account = MangoAccount::zeroed();
+    let mut bank_cache = RootBankCache::zeroed();
+    bank_cache.deposit_index = I80F48::from_num(2);
+    bank_cache.borrow_index = I80F48::from_num(2);
+    let one = I80F48::from_num(1);
+
+    mango_account.deposits[QUOTE_INDEX] = I80F48::from_num(500);
+    let before = get_mango_spot_value(&mango_account, &bank_cache, one, QUOTE_INDEX).unwrap();
+
+    // 1_000 native credited as 500 deposit units at index 2
+    mango_account.deposits[QUOTE_INDEX] = I80F48::from_num(1_000);
+    let after = get_mango_spot_value(&mango_account, &bank_cache, one, QUOTE_INDEX).unwrap();
+    assert!(check_deposit_credited(before, after, 1_000).is_ok());
+    // a native unit lost to rounding is tolerated, more isn't
+    assert!(check_deposit_credited(before, after - one, 1_000).is_ok());
+    assert!(is_quasar_error(
+        check_deposit_credited(before, after - one * 2, 1_000),
+        QuasarErrorCode::DepositNotCredited
+    ));
+
+    // repaying a borrow counts as credited too
+    mango_account.deposits[QUOTE_INDEX] = I80F48::from_num(0);
+    mango_account.borrows[QUOTE_INDEX] = I80F48::from_num(500);
+    let before = get_mango_spot_value(&mango_account, &bank_cache, one, QUOTE_INDEX).unwrap();
+    mango_account.borrows[QUOTE_INDEX] = I80F48::from_num(0);
+    let after = get_mango_spot_value(&mango_account, &bank_cache, one, QUOTE_INDEX).unwrap();
+    assert!(check_deposit_credited(before, after, 1_000).is_ok());
+}
+
+#[test]
+fn test_quote_is_counted_once_at_face_value() {
+    // market loops run over `num_oracles <= MAX_PAIRS`, so they never reach the quote
+    assert!(QUOTE_INDEX >= MAX_PAIRS);
+
+    let mut mango_account = MangoAccount::zeroed();
+    let mut mango_cache = MangoCache::zeroed();
+    mango_cache.root_bank_cache[QUOTE_INDEX].deposit_index = I80F48::from_num(1.5);
+    mango_cache.root_bank_cache[QUOTE_INDEX].borrow_index = I80F48::from_num(2);
+    // no market price applies to the quote
+    for price_cache in mango_cache.price_cache.iter_mut() {
+        price_cache.price = I80F48::from_num(3);
+    }
+
+    mango_account.deposits[QUOTE_INDEX] = I80F48::from_num(1_000);
+    assert_eq!(
+        get_mango_quote_value(&mango_account, &mango_cache).unwrap(),
+        I80F48::from_num(1_500)
+    );
+
+    mango_account.deposits[QUOTE_INDEX] = I80F48::from_num(0);
+    mango_account.borrows[QUOTE_INDEX] = I80F48::from_num(100);
+    assert_eq!(
+        get_mango_quote_value(&mango_account, &mango_cache).unwrap(),
+        I80F48::from_num(-200)
+    );
+}
+
+#[test]
+fn test_perp_market_repoint_requires_flat_account() {
+    let mut mango_account = MangoAccount::zeroed();
+    assert!(check_perp_flat(&mango_account).is_ok());
+
+    mango_account.perp_accounts[2].base_position = -5;
+    assert!(is_quasar_error(
+        check_perp_flat(&mango_account),
+        QuasarErrorCode::PositionNotFlat
+    ));
+
+    // a taker fill not consumed yet closes the position
+    mango_account.perp_accounts[2].taker_base = 5;
+    assert!(check_perp_flat(&mango_account).is_ok());
+
+    // a resting order would open one later
+    mango_account.perp_accounts[0].bids_quantity = 1;
+    assert!(is_quasar_error(
+        check_perp_flat(&mango_account),
+        QuasarErrorCode::PositionNotFlat
+    ));
+}
+
+#[test]
+fn test_mango_account_empty_ignores_dust() {
+    let mut mango_account = MangoAccount::zeroed();
+    assert!(check_mango_account_empty(&mango_account).is_ok());
+
+    mango_account.deposits[QUOTE_INDEX] = I80F48::from_num(0.9);
+    mango_account.borrows[0] = I80F48::from_num(0.5);
+    mango_account.perp_accounts[1].quote_position = I80F48::from_num(-0.9);
+    assert!(check_mango_account_empty(&mango_account).is_ok());
+
+    let edits: Vec<fn(&mut MangoAccount)> = vec![
+        |account| account.deposits[QUOTE_INDEX] = I80F48::from_num(1),
+        |account| account.borrows[0] = I80F48::from_num(2),
+        |account| account.perp_accounts[1].quote_position = I80F48::from_num(-1),
+        |account| account.perp_accounts[1].taker_quote = 1,
+    ];
+    for edit in edits {
+        let mut not_empty = mango_account;
+        edit(&mut not_empty);
+        assert!(is_quasar_error(
+            check_mango_account_empty(&not_empty),
+            QuasarErrorCode::MangoAccountNotEmpty
+        ));
+    }
+
+    // an open perp position is reported as such
+    mango_account.perp_accounts[1].base_position = 1;
+    assert!(is_quasar_error(
+        check_mango_account_empty(&mango_account),
+        QuasarErrorCode::PositionNotFlat
+    ));
+}
+
+#[test]
+fn test_nav_breakdown_reports_resting_orders() {
+    let mut mango_group = MangoGroup::zeroed();
+    mango_group.num_oracles = 1;
+    mango_group.perp_markets[0].base_lot_size = 100;
+    mango_group.perp_markets[0].quote_lot_size = 10;
+    let mut mango_cache = MangoCache::zeroed();
+    mango_cache.price_cache[0].price = I80F48::from_num(2);
+    mango_cache.root_bank_cache[QUOTE_INDEX].deposit_index = I80F48::from_num(1);
+    let mut mango_account = MangoAccount::zeroed();
+    mango_account.deposits[QUOTE_INDEX] = I80F48::from_num(1_000);
+    mango_account.perp_accounts[0].base_position = 5;
+
+    let filled = NavBreakdown::load(&mango_group, &mango_cache, &mango_account, 0).unwrap();
+    assert_eq!(filled.exposure, I80F48::from_num(1_000));
+    assert_eq!(filled.pending_exposure, I80F48::from_num(0));
+    assert_eq!(filled.effective_leverage(), filled.intended_leverage());
+
+    // a rebalance bid of 3 lots left resting, 1 lot of which sells back
+    mango_account.perp_accounts[0].bids_quantity = 3;
+    mango_account.perp_accounts[0].asks_quantity = 1;
+    let resting = NavBreakdown::load(&mango_group, &mango_cache, &mango_account, 0).unwrap();
+    assert_eq!(resting.net_asset_value, filled.net_asset_value);
+    assert_eq!(resting.exposure, filled.exposure);
+    assert_eq!(resting.pending_exposure, I80F48::from_num(400));
+    assert!(resting.intended_leverage().unwrap() > resting.effective_leverage().unwrap());
+
+    let data = resting.pack();
+    assert_eq!(data[32..], resting.pending_exposure.to_le_bytes());
+}
+
+#[test]
+fn test_net_asset_value_counts_every_market() {
+    // 1_000 quote, 5 base deposited on market 0 and 10 long on market 1, both at a price of 2
+    let mut mango_group = MangoGroup::zeroed();
+    mango_group.num_oracles = 2;
+    mango_group.perp_markets[1].base_lot_size = 1;
+    mango_group.perp_markets[1].quote_lot_size = 1;
+    let mut mango_cache = MangoCache::zeroed();
+    mango_cache.price_cache[0].price = I80F48::from_num(2);
+    mango_cache.price_cache[1].price = I80F48::from_num(2);
+    mango_cache.root_bank_cache[0].deposit_index = I80F48::ONE;
+    mango_cache.root_bank_cache[QUOTE_INDEX].deposit_index = I80F48::ONE;
+    let mut mango_account = MangoAccount::zeroed();
+    mango_account.deposits[QUOTE_INDEX] = I80F48::from_num(1_000);
+    mango_account.deposits[0] = I80F48::from_num(5);
+    mango_account.perp_accounts[1].base_position = 10;
+    mango_account.perp_accounts[1].quote_position = I80F48::from_num(-15);
+
+    let net_asset_value =
+        mango_net_asset_value(&mango_group, &mango_cache, &mango_account).unwrap();
+    assert_eq!(net_asset_value, I80F48::from_num(1_015));
+
+    // the breakdown of a token on market 0 values the account the same, with its own exposure
+    let breakdown = NavBreakdown::load(&mango_group, &mango_cache, &mango_account, 0).unwrap();
+    assert_eq!(breakdown.net_asset_value, net_asset_value);
+    assert_eq!(breakdown.exposure, I80F48::from_num(10));
+}
+
+#[test]
+fn test_liquidation_distance_of_leveraged_positions() {
+    // 1_000 USDC long 3x, with 10% of the perp position discounted at maintenance
+    let mut mango_group = MangoGroup::zeroed();
+    mango_group.num_oracles = 1;
+    mango_group.perp_markets[0].base_lot_size = 100;
+    mango_group.perp_markets[0].quote_lot_size = 10;
+    mango_group.perp_markets[0].maint_asset_weight = I80F48::from_num(0.9);
+    mango_group.perp_markets[0].maint_liab_weight = I80F48::from_num(1.1);
+    let mut mango_cache = MangoCache::zeroed();
+    mango_cache.price_cache[0].price = I80F48::from_num(2);
+    mango_cache.root_bank_cache[QUOTE_INDEX].deposit_index = I80F48::from_num(1);
+    let mut mango_account = MangoAccount::zeroed();
+    mango_account.deposits[QUOTE_INDEX] = I80F48::from_num(1_000);
+    mango_account.perp_accounts[0].base_position = 15;
+    mango_account.perp_accounts[0].quote_position = I80F48::from_num(-3_000);
+
+    let breakdown = NavBreakdown::load(&mango_group, &mango_cache, &mango_account, 0).unwrap();
+    assert_eq!(breakdown.net_asset_value, I80F48::from_num(1_000));
+    assert_eq!(breakdown.exposure, I80F48::from_num(3_000));
+    let weighted_exposure =
+        get_maint_weighted_exposure(&mango_group, &mango_cache, &mango_account, 0).unwrap();
+    assert_eq!(weighted_exposure, I80F48::from_num(2_700));
+
+    // health 1_000 - 3_000 + 2_700 * ratio reaches zero at 20/27 of the price
+    let distance = liquidation_distance(
+        breakdown.net_asset_value,
+        breakdown.exposure,
+        weighted_exposure,
+    )
+    .unwrap();
+    let expected = I80F48::from_num(7) / I80F48::from_num(27);
+    assert!((distance - expected).abs() < I80F48::from_num(0.000001));
+
+    // an inverse token is liquidated by a rise, 2x short with 1.1 liability weight at 15/11
+    let distance = liquidation_distance(
+        I80F48::from_num(1_000),
+        I80F48::from_num(-2_000),
+        I80F48::from_num(-2_200),
+    )
+    .unwrap();
+    let expected = I80F48::from_num(4) / I80F48::from_num(11);
+    assert!((distance - expected).abs() < I80F48::from_num(0.000001));
+
+    // a flat or unleveraged long account can't be liquidated by the price
+    let nav = I80F48::from_num(1_000);
+    assert_eq!(
+        liquidation_distance(nav, I80F48::from_num(0), I80F48::from_num(0)).unwrap(),
+        I80F48::MAX
+    );
+    assert_eq!(
+        liquidation_distance(nav, nav, nav * I80F48::from_num(0.9)).unwrap(),
+        I80F48::MAX
+    );
+    assert_eq!(
+        liquidation_distance(I80F48::from_num(0), nav, nav).unwrap(),
+        I80F48::from_num(0)
+    );
+}
+
+#[test]
+fn test_oracle_price_is_converted_to_native_units() {
+    // SOL at 40 USDC: 40 * 10^6 native quote per 10^9 native base
+    assert_eq!(
+        oracle_to_native_price(I80F48::from_num(40), 9, 6).unwrap(),
+        I80F48::from_num(40_000_000) / I80F48::from_num(1_000_000_000)
+    );
+    // BTC at 50_000 USDC, both with 6 decimals, trades at the oracle price
+    assert_eq!(
+        oracle_to_native_price(I80F48::from_num(50_000), 6, 6).unwrap(),
+        I80F48::from_num(50_000)
+    );
+    assert!(is_quasar_error(
+        oracle_to_native_price(I80F48::from_num(1), 20, 6),
+        QuasarErrorCode::MathError
+    ));
+}
+
+#[test]
+fn test_ui_price_is_native_price_over_quote_units() {
+    let quote_unit = I80F48::from_num(1_000_000);
+    // a token without decimals is priced per native unit
+    let native_price = I80F48::from_num(2_500_000);
+    assert_eq!(
+        native_to_ui_price(native_price, 0, 6).unwrap(),
+        native_price / quote_unit
+    );
+    // leveraged tokens share the quote decimals, so the native price is already per token
+    let native_price = I80F48::from_num(1.25);
+    assert_eq!(
+        native_to_ui_price(native_price, 6, 6).unwrap(),
+        native_price
+    );
+    assert!(is_quasar_error(
+        native_to_ui_price(native_price, 6, 20),
+        QuasarErrorCode::MathError
+    ));
+}
+
+#[test]
+fn test_rebalance_result_packing() {
+    let before = I80F48::from_num(3.5);
+    let after = I80F48::from_num(3);
+    let traded = RebalanceResult {
+        traded: true,
+        side: Side::Ask,
+        price: 4_000,
+        quantity: 25,
+        effective_leverage_before: before,
+        effective_leverage_after: after,
+    }
+    .pack();
+    assert_eq!(traded[0], 1);
+    assert_eq!(traded[1], Side::Ask as u8);
+    assert_eq!(traded[2..10], 4_000i64.to_le_bytes());
+    assert_eq!(traded[10..18], 25i64.to_le_bytes());
+    assert_eq!(traded[18..34], before.to_le_bytes());
+    assert_eq!(traded[34..], after.to_le_bytes());
+
+    // a token within its band keeps its leverage
+    let idle = RebalanceResult {
+        traded: false,
+        side: Side::Bid,
+        price: 0,
+        quantity: 0,
+        effective_leverage_before: after,
+        effective_leverage_after: after,
+    }
+    .pack();
+    assert_eq!(idle[..18], [0u8; 18]);
+    assert_eq!(idle[18..34], idle[34..]);
+}
+
+#[test]
+fn test_exposure_sign_follows_target_leverage() {
+    let nav = I80F48::from_num(1_000);
+    let long = I80F48::from_num(3);
+    let inverse = I80F48::from_num(-2);
+    assert!(check_exposure_sign(long, nav * long).is_ok());
+    assert!(check_exposure_sign(inverse, nav * inverse).is_ok());
+    assert!(check_exposure_sign(long, I80F48::from_num(0)).is_ok());
+
+    // a negative NAV flips the exposure of either token
+    assert!(is_quasar_error(
+        check_exposure_sign(long, -nav * long),
+        QuasarErrorCode::MathError
+    ));
+    assert!(is_quasar_error(
+        check_exposure_sign(inverse, -nav * inverse),
+        QuasarErrorCode::MathError
+    ));
+}
+
+#[test]
+fn test_order_price_below_one_quote_lot_is_rejected() {
+    // a 0.5 USDC base of 6 decimals on lots of 1 base and 100 quote
+    let units = PerpMarketUnits::new(6, 6, 1, 100).unwrap();
+    let price = units.price_to_quote_lots(I80F48::from_num(0.5)).unwrap();
+    assert!(price < I80F48::from_num(1));
+    assert!(is_quasar_error(
+        units.check_order_price(price),
+        QuasarErrorCode::PriceRoundsToZero
+    ));
+
+    // finer lots quote it above one lot
+    let units = PerpMarketUnits::new(6, 6, 1_000, 1).unwrap();
+    let price = units.price_to_quote_lots(I80F48::from_num(0.5)).unwrap();
+    assert!(units.check_order_price(price).is_ok());
+}
+
+#[test]
+fn test_attested_exposure_is_bounded_by_max_leverage() {
+    let nav = I80F48::from_num(1_000);
+    let long = I80F48::from_num(2);
+    let inverse = I80F48::from_num(-2);
+    let max_target_leverage = I80F48::from_num(3);
+    assert_eq!(
+        check_attested_exposure(I80F48::from_num(2_500), nav, long, max_target_leverage).unwrap(),
+        I80F48::from_num(2.5)
+    );
+    assert_eq!(
+        check_attested_exposure(I80F48::from_num(-3_000), nav, inverse, max_target_leverage)
+            .unwrap(),
+        I80F48::from_num(-3)
+    );
+    assert!(is_quasar_error(
+        check_attested_exposure(I80F48::from_num(3_001), nav, long, max_target_leverage),
+        QuasarErrorCode::InvalidParam
+    ));
+    assert!(check_attested_exposure(nav, I80F48::from_num(0), long, max_target_leverage).is_err());
+
+    // a keeper can't flip the token to the other side of the market
+    assert!(is_quasar_error(
+        check_attested_exposure(I80F48::from_num(-1_000), nav, long, max_target_leverage),
+        QuasarErrorCode::MathError
+    ));
+    assert!(is_quasar_error(
+        check_attested_exposure(I80F48::from_num(1_000), nav, inverse, max_target_leverage),
+        QuasarErrorCode::MathError
+    ));
+    // flat is on neither side
+    assert_eq!(
+        check_attested_exposure(I80F48::from_num(0), nav, inverse, max_target_leverage).unwrap(),
+        I80F48::from_num(0)
+    );
+}
+
+#[test]
+fn test_mint_then_burn_round_trips_across_decimals() {
+    // every pair of token and quote decimals, from an empty token up to a large supply
+    // priced well above the initial price
+    for decimals in 0..=9u8 {
+        for quote_decimals in 0..=9u8 {
+            let token_unit = 10u64.pow(decimals.into());
+            let quote_unit = 10u64.pow(quote_decimals.into());
+            for &(whole_supply, price_multiple) in [(0u64, 1u64), (1, 3), (1_000, 250)].iter() {
+                let supply = whole_supply * token_unit;
+                let nav = whole_supply * quote_unit * price_multiple;
+                for &whole_quantity in [1u64, 7, 1_234].iter() {
+                    let quantity = whole_quantity * token_unit;
+
+                    let mint_price = native_price_from_nav(
+                        I80F48::from_num(nav),
+                        supply,
+                        decimals,
+                        quote_decimals,
+                    )
+                    .unwrap();
+                    let paid = get_quote_amount(quantity, mint_price, true).unwrap();
+                    assert_eq!(paid, whole_quantity * quote_unit * price_multiple);
+
+                    let burn_price = native_price_from_nav(
+                        I80F48::from_num(nav + paid),
+                        supply + quantity,
+                        decimals,
+                        quote_decimals,
+                    )
+                    .unwrap();
+                    let redeemed = get_quote_amount(quantity, burn_price, false).unwrap();
+                    assert!(
+                        redeemed <= paid && paid - redeemed <= 1,
+                        "decimals {} quote decimals {}: paid {} redeemed {}",
+                        decimals,
+                        quote_decimals,
+                        paid,
+                        redeemed
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Account info of a Mango account owned by `mango_program_id`
+fn mango_ai<'a>(
+    key: &'a Pubkey,
+    lamports: &'a mut u64,
+    data: &'a mut [u8],
+    mango_program_id: &'a Pubkey,
+) -> AccountInfo<'a> {
+    AccountInfo::new(
+        key,
+        false,
+        false,
+        lamports,
+        data,
+        mango_program_id,
+        false,
+        0,
+    )
+}
+
+#[test]
+fn test_mango_context_rejects_accounts_of_another_group() {
+    let mango_program_id = Pubkey::new_unique();
+    let group_key = Pubkey::new_unique();
+    let cache_key = Pubkey::new_unique();
+    let other_cache_key = Pubkey::new_unique();
+    let account_key = Pubkey::new_unique();
+
+    let mut mango_group = MangoGroup::zeroed();
+    mango_group.meta_data = MangoMetaData::new(MangoDataType::MangoGroup, 0, true);
+    mango_group.mango_cache = cache_key;
+    let mut mango_cache = MangoCache::zeroed();
+    mango_cache.meta_data = MangoMetaData::new(MangoDataType::MangoCache, 0, true);
+    let mut other_cache = MangoCache::zeroed();
+    other_cache.meta_data = mango_cache.meta_data;
+    let mut mango_account = MangoAccount::zeroed();
+    mango_account.meta_data = MangoMetaData::new(MangoDataType::MangoAccount, 0, true);
+    mango_account.mango_group = group_key;
+
+    let mut lamports = [0u64; 4];
+    let [group_lamports, cache_lamports, other_cache_lamports, account_lamports] = &mut lamports;
+    let group_ai = mango_ai(
+        &group_key,
+        group_lamports,
+        bytemuck::bytes_of_mut(&mut mango_group),
+        &mango_program_id,
+    );
+    let cache_ai = mango_ai(
+        &cache_key,
+        cache_lamports,
+        bytemuck::bytes_of_mut(&mut mango_cache),
+        &mango_program_id,
+    );
+    let other_cache_ai = mango_ai(
+        &other_cache_key,
+        other_cache_lamports,
+        bytemuck::bytes_of_mut(&mut other_cache),
+        &mango_program_id,
+    );
+    let account_ai = mango_ai(
+        &account_key,
+        account_lamports,
+        bytemuck::bytes_of_mut(&mut mango_account),
+        &mango_program_id,
+    );
+
+    assert!(load_mango_context(&mango_program_id, &group_ai, &cache_ai, &account_ai).is_ok());
+
+    // the cache of another Mango group
+    assert!(is_quasar_error(
+        load_mango_context(&mango_program_id, &group_ai, &other_cache_ai, &account_ai),
+        QuasarErrorCode::InvalidAccount
+    ));
+
+    // an account of another Mango group
+    account_ai.try_borrow_mut_data().unwrap()[..].copy_from_slice(bytemuck::bytes_of(&{
+        let mut foreign_account = MangoAccount::zeroed();
+        foreign_account.meta_data = MangoMetaData::new(MangoDataType::MangoAccount, 0, true);
+        foreign_account.mango_group = Pubkey::new_unique();
+        foreign_account
+    }));
+    assert!(load_mango_context(&mango_program_id, &group_ai, &cache_ai, &account_ai).is_err());
+}
+
+#[test]
+fn test_mango_cache_freshness() {
+    let mut mango_group = MangoGroup::zeroed();
+    mango_group.num_oracles = 1;
+    mango_group.valid_interval = 10;
+    let mut mango_cache = MangoCache::zeroed();
+    mango_cache.price_cache[0].last_update = 100;
+
+    assert!(check_mango_cache_fresh(&mango_group, &mango_cache, 110).is_ok());
+    assert!(is_quasar_error(
+        check_mango_cache_fresh(&mango_group, &mango_cache, 111),
+        QuasarErrorCode::StaleOracle
+    ));
+
+    // an interval reaching past u64::MAX saturates instead of overflowing
+    mango_group.valid_interval = u64::MAX;
+    assert!(check_mango_cache_fresh(&mango_group, &mango_cache, u64::MAX).is_ok());
+}